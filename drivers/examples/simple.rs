@@ -3,7 +3,9 @@ use std::net::SocketAddr;
 use async_trait::async_trait;
 use clap::Parser;
 use tabled::{Table, Tabled};
-use turn_driver::{start_hooks_server, Controller, Events, Hooks, SessionAddr, Transport};
+use turn_driver::{
+    start_hooks_server, Controller, Credential, Events, HookError, Hooks, SessionAddr,
+};
 
 struct SimperHooks;
 
@@ -15,13 +17,13 @@ impl Hooks for SimperHooks {
         username: &str,
         realm: &str,
         nonce: &str,
-    ) -> Option<&str> {
+    ) -> Result<Option<Credential>, HookError> {
         println!(
             "auth: address={:?}, interface={:?}, username={:?}, realm={}, nonce={}",
             addr.address, addr.interface, username, realm, nonce
         );
 
-        Some("test")
+        Ok(Some("test".into()))
     }
 
     async fn on(&self, event: &Events, realm: &str, nonce: &str) {
@@ -67,7 +69,6 @@ async fn main() {
                 port_allocated: info.payload.port_allocated,
                 port_capacity: info.payload.port_capacity,
             }])
-            .to_string()
         );
 
         println!("Interfaces:");
@@ -78,18 +79,12 @@ async fn main() {
                     .interfaces
                     .into_iter()
                     .map(|it| Interface {
-                        transport: if it.transport == Transport::UDP {
-                            "UDP"
-                        } else {
-                            "TCP"
-                        }
-                        .to_string(),
+                        transport: format!("{:?}", it.transport),
                         external: it.external,
                         bind: it.bind,
                     })
                     .collect::<Vec<Interface>>()
             )
-            .to_string()
         );
     } else {
         println!("turn server not runing!");
@@ -1,24 +1,26 @@
-use std::{fmt::Display, future::Future, net::SocketAddr, sync::Arc, time::Duration};
+use std::{
+    fmt::Display,
+    future::Future,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
 
 use async_trait::async_trait;
 use axum::{
     extract::{Json as Body, Query, State},
-    http::HeaderMap,
-    response::IntoResponse,
+    http::{HeaderMap, HeaderValue},
+    middleware,
+    response::{IntoResponse, Response as HttpResponse},
     routing::{get, post},
     Router,
 };
 
-use reqwest::{Client, ClientBuilder, Response, StatusCode};
+use reqwest::{Client, ClientBuilder, Response, StatusCode, Url};
 use serde::{Deserialize, Serialize};
 use tokio::net::TcpListener;
 
-#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
-#[serde(rename_all = "lowercase")]
-pub enum Transport {
-    TCP,
-    UDP,
-}
+pub use stun::Transport;
 
 #[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
 pub struct SessionAddr {
@@ -26,13 +28,26 @@ pub struct SessionAddr {
     pub interface: SocketAddr,
 }
 
+/// The transport protocol of an interface, the sessions of a TLS interface
+/// are connected over TCP
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum InterfaceTransport {
+    UDP,
+    TCP,
+    TLS,
+}
+
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Interface {
-    pub transport: Transport,
+    pub transport: InterfaceTransport,
     /// turn server listen address
     pub bind: SocketAddr,
     /// specify the node external address and port
     pub external: SocketAddr,
+    /// The realm of the interface, if it has its own realm
+    #[serde(default)]
+    pub realm: Option<String>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -47,6 +62,31 @@ pub struct Info {
     pub port_capacity: u16,
     /// Turn all interfaces bound to the server
     pub interfaces: Vec<Interface>,
+    /// The memory used by the subsystems of the turn server
+    #[serde(default)]
+    pub memory: Option<Memory>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Memory {
+    /// An estimate of the bytes used by the session tables
+    pub sessions: usize,
+    /// The bytes of the data waiting to be forwarded between the interfaces
+    pub router: usize,
+    /// The bytes of the events waiting for the hooks and the event sinks
+    pub events: usize,
+    pub allocator: Allocator,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Allocator {
+    /// "mimalloc", "jemalloc" or "system"
+    pub name: String,
+    /// The bytes allocated by the turn server, only reported by jemalloc
+    pub allocated: Option<usize>,
+    /// The bytes of physical memory mapped by the allocator, only reported
+    /// by jemalloc
+    pub resident: Option<usize>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -59,9 +99,133 @@ pub struct Session {
     pub channels: Vec<u16>,
     /// Port numbers that have been assigned to the session
     pub port: Option<u16>,
+    /// The ip address advertised as the relayed transport address
+    #[serde(default)]
+    pub relay_ip: Option<IpAddr>,
+    /// The ORIGIN attribute of the request that authenticated the session
+    #[serde(default)]
+    pub origin: Option<String>,
     /// The validity period of the current session application, in seconds
     pub expires: u32,
     pub permissions: Vec<u16>,
+    /// The transport protocol that the client is connected over
+    pub transport: Transport,
+    /// The mechanism that the session was authenticated with
+    #[serde(default)]
+    pub auth_method: Option<String>,
+    /// When the session was authenticated, a unix timestamp in seconds
+    #[serde(default)]
+    pub created_at: u64,
+    /// When the session last sent an authenticated request, a unix timestamp
+    /// in seconds
+    #[serde(default)]
+    pub last_request_at: u64,
+    /// When the session last sent data to a peer, a unix timestamp in seconds
+    #[serde(default)]
+    pub last_data_at: Option<u64>,
+    /// When the current nonce of the session was issued, a unix timestamp in
+    /// seconds
+    #[serde(default)]
+    pub nonce_issued_at: Option<u64>,
+    /// Whether the relayed data of the session is mirrored to the multicast
+    /// group of the turn server
+    #[serde(default)]
+    pub mirrored: bool,
+    /// Whether the forwarded packets of the session are traced
+    #[serde(default)]
+    pub traced: bool,
+    /// The relayed address of the session on the upstream turn server, if
+    /// the session reaches peers through it
+    #[serde(default)]
+    pub upstream_relay: Option<SocketAddr>,
+}
+
+/// The filters and the page of the sessions to get, the sessions are ordered
+/// by the time they were created.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SessionsQuery {
+    /// Only the sessions that are connected over this transport protocol
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transport: Option<Transport>,
+    /// Only the sessions of this username
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    /// Only the sessions on this interface
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interface: Option<SocketAddr>,
+    /// Only the sessions that have received and sent at least this many
+    /// bytes together
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_bytes: Option<u64>,
+    /// The number of matching sessions to skip
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset: Option<usize>,
+    /// The maximum number of sessions to return
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+struct PutCredential<'a> {
+    username: &'a str,
+    password: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ttl: Option<u64>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SessionEntry {
+    #[serde(flatten)]
+    pub addr: SessionAddr,
+    #[serde(flatten)]
+    pub session: Session,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AffinityEntry {
+    #[serde(flatten)]
+    pub addr: SessionAddr,
+    /// The transport protocol that the client is connected over
+    pub transport: Transport,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    /// Sent by the traced session to the peer
+    Sent,
+    /// Received by the traced session from the peer
+    Received,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Framing {
+    ChannelData,
+    Indication,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TracedPacket {
+    pub direction: Direction,
+    /// The client address of the session on the other side
+    pub peer: SocketAddr,
+    pub framing: Framing,
+    /// The channel number of ChannelData messages
+    pub channel: Option<u16>,
+    /// The size of the ChannelData message or the Data indication
+    pub size: usize,
+    /// When the packet was forwarded, a unix timestamp in milliseconds
+    pub timestamp: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Trace {
+    /// Whether packets are still recorded
+    pub active: bool,
+    /// The packets that did not fit into the trace
+    pub dropped: u64,
+    pub packets: Vec<TracedPacket>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -76,15 +240,23 @@ pub struct Statistics {
     pub send_pkts: u64,
     /// The number of packets error by the current session
     pub error_pkts: u64,
+    /// The number of packets toward the current session dropped by strict
+    /// reverse path verification
+    #[serde(default)]
+    pub rejected_pkts: u64,
+    /// The number of transmissions of requests from the current session that
+    /// the client reports as lost
+    #[serde(default)]
+    pub retransmitted_pkts: u64,
+    /// The number of packets from the current session dropped because they
+    /// exceeded the bandwidth limits
+    #[serde(default)]
+    pub dropped_pkts: u64,
 }
 
-impl<'a> Display for SessionAddr {
+impl Display for SessionAddr {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{}",
-            format!("address={}&interface={}", self.address, self.interface)
-        )
+        write!(f, "address={}&interface={}", self.address, self.interface)
     }
 }
 
@@ -161,6 +333,81 @@ impl Controller {
         .await
     }
 
+    /// Get the sessions that match the filters of the query, a page of them if
+    /// the query has an offset or a limit.
+    pub async fn get_sessions(&self, query: &SessionsQuery) -> Option<Message<Vec<SessionEntry>>> {
+        Message::from_res(
+            self.client
+                .get(format!("{}/sessions", self.server))
+                .query(query)
+                .send()
+                .await
+                .ok()?,
+            |res| async { res.json().await.ok() },
+        )
+        .await
+    }
+
+    // The url of the path segments, usernames are path segments so they have to be
+    // encoded.
+    fn segments_url(&self, segments: &[&str]) -> Option<Url> {
+        let mut url = Url::parse(&self.server).ok()?;
+        url.path_segments_mut()
+            .ok()?
+            .pop_if_empty()
+            .extend(segments);
+
+        Some(url)
+    }
+
+    /// Get all sessions of the username, a user can have multiple sessions
+    /// at the same time.
+    pub async fn get_user_sessions(&self, username: &str) -> Option<Message<Vec<SessionEntry>>> {
+        Message::from_res(
+            self.client
+                .get(self.segments_url(&["sessions", "by-user", username])?)
+                .send()
+                .await
+                .ok()?,
+            |res| async { res.json().await.ok() },
+        )
+        .await
+    }
+
+    /// Delete all sessions of the username, for example to evict a banned
+    /// user, returns the addresses of the deleted sessions.
+    pub async fn remove_user_sessions(&self, username: &str) -> Option<Message<Vec<SessionAddr>>> {
+        Message::from_res(
+            self.client
+                .delete(self.segments_url(&["sessions", "by-user", username])?)
+                .send()
+                .await
+                .ok()?,
+            |res| async move {
+                if res.status() == StatusCode::NOT_FOUND {
+                    Some(Vec::new())
+                } else {
+                    res.json().await.ok()
+                }
+            },
+        )
+        .await
+    }
+
+    /// Get the client addresses that are pinned to an interface by an
+    /// allocation, for load balancers in front of the interfaces.
+    pub async fn get_affinity(&self) -> Option<Message<Vec<AffinityEntry>>> {
+        Message::from_res(
+            self.client
+                .get(format!("{}/affinity", self.server))
+                .send()
+                .await
+                .ok()?,
+            |res| async { res.json().await.ok() },
+        )
+        .await
+    }
+
     /// Get session statistics, which is mainly the traffic statistics of the
     /// current session
     pub async fn get_session_statistics(&self, query: &SessionAddr) -> Option<Message<Statistics>> {
@@ -175,6 +422,58 @@ impl Controller {
         .await
     }
 
+    /// Get the usernames of all static credentials, including the ones
+    /// managed at runtime.
+    pub async fn get_credentials(&self) -> Option<Message<Vec<String>>> {
+        Message::from_res(
+            self.client
+                .get(format!("{}/credentials", self.server))
+                .send()
+                .await
+                .ok()?,
+            |res| async { res.json().await.ok() },
+        )
+        .await
+    }
+
+    /// Add or replace a static credential, it is persisted if the turn server
+    /// has a credential store. The credential expires after the time to live
+    /// if there is one, the sessions of the username are closed when it does.
+    pub async fn put_credential(
+        &self,
+        username: &str,
+        password: &str,
+        ttl: Option<Duration>,
+    ) -> Option<Message<bool>> {
+        Message::from_res(
+            self.client
+                .post(format!("{}/credentials", self.server))
+                .json(&PutCredential {
+                    username,
+                    password,
+                    ttl: ttl.map(|it| it.as_secs()),
+                })
+                .send()
+                .await
+                .ok()?,
+            |res| async move { Some(res.status() == StatusCode::OK) },
+        )
+        .await
+    }
+
+    /// Remove a static credential, the sessions of the username are closed.
+    pub async fn remove_credential(&self, username: &str) -> Option<Message<bool>> {
+        Message::from_res(
+            self.client
+                .delete(self.segments_url(&["credentials", username])?)
+                .send()
+                .await
+                .ok()?,
+            |res| async move { Some(res.status() == StatusCode::OK) },
+        )
+        .await
+    }
+
     /// Delete the session. Deleting the session will cause the turn server to
     /// delete all routing information of the current session. If there is a
     /// peer, the peer will also be disconnected.
@@ -189,6 +488,85 @@ impl Controller {
         )
         .await
     }
+
+    /// Start mirroring the relayed data of the session to the multicast group
+    /// of the turn server, the token is the `mirror.token` of the turn server
+    /// configuration.
+    pub async fn start_mirror(&self, query: &SessionAddr, token: &str) -> Option<Message<bool>> {
+        Message::from_res(
+            self.client
+                .put(format!("{}/session/mirror?{}", self.server, query))
+                .bearer_auth(token)
+                .send()
+                .await
+                .ok()?,
+            |res| async move { Some(res.status() == StatusCode::OK) },
+        )
+        .await
+    }
+
+    /// Stop mirroring the relayed data of the session.
+    pub async fn stop_mirror(&self, query: &SessionAddr, token: &str) -> Option<Message<bool>> {
+        Message::from_res(
+            self.client
+                .delete(format!("{}/session/mirror?{}", self.server, query))
+                .bearer_auth(token)
+                .send()
+                .await
+                .ok()?,
+            |res| async move { Some(res.status() == StatusCode::OK) },
+        )
+        .await
+    }
+
+    /// Start tracing the forwarded packets of the session for `duration`,
+    /// the turn server traces a session for 10 minutes at most.
+    pub async fn start_trace(
+        &self,
+        query: &SessionAddr,
+        duration: Duration,
+    ) -> Option<Message<bool>> {
+        Message::from_res(
+            self.client
+                .put(format!(
+                    "{}/session/trace?{}&duration={}",
+                    self.server,
+                    query,
+                    duration.as_secs()
+                ))
+                .send()
+                .await
+                .ok()?,
+            |res| async move { Some(res.status() == StatusCode::OK) },
+        )
+        .await
+    }
+
+    /// Get the packets recorded by the trace of the session.
+    pub async fn get_trace(&self, query: &SessionAddr) -> Option<Message<Trace>> {
+        Message::from_res(
+            self.client
+                .get(format!("{}/session/trace?{}", self.server, query))
+                .send()
+                .await
+                .ok()?,
+            |res| async { res.json().await.ok() },
+        )
+        .await
+    }
+
+    /// Stop tracing the session and drop its trace.
+    pub async fn stop_trace(&self, query: &SessionAddr) -> Option<Message<bool>> {
+        Message::from_res(
+            self.client
+                .delete(format!("{}/session/trace?{}", self.server, query))
+                .send()
+                .await
+                .ok()?,
+            |res| async move { Some(res.status() == StatusCode::OK) },
+        )
+        .await
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -214,6 +592,7 @@ pub enum Events {
         session: SessionAddr,
         username: String,
         port: u16,
+        transport: Transport,
     },
     /// channel binding request
     ///
@@ -340,14 +719,150 @@ pub enum Events {
     /// session closed
     ///
     /// Triggered when the session leaves from the turn. Possible reasons: the
-    /// session life cycle has expired, external active deletion, or active
-    /// exit of the session.
+    /// session life cycle has expired, external active deletion, active exit
+    /// of the session, the allocation has been idle for too long, or the
+    /// server is shutting down.
     Closed {
         session: SessionAddr,
         username: String,
+        reason: CloseReason,
+        /// The traffic since the last `Traffic` event, when the traffic of
+        /// the sessions is collected.
+        #[serde(default)]
+        traffic: Option<Traffic>,
+    },
+    /// session traffic
+    ///
+    /// Triggered periodically for every session that relayed data since its
+    /// previous traffic event, when the turn server is configured with a
+    /// traffic interval. Together with the traffic of the closed event, the
+    /// traffic events add up to all the traffic of the session.
+    Traffic {
+        session: SessionAddr,
+        username: String,
+        traffic: Traffic,
     },
+    /// session transferred
+    ///
+    /// Triggered when a client that reconnected from another address, over
+    /// another interface or transport, re-claims its existing allocation. The
+    /// session moved from `from` to `session`.
+    Transferred {
+        session: SessionAddr,
+        username: String,
+        from: SessionAddr,
+    },
+    /// credentials locked out
+    ///
+    /// Triggered when a username or a source address has failed
+    /// authentication too many times and is locked out for `lockout`
+    /// seconds.
+    Locked {
+        session: SessionAddr,
+        username: String,
+        target: LockoutTarget,
+        lockout: u64,
+    },
+    /// authentication failed
+    ///
+    /// Triggered for every request that presents an unknown username or
+    /// fails the integrity check.
+    AuthFailed {
+        session: SessionAddr,
+        username: String,
+        reason: AuthFailureReason,
+    },
+    /// session affinity changed
+    ///
+    /// Triggered when a client address is pinned to an interface by an
+    /// allocation, or is no longer pinned to it, so that load balancers in
+    /// front of the interfaces keep the traffic of an allocation on the same
+    /// turn server. A transfer removes the previous address and adds the new
+    /// one in the same event.
+    Affinity {
+        session: SessionAddr,
+        username: String,
+        added: Vec<SessionAddr>,
+        removed: Vec<SessionAddr>,
+    },
+}
+
+/// Why a request failed authentication.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthFailureReason {
+    UnknownUsername,
+    Integrity,
+}
+
+/// The bytes and packets that a session relayed.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct Traffic {
+    pub received_bytes: u64,
+    pub send_bytes: u64,
+    pub received_pkts: u64,
+    pub send_pkts: u64,
+}
+
+/// Why a session was closed.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CloseReason {
+    Expired,
+    Client,
+    Removed,
+    Idle,
+    Shutdown,
+}
+
+/// What a credential lockout applies to.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LockoutTarget {
+    Username,
+    Address,
+}
+
+/// The credential of a user that the hooks return to the turn server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Credential {
+    pub password: String,
 }
 
+impl<T: Into<String>> From<T> for Credential {
+    fn from(value: T) -> Self {
+        Self {
+            password: value.into(),
+        }
+    }
+}
+
+/// Why the hooks do not return a credential for a user that may exist.
+///
+/// An unknown user is not an error, the hooks return `Ok(None)` for it and the
+/// turn server rejects the request with 401 Unauthorized.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HookError {
+    /// The user must not be authenticated, the reason is sent to the turn
+    /// server, which logs it and rejects the request with 403 Forbidden.
+    Denied(String),
+    /// The credential could not be looked up, for example because the
+    /// database is down. The turn server tries the next hooks server and
+    /// rejects the request with 500 Server Error, which clients may retry.
+    Unavailable(String),
+}
+
+impl Display for HookError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Denied(reason) => write!(f, "denied: {}", reason),
+            Self::Unavailable(reason) => write!(f, "unavailable: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for HookError {}
+
 /// Abstraction that handles turn server communication with the outside world
 ///
 /// ```ignore
@@ -355,8 +870,18 @@ pub enum Events {
 ///
 /// #[async_trait]
 /// impl Hooks for HooksImpl {
-///     async fn auth(&self, addr: SocketAddr, name: String, realm: String, rid: String) -> Option<&str> {
-///         get_password(username).await // Pretend this function exists
+///     async fn auth(
+///         &self,
+///         session: &SessionAddr,
+///         username: &str,
+///         realm: &str,
+///         nonce: &str,
+///     ) -> Result<Option<Credential>, HookError> {
+///         // Pretend this function exists
+///         match get_password(username).await {
+///             Ok(password) => Ok(password.map(Credential::from)),
+///             Err(e) => Err(HookError::Unavailable(e.to_string())),
+///         }
 ///     }
 ///
 ///     async fn on(&self, event: Events, realm: String, rid: String) {
@@ -368,7 +893,7 @@ pub enum Events {
 pub trait Hooks {
     /// When the turn server needs to authenticate the current user, hooks only
     /// needs to find the key according to the username and other information of
-    /// the current session and return it
+    /// the current session and return it, `None` if the user is unknown.
     #[allow(unused_variables)]
     async fn auth(
         &self,
@@ -376,20 +901,84 @@ pub trait Hooks {
         username: &str,
         realm: &str,
         nonce: &str,
-    ) -> Option<&str> {
-        None
+    ) -> Result<Option<Credential>, HookError> {
+        Ok(None)
+    }
+
+    /// Like `auth`, with the web origin of the application if the request
+    /// carried the ORIGIN attribute, which allows a policy per origin.
+    /// Defaults to `auth`.
+    #[allow(unused_variables)]
+    async fn auth_with_origin(
+        &self,
+        session: &SessionAddr,
+        username: &str,
+        realm: &str,
+        nonce: &str,
+        origin: Option<&str>,
+    ) -> Result<Option<Credential>, HookError> {
+        self.auth(session, username, realm, nonce).await
+    }
+
+    /// Whether the session may relay to the peer, asked before the turn
+    /// server installs a permission or binds a channel for the peer, `false`
+    /// rejects the request with 403 Forbidden. Only asked if `peer` is
+    /// declared in the capabilities. Defaults to allowing every peer.
+    #[allow(unused_variables)]
+    async fn allow_peer(&self, session: &SessionAddr, username: &str, peer: &SocketAddr) -> bool {
+        true
     }
 
     /// Called when the turn server pushes an event
     #[allow(unused_variables)]
     async fn on(&self, event: &Events, realm: &str, nonce: &str) {}
+
+    /// The capabilities declared to the turn server, `password` if the hooks
+    /// answer password requests, and the kinds of the events that they
+    /// accept. The turn server does not request what is not declared, so a
+    /// hooks service that only consumes events does not delay
+    /// authentication, and events introduced by newer turn servers are not
+    /// pushed to an older driver. Defaults to `password` and all events
+    /// known to this driver, add `peer` if the hooks implement
+    /// `allow_peer`.
+    fn capabilities(&self) -> Vec<&str> {
+        std::iter::once("password")
+            .chain(EVENT_KINDS.iter().copied())
+            .collect()
+    }
 }
 
+/// The version of the hooks contract implemented by this driver.
+pub const HOOKS_VERSION: u32 = 1;
+
+/// The kinds of all events known to this driver, see [`Events`].
+pub const EVENT_KINDS: &[&str] = &[
+    "allocated",
+    "channel_bind",
+    "create_permission",
+    "refresh",
+    "closed",
+    "traffic",
+    "transferred",
+    "locked",
+    "auth_failed",
+    "affinity",
+];
+
 #[derive(Deserialize)]
 struct GetPasswordQuery {
     address: SocketAddr,
     interface: SocketAddr,
     username: String,
+    origin: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct GetPeerQuery {
+    address: SocketAddr,
+    interface: SocketAddr,
+    username: String,
+    peer: SocketAddr,
 }
 
 /// Create a hooks service, which will create an HTTP server. The turn server
@@ -398,6 +987,11 @@ pub async fn start_hooks_server<T>(bind: SocketAddr, hooks: T) -> Result<(), std
 where
     T: Hooks + Send + Sync + 'static,
 {
+    // Every response carries the version and the capabilities of the hooks, which is
+    // how the turn server negotiates what it requests.
+    let capabilities = HeaderValue::from_str(&hooks.capabilities().join(","))
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+
     let app = Router::new()
         .route(
             "/password",
@@ -405,18 +999,49 @@ where
                 |headers: HeaderMap,
                  State(state): State<Arc<T>>,
                  Query(query): Query<GetPasswordQuery>| async move {
-                    if let Some((realm, nonce)) = get_realm_and_nonce(&headers) {
-                        if let Some(password) =
-                            state.auth(&SessionAddr {
-                                address: query.address,
-                                interface: query.interface,
-                            }, &query.username, realm, nonce).await
-                        {
-                            return password.to_string().into_response();
+                    let (realm, nonce) = match get_realm_and_nonce(&headers) {
+                        Some(it) => it,
+                        None => return StatusCode::NOT_FOUND.into_response(),
+                    };
+
+                    let session = SessionAddr {
+                        address: query.address,
+                        interface: query.interface,
+                    };
+
+                    // The turn server tries the next hooks server on a server error.
+                    match state
+                        .auth_with_origin(&session, &query.username, realm, nonce, query.origin.as_deref())
+                        .await
+                    {
+                        Ok(Some(it)) => it.password.into_response(),
+                        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+                        Err(HookError::Denied(reason)) => (StatusCode::FORBIDDEN, reason).into_response(),
+                        Err(HookError::Unavailable(reason)) => {
+                            (StatusCode::SERVICE_UNAVAILABLE, reason).into_response()
                         }
                     }
+                },
+            ),
+        )
+        .route(
+            "/peer",
+            get(
+                |headers: HeaderMap, State(state): State<Arc<T>>, Query(query): Query<GetPeerQuery>| async move {
+                    if get_realm_and_nonce(&headers).is_none() {
+                        return StatusCode::NOT_FOUND;
+                    }
+
+                    let session = SessionAddr {
+                        address: query.address,
+                        interface: query.interface,
+                    };
 
-                    StatusCode::NOT_FOUND.into_response()
+                    if state.allow_peer(&session, &query.username, &query.peer).await {
+                        StatusCode::OK
+                    } else {
+                        StatusCode::FORBIDDEN
+                    }
                 },
             ),
         )
@@ -432,6 +1057,15 @@ where
                 },
             ),
         )
+        .layer(middleware::map_response(move |mut res: HttpResponse| {
+            let capabilities = capabilities.clone();
+            async move {
+                let headers = res.headers_mut();
+                headers.insert("Hooks-Version", HeaderValue::from(HOOKS_VERSION));
+                headers.insert("Hooks-Capabilities", capabilities);
+                res
+            }
+        }))
         .with_state(Arc::new(hooks));
 
     axum::serve(TcpListener::bind(bind).await?, app).await?;
@@ -1,20 +1,76 @@
-use crate::StunError;
+use crate::{
+    util::{self, is_quoted_text},
+    StunError,
+};
 
 use std::{
-    fmt::Debug,
+    fmt::{Debug, Display},
     net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    str::FromStr,
 };
 
 use bytes::{BufMut, BytesMut};
 use num_enum::TryFromPrimitive;
 
+// USERNAME values from RFC 8489 agents are fewer than 509 bytes, longer values
+// up to 763 bytes are still accepted from RFC 5389 agents.
+const MAX_USERNAME_BYTES: usize = 763;
+
+// REALM, NONCE and SOFTWARE values are fewer than 128 characters.
+pub(crate) const MAX_TEXT_CHARS: usize = 128;
+
+/// The transport protocol, the discriminant is the value of the protocol in
+/// the REQUESTED-TRANSPORT attribute.
+///
+/// This is the transport type of the whole workspace, the configuration, the
+/// REST API and the hooks all use it. With the `serde` feature it is
+/// serialized as "udp" or "tcp", the same as its text form.
+///
+/// # Test
+///
+/// ```
+/// use mycrl_stun::attribute::Transport;
+///
+/// assert_eq!("udp".parse::<Transport>().unwrap(), Transport::UDP);
+/// assert_eq!("tcp".parse::<Transport>().unwrap(), Transport::TCP);
+/// assert!("quic".parse::<Transport>().is_err());
+///
+/// assert_eq!(Transport::UDP.to_string(), "udp");
+/// assert_eq!(Transport::TCP as u32, 0x06000000);
+/// ```
 #[repr(u32)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, TryFromPrimitive)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
 pub enum Transport {
     TCP = 0x06000000,
     UDP = 0x11000000,
 }
 
+impl FromStr for Transport {
+    type Err = StunError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Ok(match value {
+            "udp" => Self::UDP,
+            "tcp" => Self::TCP,
+            _ => return Err(StunError::UnknownTransport(value.to_string())),
+        })
+    }
+}
+
+impl Display for Transport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::UDP => "udp",
+            Self::TCP => "tcp",
+        })
+    }
+}
+
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum IpFamily {
@@ -34,6 +90,17 @@ impl TryFrom<u8> for IpFamily {
     }
 }
 
+/// The password algorithms of [RFC8489], which derive the key of a
+/// long-term credential.
+///
+/// [RFC8489]: https://datatracker.ietf.org/doc/html/rfc8489#section-18.5
+#[repr(u16)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, TryFromPrimitive)]
+pub enum Algorithm {
+    Md5 = 0x0001,
+    Sha256 = 0x0002,
+}
+
 /// [RFC3489]: https://datatracker.ietf.org/doc/html/rfc3489
 ///
 /// The Address attribute indicates a reflexive transport address
@@ -413,6 +480,8 @@ pub enum AttrKind {
     MappedAddress = 0x0001,
     UserName = 0x0006,
     MessageIntegrity = 0x0008,
+    MessageIntegritySha256 = 0x001C,
+    PasswordAlgorithm = 0x001D,
     ErrorCode = 0x0009,
     ChannelNumber = 0x000C,
     Lifetime = 0x000D,
@@ -427,16 +496,21 @@ pub enum AttrKind {
     DontFragment = 0x001A,
     XorMappedAddress = 0x0020,
     ReservationToken = 0x0022,
+    ConnectionId = 0x002A,
     Priority = 0x0024,
     UseCandidate = 0x0025,
     AdditionalAddressFamily = 0x8000,
     AddressErrorCode = 0x8001,
+    PasswordAlgorithms = 0x8002,
     Icmp = 0x8004,
     Software = 0x8022,
+    TransactionTransmitCounter = 0x8025,
     Fingerprint = 0x8028,
     IceControlled = 0x8029,
     IceControlling = 0x802A,
     ResponseOrigin = 0x802B,
+    Origin = 0x802F,
+    MobilityTicket = 0x8030,
 }
 
 /// dyn stun/turn message attribute.
@@ -484,7 +558,12 @@ impl<'a> Attribute<'a> for UserName {
     }
 
     fn decode(bytes: &'a [u8], _: &'a [u8]) -> Result<Self::Item, Self::Error> {
-        Ok(std::str::from_utf8(bytes)?)
+        let value = std::str::from_utf8(bytes)?;
+        if value.len() > MAX_USERNAME_BYTES {
+            return Err(StunError::InvalidString);
+        }
+
+        Ok(value)
     }
 }
 
@@ -542,7 +621,12 @@ impl<'a> Attribute<'a> for Realm {
     }
 
     fn decode(bytes: &'a [u8], _: &'a [u8]) -> Result<Self::Item, Self::Error> {
-        Ok(std::str::from_utf8(bytes)?)
+        let value = std::str::from_utf8(bytes)?;
+        if value.chars().count() >= MAX_TEXT_CHARS || !is_quoted_text(value) {
+            return Err(StunError::InvalidString);
+        }
+
+        Ok(value)
     }
 }
 
@@ -570,7 +654,12 @@ impl<'a> Attribute<'a> for Nonce {
     }
 
     fn decode(bytes: &'a [u8], _: &'a [u8]) -> Result<Self::Item, Self::Error> {
-        Ok(std::str::from_utf8(bytes)?)
+        let value = std::str::from_utf8(bytes)?;
+        if value.chars().count() >= MAX_TEXT_CHARS || !is_quoted_text(value) {
+            return Err(StunError::InvalidString);
+        }
+
+        Ok(value)
     }
 }
 
@@ -597,6 +686,36 @@ impl<'a> Attribute<'a> for Software {
         bytes.put(value.as_bytes());
     }
 
+    fn decode(bytes: &'a [u8], _: &'a [u8]) -> Result<Self::Item, Self::Error> {
+        let value = std::str::from_utf8(bytes)?;
+        if value.chars().count() >= MAX_TEXT_CHARS {
+            return Err(StunError::InvalidString);
+        }
+
+        Ok(value)
+    }
+}
+
+/// [draft-ietf-tram-stun-origin]: https://datatracker.ietf.org/doc/html/draft-ietf-tram-stun-origin-06
+/// [RFC6454]: https://datatracker.ietf.org/doc/html/rfc6454
+///
+/// The ORIGIN attribute [draft-ietf-tram-stun-origin] carries the web origin
+/// [RFC6454] of the application that uses STUN or TURN, such as
+/// `https://example.com`, browsers add it to requests on behalf of web
+/// applications.  It is a UTF-8 string of fewer than 128 characters and
+/// only informs the server, which can use it to apply policies per origin.
+pub struct Origin;
+
+impl<'a> Attribute<'a> for Origin {
+    type Error = StunError;
+    type Item = &'a str;
+
+    const KIND: AttrKind = AttrKind::Origin;
+
+    fn encode(value: Self::Item, bytes: &mut BytesMut, _: &'a [u8]) {
+        bytes.put(value.as_bytes());
+    }
+
     fn decode(bytes: &'a [u8], _: &'a [u8]) -> Result<Self::Item, Self::Error> {
         Ok(std::str::from_utf8(bytes)?)
     }
@@ -650,6 +769,136 @@ impl<'a> Attribute<'a> for MessageIntegrity {
     }
 }
 
+/// [RFC8489]: https://datatracker.ietf.org/doc/html/rfc8489#section-14.6
+///
+/// The MESSAGE-INTEGRITY-SHA256 attribute contains an HMAC-SHA256 of the
+/// STUN message.  The MESSAGE-INTEGRITY-SHA256 attribute can be present in
+/// any STUN message type.  The MESSAGE-INTEGRITY-SHA256 attribute contains
+/// an initial portion of the HMAC-SHA-256 of the STUN message.  The value
+/// will be at most 32 bytes, but it MUST be at least 16 bytes and MUST be a
+/// multiple of 4 bytes.
+///
+/// The text used as input to HMAC is the STUN message, up to and including
+/// the attribute preceding the MESSAGE-INTEGRITY-SHA256 attribute, with the
+/// Length field adjusted to point to the end of the attribute, the same as
+/// for MESSAGE-INTEGRITY.
+pub struct MessageIntegritySha256;
+
+impl<'a> Attribute<'a> for MessageIntegritySha256 {
+    type Error = StunError;
+    type Item = &'a [u8];
+
+    const KIND: AttrKind = AttrKind::MessageIntegritySha256;
+
+    fn encode(value: Self::Item, bytes: &mut BytesMut, _: &'a [u8]) {
+        bytes.put(value);
+    }
+
+    fn decode(bytes: &'a [u8], _: &'a [u8]) -> Result<Self::Item, Self::Error> {
+        Ok(bytes)
+    }
+}
+
+/// [RFC8489]: https://datatracker.ietf.org/doc/html/rfc8489#section-14.11
+///
+/// The PASSWORD-ALGORITHM attribute is present only in requests.  It
+/// contains the algorithm that the server must use to derive a key from
+/// the long-term password.  The value is a 16-bit algorithm number followed
+/// by the length of the parameters, none of the known algorithms has any.
+///
+/// # Test
+///
+/// ```
+/// use bytes::BytesMut;
+/// use mycrl_stun::attribute::*;
+///
+/// let mut buf = BytesMut::with_capacity(1280);
+/// PasswordAlgorithm::encode(Algorithm::Sha256, &mut buf, &[]);
+/// assert_eq!(&buf[..], &[0x00, 0x02, 0x00, 0x00]);
+/// assert_eq!(PasswordAlgorithm::decode(&buf, &[]).unwrap(), Algorithm::Sha256);
+/// ```
+pub struct PasswordAlgorithm;
+
+impl<'a> Attribute<'a> for PasswordAlgorithm {
+    type Error = StunError;
+    type Item = Algorithm;
+
+    const KIND: AttrKind = AttrKind::PasswordAlgorithm;
+
+    fn encode(value: Self::Item, bytes: &mut BytesMut, _: &'a [u8]) {
+        bytes.put_u16(value as u16);
+        bytes.put_u16(0);
+    }
+
+    fn decode(bytes: &'a [u8], _: &'a [u8]) -> Result<Self::Item, Self::Error> {
+        if bytes.len() < 4 {
+            return Err(StunError::InvalidInput);
+        }
+
+        Algorithm::try_from(u16::from_be_bytes(bytes[..2].try_into()?))
+            .map_err(|_| StunError::InvalidInput)
+    }
+}
+
+/// [RFC8489]: https://datatracker.ietf.org/doc/html/rfc8489#section-14.12
+///
+/// The PASSWORD-ALGORITHMS attribute may be present in requests and
+/// responses.  It contains the list of algorithms that the server can use
+/// to derive the long-term password, in order of preference.  A client
+/// that chooses an algorithm copies the attribute of the challenge into
+/// its requests, so that the server can detect a downgrade.
+///
+/// # Test
+///
+/// ```
+/// use bytes::BytesMut;
+/// use mycrl_stun::attribute::*;
+///
+/// let mut buf = BytesMut::with_capacity(1280);
+/// PasswordAlgorithms::encode(vec![Algorithm::Sha256, Algorithm::Md5], &mut buf, &[]);
+/// assert_eq!(&buf[..], &[0x00, 0x02, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00]);
+/// assert_eq!(
+///     PasswordAlgorithms::decode(&buf, &[]).unwrap(),
+///     vec![Algorithm::Sha256, Algorithm::Md5]
+/// );
+/// ```
+pub struct PasswordAlgorithms;
+
+impl<'a> Attribute<'a> for PasswordAlgorithms {
+    type Error = StunError;
+    type Item = Vec<Algorithm>;
+
+    const KIND: AttrKind = AttrKind::PasswordAlgorithms;
+
+    fn encode(value: Self::Item, bytes: &mut BytesMut, _: &'a [u8]) {
+        for algorithm in value {
+            bytes.put_u16(algorithm as u16);
+            bytes.put_u16(0);
+        }
+    }
+
+    fn decode(bytes: &'a [u8], _: &'a [u8]) -> Result<Self::Item, Self::Error> {
+        let mut algorithms = Vec::with_capacity(2);
+        let mut offset = 0;
+
+        // Each algorithm is followed by its parameters, which are padded to a
+        // multiple of 4 bytes.
+        while bytes.len() - offset >= 4 {
+            let algorithm = u16::from_be_bytes(bytes[offset..offset + 2].try_into()?);
+            let size = u16::from_be_bytes(bytes[offset + 2..offset + 4].try_into()?) as usize;
+
+            offset += 4 + size + util::pad_size(size);
+            if offset > bytes.len() {
+                return Err(StunError::InvalidInput);
+            }
+
+            algorithms.push(Algorithm::try_from(algorithm).map_err(|_| StunError::InvalidInput)?);
+        }
+
+        Ok(algorithms)
+    }
+}
+
 /// [RFC5389]: https://datatracker.ietf.org/doc/html/rfc5389
 ///
 /// The XOR-PEER-ADDRESS specifies the address and port of the peer as
@@ -833,6 +1082,9 @@ impl<'a> Attribute<'a> for ResponseOrigin {
 ///      credentials to proceed.  The client should retry the request
 ///      with proper credentials.
 ///      
+/// 405  Mobility Forbidden: Mobility request was valid but cannot be
+///      performed due to administrative or similar restrictions.
+///      
 /// 420  Unknown Attribute: The server received a STUN packet containing
 ///      a comprehension-required attribute that it did not understand.
 ///      The server MUST put this unknown attribute in the UNKNOWN-
@@ -855,6 +1107,7 @@ pub enum ErrorKind {
     BadRequest = errno(400),
     Unauthorized = errno(401),
     Forbidden = errno(403),
+    MobilityForbidden = errno(405),
     UnknownAttribute = errno(420),
     AllocationMismatch = errno(437),
     StaleNonce = errno(438),
@@ -862,6 +1115,8 @@ pub enum ErrorKind {
     WrongCredentials = errno(441),
     UnsupportedTransportAddress = errno(442),
     PeerAddressFamilyMismatch = errno(443),
+    ConnectionAlreadyExists = errno(446),
+    ConnectionTimeoutOrFailure = errno(447),
     AllocationQuotaReached = errno(486),
     ServerError = errno(500),
     InsufficientCapacity = errno(508),
@@ -1006,16 +1261,19 @@ impl From<ErrorKind> for &'static str {
             ErrorKind::BadRequest => "Bad Request",
             ErrorKind::Unauthorized => "Unauthorized",
             ErrorKind::Forbidden => "Forbidden",
+            ErrorKind::MobilityForbidden => "Mobility Forbidden",
             ErrorKind::UnknownAttribute => "Unknown Attribute",
             ErrorKind::AllocationMismatch => "Allocation Mismatch",
             ErrorKind::StaleNonce => "Stale Nonce",
             ErrorKind::AddressFamilyNotSupported => "Address Family not Supported",
             ErrorKind::WrongCredentials => "Wrong Credentials",
-            ErrorKind::UnsupportedTransportAddress => "Unsupported Transport Address",
+            ErrorKind::UnsupportedTransportAddress => "Unsupported Transport Protocol",
             ErrorKind::AllocationQuotaReached => "Allocation Quota Reached",
             ErrorKind::ServerError => "Server Error",
             ErrorKind::InsufficientCapacity => "Insufficient Capacity",
             ErrorKind::PeerAddressFamilyMismatch => "Peer Address Family Mismatch",
+            ErrorKind::ConnectionAlreadyExists => "Connection Already Exists",
+            ErrorKind::ConnectionTimeoutOrFailure => "Connection Timeout or Failure",
         }
     }
 }
@@ -1081,6 +1339,31 @@ impl<'a> Attribute<'a> for Lifetime {
     }
 }
 
+/// [RFC6062]: https://datatracker.ietf.org/doc/html/rfc6062
+///
+/// The CONNECTION-ID attribute uniquely identifies a peer data connection.
+/// It is a 32-bit unsigned integral value, it is given to the client in the
+/// Connect success response or in the ConnectionAttempt indication of a TCP
+/// allocation [RFC6062], and the client presents it in the ConnectionBind
+/// request that binds a new client data connection to the peer data
+/// connection.
+pub struct ConnectionId;
+
+impl<'a> Attribute<'a> for ConnectionId {
+    type Error = StunError;
+    type Item = u32;
+
+    const KIND: AttrKind = AttrKind::ConnectionId;
+
+    fn encode(value: Self::Item, bytes: &mut BytesMut, _: &'a [u8]) {
+        bytes.put_u32(value)
+    }
+
+    fn decode(bytes: &'a [u8], _: &'a [u8]) -> Result<Self::Item, Self::Error> {
+        Ok(u32::from_be_bytes(bytes.try_into()?))
+    }
+}
+
 /// This attribute is used by the client to request a specific transport
 /// protocol for the allocated transport address.
 ///
@@ -1106,7 +1389,7 @@ impl<'a> Attribute<'a> for ReqeestedTransport {
 
     fn decode(bytes: &'a [u8], _: &'a [u8]) -> Result<Self::Item, Self::Error> {
         let value = u32::from_be_bytes(bytes.try_into()?);
-        Transport::try_from(value).map_err(|_| StunError::InvalidInput)
+        Transport::try_from(value).map_err(|_| StunError::UnsupportedTransport((value >> 24) as u8))
     }
 }
 
@@ -1304,6 +1587,25 @@ impl<'a> Attribute<'a> for ReservationToken {
 /// transport address be even, and (optionally) that the server reserve the
 /// next-higher port number.  The value portion of this attribute is 1 byte
 /// long.
+///
+/// The value is the R bit, which asks the server to reserve the next-higher
+/// port number, the other bits are reserved for future use and ignored.
+///
+/// # Test
+///
+/// ```
+/// use bytes::BytesMut;
+/// use mycrl_stun::attribute::*;
+///
+/// let mut bytes = BytesMut::new();
+/// EvenPort::encode(true, &mut bytes, &[]);
+/// assert_eq!(&bytes[..], &[0x80]);
+///
+/// assert!(EvenPort::decode(&[0x80], &[]).unwrap());
+/// assert!(EvenPort::decode(&[0x81], &[]).unwrap());
+/// assert!(!EvenPort::decode(&[0x00], &[]).unwrap());
+/// assert!(EvenPort::decode(&[], &[]).is_err());
+/// ```
 pub struct EvenPort;
 
 impl<'a> Attribute<'a> for EvenPort {
@@ -1317,7 +1619,8 @@ impl<'a> Attribute<'a> for EvenPort {
     }
 
     fn decode(bytes: &'a [u8], _: &'a [u8]) -> Result<Self::Item, Self::Error> {
-        Ok(bytes[0] == 0b10000000)
+        let flags = bytes.first().ok_or(StunError::InvalidInput)?;
+        Ok(flags & 0b10000000 != 0)
     }
 }
 
@@ -1365,6 +1668,57 @@ impl<'a> Attribute<'a> for AdditionalAddressFamily {
     }
 }
 
+/// This attribute is used by servers to signal the reason for not
+/// allocating the requested address family.  The value starts with an
+/// 8-bit address family, the remaining fields are the ones of the
+/// ERROR-CODE attribute.
+///
+/// # Test
+///
+/// ```
+/// use bytes::BytesMut;
+/// use mycrl_stun::attribute::*;
+///
+/// let mut buf = BytesMut::with_capacity(1280);
+/// let error = Error::from(ErrorKind::AddressFamilyNotSupported);
+/// AddressErrorCode::encode((IpFamily::V6, error), &mut buf, &[]);
+/// assert_eq!(&buf[..4], &[0x02, 0x00, 0x04, 0x28]);
+///
+/// let (family, error) = AddressErrorCode::decode(&buf, &[]).unwrap();
+/// assert_eq!(family, IpFamily::V6);
+/// assert_eq!(error.code, ErrorKind::AddressFamilyNotSupported as u16);
+/// assert_eq!(error.message, "Address Family not Supported");
+/// ```
+pub struct AddressErrorCode;
+
+impl<'a> Attribute<'a> for AddressErrorCode {
+    type Error = StunError;
+    type Item = (IpFamily, Error<'a>);
+
+    const KIND: AttrKind = AttrKind::AddressErrorCode;
+
+    fn encode(value: Self::Item, bytes: &mut BytesMut, _: &'a [u8]) {
+        bytes.put_u8(value.0 as u8);
+        bytes.put_u8(0x00);
+        bytes.put_u16(value.1.code);
+        bytes.put(value.1.message.as_bytes());
+    }
+
+    fn decode(bytes: &'a [u8], _: &'a [u8]) -> Result<Self::Item, Self::Error> {
+        if bytes.len() < 4 {
+            return Err(StunError::InvalidInput);
+        }
+
+        Ok((
+            IpFamily::try_from(bytes[0])?,
+            Error {
+                code: u16::from_be_bytes(bytes[2..4].try_into()?),
+                message: std::str::from_utf8(&bytes[4..])?,
+            },
+        ))
+    }
+}
+
 /// This attribute is used by the client to request that the server set the DF
 /// (Don't Fragment) bit in the IP header when relaying the application data
 /// onward to the peer and for determining the server capability in Allocate
@@ -1382,3 +1736,170 @@ impl<'a> Attribute<'a> for DontFragment {
         Ok(())
     }
 }
+
+/// The ICMP type, code and error data carried by the ICMP attribute.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IcmpError {
+    pub kind: u8,
+    pub code: u8,
+    pub data: u32,
+}
+
+impl IcmpError {
+    /// Destination unreachable, port unreachable, in the ICMP type and code
+    /// space of the address family.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use mycrl_stun::attribute::IcmpError;
+    ///
+    /// let error = IcmpError::port_unreachable(false);
+    /// assert_eq!((error.kind, error.code), (3, 3));
+    ///
+    /// let error = IcmpError::port_unreachable(true);
+    /// assert_eq!((error.kind, error.code), (1, 4));
+    /// ```
+    pub fn port_unreachable(is_ipv6: bool) -> Self {
+        let (kind, code) = if is_ipv6 { (1, 4) } else { (3, 3) };
+        Self {
+            kind,
+            code,
+            data: 0,
+        }
+    }
+}
+
+/// This attribute is used by servers to signal the reason a UDP packet was
+/// dropped.  The value portion of this attribute is 8 bytes long: two
+/// reserved bytes, the ICMP type, the ICMP code and four bytes of error
+/// data.  The ICMP attribute is only included in Data indications, together
+/// with the XOR-PEER-ADDRESS of the peer that the error refers to, and
+/// without a DATA attribute.
+pub struct Icmp;
+
+impl<'a> Attribute<'a> for Icmp {
+    type Error = StunError;
+    type Item = IcmpError;
+
+    const KIND: AttrKind = AttrKind::Icmp;
+
+    fn encode(value: Self::Item, bytes: &mut BytesMut, _: &'a [u8]) {
+        bytes.put_u16(0);
+        bytes.put_u8(value.kind);
+        bytes.put_u8(value.code);
+        bytes.put_u32(value.data);
+    }
+
+    fn decode(bytes: &'a [u8], _: &'a [u8]) -> Result<Self::Item, Self::Error> {
+        if bytes.len() < 8 {
+            return Err(StunError::InvalidInput);
+        }
+
+        Ok(IcmpError {
+            kind: bytes[2],
+            code: bytes[3],
+            data: u32::from_be_bytes(bytes[4..8].try_into()?),
+        })
+    }
+}
+
+/// The transmission counts carried by the TRANSACTION-TRANSMIT-COUNTER
+/// attribute.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransmitCounter {
+    /// The number of times the request has been transmitted, this one
+    /// included.
+    pub req: u8,
+    /// The number of responses that the server has sent for the
+    /// transaction, this one included, zero in requests.
+    pub resp: u8,
+}
+
+/// [RFC7982]: https://datatracker.ietf.org/doc/html/rfc7982
+///
+/// The TRANSACTION-TRANSMIT-COUNTER attribute lets the client tell the
+/// server how many times a request has been transmitted, and the server
+/// tell the client how many responses it has sent to the transaction.  The
+/// value portion of this attribute is 4 bytes long: two reserved bytes, the
+/// Req count and the Resp count.  A client sets Req to the number of
+/// transmissions of the request, starting at one, and leaves Resp at zero.
+/// The server copies Req into the response and sets Resp.
+///
+/// # Test
+///
+/// ```
+/// use bytes::BytesMut;
+/// use mycrl_stun::attribute::*;
+///
+/// let counter = TransmitCounter { req: 3, resp: 0 };
+///
+/// let mut bytes = BytesMut::new();
+/// TransactionTransmitCounter::encode(counter, &mut bytes, &[]);
+/// assert_eq!(&bytes[..], &[0, 0, 3, 0]);
+///
+/// let decoded = TransactionTransmitCounter::decode(&bytes, &[]).unwrap();
+/// assert_eq!(decoded, counter);
+/// ```
+pub struct TransactionTransmitCounter;
+
+impl<'a> Attribute<'a> for TransactionTransmitCounter {
+    type Error = StunError;
+    type Item = TransmitCounter;
+
+    const KIND: AttrKind = AttrKind::TransactionTransmitCounter;
+
+    fn encode(value: Self::Item, bytes: &mut BytesMut, _: &'a [u8]) {
+        bytes.put_u16(0);
+        bytes.put_u8(value.req);
+        bytes.put_u8(value.resp);
+    }
+
+    fn decode(bytes: &'a [u8], _: &'a [u8]) -> Result<Self::Item, Self::Error> {
+        if bytes.len() < 4 {
+            return Err(StunError::InvalidInput);
+        }
+
+        Ok(TransmitCounter {
+            req: bytes[2],
+            resp: bytes[3],
+        })
+    }
+}
+
+/// [RFC8016]: https://datatracker.ietf.org/doc/html/rfc8016
+///
+/// The MOBILITY-TICKET attribute is used to retain an allocation on the TURN
+/// server.  It is exchanged between the client and server to aid mobility.
+/// The value of the MOBILITY-TICKET is encrypted and is of variable length.
+/// The client sends it empty in an Allocate request to ask for mobility,
+/// and presents the ticket of the response in a Refresh request from its
+/// new address.
+///
+/// # Test
+///
+/// ```
+/// use bytes::BytesMut;
+/// use mycrl_stun::attribute::*;
+///
+/// let mut bytes = BytesMut::new();
+/// MobilityTicket::encode(&[1, 2, 3, 4], &mut bytes, &[]);
+/// assert_eq!(MobilityTicket::decode(&bytes, &[]).unwrap(), &[1, 2, 3, 4]);
+/// assert!(MobilityTicket::decode(&[], &[]).unwrap().is_empty());
+/// ```
+pub struct MobilityTicket;
+
+impl<'a> Attribute<'a> for MobilityTicket {
+    type Error = StunError;
+    type Item = &'a [u8];
+
+    const KIND: AttrKind = AttrKind::MobilityTicket;
+
+    fn encode(value: Self::Item, bytes: &mut BytesMut, _: &'a [u8]) {
+        bytes.put(value);
+    }
+
+    fn decode(bytes: &'a [u8], _: &'a [u8]) -> Result<Self::Item, Self::Error> {
+        Ok(bytes)
+    }
+}
@@ -0,0 +1,244 @@
+//! ## Credentials
+//!
+//! [RFC8265]: https://tools.ietf.org/html/rfc8265
+//! [RFC8489]: https://tools.ietf.org/html/rfc8489#section-9.2.2
+//! [TURN REST]: https://datatracker.ietf.org/doc/html/draft-uberti-behave-turn-rest-00#section-2.2
+//!
+//! Username and password handling shared by the server and the clients. The
+//! long-term credential key is computed over strings prepared with the
+//! OpaqueString profile of [RFC8265], as required by [RFC8489], so that
+//! visually identical credentials entered in different Unicode forms produce
+//! the same key. Passwords derived from a shared secret follow [TURN REST].
+
+use std::borrow::Cow;
+
+use base64::{prelude::BASE64_STANDARD, Engine};
+use md5::{Digest, Md5};
+use precis_core::profile::PrecisFastInvocation;
+use precis_profiles::OpaqueString;
+use sha2::Sha256;
+
+use crate::{
+    attribute::{Algorithm, MAX_TEXT_CHARS},
+    util::{hmac_sha1, is_quoted_text},
+    StunError,
+};
+
+/// Prepare a string with the OpaqueString profile.
+///
+/// Non-ASCII spaces are mapped to the ASCII space and the string is
+/// normalized to NFC. Empty strings and strings containing disallowed code
+/// points, such as control characters, are rejected.
+///
+/// # Test
+///
+/// ```
+/// use mycrl_stun::auth::opaque_string;
+///
+/// assert_eq!(opaque_string("panda").unwrap(), "panda");
+/// assert_eq!(opaque_string("correct\u{3000}horse").unwrap(), "correct horse");
+/// assert_eq!(opaque_string("e\u{301}").unwrap(), "\u{e9}");
+/// assert!(opaque_string("").is_err());
+/// assert!(opaque_string("pan\u{7}da").is_err());
+/// ```
+pub fn opaque_string(value: &str) -> Result<Cow<'_, str>, StunError> {
+    OpaqueString::enforce(value).map_err(|_| StunError::InvalidString)
+}
+
+/// Prepare the realm of a server.
+///
+/// The realm is prepared with the OpaqueString profile, and it must be fewer
+/// than 128 characters of qdtext or quoted-pair, as the REALM attribute
+/// requires.
+///
+/// # Test
+///
+/// ```
+/// use mycrl_stun::auth::prepare_realm;
+///
+/// assert_eq!(prepare_realm("example.com").unwrap(), "example.com");
+/// assert_eq!(prepare_realm("bu\u{308}cher.example").unwrap(), "b\u{fc}cher.example");
+/// assert!(prepare_realm("\"example.com\"").is_err());
+/// assert!(prepare_realm(&"a".repeat(128)).is_err());
+/// ```
+pub fn prepare_realm(value: &str) -> Result<Cow<'_, str>, StunError> {
+    let value = opaque_string(value)?;
+    if value.chars().count() >= MAX_TEXT_CHARS || !is_quoted_text(&value) {
+        return Err(StunError::InvalidString);
+    }
+
+    Ok(value)
+}
+
+/// create long term credential.
+///
+/// > key = MD5(username ":" OpaqueString(realm) ":" OpaqueString(password))
+///
+/// The username is prepared with OpaqueString as well, which leaves
+/// usernames that the client already prepared unchanged.
+///
+/// # Test
+///
+/// ```
+/// let buffer = [
+///     0x3eu8, 0x2f, 0x79, 0x1e, 0x1f, 0x14, 0xd1, 0x73, 0xfc, 0x91, 0xff,
+///     0x2f, 0x59, 0xb5, 0x0f, 0xd1,
+/// ];
+///
+/// let key = mycrl_stun::auth::long_term_credential_digest("panda", "panda", "raspberry");
+/// assert_eq!(key.unwrap(), buffer);
+///
+/// assert_eq!(
+///     mycrl_stun::auth::long_term_credential_digest("panda", "pa\u{301}nda", "raspberry").unwrap(),
+///     mycrl_stun::auth::long_term_credential_digest("panda", "p\u{e1}nda", "raspberry").unwrap(),
+/// );
+///
+/// assert!(mycrl_stun::auth::long_term_credential_digest("panda", "", "raspberry").is_err());
+/// ```
+pub fn long_term_credential_digest(
+    username: &str,
+    password: &str,
+    realm: &str,
+) -> Result<[u8; 16], StunError> {
+    let mut hasher = Md5::new();
+    hasher.update(
+        [
+            opaque_string(username)?,
+            opaque_string(realm)?,
+            opaque_string(password)?,
+        ]
+        .join(":"),
+    );
+
+    Ok(hasher.finalize().into())
+}
+
+/// The key of a long-term credential.
+///
+/// The key is derived with the password algorithm that the client chose,
+/// MD5 unless the client chose another one with the PASSWORD-ALGORITHM
+/// attribute of [RFC8489].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Md5([u8; 16]),
+    Sha256([u8; 32]),
+}
+
+impl Key {
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            Self::Md5(it) => it,
+            Self::Sha256(it) => it,
+        }
+    }
+}
+
+/// create long term credential with a password algorithm.
+///
+/// > key = SHA-256(username ":" OpaqueString(realm) ":" OpaqueString(password))
+///
+/// For the MD5 algorithm the key is the one of
+/// [`long_term_credential_digest`].
+///
+/// # Test
+///
+/// ```
+/// use mycrl_stun::{attribute::Algorithm, auth::*};
+///
+/// let buffer = [
+///     0xa5u8, 0xbf, 0xe0, 0x2c, 0xcd, 0x8b, 0x08, 0xdd, 0x99, 0x54, 0x40,
+///     0xde, 0xbc, 0x9b, 0x4e, 0x69, 0xfb, 0x0a, 0xec, 0x3d, 0x69, 0x0a, 0x5f,
+///     0x47, 0x92, 0x11, 0xfd, 0xf7, 0x89, 0x05, 0x56, 0xe6,
+/// ];
+///
+/// let key = long_term_credential_key("panda", "panda", "raspberry", Algorithm::Sha256);
+/// assert_eq!(key.unwrap(), Key::Sha256(buffer));
+///
+/// let key = long_term_credential_key("panda", "panda", "raspberry", Algorithm::Md5);
+/// assert_eq!(
+///     key.unwrap(),
+///     Key::Md5(long_term_credential_digest("panda", "panda", "raspberry").unwrap())
+/// );
+/// ```
+pub fn long_term_credential_key(
+    username: &str,
+    password: &str,
+    realm: &str,
+    algorithm: Algorithm,
+) -> Result<Key, StunError> {
+    Ok(match algorithm {
+        Algorithm::Md5 => Key::Md5(long_term_credential_digest(username, password, realm)?),
+        Algorithm::Sha256 => {
+            let mut hasher = Sha256::new();
+            hasher.update(
+                [
+                    opaque_string(username)?,
+                    opaque_string(realm)?,
+                    opaque_string(password)?,
+                ]
+                .join(":"),
+            );
+
+            Key::Sha256(hasher.finalize().into())
+        }
+    })
+}
+
+/// Create the password of a username from a shared secret.
+///
+/// > password = base64(HMAC-SHA1(secret, username))
+///
+/// The username carries the unix time at which the credential expires, for
+/// example `1735689600:alice`, see [`static_auth_secret_expires`].
+///
+/// # Test
+///
+/// ```
+/// let password =
+///     mycrl_stun::auth::static_auth_secret_password("secret", "1735689600:alice").unwrap();
+///
+/// assert_eq!(password, "Utv7WtALdDlVkVQE++ZVAdNMC/g=");
+/// ```
+pub fn static_auth_secret_password(secret: &str, username: &str) -> Result<String, StunError> {
+    Ok(BASE64_STANDARD.encode(
+        hmac_sha1(secret.as_bytes(), &[username.as_bytes()])?
+            .into_bytes()
+            .as_slice(),
+    ))
+}
+
+/// Get the expiry timestamp of a username for a shared secret.
+///
+/// The username is `timestamp:userid`, or only the timestamp. As coturn
+/// does, the timestamp is also accepted after the separator when the part
+/// before it is not a number, as in `alice:1735689600`. Returns `None` if the
+/// username has no timestamp, such a username does not authenticate.
+///
+/// # Test
+///
+/// ```
+/// use mycrl_stun::auth::static_auth_secret_expires;
+///
+/// assert_eq!(static_auth_secret_expires("1735689600:alice"), Some(1735689600));
+/// assert_eq!(static_auth_secret_expires("alice:1735689600"), Some(1735689600));
+/// assert_eq!(static_auth_secret_expires("1735689600"), Some(1735689600));
+/// assert_eq!(static_auth_secret_expires("1735689600:a:b"), Some(1735689600));
+/// assert_eq!(static_auth_secret_expires("alice"), None);
+/// assert_eq!(static_auth_secret_expires("alice:bob"), None);
+/// assert_eq!(static_auth_secret_expires(":1735689600"), None);
+/// ```
+pub fn static_auth_secret_expires(username: &str) -> Option<u64> {
+    let parse = |it: &str| {
+        if !it.is_empty() && it.bytes().all(|it| it.is_ascii_digit()) {
+            it.parse().ok()
+        } else {
+            None
+        }
+    };
+
+    match username.split_once(':') {
+        Some(("", _)) => None,
+        Some((timestamp, userid)) => parse(timestamp).or_else(|| parse(userid)),
+        None => parse(username),
+    }
+}
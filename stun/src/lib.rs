@@ -45,6 +45,7 @@
 //! More information on STUN Usages can be found in [Section 13].
 
 pub mod attribute;
+pub mod auth;
 pub mod channel;
 pub mod message;
 pub mod util;
@@ -69,6 +70,10 @@ pub enum StunError {
     NotIntegrity,
     #[error("IntegrityFailed")]
     IntegrityFailed,
+    #[error("NotFingerprint")]
+    NotFingerprint,
+    #[error("FingerprintFailed")]
+    FingerprintFailed,
     #[error("NotCookie")]
     NotCookie,
     #[error("UnknownMethod")]
@@ -79,6 +84,16 @@ pub enum StunError {
     Utf8Error(#[from] std::str::Utf8Error),
     #[error("TryFromSliceError: {0}")]
     TryFromSliceError(#[from] std::array::TryFromSliceError),
+    #[error("InvalidString")]
+    InvalidString,
+    #[error("ReservedAttribute: {0:?}")]
+    ReservedAttribute(AttrKind),
+    #[error("MessageTooLarge")]
+    MessageTooLarge,
+    #[error("UnsupportedTransport: {0}")]
+    UnsupportedTransport(u8),
+    #[error("UnknownTransport: {0}")]
+    UnknownTransport(String),
 }
 
 /// STUN Methods Registry
@@ -86,6 +101,7 @@ pub enum StunError {
 /// [RFC5389]: https://datatracker.ietf.org/doc/html/rfc5389
 /// [RFC8489]: https://datatracker.ietf.org/doc/html/rfc8489
 /// [RFC8126]: https://datatracker.ietf.org/doc/html/rfc8126
+/// [RFC6062]: https://datatracker.ietf.org/doc/html/rfc6062
 /// [Section 5]: https://datatracker.ietf.org/doc/html/rfc8489#section-5
 ///
 /// A STUN method is a hex number in the range 0x000-0x0FF.  The encoding
@@ -112,6 +128,13 @@ pub enum StunError {
 /// 0x007: Data
 /// 0x008: CreatePermission
 /// 0x009: ChannelBind
+///
+/// The methods of the TURN extension for TCP allocations are registered by
+/// [RFC6062]:
+///
+/// 0x00A: Connect
+/// 0x00B: ConnectionBind
+/// 0x00C: ConnectionAttempt
 #[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
 pub enum Kind {
     Request,
@@ -126,20 +149,38 @@ pub enum Method {
     CreatePermission(Kind),
     ChannelBind(Kind),
     Refresh(Kind),
+    Connect(Kind),
+    ConnectionBind(Kind),
     SendIndication,
     DataIndication,
+    ConnectionAttemptIndication,
 }
 
 impl Method {
+    pub fn is_request(&self) -> bool {
+        matches!(
+            self,
+            Method::Binding(Kind::Request)
+                | Method::Refresh(Kind::Request)
+                | Method::Allocate(Kind::Request)
+                | Method::CreatePermission(Kind::Request)
+                | Method::ChannelBind(Kind::Request)
+                | Method::Connect(Kind::Request)
+                | Method::ConnectionBind(Kind::Request)
+        )
+    }
+
     pub fn is_error(&self) -> bool {
-        match self {
+        matches!(
+            self,
             Method::Binding(Kind::Error)
-            | Method::Refresh(Kind::Error)
-            | Method::Allocate(Kind::Error)
-            | Method::CreatePermission(Kind::Error)
-            | Method::ChannelBind(Kind::Error) => true,
-            _ => false,
-        }
+                | Method::Refresh(Kind::Error)
+                | Method::Allocate(Kind::Error)
+                | Method::CreatePermission(Kind::Error)
+                | Method::ChannelBind(Kind::Error)
+                | Method::Connect(Kind::Error)
+                | Method::ConnectionBind(Kind::Error)
+        )
     }
 }
 
@@ -212,8 +253,36 @@ impl TryFrom<u16> for Method {
     ///     Method::try_from(0x0114).unwrap(),
     ///     Method::Refresh(Kind::Error)
     /// );
+    /// assert_eq!(
+    ///     Method::try_from(0x000a).unwrap(),
+    ///     Method::Connect(Kind::Request)
+    /// );
+    /// assert_eq!(
+    ///     Method::try_from(0x010a).unwrap(),
+    ///     Method::Connect(Kind::Response)
+    /// );
+    /// assert_eq!(
+    ///     Method::try_from(0x011a).unwrap(),
+    ///     Method::Connect(Kind::Error)
+    /// );
+    /// assert_eq!(
+    ///     Method::try_from(0x000b).unwrap(),
+    ///     Method::ConnectionBind(Kind::Request)
+    /// );
+    /// assert_eq!(
+    ///     Method::try_from(0x010b).unwrap(),
+    ///     Method::ConnectionBind(Kind::Response)
+    /// );
+    /// assert_eq!(
+    ///     Method::try_from(0x011b).unwrap(),
+    ///     Method::ConnectionBind(Kind::Error)
+    /// );
     /// assert_eq!(Method::try_from(0x0016).unwrap(), Method::SendIndication);
     /// assert_eq!(Method::try_from(0x0017).unwrap(), Method::DataIndication);
+    /// assert_eq!(
+    ///     Method::try_from(0x001c).unwrap(),
+    ///     Method::ConnectionAttemptIndication
+    /// );
     /// ```
     fn try_from(value: u16) -> Result<Self, Self::Error> {
         Ok(match value {
@@ -232,8 +301,15 @@ impl TryFrom<u16> for Method {
             0x0004 => Self::Refresh(Kind::Request),
             0x0104 => Self::Refresh(Kind::Response),
             0x0114 => Self::Refresh(Kind::Error),
+            0x000a => Self::Connect(Kind::Request),
+            0x010a => Self::Connect(Kind::Response),
+            0x011a => Self::Connect(Kind::Error),
+            0x000b => Self::ConnectionBind(Kind::Request),
+            0x010b => Self::ConnectionBind(Kind::Response),
+            0x011b => Self::ConnectionBind(Kind::Error),
             0x0016 => Self::SendIndication,
             0x0017 => Self::DataIndication,
+            0x001c => Self::ConnectionAttemptIndication,
             _ => return Err(StunError::UnknownMethod),
         })
     }
@@ -261,8 +337,15 @@ impl From<Method> for u16 {
     /// assert_eq!(0x0004u16, Method::Refresh(Kind::Request).into());
     /// assert_eq!(0x0104u16, Method::Refresh(Kind::Response).into());
     /// assert_eq!(0x0114u16, Method::Refresh(Kind::Error).into());
+    /// assert_eq!(0x000au16, Method::Connect(Kind::Request).into());
+    /// assert_eq!(0x010au16, Method::Connect(Kind::Response).into());
+    /// assert_eq!(0x011au16, Method::Connect(Kind::Error).into());
+    /// assert_eq!(0x000bu16, Method::ConnectionBind(Kind::Request).into());
+    /// assert_eq!(0x010bu16, Method::ConnectionBind(Kind::Response).into());
+    /// assert_eq!(0x011bu16, Method::ConnectionBind(Kind::Error).into());
     /// assert_eq!(0x0016u16, Method::SendIndication.into());
     /// assert_eq!(0x0017u16, Method::DataIndication.into());
+    /// assert_eq!(0x001cu16, Method::ConnectionAttemptIndication.into());
     /// ```
     fn from(val: Method) -> Self {
         match val {
@@ -281,8 +364,15 @@ impl From<Method> for u16 {
             Method::Refresh(Kind::Request) => 0x0004,
             Method::Refresh(Kind::Response) => 0x0104,
             Method::Refresh(Kind::Error) => 0x0114,
+            Method::Connect(Kind::Request) => 0x000a,
+            Method::Connect(Kind::Response) => 0x010a,
+            Method::Connect(Kind::Error) => 0x011a,
+            Method::ConnectionBind(Kind::Request) => 0x000b,
+            Method::ConnectionBind(Kind::Response) => 0x010b,
+            Method::ConnectionBind(Kind::Error) => 0x011b,
             Method::SendIndication => 0x0016,
             Method::DataIndication => 0x0017,
+            Method::ConnectionAttemptIndication => 0x001c,
         }
     }
 }
@@ -329,7 +419,6 @@ impl Attributes {
             .iter()
             .filter(move |(k, _)| k == kind)
             .map(|(_, v)| v)
-            .into_iter()
     }
 
     pub fn clear(&mut self) {
@@ -4,6 +4,7 @@ use std::convert::TryFrom;
 
 use super::{
     attribute::{AttrKind, Attribute, MessageIntegrity},
+    auth::Key,
     util, Attributes, Method, StunError,
 };
 
@@ -13,19 +14,46 @@ const COOKIE: [u8; 4] = 0x2112A442u32.to_be_bytes();
 /// (username, password, realm)
 type Digest = [u8; 16];
 
+/// The attribute that a message is signed with, and the key of the
+/// long-term credential that it is signed with.
+///
+/// MESSAGE-INTEGRITY is the HMAC-SHA1 of the message, and
+/// MESSAGE-INTEGRITY-SHA256 of [RFC8489] the HMAC-SHA256, the key of either
+/// can be derived with either password algorithm.
+///
+/// [RFC8489]: https://datatracker.ietf.org/doc/html/rfc8489#section-14.6
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Integrity {
+    Sha1(Key),
+    Sha256(Key),
+}
+
+impl Integrity {
+    pub fn key(&self) -> &Key {
+        match self {
+            Self::Sha1(it) | Self::Sha256(it) => it,
+        }
+    }
+}
+
 pub struct MessageWriter<'a> {
     pub token: &'a [u8],
     pub bytes: &'a mut BytesMut,
+    fingerprint: bool,
 }
 
-impl<'a, 'b> MessageWriter<'a> {
+impl<'a> MessageWriter<'a> {
     pub fn new(method: Method, token: &'a [u8; 12], bytes: &'a mut BytesMut) -> Self {
         unsafe { bytes.set_len(0) }
         bytes.put_u16(method.into());
         bytes.put_u16(0);
         bytes.put(&COOKIE[..]);
         bytes.put(token.as_slice());
-        Self { bytes, token }
+        Self {
+            fingerprint: false,
+            bytes,
+            token,
+        }
     }
 
     /// rely on old message to create new message.
@@ -56,7 +84,11 @@ impl<'a, 'b> MessageWriter<'a> {
         bytes.put_u16(0);
         bytes.put(&COOKIE[..]);
         bytes.put(token);
-        Self { bytes, token }
+        Self {
+            fingerprint: false,
+            bytes,
+            token,
+        }
     }
 
     /// append attribute.
@@ -144,26 +176,90 @@ impl<'a, 'b> MessageWriter<'a> {
     ///     MessageWriter::extend(Method::Binding(Kind::Request), &old, &mut buf);
     ///
     /// message
-    ///     .flush(Some(&util::long_term_credential_digest(
-    ///         "panda",
-    ///         "panda",
-    ///         "raspberry",
-    ///     )))
+    ///     .flush(Some(
+    ///         &auth::long_term_credential_digest("panda", "panda", "raspberry").unwrap(),
+    ///     ))
     ///     .unwrap();
     /// assert_eq!(&buf[..], &result);
     /// ```
     pub fn flush(&mut self, digest: Option<&Digest>) -> Result<(), StunError> {
+        self.flush_integrity(digest.map(|it| Integrity::Sha1(Key::Md5(*it))).as_ref())
+    }
+
+    /// flush the message, signed with MESSAGE-INTEGRITY or
+    /// MESSAGE-INTEGRITY-SHA256.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use bytes::BytesMut;
+    /// use mycrl_stun::attribute::*;
+    /// use mycrl_stun::*;
+    ///
+    /// let key = auth::long_term_credential_key(
+    ///     "panda",
+    ///     "panda",
+    ///     "raspberry",
+    ///     Algorithm::Sha256,
+    /// )
+    /// .unwrap();
+    ///
+    /// let mut buf = BytesMut::new();
+    /// let mut message =
+    ///     MessageWriter::new(Method::Binding(Kind::Request), &[0u8; 12], &mut buf);
+    /// message.append::<UserName>("panda");
+    /// message.flush_integrity(Some(&Integrity::Sha256(key))).unwrap();
+    ///
+    /// let mut attributes = Attributes::default();
+    /// let message = MessageReader::decode(&buf[..], &mut attributes).unwrap();
+    /// assert_eq!(message.get::<MessageIntegritySha256>().map(|it| it.len()), Some(32));
+    /// assert!(message.get::<MessageIntegrity>().is_none());
+    /// assert!(message.check_integrity(&Integrity::Sha256(key)).is_ok());
+    /// assert!(message.check_integrity(&Integrity::Sha1(key)).is_err());
+    /// assert!(message.fingerprint().is_ok());
+    /// ```
+    pub fn flush_integrity(&mut self, integrity: Option<&Integrity>) -> Result<(), StunError> {
         // write attribute list size.
         self.set_len(self.bytes.len() - 20);
 
         // if need message integrity?
-        if let Some(a) = digest {
-            self.integrity(a)?;
+        match integrity {
+            Some(Integrity::Sha1(key)) => self.integrity(key.as_bytes())?,
+            Some(Integrity::Sha256(key)) => self.integrity_sha256(key.as_bytes())?,
+            None if self.fingerprint => self.append_fingerprint(),
+            None => (),
         }
 
         Ok(())
     }
 
+    /// Whether to write FINGERPRINT when the message is flushed without
+    /// MESSAGE-INTEGRITY, a signed message always ends with FINGERPRINT.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use bytes::BytesMut;
+    /// use mycrl_stun::attribute::*;
+    /// use mycrl_stun::*;
+    ///
+    /// let mut buf = BytesMut::new();
+    /// let mut message =
+    ///     MessageWriter::new(Method::Binding(Kind::Response), &[0u8; 12], &mut buf);
+    /// message.fingerprint(true);
+    /// message.append::<Software>("test");
+    /// message.flush(None).unwrap();
+    ///
+    /// let mut attributes = Attributes::default();
+    /// let message = MessageReader::decode(&buf[..], &mut attributes).unwrap();
+    /// assert_eq!(message.get::<Software>(), Some("test"));
+    /// assert!(message.fingerprint().is_ok());
+    /// ```
+    pub fn fingerprint(&mut self, enable: bool) -> &mut Self {
+        self.fingerprint = enable;
+        self
+    }
+
     /// append MessageIntegrity attribute.
     ///
     /// add the `MessageIntegrity` attribute to the stun message
@@ -195,15 +291,13 @@ impl<'a, 'b> MessageWriter<'a> {
     ///     MessageWriter::extend(Method::Binding(Kind::Request), &old, &mut buf);
     ///
     /// message
-    ///     .flush(Some(&util::long_term_credential_digest(
-    ///         "panda",
-    ///         "panda",
-    ///         "raspberry",
-    ///     )))
+    ///     .flush(Some(
+    ///         &auth::long_term_credential_digest("panda", "panda", "raspberry").unwrap(),
+    ///     ))
     ///     .unwrap();
     /// assert_eq!(&buf[..], &result);
     /// ```
-    fn integrity(&mut self, digest: &Digest) -> Result<(), StunError> {
+    fn integrity(&mut self, key: &[u8]) -> Result<(), StunError> {
         assert!(self.bytes.len() >= 20);
         let len = self.bytes.len();
 
@@ -212,22 +306,44 @@ impl<'a, 'b> MessageWriter<'a> {
         self.set_len(len + 4);
 
         // write MessageIntegrity attribute.
-        let hmac_output = util::hmac_sha1(digest, &[self.bytes])?.into_bytes();
+        let hmac_output = util::hmac_sha1(key, &[self.bytes])?.into_bytes();
         self.bytes.put_u16(AttrKind::MessageIntegrity as u16);
         self.bytes.put_u16(20);
         self.bytes.put(hmac_output.as_slice());
 
+        self.append_fingerprint();
+        Ok(())
+    }
+
+    fn integrity_sha256(&mut self, key: &[u8]) -> Result<(), StunError> {
+        assert!(self.bytes.len() >= 20);
+        let len = self.bytes.len();
+
+        // compute new size,
+        // new size include the MessageIntegritySha256 attribute size.
+        self.set_len(len - 20 + 4 + 32);
+
+        // write MessageIntegritySha256 attribute.
+        let hmac_output = util::hmac_sha256(key, &[self.bytes])?.into_bytes();
+        self.bytes.put_u16(AttrKind::MessageIntegritySha256 as u16);
+        self.bytes.put_u16(32);
+        self.bytes.put(hmac_output.as_slice());
+
+        self.append_fingerprint();
+        Ok(())
+    }
+
+    fn append_fingerprint(&mut self) {
         // compute new size,
         // new size include the Fingerprint attribute size.
-        self.set_len(len + 4 + 8);
+        let len = self.bytes.len() - 20;
+        self.set_len(len + 8);
 
         // CRC Fingerprint
         let fingerprint = util::fingerprint(self.bytes);
         self.bytes.put_u16(AttrKind::Fingerprint as u16);
         self.bytes.put_u16(4);
         self.bytes.put_u32(fingerprint);
-
-        Ok(())
     }
 
     // set stun message header size.
@@ -236,6 +352,158 @@ impl<'a, 'b> MessageWriter<'a> {
     }
 }
 
+/// Builds a message with the attributes in the right order.
+///
+/// MESSAGE-INTEGRITY has to follow all other attributes except FINGERPRINT,
+/// and FINGERPRINT has to be the last attribute, which [`MessageWriter`]
+/// leaves to the caller. The builder writes both when the message is built,
+/// after every appended attribute, no matter when they were requested, and
+/// refuses to append them as regular attributes. FINGERPRINT can also be
+/// written without MESSAGE-INTEGRITY.
+///
+/// # Test
+///
+/// ```
+/// use bytes::BytesMut;
+/// use mycrl_stun::attribute::*;
+/// use mycrl_stun::*;
+///
+/// let token = [0u8; 12];
+/// let digest =
+///     auth::long_term_credential_digest("panda", "panda", "raspberry").unwrap();
+///
+/// let mut buf = BytesMut::new();
+/// let mut builder =
+///     MessageBuilder::new(Method::Binding(Kind::Request), &token, &mut buf);
+/// builder.integrity(&digest);
+/// builder.append::<UserName>("panda").unwrap();
+/// assert!(matches!(
+///     builder.append::<Fingerprint>(0),
+///     Err(StunError::ReservedAttribute(AttrKind::Fingerprint))
+/// ));
+///
+/// builder.build().unwrap();
+///
+/// let mut attributes = Attributes::default();
+/// let message = MessageReader::decode(&buf[..], &mut attributes).unwrap();
+/// assert_eq!(message.get::<UserName>(), Some("panda"));
+/// assert!(message.integrity(&digest).is_ok());
+///
+/// let mut buf = BytesMut::new();
+/// let mut builder =
+///     MessageBuilder::new(Method::Binding(Kind::Request), &token, &mut buf);
+/// builder.fingerprint(true);
+/// builder.build().unwrap();
+///
+/// let mut attributes = Attributes::default();
+/// let message = MessageReader::decode(&buf[..], &mut attributes).unwrap();
+/// assert!(message.get::<Fingerprint>().is_some());
+/// assert!(message.get::<MessageIntegrity>().is_none());
+/// ```
+pub struct MessageBuilder<'a> {
+    writer: MessageWriter<'a>,
+    digest: Option<Digest>,
+    fingerprint: bool,
+}
+
+impl<'a> MessageBuilder<'a> {
+    pub fn new(method: Method, token: &'a [u8; 12], bytes: &'a mut BytesMut) -> Self {
+        Self::from(MessageWriter::new(method, token, bytes))
+    }
+
+    /// rely on old message to create new message.
+    pub fn extend(method: Method, reader: &MessageReader<'a>, bytes: &'a mut BytesMut) -> Self {
+        Self::from(MessageWriter::extend(method, reader, bytes))
+    }
+
+    /// append attribute.
+    ///
+    /// MESSAGE-INTEGRITY and FINGERPRINT are written by the builder, use
+    /// [`MessageBuilder::integrity`] and [`MessageBuilder::fingerprint`]
+    /// instead.
+    pub fn append<'c, T: Attribute<'c>>(&'c mut self, value: T::Item) -> Result<(), StunError> {
+        match T::KIND {
+            AttrKind::MessageIntegrity
+            | AttrKind::MessageIntegritySha256
+            | AttrKind::Fingerprint => Err(StunError::ReservedAttribute(T::KIND)),
+            _ => {
+                self.writer.append::<T>(value);
+                Ok(())
+            }
+        }
+    }
+
+    /// Sign the message with MESSAGE-INTEGRITY, which is always followed by
+    /// FINGERPRINT.
+    pub fn integrity(&mut self, digest: &Digest) -> &mut Self {
+        self.digest = Some(*digest);
+        self
+    }
+
+    /// Whether to write FINGERPRINT, it is written anyway if the message is
+    /// signed.
+    pub fn fingerprint(&mut self, enable: bool) -> &mut Self {
+        self.fingerprint = enable;
+        self
+    }
+
+    /// Write the message header, MESSAGE-INTEGRITY and FINGERPRINT.
+    ///
+    /// Returns [`StunError::MessageTooLarge`] if the attributes do not fit
+    /// in the length of the message header.
+    pub fn build(mut self) -> Result<(), StunError> {
+        // The attributes that are still to be written count towards the length.
+        let trailer = match (self.digest.is_some(), self.fingerprint) {
+            (true, _) => 24 + 8,
+            (false, true) => 8,
+            (false, false) => 0,
+        };
+
+        if self.writer.bytes.len() - 20 + trailer > u16::MAX as usize {
+            return Err(StunError::MessageTooLarge);
+        }
+
+        self.writer.fingerprint(self.fingerprint);
+        self.writer.flush(self.digest.as_ref())
+    }
+}
+
+impl<'a> From<MessageWriter<'a>> for MessageBuilder<'a> {
+    fn from(writer: MessageWriter<'a>) -> Self {
+        Self {
+            digest: None,
+            fingerprint: false,
+            writer,
+        }
+    }
+}
+
+/// An attribute of a message as it was received, whether or not the codec
+/// knows it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RawAttribute<'a> {
+    /// attribute type.
+    pub kind: u16,
+    /// attribute value, without the padding.
+    pub value: &'a [u8],
+}
+
+impl RawAttribute<'_> {
+    /// The kind of the attribute, `None` if the codec does not know it.
+    pub fn known(&self) -> Option<AttrKind> {
+        match AttrKind::try_from(self.kind) {
+            Ok(AttrKind::Unknown) | Err(_) => None,
+            Ok(it) => Some(it),
+        }
+    }
+
+    /// Whether the attribute is comprehension-required (0x0000-0x7FFF), an
+    /// agent that does not know such an attribute cannot process the message.
+    pub fn is_comprehension_required(&self) -> bool {
+        self.kind < 0x8000
+    }
+}
+
 #[derive(Debug)]
 pub struct MessageReader<'a> {
     /// message type.
@@ -276,6 +544,39 @@ impl<'a> MessageReader<'a> {
         T::decode(&self.bytes[range.clone()], self.token).ok()
     }
 
+    /// Get an attribute, like [`MessageReader::get`], but keep the error of an
+    /// attribute that failed to decode.
+    ///
+    /// Returns `None` if the message does not contain the attribute, which
+    /// tells a missing attribute apart from an invalid one.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use mycrl_stun::attribute::*;
+    /// use mycrl_stun::*;
+    ///
+    /// let buffer = [
+    ///     0x00u8, 0x03, 0x00, 0x08, 0x21, 0x12, 0xa4, 0x42, 0x72, 0x6d, 0x49,
+    ///     0x42, 0x72, 0x52, 0x64, 0x48, 0x57, 0x62, 0x4b, 0x2b, 0x00, 0x19,
+    ///     0x00, 0x04, 0x84, 0x00, 0x00, 0x00,
+    /// ];
+    ///
+    /// let mut attributes = Attributes::default();
+    /// let message = MessageReader::decode(&buffer[..], &mut attributes).unwrap();
+    ///
+    /// assert!(message.get::<ReqeestedTransport>().is_none());
+    /// assert!(matches!(
+    ///     message.try_get::<ReqeestedTransport>(),
+    ///     Some(Err(StunError::UnsupportedTransport(0x84)))
+    /// ));
+    /// assert!(message.try_get::<UserName>().is_none());
+    /// ```
+    pub fn try_get<T: Attribute<'a>>(&self) -> Option<Result<T::Item, T::Error>> {
+        let range = self.attributes.get(&T::KIND)?;
+        Some(T::decode(&self.bytes[range.clone()], self.token))
+    }
+
     /// Gets all the values of an attribute from a list.
     ///
     /// Normally a stun message can have multiple attributes with the same name,
@@ -301,9 +602,67 @@ impl<'a> MessageReader<'a> {
     pub fn get_all<T: Attribute<'a>>(&self) -> impl Iterator<Item = T::Item> {
         self.attributes
             .get_all(&T::KIND)
-            .map(|it| T::decode(&self.bytes[it.clone()], self.token))
-            .filter(|it| it.is_ok())
-            .map(|it| it.unwrap())
+            .filter_map(|it| T::decode(&self.bytes[it.clone()], self.token).ok())
+    }
+
+    /// Iterate all attributes of the message in the order they were received.
+    ///
+    /// Unlike [`MessageReader::get`], the attributes are not decoded, and the
+    /// attributes that the codec does not know, such as proprietary ones, are
+    /// included. This is meant for diagnostics that dump exactly what a
+    /// client sent. A truncated attribute at the end of the message ends the
+    /// iteration.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use mycrl_stun::attribute::*;
+    /// use mycrl_stun::*;
+    ///
+    /// let buffer = [
+    ///     0x00u8, 0x01, 0x00, 0x10, 0x21, 0x12, 0xa4, 0x42, 0x72, 0x6d, 0x49,
+    ///     0x42, 0x72, 0x52, 0x64, 0x48, 0x57, 0x62, 0x4b, 0x2b, 0xc0, 0x57,
+    ///     0x00, 0x04, 0x00, 0x00, 0x03, 0xe7, 0x00, 0x06, 0x00, 0x03, 0x61,
+    ///     0x62, 0x63, 0x00,
+    /// ];
+    ///
+    /// let mut attributes = Attributes::default();
+    /// let message = MessageReader::decode(&buffer[..], &mut attributes).unwrap();
+    /// let raw = message.raw_attributes().collect::<Vec<_>>();
+    ///
+    /// assert_eq!(raw.len(), 2);
+    /// assert_eq!(raw[0].kind, 0xc057);
+    /// assert_eq!(raw[0].value, &[0x00, 0x00, 0x03, 0xe7]);
+    /// assert_eq!(raw[0].known(), None);
+    /// assert!(!raw[0].is_comprehension_required());
+    /// assert_eq!(raw[1].known(), Some(AttrKind::UserName));
+    /// assert_eq!(raw[1].value, b"abc");
+    /// assert!(raw[1].is_comprehension_required());
+    /// ```
+    pub fn raw_attributes(&self) -> impl Iterator<Item = RawAttribute<'a>> {
+        let bytes = self.bytes;
+        let size = bytes
+            .len()
+            .min(u16::from_be_bytes([bytes[2], bytes[3]]) as usize + 20);
+        let mut offset = 20;
+
+        std::iter::from_fn(move || {
+            if size - offset < 4 {
+                return None;
+            }
+
+            let kind = u16::from_be_bytes([bytes[offset], bytes[offset + 1]]);
+            let len = u16::from_be_bytes([bytes[offset + 2], bytes[offset + 3]]) as usize;
+            offset += 4;
+
+            if size - offset < len {
+                return None;
+            }
+
+            let value = &bytes[offset..offset + len];
+            offset = (offset + len + util::pad_size(len)).min(size);
+            Some(RawAttribute { kind, value })
+        })
     }
 
     /// check MessageReaderIntegrity attribute.
@@ -332,40 +691,129 @@ impl<'a> MessageReader<'a> {
     /// let mut attributes = Attributes::default();
     /// let message = MessageReader::decode(&buffer[..], &mut attributes).unwrap();
     /// let result = message
-    ///     .integrity(&util::long_term_credential_digest(
-    ///         "panda",
-    ///         "panda",
-    ///         "raspberry",
-    ///     ))
+    ///     .integrity(&auth::long_term_credential_digest("panda", "panda", "raspberry").unwrap())
     ///     .is_ok();
     /// assert!(result);
     /// ```
     pub fn integrity(&self, digest: &Digest) -> Result<(), StunError> {
+        self.check_integrity(&Integrity::Sha1(Key::Md5(*digest)))
+    }
+
+    /// check MessageIntegrity or MessageIntegritySha256 attribute, the one
+    /// that the integrity is of.
+    ///
+    /// MESSAGE-INTEGRITY-SHA256 may be truncated to no less than 16 bytes,
+    /// in which case it is compared with as many leading bytes of the HMAC.
+    pub fn check_integrity(&self, integrity: &Integrity) -> Result<(), StunError> {
         if self.bytes.is_empty() || self.valid_offset < 20 {
             return Err(StunError::InvalidInput);
         }
 
-        // unwrap MessageIntegrity attribute,
-        // an error occurs if not found.
-        let integrity = self
-            .get::<MessageIntegrity>()
-            .ok_or(StunError::NotIntegrity)?;
-
-        // create multiple submit.
-        let size_buf = (self.valid_offset + 4).to_be_bytes();
-        let body = [
-            &self.bytes[0..2],
-            &size_buf,
-            &self.bytes[4..self.valid_offset as usize],
-        ];
-
-        // digest the message buffer.
-        let hmac_output = util::hmac_sha1(digest, &body)?.into_bytes();
-        let hmac_buf = hmac_output.as_slice();
-
-        // Compare local and original attribute.
-        if integrity != hmac_buf {
-            return Err(StunError::IntegrityFailed);
+        match integrity {
+            Integrity::Sha1(key) => {
+                // unwrap MessageIntegrity attribute,
+                // an error occurs if not found.
+                let integrity = self
+                    .get::<MessageIntegrity>()
+                    .ok_or(StunError::NotIntegrity)?;
+
+                // create multiple submit.
+                let size_buf = (self.valid_offset + 4).to_be_bytes();
+                let body = [
+                    &self.bytes[0..2],
+                    &size_buf,
+                    &self.bytes[4..self.valid_offset as usize],
+                ];
+
+                // digest the message buffer.
+                let hmac_output = util::hmac_sha1(key.as_bytes(), &body)?.into_bytes();
+                let hmac_buf = hmac_output.as_slice();
+
+                // Compare local and original attribute.
+                if integrity != hmac_buf {
+                    return Err(StunError::IntegrityFailed);
+                }
+            }
+            Integrity::Sha256(key) => {
+                let range = self
+                    .attributes
+                    .get(&AttrKind::MessageIntegritySha256)
+                    .ok_or(StunError::NotIntegrity)?;
+
+                let integrity = &self.bytes[range.clone()];
+                if integrity.len() < 16 || integrity.len() > 32 || range.start < 24 {
+                    return Err(StunError::IntegrityFailed);
+                }
+
+                // the message is digested up to the attribute header, with a
+                // length that ends after the attribute.
+                let offset = range.start - 4;
+                let size_buf = ((offset - 20 + 4 + integrity.len()) as u16).to_be_bytes();
+                let body = [&self.bytes[0..2], &size_buf, &self.bytes[4..offset]];
+
+                let hmac_output = util::hmac_sha256(key.as_bytes(), &body)?.into_bytes();
+                if integrity != &hmac_output.as_slice()[..integrity.len()] {
+                    return Err(StunError::IntegrityFailed);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// check Fingerprint attribute.
+    ///
+    /// FINGERPRINT has to be the last attribute of the message, and its value
+    /// has to be the CRC-32 of the message up to the attribute, XOR'ed with
+    /// 0x5354554e.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use bytes::BytesMut;
+    /// use mycrl_stun::*;
+    ///
+    /// let mut buf = BytesMut::new();
+    /// let mut message =
+    ///     MessageWriter::new(Method::Binding(Kind::Request), &[0u8; 12], &mut buf);
+    /// message.flush(None).unwrap();
+    ///
+    /// let mut attributes = Attributes::default();
+    /// let message = MessageReader::decode(&buf[..], &mut attributes).unwrap();
+    /// assert!(matches!(message.fingerprint(), Err(StunError::NotFingerprint)));
+    ///
+    /// let mut buf = BytesMut::new();
+    /// let mut message =
+    ///     MessageWriter::new(Method::Binding(Kind::Request), &[0u8; 12], &mut buf);
+    /// message.fingerprint(true);
+    /// message.flush(None).unwrap();
+    ///
+    /// let mut attributes = Attributes::default();
+    /// let message = MessageReader::decode(&buf[..], &mut attributes).unwrap();
+    /// assert!(message.fingerprint().is_ok());
+    ///
+    /// let len = buf.len();
+    /// buf[len - 1] ^= 1;
+    ///
+    /// let mut attributes = Attributes::default();
+    /// let message = MessageReader::decode(&buf[..], &mut attributes).unwrap();
+    /// assert!(matches!(message.fingerprint(), Err(StunError::FingerprintFailed)));
+    /// ```
+    pub fn fingerprint(&self) -> Result<(), StunError> {
+        let range = self
+            .attributes
+            .get(&AttrKind::Fingerprint)
+            .ok_or(StunError::NotFingerprint)?;
+
+        // The attribute is the last one, so it ends where the message ends.
+        let size = u16::from_be_bytes([self.bytes[2], self.bytes[3]]) as usize + 20;
+        if range.len() != 4 || range.end != size {
+            return Err(StunError::FingerprintFailed);
+        }
+
+        let fingerprint = u32::from_be_bytes(self.bytes[range.clone()].try_into()?);
+        if fingerprint != util::fingerprint(&self.bytes[..range.start - 4]) {
+            return Err(StunError::FingerprintFailed);
         }
 
         Ok(())
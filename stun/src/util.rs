@@ -30,20 +30,29 @@ pub fn pad_size(size: usize) -> usize {
 ///
 /// > key = MD5(username ":" OpaqueString(realm) ":" OpaqueString(password))
 ///
+/// Moved to [`crate::auth::long_term_credential_digest`], which prepares the
+/// strings with the OpaqueString profile and reports the strings that cannot
+/// be prepared. They are hashed as they are here.
+///
+/// # Test
+///
 /// ```
 /// let buffer = [
 ///     0x3eu8, 0x2f, 0x79, 0x1e, 0x1f, 0x14, 0xd1, 0x73, 0xfc, 0x91, 0xff,
 ///     0x2f, 0x59, 0xb5, 0x0f, 0xd1,
 /// ];
 ///
-/// let key =
-///     mycrl_stun::util::long_term_credential_digest("panda", "panda", "raspberry");
+/// #[allow(deprecated)]
+/// let key = mycrl_stun::util::long_term_credential_digest("panda", "panda", "raspberry");
 /// assert_eq!(key, buffer);
 /// ```
+#[deprecated(note = "use `auth::long_term_credential_digest` instead")]
 pub fn long_term_credential_digest(username: &str, password: &str, realm: &str) -> [u8; 16] {
-    let mut hasher = Md5::new();
-    hasher.update([username, realm, password].join(":"));
-    hasher.finalize().into()
+    crate::auth::long_term_credential_digest(username, password, realm).unwrap_or_else(|_| {
+        let mut hasher = Md5::new();
+        hasher.update([username, realm, password].join(":"));
+        hasher.finalize().into()
+    })
 }
 
 /// HMAC SHA1 digest.
@@ -89,6 +98,41 @@ pub fn hmac_sha1(key: &[u8], source: &[&[u8]]) -> Result<CtOutput<Hmac<sha1::Sha
     }
 }
 
+/// HMAC SHA256 digest.
+///
+/// # Test
+///
+/// ```
+/// let sign = [
+///     0xf7u8, 0xbc, 0x83, 0xf4, 0x30, 0x53, 0x84, 0x24, 0xb1, 0x32, 0x98,
+///     0xe6, 0xaa, 0x6f, 0xb1, 0x43, 0xef, 0x4d, 0x59, 0xa1, 0x49, 0x46, 0x17,
+///     0x59, 0x97, 0x47, 0x9d, 0xbc, 0x2d, 0x1a, 0x3c, 0xd8,
+/// ];
+///
+/// let hmac_output = mycrl_stun::util::hmac_sha256(
+///     b"key",
+///     &[b"The quick brown fox ", b"jumps over the lazy dog"],
+/// )
+/// .unwrap()
+/// .into_bytes();
+/// assert_eq!(hmac_output.as_slice(), &sign);
+/// ```
+pub fn hmac_sha256(
+    key: &[u8],
+    source: &[&[u8]],
+) -> Result<CtOutput<Hmac<sha2::Sha256>>, StunError> {
+    match Hmac::<sha2::Sha256>::new_from_slice(key) {
+        Err(_) => Err(StunError::SummaryFailed),
+        Ok(mut mac) => {
+            for buf in source {
+                mac.update(buf);
+            }
+
+            Ok(mac.finalize())
+        }
+    }
+}
+
 /// CRC32 Fingerprint.
 ///
 /// # Test
@@ -99,3 +143,42 @@ pub fn hmac_sha1(key: &[u8], source: &[&[u8]]) -> Result<CtOutput<Hmac<sha1::Sha
 pub fn fingerprint(bytes: &[u8]) -> u32 {
     Crc::<u32>::new(&CRC_32_ISO_HDLC).checksum(bytes) ^ 0x5354_554e
 }
+
+/// Check that a string is a sequence of qdtext or quoted-pair.
+///
+/// This is the grammar of the REALM and NONCE attributes, defined in
+/// [RFC3261](https://datatracker.ietf.org/doc/html/rfc3261#section-25.1).
+/// Double quotes and control characters other than tabs must be escaped with
+/// a backslash, non-ASCII characters are allowed as they are.
+///
+/// # Test
+///
+/// ```
+/// use mycrl_stun::util::is_quoted_text;
+///
+/// assert!(is_quoted_text("example.com"));
+/// assert!(is_quoted_text("bücher.example"));
+/// assert!(is_quoted_text("say \\\"hello\\\""));
+/// assert!(!is_quoted_text("say \"hello\""));
+/// assert!(!is_quoted_text("trailing\\"));
+/// assert!(!is_quoted_text("line\nbreak"));
+/// ```
+pub fn is_quoted_text(value: &str) -> bool {
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            // quoted-pair = "\" (%x00-09 / %x0B-0C / %x0E-7F)
+            '\\' => match chars.next() {
+                Some('\n' | '\r') | None => return false,
+                Some(c) if !c.is_ascii() => return false,
+                _ => (),
+            },
+            // qdtext = LWS / %x21 / %x23-5B / %x5D-7E / UTF8-NONASCII
+            ' ' | '\t' | '\x21' | '\x23'..='\x5b' | '\x5d'..='\x7e' => (),
+            c if !c.is_ascii() => (),
+            _ => return false,
+        }
+    }
+
+    true
+}
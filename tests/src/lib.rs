@@ -1,32 +1,48 @@
+#[cfg(all(test, feature = "webrtc"))]
+mod webrtc;
+
 #[cfg(test)]
 mod tests {
-    use std::{collections::HashMap, net::SocketAddr, sync::Arc, time::Duration};
+    use std::{
+        collections::HashMap,
+        net::SocketAddr,
+        str::FromStr,
+        sync::{Arc, Mutex},
+        time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+    };
 
     use anyhow::{ensure, Result};
     use async_trait::async_trait;
-    use base64::{prelude::BASE64_STANDARD, Engine};
     use bytes::BytesMut;
     use stun::{
         attribute::{
-            ChannelNumber, Data, ErrorCode, ErrorKind, Lifetime, MappedAddress, Nonce, Realm,
-            ReqeestedTransport, ResponseOrigin, Transport, UserName, XorMappedAddress,
-            XorPeerAddress, XorRelayedAddress,
+            ChannelNumber, Data, ErrorCode, ErrorKind, Icmp, IcmpError, Lifetime, MappedAddress,
+            Nonce, Realm, ReqeestedTransport, ResponseOrigin, TransactionTransmitCounter,
+            TransmitCounter, Transport, UserName, XorMappedAddress, XorPeerAddress,
+            XorRelayedAddress,
         },
         ChannelData, Decoder, Kind, MessageReader, MessageWriter, Method, Payload,
     };
     use turn_driver::{
-        start_hooks_server, Controller, Events, Hooks, SessionAddr, Transport as DriverTransport,
+        start_hooks_server, Controller, Credential, Events, HookError, Hooks, InterfaceTransport,
+        SessionAddr, SessionsQuery, Transport as DriverTransport,
     };
 
     use once_cell::sync::Lazy;
     use rand::seq::SliceRandom;
     use tokio::{
         net::UdpSocket,
+        sync::Semaphore,
+        task::{JoinHandle, JoinSet},
         time::{sleep, timeout},
     };
 
     use turn_server::{
-        config::{Api, Auth, Config, Interface, Log, Transport as TurnTransport, Turn},
+        config::{
+            Acl, Api, Audit, Auth, Cascade, Config, GeoIp, HooksRoute, Interface, Ipfix, Log,
+            Mirror, Profiling, Realm as TurnRealm, RelayAddress, SessionMatch, Sinks,
+            Transport as TurnTransport, Turn,
+        },
         startup,
     };
 
@@ -38,6 +54,27 @@ mod tests {
     });
 
     pub async fn create_turn_server(bind: SocketAddr, auth: Auth, api: Api) -> Result<()> {
+        create_cascade_turn_server(bind, auth, api, Cascade::default()).await
+    }
+
+    pub async fn create_cascade_turn_server(
+        bind: SocketAddr,
+        auth: Auth,
+        api: Api,
+        cascade: Cascade,
+    ) -> Result<()> {
+        create_custom_turn_server(bind, auth, api, cascade, Turn::default()).await
+    }
+
+    // The realm, the interfaces and the acl of `turn` are replaced, the other
+    // options are kept.
+    pub async fn create_custom_turn_server(
+        bind: SocketAddr,
+        auth: Auth,
+        api: Api,
+        cascade: Cascade,
+        turn: Turn,
+    ) -> Result<()> {
         tokio::spawn(async move {
             startup(Arc::new(Config {
                 log: Log::default(),
@@ -47,10 +84,27 @@ mod tests {
                         transport: TurnTransport::UDP,
                         external: bind,
                         bind,
+                        external_host: None,
+                        certificate: None,
+                        private_key: None,
+                        realm: None,
                     }],
+                    // The upstream of the cascade tests is on the loopback network.
+                    acl: Acl {
+                        allowed_peers: vec!["127.0.0.0/8".parse().unwrap()],
+                        ..Default::default()
+                    },
+                    ..turn
                 },
                 auth,
                 api,
+                sinks: Sinks::default(),
+                geoip: GeoIp::default(),
+                mirror: Mirror::default(),
+                ipfix: Ipfix::default(),
+                profiling: Profiling::default(),
+                audit: Audit::default(),
+                cascade,
             }))
             .await
             .unwrap();
@@ -84,7 +138,7 @@ mod tests {
             Ok(self.socket.local_addr()?)
         }
 
-        fn create_message(&mut self, method: Method) -> MessageWriter {
+        fn create_message(&mut self, method: Method) -> MessageWriter<'_> {
             MessageWriter::new(method, &TOKEN, &mut self.send_bytes)
         }
 
@@ -97,7 +151,17 @@ mod tests {
             Ok(())
         }
 
-        async fn read_message(&mut self) -> Result<MessageReader> {
+        async fn read_message(&mut self) -> Result<MessageReader<'_>> {
+            let message = self.read_indication().await?;
+            if message.token != TOKEN.as_slice() {
+                Err(anyhow::anyhow!("Message token does not match"))
+            } else {
+                Ok(message)
+            }
+        }
+
+        // Indications that the server starts itself carry a token of their own.
+        async fn read_indication(&mut self) -> Result<MessageReader<'_>> {
             let size = timeout(
                 Duration::from_secs(1),
                 self.socket.recv(&mut self.recv_bytes),
@@ -105,17 +169,13 @@ mod tests {
             .await??;
 
             if let Payload::Message(message) = self.decoder.decode(&self.recv_bytes[..size])? {
-                if message.token != TOKEN.as_slice() {
-                    Err(anyhow::anyhow!("Message token does not match"))
-                } else {
-                    Ok(message)
-                }
+                Ok(message)
             } else {
                 Err(anyhow::anyhow!("payload not a message"))
             }
         }
 
-        async fn read_channel_data(&mut self) -> Result<ChannelData> {
+        async fn read_channel_data(&mut self) -> Result<ChannelData<'_>> {
             let size = timeout(
                 Duration::from_secs(1),
                 self.socket.recv(&mut self.recv_bytes),
@@ -162,7 +222,7 @@ mod tests {
         }
 
         pub fn local_addr(&self) -> Result<SocketAddr> {
-            Ok(self.operationer.local_addr()?)
+            self.operationer.local_addr()
         }
 
         pub async fn binding(&mut self) -> Result<()> {
@@ -185,37 +245,87 @@ mod tests {
             Ok(())
         }
 
-        pub async fn allocate(&mut self) -> Result<u16> {
+        // Sends a Binding request that claims to be the `req`th transmission,
+        // the server echoes the count in the response.
+        pub async fn binding_retransmitted(&mut self, req: u8) -> Result<()> {
             {
-                {
-                    let mut message = self
-                        .operationer
-                        .create_message(Method::Allocate(Kind::Request));
-                    message.append::<ReqeestedTransport>(Transport::UDP);
-                    message.flush(None)?;
+                let mut message = self
+                    .operationer
+                    .create_message(Method::Binding(Kind::Request));
+                message.append::<TransactionTransmitCounter>(TransmitCounter { req, resp: 0 });
+                message.flush(None)?;
 
-                    self.operationer.send().await?;
-                }
+                self.operationer.send().await?;
+            }
 
-                let message = self.operationer.read_message().await?;
+            let message = self.operationer.read_message().await?;
 
-                ensure!(message.method == Method::Allocate(Kind::Error));
-                ensure!(message.get::<ErrorCode>().unwrap().code == ErrorKind::Unauthorized as u16);
+            ensure!(message.method == Method::Binding(Kind::Response));
+            ensure!(
+                message.get::<TransactionTransmitCounter>()
+                    == Some(TransmitCounter { req, resp: 1 })
+            );
+            Ok(())
+        }
 
-                self.state.nonce = message.get::<Nonce>().unwrap().to_string();
-                self.state.realm = message.get::<Realm>().unwrap().to_string();
-                self.state.digest = stun::util::long_term_credential_digest(
-                    &self.credentials.username,
-                    &self.credentials.password,
-                    &self.state.realm,
-                );
+        // Sends an unauthenticated Allocate request to get the nonce and the realm.
+        async fn challenge(&mut self) -> Result<()> {
+            {
+                let mut message = self
+                    .operationer
+                    .create_message(Method::Allocate(Kind::Request));
+                message.append::<ReqeestedTransport>(Transport::UDP);
+                message.flush(None)?;
+
+                self.operationer.send().await?;
             }
 
+            let message = self.operationer.read_message().await?;
+
+            ensure!(message.method == Method::Allocate(Kind::Error));
+            ensure!(message.get::<ErrorCode>().unwrap().code == ErrorKind::Unauthorized as u16);
+
+            self.state.nonce = message.get::<Nonce>().unwrap().to_string();
+            self.state.realm = message.get::<Realm>().unwrap().to_string();
+            self.state.digest = stun::auth::long_term_credential_digest(
+                &self.credentials.username,
+                &self.credentials.password,
+                &self.state.realm,
+            )?;
+
+            Ok(())
+        }
+
+        pub async fn allocate(&mut self) -> Result<u16> {
+            let (port, lifetime) = self.allocate_lifetime(None).await?;
+            ensure!(lifetime == 600);
+
+            Ok(port)
+        }
+
+        // Allocates with the requested lifetime and returns the relay port and the
+        // granted lifetime.
+        pub async fn allocate_lifetime(&mut self, lifetime: Option<u32>) -> Result<(u16, u32)> {
+            let (relay, lifetime) = self.allocate_relay(lifetime).await?;
+            ensure!(relay.ip() == self.server.ip());
+
+            Ok((relay.port(), lifetime))
+        }
+
+        // Allocates with the requested lifetime and returns the relayed transport
+        // address and the granted lifetime.
+        pub async fn allocate_relay(&mut self, lifetime: Option<u32>) -> Result<(SocketAddr, u32)> {
+            self.challenge().await?;
+
             {
                 let mut message = self
                     .operationer
                     .create_message(Method::Allocate(Kind::Request));
                 message.append::<ReqeestedTransport>(Transport::UDP);
+                if let Some(lifetime) = lifetime {
+                    message.append::<Lifetime>(lifetime);
+                }
+
                 message.append::<UserName>(&self.credentials.username);
                 message.append::<Realm>(&self.state.realm);
                 message.append::<Nonce>(&self.state.nonce);
@@ -231,19 +341,104 @@ mod tests {
             message.integrity(&self.state.digest)?;
 
             let relay = message.get::<XorRelayedAddress>().unwrap();
-
-            ensure!(relay.ip() == self.server.ip());
             ensure!(message.get::<XorMappedAddress>() == Some(local_addr));
-            ensure!(message.get::<Lifetime>() == Some(600));
 
-            Ok(relay.port())
+            Ok((relay, message.get::<Lifetime>().unwrap()))
         }
 
-        pub async fn create_permission(&mut self, port: u16) -> Result<()> {
+        // Sends an unauthenticated Allocate request and returns the error code of the
+        // response.
+        pub async fn allocate_error(&mut self, transport: Option<Transport>) -> Result<u16> {
             {
-                let mut peer = self.server.clone();
-                peer.set_port(port);
+                let mut message = self
+                    .operationer
+                    .create_message(Method::Allocate(Kind::Request));
+                if let Some(it) = transport {
+                    message.append::<ReqeestedTransport>(it);
+                }
+
+                message.flush(None)?;
+                self.operationer.send().await?;
+            }
+
+            let message = self.operationer.read_message().await?;
+
+            ensure!(message.method == Method::Allocate(Kind::Error));
+            Ok(message.get::<ErrorCode>().unwrap().code)
+        }
+
+        // Sends an authenticated Allocate request and returns the error code of the
+        // response.
+        pub async fn allocate_auth_error(&mut self) -> Result<u16> {
+            self.challenge().await?;
 
+            {
+                let mut message = self
+                    .operationer
+                    .create_message(Method::Allocate(Kind::Request));
+                message.append::<ReqeestedTransport>(Transport::UDP);
+                message.append::<UserName>(&self.credentials.username);
+                message.append::<Realm>(&self.state.realm);
+                message.append::<Nonce>(&self.state.nonce);
+                message.flush(Some(&self.state.digest))?;
+
+                self.operationer.send().await?;
+            }
+
+            let message = self.operationer.read_message().await?;
+
+            ensure!(message.method == Method::Allocate(Kind::Error));
+            Ok(message.get::<ErrorCode>().unwrap().code)
+        }
+
+        // Re-claims the allocation of the other client with the nonce of its session,
+        // which is answered with a challenge of the nonce of this address first.
+        pub async fn reclaim(&mut self, other: &TurnClient) -> Result<SocketAddr> {
+            self.state = State {
+                digest: other.state.digest,
+                nonce: other.state.nonce.clone(),
+                realm: other.state.realm.clone(),
+            };
+
+            for _ in 0..2 {
+                {
+                    let mut message = self
+                        .operationer
+                        .create_message(Method::Allocate(Kind::Request));
+                    message.append::<ReqeestedTransport>(Transport::UDP);
+                    message.append::<UserName>(&self.credentials.username);
+                    message.append::<Realm>(&self.state.realm);
+                    message.append::<Nonce>(&self.state.nonce);
+                    message.flush(Some(&self.state.digest))?;
+
+                    self.operationer.send().await?;
+                }
+
+                let message = self.operationer.read_message().await?;
+                if message.method == Method::Allocate(Kind::Response) {
+                    message.integrity(&self.state.digest)?;
+                    return Ok(message.get::<XorRelayedAddress>().unwrap());
+                }
+
+                ensure!(message.get::<ErrorCode>().unwrap().code == ErrorKind::Unauthorized as u16);
+
+                let nonce = message.get::<Nonce>().unwrap().to_string();
+                ensure!(nonce != self.state.nonce);
+                self.state.nonce = nonce;
+            }
+
+            Err(anyhow::anyhow!("allocation not re-claimed"))
+        }
+
+        pub async fn create_permission(&mut self, port: u16) -> Result<()> {
+            let mut peer = self.server;
+            peer.set_port(port);
+
+            self.create_permission_to(peer).await
+        }
+
+        pub async fn create_permission_to(&mut self, peer: SocketAddr) -> Result<()> {
+            {
                 let mut message = self
                     .operationer
                     .create_message(Method::CreatePermission(Kind::Request));
@@ -265,10 +460,14 @@ mod tests {
         }
 
         pub async fn channel_bind(&mut self, port: u16, channel: u16) -> Result<()> {
-            {
-                let mut peer = self.server.clone();
-                peer.set_port(port);
+            let mut peer = self.server;
+            peer.set_port(port);
 
+            self.channel_bind_to(peer, channel).await
+        }
+
+        pub async fn channel_bind_to(&mut self, peer: SocketAddr, channel: u16) -> Result<()> {
+            {
                 let mut message = self
                     .operationer
                     .create_message(Method::ChannelBind(Kind::Request));
@@ -291,6 +490,13 @@ mod tests {
         }
 
         pub async fn refresh(&mut self, lifetime: u32) -> Result<()> {
+            ensure!(self.refresh_lifetime(lifetime).await? == lifetime);
+
+            Ok(())
+        }
+
+        // Refreshes with the requested lifetime and returns the granted lifetime.
+        pub async fn refresh_lifetime(&mut self, lifetime: u32) -> Result<u32> {
             {
                 let mut message = self
                     .operationer
@@ -309,13 +515,11 @@ mod tests {
             ensure!(message.method == Method::Refresh(Kind::Response));
             message.integrity(&self.state.digest)?;
 
-            ensure!(message.get::<Lifetime>() == Some(lifetime));
-
-            Ok(())
+            Ok(message.get::<Lifetime>().unwrap())
         }
 
         pub async fn send_indication(&mut self, port: u16, data: &[u8]) -> Result<()> {
-            let mut peer = self.server.clone();
+            let mut peer = self.server;
             peer.set_port(port);
 
             let mut message = self.operationer.create_message(Method::SendIndication);
@@ -337,6 +541,17 @@ mod tests {
             Ok((peer.port(), data))
         }
 
+        pub async fn recv_icmp(&mut self) -> Result<(u16, IcmpError)> {
+            let message = self.operationer.read_indication().await?;
+
+            ensure!(message.method == Method::DataIndication);
+            ensure!(message.get::<Data>().is_none());
+
+            let peer = message.get::<XorPeerAddress>().unwrap();
+            let icmp = message.get::<Icmp>().unwrap();
+            Ok((peer.port(), icmp))
+        }
+
         pub async fn send_channel_data(&mut self, channel: u16, data: &[u8]) -> Result<()> {
             self.operationer.create_channel_data(channel, data);
             self.operationer.send().await?;
@@ -349,12 +564,72 @@ mod tests {
         }
     }
 
-    fn encode_password(username: &str, password: &str) -> Result<String> {
-        Ok(BASE64_STANDARD.encode(
-            stun::util::hmac_sha1(password.as_bytes(), &[username.as_bytes()])?
-                .into_bytes()
-                .as_slice(),
-        ))
+    /// A peer that echoes the data relayed to it.
+    ///
+    /// turn-rs only relays between its own allocations, so the peer is a
+    /// client with an allocation of its own. It permits the given ports to
+    /// send to it, and sends every Data indication back to its source with
+    /// `tag` prepended, recording the relay ports that the data came from.
+    /// This verifies the whole relay path in both directions, including the
+    /// permissions of both sides.
+    pub struct MockPeer {
+        port: u16,
+        sources: Arc<Mutex<Vec<u16>>>,
+        task: JoinHandle<()>,
+    }
+
+    impl MockPeer {
+        pub async fn start(
+            server: SocketAddr,
+            credentials: Credentials,
+            tag: &'static [u8],
+            permissions: &[u16],
+        ) -> Result<Self> {
+            let mut client = TurnClient::new(server, credentials).await?;
+            let port = client.allocate().await?;
+            for it in permissions {
+                client.create_permission(*it).await?;
+            }
+
+            let sources = Arc::new(Mutex::new(Vec::new()));
+            let sources_ = sources.clone();
+            let task = tokio::spawn(async move {
+                loop {
+                    // Reading times out every second while nothing is relayed.
+                    let (source, data) = match client.recv_indication().await {
+                        Ok((source, data)) => (source, [tag, data].concat()),
+                        Err(_) => continue,
+                    };
+
+                    sources_.lock().unwrap().push(source);
+                    if client.send_indication(source, &data).await.is_err() {
+                        break;
+                    }
+                }
+            });
+
+            Ok(Self {
+                port,
+                sources,
+                task,
+            })
+        }
+
+        /// The relay port of the peer.
+        pub fn port(&self) -> u16 {
+            self.port
+        }
+
+        /// The relay ports of the data that the peer has received, in order.
+        pub fn sources(&self) -> Vec<u16> {
+            self.sources.lock().unwrap().clone()
+        }
+    }
+
+    impl Drop for MockPeer {
+        fn drop(&mut self) {
+            self.task.abort();
+        }
     }
 
     struct HooksImpl(Arc<Controller>);
@@ -367,12 +642,12 @@ mod tests {
             username: &str,
             _realm: &str,
             _nonce: &str,
-        ) -> Option<&str> {
-            if username == "hooks" {
-                Some("hooks")
+        ) -> Result<Option<Credential>, HookError> {
+            Ok(if username == "hooks" {
+                Some("hooks".into())
             } else {
                 None
-            }
+            })
         }
 
         async fn on(&self, event: &Events, realm: &str, nonce: &str) {
@@ -391,9 +666,23 @@ mod tests {
                     session,
                     username,
                     port,
+                    transport,
                 } => {
                     let session = get_session(session, username.to_string()).await;
                     assert_eq!(session.port, Some(*port));
+                    assert_eq!(session.transport, *transport);
+                    assert_eq!(session.transport, DriverTransport::UDP);
+                    assert_eq!(
+                        session.auth_method.as_deref(),
+                        Some(if username == "hooks" {
+                            "hooks"
+                        } else {
+                            "static_credentials"
+                        })
+                    );
+                    assert!(
+                        session.created_at > 0 && session.created_at <= session.last_request_at
+                    );
                 }
                 Events::CreatePermission {
                     session,
@@ -426,35 +715,714 @@ mod tests {
                 Events::Closed { session, .. } => {
                     assert!(self.0.get_session(session).await.is_none());
                 }
+                Events::Locked { .. }
+                | Events::Transferred { .. }
+                | Events::Traffic { .. }
+                | Events::AuthFailed { .. }
+                | Events::Affinity { .. } => {}
             }
         }
     }
 
+    #[tokio::test]
+    async fn turn_requested_transport_testing() -> Result<()> {
+        create_turn_server(
+            "127.0.0.1:3483".parse()?,
+            Auth {
+                static_auth_secret: None,
+                store: None,
+                static_credentials: {
+                    let mut it = HashMap::with_capacity(1);
+                    it.insert("transport".to_string(), "transport".to_string());
+                    it
+                },
+            },
+            Api {
+                bind: "127.0.0.1:3005".parse()?,
+                ..Default::default()
+            },
+        )
+        .await?;
+
+        let mut turn = TurnClient::new(
+            "127.0.0.1:3483".parse()?,
+            Credentials {
+                username: "transport".to_string(),
+                password: "transport".to_string(),
+            },
+        )
+        .await?;
+
+        assert_eq!(
+            turn.allocate_error(None).await?,
+            ErrorKind::BadRequest as u16
+        );
+
+        // TCP allocations are only allowed to clients connected over TCP.
+        assert_eq!(
+            turn.allocate_error(Some(Transport::TCP)).await?,
+            ErrorKind::UnsupportedTransportAddress as u16
+        );
+
+        assert_eq!(
+            turn.allocate_error(Some(Transport::UDP)).await?,
+            ErrorKind::Unauthorized as u16
+        );
+
+        turn.allocate().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn turn_lifetime_testing() -> Result<()> {
+        create_turn_server(
+            "127.0.0.1:3485".parse()?,
+            Auth {
+                static_auth_secret: None,
+                store: None,
+                static_credentials: {
+                    let mut it = HashMap::with_capacity(1);
+                    it.insert("lifetime".to_string(), "lifetime".to_string());
+                    it
+                },
+            },
+            Api {
+                bind: "127.0.0.1:3007".parse()?,
+                ..Default::default()
+            },
+        )
+        .await?;
+
+        let credentials = || Credentials {
+            username: "lifetime".to_string(),
+            password: "lifetime".to_string(),
+        };
+
+        // The requested lifetime is clamped to the maximum lifetime, and so are the
+        // refreshes of the allocation.
+        let mut turn = TurnClient::new("127.0.0.1:3485".parse()?, credentials()).await?;
+        assert_eq!(turn.allocate_lifetime(Some(86400)).await?.1, 3600);
+        assert_eq!(turn.refresh_lifetime(7200).await?, 3600);
+        assert_eq!(turn.refresh_lifetime(1800).await?, 1800);
+
+        // Lifetimes shorter than the default lifetime are raised to it.
+        assert_eq!(turn.refresh_lifetime(60).await?, 600);
+
+        let mut turn = TurnClient::new("127.0.0.1:3485".parse()?, credentials()).await?;
+        assert_eq!(turn.allocate_lifetime(Some(60)).await?.1, 600);
+        assert_eq!(turn.refresh_lifetime(3600).await?, 3600);
+
+        let mut turn = TurnClient::new("127.0.0.1:3485".parse()?, credentials()).await?;
+        assert_eq!(turn.allocate_lifetime(Some(1200)).await?.1, 1200);
+
+        turn.refresh(0).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn turn_cascade_testing() -> Result<()> {
+        let credentials = |it: &str| Credentials {
+            username: it.to_string(),
+            password: it.to_string(),
+        };
+
+        let static_credentials = |it: &[&str]| {
+            it.iter()
+                .map(|it| (it.to_string(), it.to_string()))
+                .collect::<HashMap<_, _>>()
+        };
+
+        // The upstream is on another address, the peers on it are told apart from
+        // the sessions of the cascading server by that.
+        create_turn_server(
+            "127.0.0.2:3486".parse()?,
+            Auth {
+                static_auth_secret: None,
+                store: None,
+                static_credentials: static_credentials(&["upstream", "cascade"]),
+            },
+            Api {
+                bind: "127.0.0.1:3008".parse()?,
+                ..Default::default()
+            },
+        )
+        .await?;
+
+        create_cascade_turn_server(
+            "127.0.0.1:3487".parse()?,
+            Auth {
+                static_auth_secret: None,
+                store: None,
+                static_credentials: static_credentials(&["local"]),
+            },
+            Api {
+                bind: "127.0.0.1:3009".parse()?,
+                ..Default::default()
+            },
+            Cascade {
+                upstream: Some("127.0.0.2:3486".parse()?),
+                username: "cascade".to_string(),
+                password: "cascade".to_string(),
+                ..Default::default()
+            },
+        )
+        .await?;
+
+        let mut upstream =
+            TurnClient::new("127.0.0.2:3486".parse()?, credentials("upstream")).await?;
+        let upstream_port = upstream.allocate().await?;
+
+        let mut local = TurnClient::new("127.0.0.1:3487".parse()?, credentials("local")).await?;
+        local.allocate().await?;
+        local
+            .channel_bind_to(SocketAddr::new("127.0.0.2".parse()?, upstream_port), 0x4000)
+            .await?;
+
+        // The session is allocated on the upstream in the background, the peer
+        // learns the relayed address of that allocation.
+        sleep(Duration::from_secs(1)).await;
+
+        let controller = Controller::new("http://127.0.0.1:3009")?;
+        let session = controller
+            .get_session(&SessionAddr {
+                address: local.local_addr()?,
+                interface: "127.0.0.1:3487".parse()?,
+            })
+            .await
+            .unwrap()
+            .payload;
+
+        let relay = session.upstream_relay.unwrap();
+        assert_eq!(relay.ip().to_string(), "127.0.0.2");
+
+        // The leg binds the channels from 0x4000 on, the sessions of the upstream
+        // relay the channel data to each other on the same channels.
+        upstream.channel_bind(relay.port(), 0x4000).await?;
+
+        local.send_channel_data(0x4000, b"to upstream").await?;
+        assert_eq!(
+            upstream.recv_channel_data().await?,
+            (0x4000, &b"to upstream"[..])
+        );
+
+        upstream.send_channel_data(0x4000, b"to local").await?;
+        assert_eq!(local.recv_channel_data().await?, (0x4000, &b"to local"[..]));
+
+        upstream.send_indication(relay.port(), b"to local").await?;
+        assert_eq!(local.recv_channel_data().await?, (0x4000, &b"to local"[..]));
+
+        local.refresh(0).await?;
+        Ok(())
+    }
+
     #[tokio::test]
     async fn turn_static_auth_secret_testing() -> Result<()> {
         create_turn_server(
             "127.0.0.1:3479".parse()?,
             Auth {
                 static_auth_secret: Some("static_auth_secret".to_string()),
+                store: None,
                 static_credentials: HashMap::with_capacity(1),
             },
             Api {
                 bind: "127.0.0.1:3001".parse()?,
-                hooks: None,
+                ..Default::default()
+            },
+        )
+        .await?;
+
+        // The username of a shared secret carries the time at which it expires.
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let username = format!("{}:static_auth_secret", now + 3600);
+        let mut turn = TurnClient::new(
+            "127.0.0.1:3479".parse()?,
+            Credentials {
+                password: stun::auth::static_auth_secret_password("static_auth_secret", &username)?,
+                username,
+            },
+        )
+        .await?;
+
+        turn.allocate().await?;
+
+        // The session is closed when the time of its username is reached, refreshing
+        // the allocation does not keep it.
+        let username = format!("{}:static_auth_secret", now + 2);
+        let mut turn = TurnClient::new(
+            "127.0.0.1:3479".parse()?,
+            Credentials {
+                password: stun::auth::static_auth_secret_password("static_auth_secret", &username)?,
+                username,
             },
         )
         .await?;
 
+        turn.allocate().await?;
+        sleep(Duration::from_secs(4)).await;
+        assert!(turn.refresh(600).await.is_err());
+
+        let username = format!("{}:static_auth_secret", now - 3600);
+        let mut turn = TurnClient::new(
+            "127.0.0.1:3479".parse()?,
+            Credentials {
+                password: stun::auth::static_auth_secret_password("static_auth_secret", &username)?,
+                username,
+            },
+        )
+        .await?;
+
+        assert!(turn.allocate().await.is_err());
+
+        // Credentials added at runtime take precedence over the static auth secret.
+        let controller = Controller::new("http://127.0.0.1:3001")?;
+        assert!(
+            controller
+                .put_credential("runtime", "runtime", None)
+                .await
+                .unwrap()
+                .payload
+        );
+        assert_eq!(
+            controller.get_credentials().await.unwrap().payload,
+            vec!["runtime".to_string()]
+        );
+
+        let mut turn = TurnClient::new(
+            "127.0.0.1:3479".parse()?,
+            Credentials {
+                username: "runtime".to_string(),
+                password: "runtime".to_string(),
+            },
+        )
+        .await?;
+
+        turn.allocate().await?;
+
+        assert!(
+            controller
+                .remove_credential("runtime")
+                .await
+                .unwrap()
+                .payload
+        );
+        assert!(
+            !controller
+                .remove_credential("runtime")
+                .await
+                .unwrap()
+                .payload
+        );
+
+        // Removing the credential closes the sessions that were authenticated with it.
+        assert!(turn.refresh(600).await.is_err());
+
+        let mut turn = TurnClient::new(
+            "127.0.0.1:3479".parse()?,
+            Credentials {
+                username: "runtime".to_string(),
+                password: "runtime".to_string(),
+            },
+        )
+        .await?;
+
+        assert!(turn.allocate().await.is_err());
+
+        // So does the expiry of the credential.
+        assert!(
+            controller
+                .put_credential("expiring", "expiring", Some(Duration::from_secs(2)))
+                .await
+                .unwrap()
+                .payload
+        );
+
         let mut turn = TurnClient::new(
             "127.0.0.1:3479".parse()?,
             Credentials {
-                username: "static_auth_secret".to_string(),
-                password: encode_password("static_auth_secret", "static_auth_secret")?,
+                username: "expiring".to_string(),
+                password: "expiring".to_string(),
+            },
+        )
+        .await?;
+
+        turn.allocate().await?;
+        sleep(Duration::from_secs(4)).await;
+        assert!(turn.refresh(600).await.is_err());
+        Ok(())
+    }
+
+    struct RoutedHooks;
+
+    #[async_trait]
+    impl Hooks for RoutedHooks {
+        async fn auth(
+            &self,
+            _addr: &SessionAddr,
+            username: &str,
+            _realm: &str,
+            _nonce: &str,
+        ) -> Result<Option<Credential>, HookError> {
+            match username {
+                "tenant-a:user" => Ok(Some("tenant-a".into())),
+                "tenant-a:banned" => Err(HookError::Denied("banned".to_string())),
+                "tenant-a:down" => Err(HookError::Unavailable("database is down".to_string())),
+                _ => Ok(None),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn turn_hooks_routes_testing() -> Result<()> {
+        tokio::spawn(start_hooks_server("127.0.0.1:8089".parse()?, RoutedHooks));
+
+        create_turn_server(
+            "127.0.0.1:3481".parse()?,
+            Auth::default(),
+            Api {
+                bind: "127.0.0.1:3003".parse()?,
+                hooks_routes: vec![HooksRoute {
+                    matches: SessionMatch {
+                        username_prefix: Some("tenant-a:".to_string()),
+                        ..Default::default()
+                    },
+                    // The first server is unreachable, so the request fails over to the
+                    // second one.
+                    servers: vec![
+                        "http://127.0.0.1:9".to_string(),
+                        "http://127.0.0.1:8089".to_string(),
+                    ],
+                }],
+                ..Default::default()
+            },
+        )
+        .await?;
+
+        let mut turn = TurnClient::new(
+            "127.0.0.1:3481".parse()?,
+            Credentials {
+                username: "tenant-a:user".to_string(),
+                password: "tenant-a".to_string(),
             },
         )
         .await?;
 
         turn.allocate().await?;
+
+        // Users that do not match any route and have no default hooks server are
+        // rejected.
+        let mut turn = TurnClient::new(
+            "127.0.0.1:3481".parse()?,
+            Credentials {
+                username: "tenant-b:user".to_string(),
+                password: "tenant-a".to_string(),
+            },
+        )
+        .await?;
+
+        assert!(turn.allocate().await.is_err());
+
+        // Users that the hooks refuse are forbidden, and a failed lookup is a server
+        // error that the client may retry.
+        for (username, code) in [
+            ("tenant-a:banned", ErrorKind::Forbidden),
+            ("tenant-a:down", ErrorKind::ServerError),
+        ] {
+            let mut turn = TurnClient::new(
+                "127.0.0.1:3481".parse()?,
+                Credentials {
+                    username: username.to_string(),
+                    password: "tenant-a".to_string(),
+                },
+            )
+            .await?;
+
+            assert_eq!(turn.allocate_auth_error().await?, code as u16);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn turn_mock_peer_testing() -> Result<()> {
+        create_turn_server(
+            "127.0.0.1:3484".parse()?,
+            Auth {
+                static_auth_secret: None,
+                store: None,
+                static_credentials: {
+                    let mut it = HashMap::with_capacity(1);
+                    it.insert("peer".to_string(), "peer".to_string());
+                    it
+                },
+            },
+            Api {
+                bind: "127.0.0.1:3006".parse()?,
+                ..Default::default()
+            },
+        )
+        .await?;
+
+        let credentials = || Credentials {
+            username: "peer".to_string(),
+            password: "peer".to_string(),
+        };
+
+        let mut turn = TurnClient::new("127.0.0.1:3484".parse()?, credentials()).await?;
+        let turn_port = turn.allocate().await?;
+
+        let peer = MockPeer::start(
+            "127.0.0.1:3484".parse()?,
+            credentials(),
+            b"echo:",
+            &[turn_port],
+        )
+        .await?;
+
+        // The peer has permitted the client, but the echo is not relayed back
+        // without a permission of the client.
+        turn.send_indication(peer.port(), b"hello").await?;
+        assert!(turn.recv_indication().await.is_err());
+        assert_eq!(peer.sources(), vec![turn_port]);
+
+        turn.create_permission(peer.port()).await?;
+        turn.send_indication(peer.port(), b"hello").await?;
+
+        let ret = turn.recv_indication().await?;
+        assert_eq!(ret.0, peer.port());
+        assert_eq!(ret.1, b"echo:hello");
+        assert_eq!(peer.sources(), vec![turn_port, turn_port]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn turn_relay_address_testing() -> Result<()> {
+        // The whole loopback network reaches the interface, the allocations are
+        // advertised with another address of it.
+        let relay_ip = "127.0.0.2".parse()?;
+        create_custom_turn_server(
+            "127.0.0.1:3488".parse()?,
+            Auth {
+                static_auth_secret: None,
+                store: None,
+                static_credentials: {
+                    let mut it = HashMap::with_capacity(1);
+                    it.insert("relay".to_string(), "relay".to_string());
+                    it
+                },
+            },
+            Api {
+                bind: "127.0.0.1:3010".parse()?,
+                ..Default::default()
+            },
+            Cascade::default(),
+            Turn {
+                relay_addresses: vec![RelayAddress {
+                    matches: SessionMatch {
+                        realm: None,
+                        username_prefix: Some("relay".to_string()),
+                        interface: None,
+                    },
+                    address: relay_ip,
+                }],
+                ..Default::default()
+            },
+        )
+        .await?;
+
+        let credentials = || Credentials {
+            username: "relay".to_string(),
+            password: "relay".to_string(),
+        };
+
+        let mut turn_1 = TurnClient::new("127.0.0.1:3488".parse()?, credentials()).await?;
+        let mut turn_2 = TurnClient::new("127.0.0.1:3488".parse()?, credentials()).await?;
+
+        let (turn_1_relay, _) = turn_1.allocate_relay(None).await?;
+        let (turn_2_relay, _) = turn_2.allocate_relay(None).await?;
+        assert_eq!(turn_1_relay.ip(), relay_ip);
+        assert_eq!(turn_2_relay.ip(), relay_ip);
+
+        // The peers are addressed with the advertised relay addresses.
+        turn_1.create_permission_to(turn_2_relay).await?;
+        turn_2.create_permission_to(turn_1_relay).await?;
+        turn_1.channel_bind_to(turn_2_relay, 0x4000).await?;
+        turn_2.channel_bind_to(turn_1_relay, 0x4000).await?;
+
+        turn_1.send_channel_data(0x4000, b"hello").await?;
+        let ret = turn_2.recv_channel_data().await?;
+        assert_eq!(ret.0, 0x4000);
+        assert_eq!(ret.1, b"hello");
+
+        turn_2.send_channel_data(0x4000, b"world").await?;
+        let ret = turn_1.recv_channel_data().await?;
+        assert_eq!(ret.0, 0x4000);
+        assert_eq!(ret.1, b"world");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn turn_strict_reverse_path_testing() -> Result<()> {
+        create_custom_turn_server(
+            "127.0.0.1:3489".parse()?,
+            Auth {
+                static_auth_secret: None,
+                store: None,
+                static_credentials: {
+                    let mut it = HashMap::with_capacity(1);
+                    it.insert("strict".to_string(), "strict".to_string());
+                    it
+                },
+            },
+            Api {
+                bind: "127.0.0.1:3011".parse()?,
+                ..Default::default()
+            },
+            Cascade::default(),
+            Turn {
+                strict_reverse_path: true,
+                ..Default::default()
+            },
+        )
+        .await?;
+
+        let credentials = || Credentials {
+            username: "strict".to_string(),
+            password: "strict".to_string(),
+        };
+
+        let mut turn_1 = TurnClient::new("127.0.0.1:3489".parse()?, credentials()).await?;
+        let mut turn_2 = TurnClient::new("127.0.0.1:3489".parse()?, credentials()).await?;
+
+        let turn_1_port = turn_1.allocate().await?;
+        let turn_2_port = turn_2.allocate().await?;
+        turn_1.create_permission(turn_2_port).await?;
+        turn_2.create_permission(turn_1_port).await?;
+
+        // The client that sends first has not been sent anything by the other
+        // client, that data is dropped even though both have a permission.
+        turn_1.send_indication(turn_2_port, b"hello").await?;
+        assert!(turn_2.recv_indication().await.is_err());
+
+        // The send was recorded though, so the reply passes and from then on
+        // the data passes in both directions.
+        turn_2.send_indication(turn_1_port, b"world").await?;
+        assert_eq!(
+            turn_1.recv_indication().await?,
+            (turn_2_port, &b"world"[..])
+        );
+
+        turn_1.send_indication(turn_2_port, b"hello").await?;
+        assert_eq!(
+            turn_2.recv_indication().await?,
+            (turn_1_port, &b"hello"[..])
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn turn_transfer_testing() -> Result<()> {
+        create_turn_server(
+            "127.0.0.1:3491".parse()?,
+            Auth {
+                static_auth_secret: None,
+                store: None,
+                static_credentials: {
+                    let mut it = HashMap::with_capacity(2);
+                    it.insert("transfer".to_string(), "transfer".to_string());
+                    it.insert("peer".to_string(), "peer".to_string());
+                    it
+                },
+            },
+            Api {
+                bind: "127.0.0.1:3013".parse()?,
+                ..Default::default()
+            },
+        )
+        .await?;
+
+        let credentials = |it: &str| Credentials {
+            username: it.to_string(),
+            password: it.to_string(),
+        };
+
+        let mut turn = TurnClient::new("127.0.0.1:3491".parse()?, credentials("transfer")).await?;
+        let mut peer = TurnClient::new("127.0.0.1:3491".parse()?, credentials("peer")).await?;
+
+        let port = turn.allocate().await?;
+        let peer_port = peer.allocate().await?;
+        turn.create_permission(peer_port).await?;
+        peer.create_permission(port).await?;
+
+        // The client moves to another address, the nonce of its session is only
+        // accepted after it has authenticated with the nonce of the new address.
+        let mut moved = TurnClient::new("127.0.0.1:3491".parse()?, credentials("transfer")).await?;
+        assert_eq!(moved.reclaim(&turn).await?.port(), port);
+
+        peer.send_indication(port, b"hello").await?;
+        assert_eq!(moved.recv_indication().await?, (peer_port, &b"hello"[..]));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn turn_realm_settings_testing() -> Result<()> {
+        create_custom_turn_server(
+            "127.0.0.1:3490".parse()?,
+            Auth {
+                static_auth_secret: None,
+                store: None,
+                static_credentials: {
+                    let mut it = HashMap::with_capacity(1);
+                    it.insert("quota".to_string(), "quota".to_string());
+                    it
+                },
+            },
+            Api {
+                bind: "127.0.0.1:3012".parse()?,
+                ..Default::default()
+            },
+            Cascade::default(),
+            Turn {
+                default_lifetime: Some(300),
+                realms: {
+                    let mut it = HashMap::with_capacity(1);
+                    it.insert(
+                        "localhost".to_string(),
+                        TurnRealm {
+                            default_lifetime: Some(900),
+                            max_allocations: Some(1),
+                            ..Default::default()
+                        },
+                    );
+                    it
+                },
+                ..Default::default()
+            },
+        )
+        .await?;
+
+        let credentials = || Credentials {
+            username: "quota".to_string(),
+            password: "quota".to_string(),
+        };
+
+        let mut turn_1 = TurnClient::new("127.0.0.1:3490".parse()?, credentials()).await?;
+        let mut turn_2 = TurnClient::new("127.0.0.1:3490".parse()?, credentials()).await?;
+
+        // The default lifetime of the realm overrides the one of the server.
+        let (_, lifetime) = turn_1.allocate_lifetime(None).await?;
+        assert_eq!(lifetime, 900);
+
+        // The username has used up the quota of the realm.
+        assert_eq!(
+            turn_2.allocate_auth_error().await?,
+            ErrorKind::AllocationQuotaReached as u16
+        );
+
+        // Deleting the allocation gives back the quota.
+        turn_1.refresh(0).await?;
+
+        let (_, lifetime) = turn_2.allocate_lifetime(None).await?;
+        assert_eq!(lifetime, 900);
         Ok(())
     }
 
@@ -474,6 +1442,7 @@ mod tests {
             "127.0.0.1:3478".parse()?,
             Auth {
                 static_auth_secret: None,
+                store: None,
                 static_credentials: {
                     let mut it = HashMap::with_capacity(1);
                     it.insert(
@@ -483,10 +1452,9 @@ mod tests {
                     it
                 },
             },
-            {
-                let mut api = Api::default();
-                api.hooks = Some("http://127.0.0.1:8088".to_string());
-                api
+            Api {
+                hooks: Some("http://127.0.0.1:8088".to_string()),
+                ..Default::default()
             },
         )
         .await?;
@@ -496,10 +1464,10 @@ mod tests {
             assert_eq!(info.port_allocated, 0);
             assert_eq!(info.port_capacity, 16383);
 
-            let interface = info.interfaces.get(0).unwrap();
+            let interface = info.interfaces.first().unwrap();
             assert_eq!(interface.bind, "127.0.0.1:3478".parse()?);
             assert_eq!(interface.external, "127.0.0.1:3478".parse()?);
-            assert_eq!(interface.transport, DriverTransport::UDP);
+            assert_eq!(interface.transport, InterfaceTransport::UDP);
         }
 
         let mut turn_1 = TurnClient::new(
@@ -542,6 +1510,7 @@ mod tests {
             turn_1.binding().await?;
             turn_2.binding().await?;
             turn_3.binding().await?;
+            turn_4.binding_retransmitted(3).await?;
         }
 
         let turn_1_port = turn_1.allocate().await?;
@@ -732,15 +1701,76 @@ mod tests {
             assert!(turn_3.recv_indication().await.is_err());
         }
 
+        {
+            let all = controller
+                .get_sessions(&SessionsQuery {
+                    transport: Some(DriverTransport::UDP),
+                    ..Default::default()
+                })
+                .await
+                .unwrap()
+                .payload;
+            assert_eq!(all.len(), 4);
+            assert!(all
+                .iter()
+                .any(|it| it.addr.address == turn_1.local_addr().unwrap()
+                    && it.session.port == Some(turn_1_port)));
+
+            let sessions = controller
+                .get_sessions(&SessionsQuery {
+                    transport: Some(DriverTransport::TCP),
+                    ..Default::default()
+                })
+                .await
+                .unwrap()
+                .payload;
+            assert!(sessions.is_empty());
+
+            let page = controller
+                .get_sessions(&SessionsQuery {
+                    offset: Some(1),
+                    limit: Some(2),
+                    ..Default::default()
+                })
+                .await
+                .unwrap()
+                .payload;
+            assert_eq!(page.len(), 2);
+            assert_eq!(page[0].addr, all[1].addr);
+            assert_eq!(page[1].addr, all[2].addr);
+
+            let sessions = controller
+                .get_sessions(&SessionsQuery {
+                    interface: Some("127.0.0.1:3478".parse().unwrap()),
+                    min_bytes: Some(u64::MAX),
+                    ..Default::default()
+                })
+                .await
+                .unwrap()
+                .payload;
+            assert!(sessions.is_empty());
+
+            let sessions = controller.get_user_sessions("hooks").await.unwrap().payload;
+            assert_eq!(sessions.len(), 2);
+            assert!(sessions.iter().all(|it| it.session.username == "hooks"));
+
+            let affinity = controller.get_affinity().await.unwrap().payload;
+            assert_eq!(affinity.len(), 4);
+            assert!(affinity
+                .iter()
+                .all(|it| it.addr.interface == "127.0.0.1:3478".parse().unwrap()
+                    && it.transport == DriverTransport::UDP));
+        }
+
         {
             let info = controller.get_info().await.unwrap().payload;
             assert_eq!(info.port_allocated, 4);
             assert_eq!(info.port_capacity, 16383);
 
-            let interface = info.interfaces.get(0).unwrap();
+            let interface = info.interfaces.first().unwrap();
             assert_eq!(interface.bind, "127.0.0.1:3478".parse()?);
             assert_eq!(interface.external, "127.0.0.1:3478".parse()?);
-            assert_eq!(interface.transport, DriverTransport::UDP);
+            assert_eq!(interface.transport, InterfaceTransport::UDP);
         }
 
         {
@@ -781,6 +1811,248 @@ mod tests {
             .await
             .is_some());
 
+        // The peer has gone, data sent to it is answered with an ICMP port
+        // unreachable error.
+        {
+            let data = "4 forwards to closed 1".as_bytes();
+            turn_4.send_indication(turn_1_port, data).await?;
+            let ret = turn_4.recv_icmp().await?;
+            assert_eq!(ret.0, turn_1_port);
+            assert_eq!(ret.1, IcmpError::port_unreachable(false));
+
+            turn_4.send_channel_data(0x4002, data).await?;
+            let ret = turn_4.recv_icmp().await?;
+            assert_eq!(ret.0, turn_1_port);
+            assert_eq!(ret.1, IcmpError::port_unreachable(false));
+        }
+
+        {
+            let removed = controller
+                .remove_user_sessions("hooks")
+                .await
+                .unwrap()
+                .payload;
+            assert_eq!(removed.len(), 1);
+            assert_eq!(removed[0].address, turn_4.local_addr()?);
+
+            let removed = controller
+                .remove_user_sessions("hooks")
+                .await
+                .unwrap()
+                .payload;
+            assert!(removed.is_empty());
+        }
+
+        Ok(())
+    }
+
+    /// Reads a soak test knob from the environment, falling back to the given
+    /// default when the variable is unset or cannot be parsed.
+    fn env_or<T: FromStr>(key: &str, default: T) -> T {
+        std::env::var(key)
+            .ok()
+            .and_then(|it| it.parse().ok())
+            .unwrap_or(default)
+    }
+
+    /// Resident set size of the current process in KiB, the server runs in the
+    /// same process as the clients so this covers both. Only available on
+    /// Linux, other platforms return `None`.
+    fn vm_rss_kib() -> Option<u64> {
+        std::fs::read_to_string("/proc/self/status")
+            .ok()?
+            .lines()
+            .find_map(|line| line.strip_prefix("VmRSS:"))?
+            .trim()
+            .trim_end_matches("kB")
+            .trim()
+            .parse()
+            .ok()
+    }
+
+    /// A pair of allocated clients that have a channel bound to each other,
+    /// used by the soak test.
+    struct SoakPair {
+        turn_1: TurnClient,
+        turn_2: TurnClient,
+    }
+
+    impl SoakPair {
+        /// Allocates both clients and binds a channel in each direction,
+        /// returning the latency of every request.
+        async fn new(server: SocketAddr) -> Result<(Self, Vec<Duration>)> {
+            let credentials = || Credentials {
+                username: "soak".to_string(),
+                password: "soak".to_string(),
+            };
+
+            let mut turn_1 = TurnClient::new(server, credentials()).await?;
+            let mut turn_2 = TurnClient::new(server, credentials()).await?;
+            let mut latencies = Vec::with_capacity(6);
+
+            let started = Instant::now();
+            let turn_1_port = turn_1.allocate().await?;
+            latencies.push(started.elapsed());
+
+            let started = Instant::now();
+            let turn_2_port = turn_2.allocate().await?;
+            latencies.push(started.elapsed());
+
+            let started = Instant::now();
+            turn_1.create_permission(turn_2_port).await?;
+            latencies.push(started.elapsed());
+
+            let started = Instant::now();
+            turn_2.create_permission(turn_1_port).await?;
+            latencies.push(started.elapsed());
+
+            let started = Instant::now();
+            turn_1.channel_bind(turn_2_port, 0x4000).await?;
+            latencies.push(started.elapsed());
+
+            let started = Instant::now();
+            turn_2.channel_bind(turn_1_port, 0x4000).await?;
+            latencies.push(started.elapsed());
+
+            Ok((Self { turn_1, turn_2 }, latencies))
+        }
+
+        /// Bounces channel data between both clients and returns the latency
+        /// of every round trip.
+        async fn traffic(&mut self, rounds: usize) -> Result<Vec<Duration>> {
+            let data = [0u8; 512];
+            let mut latencies = Vec::with_capacity(rounds);
+
+            for _ in 0..rounds {
+                let started = Instant::now();
+                self.turn_1.send_channel_data(0x4000, &data).await?;
+                ensure!(self.turn_2.recv_channel_data().await?.1 == data);
+                self.turn_2.send_channel_data(0x4000, &data).await?;
+                ensure!(self.turn_1.recv_channel_data().await?.1 == data);
+                latencies.push(started.elapsed());
+            }
+
+            Ok(latencies)
+        }
+    }
+
+    /// Soak test for comparing releases, ignored by default because it is slow
+    /// and load sensitive. Run it with:
+    ///
+    /// ```text
+    /// cargo test -p tests --release -- --ignored --nocapture turn_server_soak_testing
+    /// ```
+    ///
+    /// The load and the ceilings can be tuned with the `TURN_SOAK_PAIRS`,
+    /// `TURN_SOAK_ROUNDS`, `TURN_SOAK_CONCURRENCY`, `TURN_SOAK_MAX_RSS_KIB` and
+    /// `TURN_SOAK_MAX_P99_MS` environment variables. All sessions stay
+    /// allocated for the whole run, the concurrency only limits how many pairs
+    /// have requests in flight at the same time. Every pair holds two sockets,
+    /// so make sure the open files limit is large enough.
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "soak test, run explicitly with --ignored"]
+    async fn turn_server_soak_testing() -> Result<()> {
+        let pairs: usize = env_or("TURN_SOAK_PAIRS", 1000);
+        let rounds: usize = env_or("TURN_SOAK_ROUNDS", 10);
+        let concurrency: usize = env_or("TURN_SOAK_CONCURRENCY", 100);
+        let max_rss_kib: u64 = env_or("TURN_SOAK_MAX_RSS_KIB", 256 * 1024);
+        let max_p99 = Duration::from_millis(env_or("TURN_SOAK_MAX_P99_MS", 100));
+
+        let server: SocketAddr = "127.0.0.1:3480".parse()?;
+        create_turn_server(
+            server,
+            Auth {
+                static_auth_secret: None,
+                store: None,
+                static_credentials: {
+                    let mut it = HashMap::with_capacity(1);
+                    it.insert("soak".to_string(), "soak".to_string());
+                    it
+                },
+            },
+            Api {
+                bind: "127.0.0.1:3002".parse()?,
+                ..Default::default()
+            },
+        )
+        .await?;
+
+        let rss_before = vm_rss_kib();
+        let started = Instant::now();
+
+        // Allocate every session first and keep them all alive, so that the
+        // server is actually holding all of them while traffic is flowing.
+        let mut latencies = Vec::with_capacity(pairs * (6 + rounds));
+        let mut soak_pairs = Vec::with_capacity(pairs);
+        {
+            let semaphore = Arc::new(Semaphore::new(concurrency));
+            let mut tasks = JoinSet::new();
+            for _ in 0..pairs {
+                let semaphore = semaphore.clone();
+                tasks.spawn(async move {
+                    let _permit = semaphore.acquire().await?;
+                    SoakPair::new(server).await
+                });
+            }
+
+            while let Some(ret) = tasks.join_next().await {
+                let (pair, it) = ret??;
+                soak_pairs.push(pair);
+                latencies.extend(it);
+            }
+        }
+
+        let rss_peak = vm_rss_kib();
+
+        {
+            let semaphore = Arc::new(Semaphore::new(concurrency));
+            let mut tasks = JoinSet::new();
+            for mut pair in soak_pairs {
+                let semaphore = semaphore.clone();
+                tasks.spawn(async move {
+                    let _permit = semaphore.acquire().await?;
+                    pair.traffic(rounds).await
+                });
+            }
+
+            while let Some(ret) = tasks.join_next().await {
+                latencies.extend(ret??);
+            }
+        }
+
+        latencies.sort_unstable();
+        let percentile = |p: usize| latencies[(latencies.len() - 1) * p / 100];
+        let p99 = percentile(99);
+
+        println!("soak report:");
+        println!("  sessions   = {}", pairs * 2);
+        println!("  rounds     = {}", rounds);
+        println!("  concurrent = {}", concurrency);
+        println!("  samples    = {}", latencies.len());
+        println!("  elapsed    = {:?}", started.elapsed());
+        println!("  p50        = {:?}", percentile(50));
+        println!("  p90        = {:?}", percentile(90));
+        println!("  p99        = {:?}", p99);
+        println!("  max        = {:?}", latencies.last().unwrap());
+        println!("  rss before = {:?} KiB", rss_before);
+        println!("  rss peak   = {:?} KiB", rss_peak);
+
+        ensure!(
+            p99 <= max_p99,
+            "p99 latency {:?} exceeds ceiling {:?}",
+            p99,
+            max_p99
+        );
+
+        if let Some(rss) = rss_peak {
+            ensure!(
+                rss <= max_rss_kib,
+                "peak rss {} KiB exceeds ceiling {} KiB",
+                rss,
+                max_rss_kib
+            );
+        }
+
         Ok(())
     }
 }
@@ -0,0 +1,203 @@
+//! End-to-end test with a real WebRTC stack.
+//!
+//! The handcrafted client of the other tests only covers what it was written
+//! for. Here two webrtc-rs peer connections that are limited to relay
+//! candidates connect through the turn server, so the ICE connectivity
+//! checks, the DTLS handshake, SCTP and SRTP all travel over the allocations,
+//! permissions and channels of the server. Requires the `webrtc` feature:
+//!
+//! ```text
+//! cargo test -p tests --features webrtc webrtc
+//! ```
+
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use tokio::{
+    sync::mpsc::{unbounded_channel, UnboundedSender},
+    time::timeout,
+};
+use turn_server::config::{Api, Auth};
+use webrtc::{
+    api::{media_engine::MediaEngine, APIBuilder},
+    data_channel::data_channel_message::DataChannelMessage,
+    ice_transport::{ice_server::RTCIceServer, ice_transport_policy::RTCIceTransportPolicy},
+    peer_connection::{configuration::RTCConfiguration, RTCPeerConnection},
+    rtp::{header::Header, packet::Packet},
+    rtp_transceiver::rtp_codec::RTCRtpCodecCapability,
+    track::track_local::{track_local_static_rtp::TrackLocalStaticRTP, TrackLocalWriter},
+};
+
+use crate::tests::create_turn_server;
+
+/// Create a peer connection that only gathers relay candidates from the turn
+/// server, so that it cannot connect in any other way.
+async fn create_peer(server: &str) -> Result<Arc<RTCPeerConnection>> {
+    let mut media_engine = MediaEngine::default();
+    media_engine.register_default_codecs()?;
+
+    let api = APIBuilder::new().with_media_engine(media_engine).build();
+    Ok(Arc::new(
+        api.new_peer_connection(RTCConfiguration {
+            ice_servers: vec![RTCIceServer {
+                urls: vec![format!("turn:{}?transport=udp", server)],
+                username: "webrtc".to_string(),
+                credential: "webrtc".to_string(),
+                ..Default::default()
+            }],
+            ice_transport_policy: RTCIceTransportPolicy::Relay,
+            ..Default::default()
+        })
+        .await?,
+    ))
+}
+
+/// Exchange the offer and the answer with all candidates, there is no
+/// trickle ICE between the peers.
+async fn negotiate(offerer: &RTCPeerConnection, answerer: &RTCPeerConnection) -> Result<()> {
+    let offer = offerer.create_offer(None).await?;
+    let mut gathered = offerer.gathering_complete_promise().await;
+    offerer.set_local_description(offer).await?;
+    let _ = gathered.recv().await;
+
+    answerer
+        .set_remote_description(
+            offerer
+                .local_description()
+                .await
+                .ok_or_else(|| anyhow!("no offer"))?,
+        )
+        .await?;
+
+    let answer = answerer.create_answer(None).await?;
+    let mut gathered = answerer.gathering_complete_promise().await;
+    answerer.set_local_description(answer).await?;
+    let _ = gathered.recv().await;
+
+    offerer
+        .set_remote_description(
+            answerer
+                .local_description()
+                .await
+                .ok_or_else(|| anyhow!("no answer"))?,
+        )
+        .await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn turn_webrtc_testing() -> Result<()> {
+    create_turn_server(
+        "127.0.0.1:3482".parse()?,
+        Auth {
+            static_auth_secret: None,
+            store: None,
+            static_credentials: {
+                let mut it = HashMap::with_capacity(1);
+                it.insert("webrtc".to_string(), "webrtc".to_string());
+                it
+            },
+        },
+        Api {
+            bind: "127.0.0.1:3004".parse()?,
+            ..Default::default()
+        },
+    )
+    .await?;
+
+    let offerer = create_peer("127.0.0.1:3482").await?;
+    let answerer = create_peer("127.0.0.1:3482").await?;
+
+    // Everything that the answerer receives, from the data channel and from the
+    // audio track, is passed to the test.
+    let (tx, mut rx) = unbounded_channel::<(&'static str, Bytes)>();
+    {
+        let tx: UnboundedSender<_> = tx.clone();
+        answerer.on_data_channel(Box::new(move |channel| {
+            let tx = tx.clone();
+            Box::pin(async move {
+                channel.on_message(Box::new(move |message: DataChannelMessage| {
+                    let _ = tx.send(("data", message.data));
+                    Box::pin(async {})
+                }));
+            })
+        }));
+    }
+
+    answerer.on_track(Box::new(move |track, _, _| {
+        let tx = tx.clone();
+        Box::pin(async move {
+            if let Ok((packet, _)) = track.read_rtp().await {
+                let _ = tx.send(("rtp", packet.payload));
+            }
+        })
+    }));
+
+    // SCTP over DTLS.
+    let channel = offerer.create_data_channel("turn", None).await?;
+    {
+        let channel_ = channel.clone();
+        channel.on_open(Box::new(move || {
+            Box::pin(async move {
+                let _ = channel_.send_text("hello turn").await;
+            })
+        }));
+    }
+
+    // SRTP.
+    let track = Arc::new(TrackLocalStaticRTP::new(
+        RTCRtpCodecCapability {
+            mime_type: "audio/opus".to_string(),
+            clock_rate: 48000,
+            channels: 2,
+            ..Default::default()
+        },
+        "audio".to_string(),
+        "turn".to_string(),
+    ));
+
+    offerer.add_track(track.clone()).await?;
+    negotiate(&offerer, &answerer).await?;
+
+    // The media is sent until the answerer has seen it, packets that are sent
+    // before the connection is established are dropped.
+    let sender = tokio::spawn(async move {
+        for sequence_number in 0.. {
+            let _ = track
+                .write_rtp(&Packet {
+                    header: Header {
+                        version: 2,
+                        payload_type: 111,
+                        sequence_number,
+                        timestamp: sequence_number as u32 * 960,
+                        ssrc: 0x7475726e,
+                        ..Default::default()
+                    },
+                    payload: Bytes::from_static(b"hello srtp"),
+                })
+                .await;
+
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    });
+
+    let mut data = None;
+    let mut rtp = None;
+    while data.is_none() || rtp.is_none() {
+        match timeout(Duration::from_secs(30), rx.recv()).await? {
+            Some(("data", it)) => data = Some(it),
+            Some((_, it)) => rtp = Some(it),
+            None => break,
+        }
+    }
+
+    sender.abort();
+    offerer.close().await?;
+    answerer.close().await?;
+
+    assert_eq!(data.as_deref(), Some(&b"hello turn"[..]));
+    assert_eq!(rtp.as_deref(), Some(&b"hello srtp"[..]));
+    Ok(())
+}
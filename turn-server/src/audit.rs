@@ -0,0 +1,110 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    net::SocketAddr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use parking_lot::Mutex;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::config;
+
+/// The fields of request bodies that are never written to the audit log.
+const REDACTED: [&str; 3] = ["password", "secret", "token"];
+
+/// A request that changed the state of the server through the api.
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditRecord {
+    /// When the request was answered, a unix timestamp in milliseconds.
+    pub timestamp: u64,
+    /// The address that the request came from.
+    pub remote: SocketAddr,
+    /// The name of the bearer token that the request presented, such as
+    /// `mirror`, if any.
+    pub actor: Option<&'static str>,
+    pub method: String,
+    pub path: String,
+    pub query: Option<String>,
+    /// The json body of the request, without the credentials in it.
+    pub body: Option<Value>,
+    /// The status code of the response.
+    pub status: u16,
+}
+
+impl AuditRecord {
+    pub fn timestamp() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|it| it.as_millis() as u64)
+            .unwrap_or(0)
+    }
+}
+
+/// Remove the credentials from a request body before it is audited.
+///
+/// # Example
+///
+/// ```
+/// use serde_json::json;
+/// use turn_server::audit::redact;
+///
+/// let mut body = json!({ "username": "test", "password": "test" });
+/// redact(&mut body);
+///
+/// assert_eq!(body, json!({ "username": "test" }));
+/// ```
+pub fn redact(value: &mut Value) {
+    match value {
+        Value::Object(it) => {
+            it.retain(|k, _| !REDACTED.contains(&k.as_str()));
+            it.values_mut().for_each(redact);
+        }
+        Value::Array(it) => it.iter_mut().for_each(redact),
+        _ => (),
+    }
+}
+
+/// Writes the audit records of the api to an append-only file.
+///
+/// Every record is a json object on a line of its own, written before the
+/// next request is audited. Without a file the records are written to the
+/// log instead, so that they are not lost.
+pub struct Auditor(Option<Mutex<File>>);
+
+impl Auditor {
+    pub fn new(config: &config::Audit) -> anyhow::Result<Self> {
+        Ok(Self(match &config.path {
+            Some(path) => {
+                let mut options = OpenOptions::new();
+                options.create(true).append(true);
+
+                // The records name the managed users, only the owner can read them.
+                #[cfg(unix)]
+                std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+                Some(Mutex::new(options.open(path)?))
+            }
+            None => None,
+        }))
+    }
+
+    pub fn record(&self, record: &AuditRecord) {
+        let line = match serde_json::to_string(record) {
+            Ok(it) => it,
+            Err(e) => {
+                log::error!("failed to encode audit record: {}", e);
+                return;
+            }
+        };
+
+        if let Some(file) = &self.0 {
+            if let Err(e) = writeln!(file.lock(), "{}", line) {
+                log::error!("failed to write audit record: {}, record={}", e, line);
+            }
+        } else {
+            log::info!(target: "audit", "{}", line);
+        }
+    }
+}
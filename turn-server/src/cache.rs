@@ -0,0 +1,254 @@
+use std::{
+    num::NonZeroUsize,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use lru::LruCache;
+use parking_lot::Mutex;
+use turn::PasswordDigest;
+
+use crate::config::PasswordCache;
+
+/// Caches the passwords that the hooks service returns.
+///
+/// The passwords are cached as their digests, see [`PasswordDigest`], which
+/// authenticate the user but do not reveal the password. They are kept in
+/// memory for the time to live, and the least recently used ones are dropped
+/// when the cache is full. With the `redis` feature, and when a redis url is
+/// configured, the digests are also stored in redis with the same time to
+/// live, so that the servers that share the redis server request the hooks
+/// service once per username between them. A digest that is not in memory
+/// is looked up in redis, and redis errors are logged and treated as a miss.
+/// Without a configuration nothing is cached.
+///
+/// # Example
+///
+/// ```
+/// use turn::PasswordDigest;
+/// use turn_server::{cache::CredentialCache, config::PasswordCache};
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let cache = CredentialCache::new(Some(&PasswordCache {
+///         capacity: 2,
+///         ..Default::default()
+///     }));
+///
+///     let digest = |password| PasswordDigest::new("user", password, "localhost").unwrap();
+///
+///     cache.insert("user1", digest("test1")).await;
+///     cache.insert("user2", digest("test2")).await;
+///     assert_eq!(cache.get("user1").await, Some(digest("test1")));
+///
+///     // user2 is the least recently used.
+///     cache.insert("user3", digest("test3")).await;
+///     assert_eq!(cache.get("user2").await, None);
+///     assert_eq!(cache.get("user1").await, Some(digest("test1")));
+///     assert_eq!(cache.get("user3").await, Some(digest("test3")));
+///
+///     let cache = CredentialCache::new(Some(&PasswordCache {
+///         ttl: 0,
+///         ..Default::default()
+///     }));
+///
+///     cache.insert("user1", digest("test1")).await;
+///     assert_eq!(cache.get("user1").await, None);
+///
+///     let cache = CredentialCache::new(Some(&PasswordCache {
+///         capacity: 0,
+///         ..Default::default()
+///     }));
+///
+///     cache.insert("user1", digest("test1")).await;
+///     assert_eq!(cache.get("user1").await, None);
+///
+///     CredentialCache::default().insert("user1", digest("test1")).await;
+///     assert_eq!(CredentialCache::default().get("user1").await, None);
+/// }
+/// ```
+#[derive(Clone, Default)]
+pub struct CredentialCache(Option<Arc<CredentialCacheInner>>);
+
+struct CredentialCacheInner {
+    ttl: Duration,
+    // Nothing is kept in memory with a capacity of zero.
+    entries: Option<Mutex<LruCache<String, Entry>>>,
+    #[cfg(feature = "redis")]
+    redis: Option<Redis>,
+}
+
+struct Entry {
+    digest: PasswordDigest,
+    expires: Instant,
+}
+
+impl CredentialCacheInner {
+    fn get(&self, key: &str, now: Instant) -> Option<PasswordDigest> {
+        let mut entries = self.entries.as_ref()?.lock();
+        let entry = entries.get(key)?;
+        if entry.expires <= now {
+            entries.pop(key);
+            return None;
+        }
+
+        Some(entry.digest)
+    }
+
+    fn insert(&self, key: &str, digest: PasswordDigest, expires: Instant) {
+        if let Some(entries) = &self.entries {
+            entries.lock().put(key.to_string(), Entry { digest, expires });
+        }
+    }
+}
+
+impl CredentialCache {
+    pub fn new(config: Option<&PasswordCache>) -> Self {
+        Self(config.map(|it| {
+            Arc::new(CredentialCacheInner {
+                ttl: Duration::from_secs(it.ttl),
+                entries: NonZeroUsize::new(it.capacity).map(|it| Mutex::new(LruCache::new(it))),
+                #[cfg(feature = "redis")]
+                redis: it.redis.as_ref().and_then(|url| match Redis::new(url, &it.prefix) {
+                    Ok(it) => Some(it),
+                    Err(e) => {
+                        log::error!("invalid redis url of the password cache, err={}", e);
+                        None
+                    }
+                }),
+            })
+        }))
+    }
+
+    /// The cached digest of the key, if it has not expired.
+    pub async fn get(&self, key: &str) -> Option<PasswordDigest> {
+        let inner = self.0.as_ref()?;
+        if let Some(it) = inner.get(key, Instant::now()) {
+            return Some(it);
+        }
+
+        #[cfg(feature = "redis")]
+        if let Some(redis) = &inner.redis {
+            if let Some((digest, ttl)) = redis.get(key).await {
+                inner.insert(key, digest, Instant::now() + ttl);
+                return Some(digest);
+            }
+        }
+
+        None
+    }
+
+    /// Cache the digest of the key for the time to live.
+    pub async fn insert(&self, key: &str, digest: PasswordDigest) {
+        let inner = if let Some(it) = &self.0 {
+            it
+        } else {
+            return;
+        };
+
+        inner.insert(key, digest, Instant::now() + inner.ttl);
+
+        #[cfg(feature = "redis")]
+        if let Some(redis) = &inner.redis {
+            redis.set(key, &digest, inner.ttl).await;
+        }
+    }
+}
+
+#[cfg(feature = "redis")]
+struct Redis {
+    client: redis::Client,
+    prefix: String,
+    // Connected on the first use, a multiplexed connection is shared by all
+    // lookups and reconnects by itself.
+    connection: tokio::sync::OnceCell<redis::aio::MultiplexedConnection>,
+}
+
+#[cfg(feature = "redis")]
+impl Redis {
+    fn new(url: &str, prefix: &str) -> anyhow::Result<Self> {
+        Ok(Self {
+            client: redis::Client::open(url)?,
+            prefix: prefix.to_string(),
+            connection: tokio::sync::OnceCell::new(),
+        })
+    }
+
+    async fn connection(&self) -> Option<redis::aio::MultiplexedConnection> {
+        match self
+            .connection
+            .get_or_try_init(|| self.client.get_multiplexed_tokio_connection())
+            .await
+        {
+            Ok(it) => Some(it.clone()),
+            Err(e) => {
+                log::warn!("failed to connect to the redis of the password cache, err={}", e);
+                None
+            }
+        }
+    }
+
+    // The digest and the remaining time to live of the key, so that the copy in
+    // memory does not outlive the one in redis.
+    async fn get(&self, key: &str) -> Option<(PasswordDigest, Duration)> {
+        let mut connection = self.connection().await?;
+        let key = format!("{}{}", self.prefix, key);
+        let ret: redis::RedisResult<(Option<Vec<u8>>, i64)> = redis::pipe()
+            .cmd("GET")
+            .arg(&key)
+            .cmd("TTL")
+            .arg(&key)
+            .query_async(&mut connection)
+            .await;
+
+        match ret {
+            Ok((Some(value), ttl)) if ttl > 0 => Some((decode(&value)?, Duration::from_secs(ttl as u64))),
+            Ok(_) => None,
+            Err(e) => {
+                log::warn!("failed to get the password digest from redis, err={}", e);
+                None
+            }
+        }
+    }
+
+    async fn set(&self, key: &str, digest: &PasswordDigest, ttl: Duration) {
+        if ttl.is_zero() {
+            return;
+        }
+
+        let mut connection = match self.connection().await {
+            Some(it) => it,
+            None => return,
+        };
+
+        let ret: redis::RedisResult<()> = redis::cmd("SET")
+            .arg(format!("{}{}", self.prefix, key))
+            .arg(encode(digest))
+            .arg("EX")
+            .arg(ttl.as_secs())
+            .query_async(&mut connection)
+            .await;
+
+        if let Err(e) = ret {
+            log::warn!("failed to set the password digest in redis, err={}", e);
+        }
+    }
+}
+
+// The digest is stored as the MD5 key followed by the SHA-256 key.
+#[cfg(feature = "redis")]
+fn encode(digest: &PasswordDigest) -> Vec<u8> {
+    [digest.md5.as_slice(), digest.sha256.as_slice()].concat()
+}
+
+#[cfg(feature = "redis")]
+fn decode(value: &[u8]) -> Option<PasswordDigest> {
+    if value.len() != 48 {
+        return None;
+    }
+
+    Some(PasswordDigest {
+        md5: value[..16].try_into().ok()?,
+        sha256: value[16..].try_into().ok()?,
+    })
+}
@@ -0,0 +1,604 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket as StdUdpSocket},
+    sync::{Arc, Weak},
+    time::Duration,
+};
+
+use ahash::AHashMap;
+use anyhow::{anyhow, ensure};
+use bytes::BytesMut;
+use ipnet::IpNet;
+use once_cell::sync::OnceCell;
+use parking_lot::{Mutex, RwLock};
+use rand::{thread_rng, Rng};
+use stun::{
+    attribute::{
+        ChannelNumber, Data, ErrorCode, ErrorKind, Lifetime, Nonce, Realm, ReqeestedTransport, UserName,
+        XorPeerAddress, XorRelayedAddress,
+    },
+    auth::long_term_credential_digest,
+    ChannelData, Decoder, Kind, MessageWriter, Method, Payload, StunError, Transport,
+};
+use tokio::{
+    net::UdpSocket,
+    sync::Notify,
+    time::{interval_at, timeout_at, Instant},
+};
+use turn::{ResponseMethod, SessionAddr, Sessions};
+
+use crate::{config, observer::Observer, router::Router};
+
+/// The lifetime requested for the allocations on the upstream turn server.
+const LIFETIME: u32 = 600;
+
+/// The allocations on the upstream turn server and the channels in them are
+/// refreshed long before they expire, so that a lost refresh is not fatal.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(120);
+
+/// How long an allocate request waits for the response before it is sent
+/// again, and how many times it is sent.
+const RETRANSMIT_TIMEOUT: Duration = Duration::from_millis(500);
+const RETRANSMISSIONS: usize = 5;
+
+/// Bridges the sessions to the peers of an upstream turn server.
+///
+/// The sessions reach the peers in the configured networks through an
+/// allocation of their own on the upstream turn server, a leg that the turn
+/// server allocates with its own credentials the first time that the
+/// session permits such a peer. Every peer gets a channel on the leg, the
+/// data of the session is relayed to the peer on it, and the data of the
+/// peer is delivered to the session in a ChannelData message if the session
+/// has bound a channel to the peer, in a Data indication otherwise.
+///
+/// Only the permissions and the data are bridged, the upstream does not see
+/// the clients and the clients do not see the upstream, the relayed address
+/// of the leg is the address that the peers see.
+#[derive(Clone, Default)]
+pub struct Cascade(Option<Arc<CascadeInner>>);
+
+struct CascadeInner {
+    upstream: SocketAddr,
+    username: String,
+    password: String,
+    bind: IpAddr,
+    peers: Vec<IpNet>,
+    router: Router,
+    // Set once the session manager has been created, it owns the observer that
+    // this is a part of.
+    sessions: OnceCell<Weak<Sessions<Observer>>>,
+    legs: RwLock<AHashMap<SessionAddr, Arc<Leg>>>,
+}
+
+impl Cascade {
+    pub fn new(config: &config::Cascade, router: Router) -> Self {
+        let upstream = match config.upstream {
+            Some(it) => it,
+            None => return Self(None),
+        };
+
+        Self(Some(Arc::new(CascadeInner {
+            bind: config.bind.unwrap_or(if upstream.is_ipv4() {
+                IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+            } else {
+                IpAddr::V6(Ipv6Addr::UNSPECIFIED)
+            }),
+            peers: if config.peers.is_empty() {
+                vec![IpNet::from(upstream.ip())]
+            } else {
+                config.peers.clone()
+            },
+            username: config.username.clone(),
+            password: config.password.clone(),
+            sessions: OnceCell::new(),
+            legs: Default::default(),
+            upstream,
+            router,
+        })))
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.0.is_some()
+    }
+
+    /// Give the session manager to the cascade, the data of the peers is only
+    /// delivered to the sessions after this.
+    pub fn attach(&self, sessions: &Arc<Sessions<Observer>>) {
+        if let Some(inner) = &self.0 {
+            let _ = inner.sessions.set(Arc::downgrade(sessions));
+        }
+    }
+
+    /// Whether the peer is reached through the upstream turn server.
+    pub fn is_external(&self, peer: &SocketAddr) -> bool {
+        self.0
+            .as_ref()
+            .map(|it| it.peers.iter().any(|net| net.contains(&peer.ip())))
+            .unwrap_or(false)
+    }
+
+    /// The relayed address of the leg of the session on the upstream turn
+    /// server, `None` until the allocation has been made.
+    pub fn get_relay(&self, addr: &SessionAddr) -> Option<SocketAddr> {
+        self.0.as_ref()?.legs.read().get(addr)?.state.lock().relay
+    }
+
+    /// Permit the peers on the leg of the session, the leg is allocated on the
+    /// upstream turn server if the session does not have one yet.
+    pub fn permit(&self, addr: &SessionAddr, peers: &[SocketAddr]) {
+        let inner = match &self.0 {
+            Some(it) => it,
+            None => return,
+        };
+
+        let leg = match inner.open(addr) {
+            Ok(it) => it,
+            Err(e) => {
+                log::error!("cascade: failed to open leg: address={:?}, err={}", addr.address, e);
+                return;
+            }
+        };
+
+        let mut state = leg.state.lock();
+        for peer in peers {
+            if state.channels.contains_key(peer) {
+                continue;
+            }
+
+            let number = 0x4000 + state.channels.len() as u16;
+            if number > 0x7FFF {
+                log::warn!("cascade: no channel left: address={:?}, peer={:?}", addr.address, peer);
+                break;
+            }
+
+            state.channels.insert(*peer, (number, false));
+
+            // The channels requested before the allocation is made are bound
+            // once it is.
+            if state.relay.is_some() {
+                leg.channel_bind(&mut state, &inner.username, peer, number);
+            }
+        }
+    }
+
+    /// Relay the data of the session to the peer through the leg of the
+    /// session.
+    ///
+    /// This is on the path of the relayed data and never waits, the data is
+    /// dropped if the leg is not allocated yet or its socket is busy.
+    pub fn relay(&self, addr: &SessionAddr, peer: &SocketAddr, data: &[u8]) {
+        let leg = match self.0.as_ref().and_then(|it| it.legs.read().get(addr).cloned()) {
+            Some(it) => it,
+            None => return,
+        };
+
+        let mut bytes = BytesMut::with_capacity(data.len() + 64);
+
+        {
+            let state = leg.state.lock();
+            if state.relay.is_none() {
+                return;
+            }
+
+            match state.channels.get(peer) {
+                Some((number, true)) => ChannelData {
+                    number: *number,
+                    bytes: data,
+                }
+                .encode(&mut bytes),
+                _ => {
+                    let token = thread_rng().gen::<[u8; 12]>();
+                    let mut message = MessageWriter::new(Method::SendIndication, &token, &mut bytes);
+                    message.append::<XorPeerAddress>(*peer);
+                    message.append::<Data>(data);
+                    if message.flush(None).is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+
+        let _ = leg.socket.try_send(&bytes);
+    }
+
+    /// Delete the leg of the session on the upstream turn server.
+    pub fn close(&self, addr: &SessionAddr) {
+        if let Some(inner) = &self.0 {
+            if let Some(leg) = inner.legs.write().remove(addr) {
+                leg.closed.notify_one();
+            }
+        }
+    }
+
+    /// Move the leg of a session to the address that it has moved to.
+    pub fn transfer(&self, from: &SessionAddr, to: &SessionAddr) {
+        if let Some(inner) = &self.0 {
+            let mut legs = inner.legs.write();
+            if let Some(leg) = legs.remove(from) {
+                leg.state.lock().addr = *to;
+                legs.insert(*to, leg);
+            }
+        }
+    }
+}
+
+impl CascadeInner {
+    fn open(self: &Arc<Self>, addr: &SessionAddr) -> anyhow::Result<Arc<Leg>> {
+        if let Some(it) = self.legs.read().get(addr) {
+            return Ok(it.clone());
+        }
+
+        let socket = StdUdpSocket::bind(SocketAddr::new(self.bind, 0))?;
+        socket.connect(self.upstream)?;
+        socket.set_nonblocking(true)?;
+
+        let leg = Arc::new(Leg {
+            socket: UdpSocket::from_std(socket)?,
+            state: Mutex::new(LegState {
+                addr: *addr,
+                realm: String::new(),
+                nonce: String::new(),
+                digest: None,
+                relay: None,
+                channels: AHashMap::new(),
+                pending: AHashMap::new(),
+            }),
+            closed: Notify::new(),
+        });
+
+        {
+            let mut legs = self.legs.write();
+            if let Some(it) = legs.get(addr) {
+                return Ok(it.clone());
+            }
+
+            legs.insert(*addr, leg.clone());
+        }
+
+        let this = self.clone();
+        let leg_ = leg.clone();
+        tokio::spawn(async move {
+            let ret = leg_.run(&this).await;
+
+            let addr = leg_.state.lock().addr;
+            {
+                let mut legs = this.legs.write();
+                if legs.get(&addr).map(|it| Arc::ptr_eq(it, &leg_)).unwrap_or(false) {
+                    legs.remove(&addr);
+                }
+            }
+
+            if let Err(e) = ret {
+                log::error!("cascade: leg failed: address={:?}, err={}", addr.address, e);
+            }
+        });
+
+        Ok(leg)
+    }
+
+    // Deliver the data that the peer has sent to the leg to the session.
+    fn deliver(&self, leg: &Leg, peer: &SocketAddr, data: &[u8]) {
+        let sessions = match self.sessions.get().and_then(Weak::upgrade) {
+            Some(it) => it,
+            None => return,
+        };
+
+        // The leg keeps the channels of every peer that the session has ever
+        // permitted, the permissions of the session may have expired since.
+        let addr = leg.state.lock().addr;
+        if !sessions.is_external_permitted(&addr, &peer.ip()) {
+            return;
+        }
+
+        let transport = match sessions.get_session(&addr).get_ref() {
+            Some(it) => it.transport,
+            None => return,
+        };
+
+        let mut bytes = BytesMut::with_capacity(data.len() + 64);
+        let method = if let Some(number) = sessions.get_external_channel(&addr, peer) {
+            ChannelData {
+                number: number.get(),
+                bytes: data,
+            }
+            .encode(&mut bytes);
+
+            ResponseMethod::ChannelData
+        } else {
+            let token = thread_rng().gen::<[u8; 12]>();
+            let mut message = MessageWriter::new(Method::DataIndication, &token, &mut bytes);
+            message.append::<XorPeerAddress>(*peer);
+            message.append::<Data>(data);
+            if message.flush(None).is_err() {
+                return;
+            }
+
+            ResponseMethod::Stun(Method::DataIndication)
+        };
+
+        // The routes of tcp are the connections of the clients.
+        let endpoint = if transport == Transport::TCP {
+            addr.address
+        } else {
+            addr.interface
+        };
+
+        self.router.send(&endpoint, method, &addr.address, &bytes);
+    }
+}
+
+/// The allocation of a session on the upstream turn server.
+struct Leg {
+    socket: UdpSocket,
+    state: Mutex<LegState>,
+    closed: Notify,
+}
+
+struct LegState {
+    // The session that the leg belongs to.
+    addr: SessionAddr,
+    realm: String,
+    nonce: String,
+    digest: Option<[u8; 16]>,
+    // Only known once the allocation has been made.
+    relay: Option<SocketAddr>,
+    // The channels of the peers and whether the upstream has bound them.
+    channels: AHashMap<SocketAddr, (u16, bool)>,
+    // The ChannelBind requests waiting for a response.
+    pending: AHashMap<[u8; 12], SocketAddr>,
+}
+
+impl Leg {
+    async fn run(&self, inner: &CascadeInner) -> anyhow::Result<()> {
+        let mut buf = vec![0u8; 4096];
+        let mut decoder = Decoder::default();
+
+        let relay = self.allocate(inner, &mut buf, &mut decoder).await?;
+
+        {
+            let mut state = self.state.lock();
+            state.relay = Some(relay);
+
+            log::info!(
+                "cascade: leg allocated: address={:?}, upstream={:?}, relay={:?}",
+                state.addr.address,
+                inner.upstream,
+                relay
+            );
+
+            self.refresh(&mut state, &inner.username, LIFETIME);
+        }
+
+        let mut refresh = interval_at(Instant::now() + REFRESH_INTERVAL, REFRESH_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = self.closed.notified() => break,
+                _ = refresh.tick() => self.refresh(&mut self.state.lock(), &inner.username, LIFETIME),
+                ret = self.socket.recv(&mut buf) => match ret {
+                    Ok(size) => self.receive(inner, &buf[..size], &mut decoder),
+                    // The icmp errors of earlier datagrams are reported on a connected socket.
+                    Err(e) => log::warn!("cascade: upstream error: upstream={:?}, err={}", inner.upstream, e),
+                },
+            }
+        }
+
+        // The allocation is deleted rather than left on the upstream until it
+        // expires.
+        self.refresh(&mut self.state.lock(), &inner.username, 0);
+        Ok(())
+    }
+
+    // Allocate on the upstream turn server and return the relayed address.
+    async fn allocate(
+        &self,
+        inner: &CascadeInner,
+        buf: &mut [u8],
+        decoder: &mut Decoder,
+    ) -> anyhow::Result<SocketAddr> {
+        let mut bytes = BytesMut::with_capacity(1500);
+
+        // The first request is rejected with the realm and the nonce that the
+        // credentials are used with, as is a request with a stale nonce.
+        for _ in 0..3 {
+            let token = thread_rng().gen::<[u8; 12]>();
+
+            {
+                let state = self.state.lock();
+                let mut message = MessageWriter::new(Method::Allocate(Kind::Request), &token, &mut bytes);
+                message.append::<ReqeestedTransport>(Transport::UDP);
+                message.append::<Lifetime>(LIFETIME);
+                authenticate(&mut message, &state, &inner.username)?;
+            }
+
+            let size = self.transact(&bytes, &token, buf).await?;
+            let message = match decoder.decode(&buf[..size])? {
+                Payload::Message(it) => it,
+                Payload::ChannelData(_) => return Err(anyhow!("unexpected channel data")),
+            };
+
+            let mut state = self.state.lock();
+            match message.method {
+                Method::Allocate(Kind::Response) => {
+                    if let Some(digest) = &state.digest {
+                        message.integrity(digest)?;
+                    }
+
+                    return message
+                        .get::<XorRelayedAddress>()
+                        .ok_or_else(|| anyhow!("missing relayed address"));
+                }
+                Method::Allocate(Kind::Error) => {
+                    let error = message
+                        .get::<ErrorCode>()
+                        .ok_or_else(|| anyhow!("missing error code"))?;
+                    ensure!(
+                        error.code == ErrorKind::StaleNonce as u16
+                            || (error.code == ErrorKind::Unauthorized as u16 && state.digest.is_none()),
+                        "allocate rejected: code={}, message={:?}",
+                        error.code,
+                        error.message
+                    );
+
+                    let (realm, nonce) = match (message.get::<Realm>(), message.get::<Nonce>()) {
+                        (Some(realm), Some(nonce)) => (realm, nonce),
+                        _ => return Err(anyhow!("missing realm or nonce")),
+                    };
+
+                    state.digest = Some(long_term_credential_digest(&inner.username, &inner.password, realm)?);
+                    state.realm = realm.to_string();
+                    state.nonce = nonce.to_string();
+                }
+                method => return Err(anyhow!("unexpected response: {:?}", method)),
+            }
+        }
+
+        Err(anyhow!("allocate rejected"))
+    }
+
+    // Send the request until the response to it arrives and return its size.
+    async fn transact(&self, bytes: &[u8], token: &[u8; 12], buf: &mut [u8]) -> anyhow::Result<usize> {
+        for _ in 0..RETRANSMISSIONS {
+            self.socket.send(bytes).await?;
+
+            let deadline = Instant::now() + RETRANSMIT_TIMEOUT;
+            while let Ok(ret) = timeout_at(deadline, self.socket.recv(buf)).await {
+                let size = ret?;
+
+                // Nothing else is expected before the allocation is made.
+                if size >= 20 && &buf[8..20] == token {
+                    return Ok(size);
+                }
+            }
+        }
+
+        Err(anyhow!("upstream timeout: upstream={:?}", self.socket.peer_addr()?))
+    }
+
+    // Handle a datagram of the upstream turn server.
+    fn receive(&self, inner: &CascadeInner, bytes: &[u8], decoder: &mut Decoder) {
+        if bytes.len() < 4 {
+            return;
+        }
+
+        let (peer, data) = match decoder.decode(bytes) {
+            Ok(Payload::ChannelData(it)) => {
+                let state = self.state.lock();
+                match state.channels.iter().find(|(_, (number, _))| *number == it.number) {
+                    Some((peer, _)) => (*peer, it.bytes),
+                    None => return,
+                }
+            }
+            Ok(Payload::Message(message)) => match message.method {
+                Method::DataIndication => match (message.get::<XorPeerAddress>(), message.get::<Data>()) {
+                    (Some(peer), Some(data)) => (peer, data),
+                    _ => return,
+                },
+                Method::ChannelBind(Kind::Response) => {
+                    let mut state = self.state.lock();
+                    let verified = state.digest.map(|it| message.integrity(&it).is_ok()).unwrap_or(false);
+
+                    if let Ok(token) = <[u8; 12]>::try_from(message.token) {
+                        if let Some(peer) = state.pending.remove(&token) {
+                            if let Some(it) = state.channels.get_mut(&peer) {
+                                it.1 = verified;
+                            }
+                        }
+                    }
+
+                    return;
+                }
+                Method::ChannelBind(Kind::Error) | Method::Refresh(Kind::Error) => {
+                    let error = match message.get::<ErrorCode>() {
+                        Some(it) => it,
+                        None => return,
+                    };
+
+                    let mut state = self.state.lock();
+                    if error.code == ErrorKind::StaleNonce as u16 {
+                        if let Some(nonce) = message.get::<Nonce>() {
+                            state.nonce = nonce.to_string();
+                            self.refresh(&mut state, &inner.username, LIFETIME);
+                        }
+                    } else {
+                        log::warn!(
+                            "cascade: upstream rejected {:?}: address={:?}, code={}, message={:?}",
+                            message.method,
+                            state.addr.address,
+                            error.code,
+                            error.message
+                        );
+                    }
+
+                    return;
+                }
+                _ => return,
+            },
+            Err(_) => return,
+        };
+
+        inner.deliver(self, &peer, data);
+    }
+
+    // Refresh the allocation and bind the channels again, which also refreshes
+    // their permissions. A lifetime of zero deletes the allocation.
+    fn refresh(&self, state: &mut LegState, username: &str, lifetime: u32) {
+        let token = thread_rng().gen::<[u8; 12]>();
+        let mut bytes = BytesMut::with_capacity(256);
+
+        {
+            let mut message = MessageWriter::new(Method::Refresh(Kind::Request), &token, &mut bytes);
+            message.append::<Lifetime>(lifetime);
+            if authenticate(&mut message, state, username).is_err() {
+                return;
+            }
+        }
+
+        let _ = self.socket.try_send(&bytes);
+
+        if lifetime > 0 {
+            // The requests that have not been answered by now are lost.
+            state.pending.clear();
+
+            let channels = state
+                .channels
+                .iter()
+                .map(|(peer, (number, _))| (*peer, *number))
+                .collect::<Vec<_>>();
+
+            for (peer, number) in channels {
+                self.channel_bind(state, username, &peer, number);
+            }
+        }
+    }
+
+    fn channel_bind(&self, state: &mut LegState, username: &str, peer: &SocketAddr, number: u16) {
+        let token = thread_rng().gen::<[u8; 12]>();
+        let mut bytes = BytesMut::with_capacity(256);
+
+        {
+            let mut message = MessageWriter::new(Method::ChannelBind(Kind::Request), &token, &mut bytes);
+            message.append::<ChannelNumber>(number);
+            message.append::<XorPeerAddress>(*peer);
+            if authenticate(&mut message, state, username).is_err() {
+                return;
+            }
+        }
+
+        if self.socket.try_send(&bytes).is_ok() {
+            state.pending.insert(token, *peer);
+        }
+    }
+}
+
+// Add the long-term credentials to the request, the first allocate request
+// is sent without them.
+fn authenticate(message: &mut MessageWriter, state: &LegState, username: &str) -> Result<(), StunError> {
+    match &state.digest {
+        Some(digest) => {
+            message.append::<UserName>(username);
+            message.append::<Realm>(&state.realm);
+            message.append::<Nonce>(&state.nonce);
+            message.flush(Some(digest))
+        }
+        None => message.flush(None),
+    }
+}
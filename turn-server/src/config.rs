@@ -1,31 +1,71 @@
-use std::{collections::HashMap, fs::read_to_string, net::SocketAddr, str::FromStr};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs},
+    path::PathBuf,
+    str::FromStr,
+};
+
+#[cfg(feature = "cli")]
+use std::{
+    fs::read_to_string,
+    io::{stdin, Read},
+};
 
 use anyhow::anyhow;
-use clap::Parser;
+#[cfg(feature = "cli")]
+use clap::{Parser, Subcommand};
+use ipnet::IpNet;
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
+use stun::auth::{opaque_string, prepare_realm};
+use turn::{lockout::LockoutOptions, sessions, PortAllocatePools};
 
-#[repr(C)]
-#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+/// The transport protocol of an interface.
+///
+/// # Example
+///
+/// ```
+/// use turn_server::config::Transport;
+///
+/// assert_eq!("tls".parse::<Transport>().unwrap(), Transport::TLS);
+/// assert_eq!(Transport::TLS.session_transport(), stun::Transport::TCP);
+/// assert_eq!(Transport::UDP.session_transport(), stun::Transport::UDP);
+/// ```
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[serde(rename_all = "lowercase")]
 pub enum Transport {
-    TCP = 0,
-    UDP = 1,
+    UDP,
+    TCP,
+    /// TCP with TLS on top, for the `turns:` URIs.
+    TLS,
+}
+
+impl Transport {
+    /// The transport that the sessions of the interface are connected over,
+    /// the sessions of a TLS interface are connected over TCP.
+    pub fn session_transport(self) -> stun::Transport {
+        match self {
+            Self::UDP => stun::Transport::UDP,
+            Self::TCP | Self::TLS => stun::Transport::TCP,
+        }
+    }
 }
 
 impl FromStr for Transport {
     type Err = anyhow::Error;
 
-    fn from_str(value: &str) -> Result<Self, Self::Err> {
-        Ok(match value {
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
             "udp" => Self::UDP,
             "tcp" => Self::TCP,
-            _ => return Err(anyhow!("unknown transport: {value}")),
+            "tls" => Self::TLS,
+            _ => return Err(anyhow!("unknown transport: {}", s)),
         })
     }
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(try_from = "InterfaceAddrs")]
 pub struct Interface {
     pub transport: Transport,
     /// turn server listen address
@@ -37,6 +77,101 @@ pub struct Interface {
     /// you need to manually specify the server external IP
     /// address and service listening port.
     pub external: SocketAddr,
+    /// The host name of the external address, if it was configured as a host
+    /// name instead of an ip address.
+    ///
+    /// The external address keeps identifying the interface, while the
+    /// address advertised to clients follows the host name when it is
+    /// resolved again.
+    #[serde(skip)]
+    pub external_host: Option<String>,
+    /// The certificate chain of a TLS interface, in PEM.
+    #[serde(skip_serializing)]
+    pub certificate: Option<PathBuf>,
+    /// The private key of the certificate of a TLS interface, in PEM.
+    #[serde(skip_serializing)]
+    pub private_key: Option<PathBuf>,
+    /// The realm of the clients of the interface, the realm of the turn
+    /// server if not set.
+    pub realm: Option<String>,
+}
+
+impl Interface {
+    /// Resolve an address that is either an ip address and port, or a host
+    /// name and port, with the resolver of the system.
+    ///
+    /// Returns the host name as well when a host name was resolved.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use turn_server::config::Interface;
+    ///
+    /// let (addr, host) = Interface::resolve("127.0.0.1:3478").unwrap();
+    /// assert_eq!(addr, "127.0.0.1:3478".parse().unwrap());
+    /// assert_eq!(host, None);
+    ///
+    /// let (addr, host) = Interface::resolve("localhost:3478").unwrap();
+    /// assert!(addr.ip().is_loopback());
+    /// assert_eq!(addr.port(), 3478);
+    /// assert_eq!(host, Some("localhost".to_string()));
+    /// ```
+    pub fn resolve(value: &str) -> anyhow::Result<(SocketAddr, Option<String>)> {
+        if let Ok(addr) = value.parse::<SocketAddr>() {
+            return Ok((addr, None));
+        }
+
+        let (host, _) = value
+            .rsplit_once(':')
+            .ok_or_else(|| anyhow!("invalid interface address: {}", value))?;
+
+        let addr = value
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| anyhow!("failed to resolve interface address: {}", value))?;
+
+        Ok((addr, Some(host.to_string())))
+    }
+}
+
+/// The addresses of an interface as written in the configuration file, they
+/// are host names or ip addresses.
+#[derive(Deserialize)]
+struct InterfaceAddrs {
+    transport: Transport,
+    bind: String,
+    external: String,
+    #[serde(default)]
+    certificate: Option<PathBuf>,
+    #[serde(default)]
+    private_key: Option<PathBuf>,
+    #[serde(default)]
+    realm: Option<String>,
+}
+
+impl TryFrom<InterfaceAddrs> for Interface {
+    type Error = anyhow::Error;
+
+    fn try_from(value: InterfaceAddrs) -> Result<Self, Self::Error> {
+        let (external, external_host) = Interface::resolve(&value.external)?;
+
+        if value.transport == Transport::TLS && (value.certificate.is_none() || value.private_key.is_none()) {
+            return Err(anyhow!(
+                "tls interface needs a certificate and a private key: {}",
+                value.external
+            ));
+        }
+
+        Ok(Interface {
+            bind: Interface::resolve(&value.bind)?.0,
+            transport: value.transport,
+            certificate: value.certificate,
+            private_key: value.private_key,
+            realm: value.realm,
+            external_host,
+            external,
+        })
+    }
 }
 
 impl FromStr for Interface {
@@ -53,11 +188,342 @@ impl FromStr for Interface {
             .collect_tuple()
             .ok_or_else(|| anyhow!("invalid interface address: {}", s))?;
 
-        Ok(Interface {
-            external: external.parse::<SocketAddr>()?,
-            bind: bind.parse::<SocketAddr>()?,
+        InterfaceAddrs {
             transport: transport.parse()?,
-        })
+            bind: bind.to_string(),
+            external: external.to_string(),
+            certificate: None,
+            private_key: None,
+            realm: None,
+        }
+        .try_into()
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct AutoInterfaces {
+    /// The port that the automatically created interfaces listen on.
+    #[serde(default = "AutoInterfaces::port")]
+    pub port: u16,
+    /// Only addresses in these networks are used, all addresses are used if
+    /// empty.
+    #[serde(default)]
+    pub include: Vec<IpNet>,
+    /// Addresses in these networks are never used, this takes precedence over
+    /// `include`.
+    #[serde(default)]
+    pub exclude: Vec<IpNet>,
+}
+
+impl AutoInterfaces {
+    fn port() -> u16 {
+        3478
+    }
+
+    /// Enumerate the addresses of all network interfaces of the host and
+    /// create a udp interface for each of them.
+    ///
+    /// Loopback and link-local addresses are skipped, and the bind address is
+    /// also used as the external address, so hosts behind NAT still need to
+    /// configure their interfaces manually.
+    pub fn enumerate(&self) -> anyhow::Result<Vec<Interface>> {
+        Ok(if_addrs::get_if_addrs()?
+            .into_iter()
+            .filter(|it| !it.is_loopback() && !it.is_link_local())
+            .map(|it| it.ip())
+            .filter(|ip| self.include.is_empty() || self.include.iter().any(|net| net.contains(ip)))
+            .filter(|ip| !self.exclude.iter().any(|net| net.contains(ip)))
+            .unique()
+            .map(|ip| {
+                let addr = SocketAddr::new(ip, self.port);
+                Interface {
+                    transport: Transport::UDP,
+                    external: addr,
+                    bind: addr,
+                    external_host: None,
+                    certificate: None,
+                    private_key: None,
+                    realm: None,
+                }
+            })
+            .collect())
+    }
+}
+
+impl Default for AutoInterfaces {
+    fn default() -> Self {
+        Self {
+            port: Self::port(),
+            include: Vec::new(),
+            exclude: Vec::new(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Lockout {
+    /// The maximum number of usernames and addresses whose failures are
+    /// tracked at the same time.
+    #[serde(default = "Lockout::capacity")]
+    pub capacity: usize,
+    /// The number of failed authentications allowed before a username or an
+    /// address is locked out.
+    #[serde(default = "Lockout::max_failures")]
+    pub max_failures: u32,
+    /// The lockout time in seconds, doubled for each further failure.
+    #[serde(default = "Lockout::lockout")]
+    pub lockout: u64,
+    /// The upper limit of the lockout time in seconds.
+    #[serde(default = "Lockout::max_lockout")]
+    pub max_lockout: u64,
+}
+
+impl Lockout {
+    fn capacity() -> usize {
+        LockoutOptions::default().capacity
+    }
+
+    fn max_failures() -> u32 {
+        LockoutOptions::default().max_failures
+    }
+
+    fn lockout() -> u64 {
+        LockoutOptions::default().lockout
+    }
+
+    fn max_lockout() -> u64 {
+        LockoutOptions::default().max_lockout
+    }
+}
+
+impl From<&Lockout> for LockoutOptions {
+    fn from(value: &Lockout) -> Self {
+        Self {
+            capacity: value.capacity,
+            max_failures: value.max_failures,
+            lockout: value.lockout,
+            max_lockout: value.max_lockout,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum IpFamily {
+    IPv4,
+    IPv6,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct PortRange {
+    /// The external address of the interface whose allocations take their
+    /// ports from the range, any interface if not set.
+    #[serde(default)]
+    pub interface: Option<SocketAddr>,
+    /// The address family of the relayed addresses of the allocations that
+    /// take their ports from the range, either family if not set.
+    #[serde(default)]
+    pub family: Option<IpFamily>,
+    /// The first port of the range.
+    pub start: u16,
+    /// The last port of the range, inclusive.
+    pub end: u16,
+}
+
+impl From<&PortRange> for sessions::PortRange {
+    fn from(value: &PortRange) -> Self {
+        Self {
+            interface: value.interface,
+            ipv6: value.family.map(|it| it == IpFamily::IPv6),
+            ports: value.start..value.end.saturating_add(1),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Shedding {
+    /// The number of relayed packets waiting to be forwarded between the
+    /// interfaces at which new allocations are shed.
+    pub max_queue_depth: Option<usize>,
+    /// The deadline budget of a request in milliseconds, new allocations are
+    /// shed while the average processing time of the requests exceeds it.
+    pub deadline: Option<u64>,
+}
+
+/// The settings of the sessions of a realm, the settings that are not set are
+/// taken from the turn server.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct Realm {
+    /// The SOFTWARE attribute of the responses on the interfaces of the realm.
+    pub software: Option<String>,
+    /// See [`Turn::nonce_lifetime`].
+    pub nonce_lifetime: Option<u32>,
+    /// See [`Turn::default_lifetime`].
+    pub default_lifetime: Option<u32>,
+    /// See [`Turn::max_allocations`].
+    pub max_allocations: Option<usize>,
+}
+
+impl From<&Realm> for sessions::RealmOptions {
+    fn from(value: &Realm) -> Self {
+        Self {
+            nonce_lifetime: value.nonce_lifetime,
+            default_lifetime: value.default_lifetime,
+            max_allocations: value.max_allocations,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Bandwidth {
+    /// The bytes per second that each session can relay to its peers.
+    pub session: Option<u64>,
+    /// The bytes per second that all sessions together can relay to their
+    /// peers.
+    pub global: Option<u64>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Acl {
+    /// The peers that the sessions may relay to even if they are in the
+    /// denied networks.
+    #[serde(default)]
+    pub allowed_peers: Vec<IpNet>,
+    /// The peers that the sessions of a username may relay to even if they
+    /// are in the denied networks, in addition to `allowed_peers`.
+    ///
+    /// Example: user1 = ["10.1.0.0/16"]
+    #[serde(default)]
+    pub user_peers: HashMap<String, Vec<IpNet>>,
+    /// The peers that the sessions must not relay to, the private, loopback
+    /// and link-local networks by default.
+    #[serde(default = "Acl::denied_peers")]
+    pub denied_peers: Vec<IpNet>,
+    /// The source addresses that clients may authenticate from, every
+    /// address if empty.
+    #[serde(default)]
+    pub allowed_clients: Vec<IpNet>,
+    /// The source addresses that the clients of a username may authenticate
+    /// from, in addition to `allowed_clients`.
+    ///
+    /// Example: user1 = ["10.0.0.0/8"]
+    #[serde(default)]
+    pub users: HashMap<String, Vec<IpNet>>,
+}
+
+impl Acl {
+    fn denied_peers() -> Vec<IpNet> {
+        [
+            "10.0.0.0/8",
+            "172.16.0.0/12",
+            "192.168.0.0/16",
+            "127.0.0.0/8",
+            "169.254.0.0/16",
+            "::1/128",
+            "fc00::/7",
+            "fe80::/10",
+        ]
+        .iter()
+        .map(|it| it.parse().unwrap())
+        .collect()
+    }
+
+    /// Whether the sessions of the username may relay to the peer, the
+    /// allowed peers take precedence over the denied peers.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use turn_server::config::Acl;
+    ///
+    /// let mut acl = Acl::default();
+    /// assert!(!acl.is_peer_allowed("user1", &"192.168.1.1".parse().unwrap()));
+    /// assert!(!acl.is_peer_allowed("user1", &"::1".parse().unwrap()));
+    /// assert!(acl.is_peer_allowed("user1", &"203.0.113.1".parse().unwrap()));
+    ///
+    /// acl.allowed_peers.push("192.168.1.0/24".parse().unwrap());
+    /// assert!(acl.is_peer_allowed("user1", &"192.168.1.1".parse().unwrap()));
+    /// assert!(!acl.is_peer_allowed("user1", &"192.168.2.1".parse().unwrap()));
+    ///
+    /// acl.user_peers.insert("user1".to_string(), vec!["192.168.2.0/24".parse().unwrap()]);
+    /// assert!(acl.is_peer_allowed("user1", &"192.168.2.1".parse().unwrap()));
+    /// assert!(!acl.is_peer_allowed("user2", &"192.168.2.1".parse().unwrap()));
+    /// ```
+    pub fn is_peer_allowed(&self, username: &str, peer: &IpAddr) -> bool {
+        self.allowed_peers
+            .iter()
+            .chain(self.user_peers.get(username).into_iter().flatten())
+            .any(|it| it.contains(peer))
+            || !self.denied_peers.iter().any(|it| it.contains(peer))
+    }
+
+    /// Whether a client of the username may authenticate from the source
+    /// address. Without `allowed_clients` and without networks of the
+    /// username every source is allowed.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use turn_server::config::Acl;
+    ///
+    /// let mut acl = Acl::default();
+    /// assert!(acl.is_client_allowed("user1", &"203.0.113.1".parse().unwrap()));
+    ///
+    /// acl.users.insert("user1".to_string(), vec!["10.0.0.0/8".parse().unwrap()]);
+    /// assert!(acl.is_client_allowed("user1", &"10.0.0.1".parse().unwrap()));
+    /// assert!(!acl.is_client_allowed("user1", &"203.0.113.1".parse().unwrap()));
+    /// assert!(acl.is_client_allowed("user2", &"203.0.113.1".parse().unwrap()));
+    ///
+    /// acl.allowed_clients.push("203.0.113.0/24".parse().unwrap());
+    /// assert!(acl.is_client_allowed("user1", &"203.0.113.1".parse().unwrap()));
+    /// assert!(!acl.is_client_allowed("user2", &"198.51.100.1".parse().unwrap()));
+    /// ```
+    pub fn is_client_allowed(&self, username: &str, ip: &IpAddr) -> bool {
+        let user = self.users.get(username);
+        if self.allowed_clients.is_empty() && user.is_none() {
+            return true;
+        }
+
+        self.allowed_clients
+            .iter()
+            .chain(user.into_iter().flatten())
+            .any(|it| it.contains(ip))
+    }
+}
+
+impl Default for Acl {
+    fn default() -> Self {
+        Self {
+            allowed_peers: Vec::new(),
+            user_peers: HashMap::new(),
+            denied_peers: Self::denied_peers(),
+            allowed_clients: Vec::new(),
+            users: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct RequestRate {
+    /// The unauthenticated requests per second that each source ip address
+    /// can send.
+    pub rate: u32,
+    /// The unauthenticated requests that a source ip address can send at
+    /// once, defaults to the rate.
+    pub burst: Option<u32>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Mdns {
+    /// The instance name of the services, the relay addresses are published
+    /// under the host name `<name>.local`.
+    #[serde(default = "Mdns::name")]
+    pub name: String,
+}
+
+impl Mdns {
+    fn name() -> String {
+        "turn-rs".to_string()
     }
 }
 
@@ -79,12 +545,269 @@ pub struct Turn {
     /// ipv4 and ipv6.
     #[serde(default = "Turn::interfaces")]
     pub interfaces: Vec<Interface>,
+
+    /// automatic interfaces
+    ///
+    /// When `interfaces = "auto"` is set, a udp interface is created for every
+    /// non-loopback address of the host, these options filter the addresses
+    /// and specify the port.
+    #[serde(default)]
+    pub auto_interfaces: AutoInterfaces,
+
+    /// strict reverse path verification
+    ///
+    /// When enabled, data arriving on a relay port is only forwarded to the
+    /// client if the client has a permission for the source and has itself
+    /// sent data to the source port before. Dropped packets are counted in
+    /// the `rejected_pkts` statistics of the session. The data of the end
+    /// that sends first is dropped until the other end has sent back.
+    #[serde(default)]
+    pub strict_reverse_path: bool,
+
+    /// credential lockout
+    ///
+    /// Usernames and source addresses that repeatedly fail authentication
+    /// are locked out for an increasing amount of time, during which their
+    /// requests are rejected without checking the credentials. Disabled if
+    /// not set.
+    #[serde(default)]
+    pub lockout: Option<Lockout>,
+
+    /// relay addresses
+    ///
+    /// Selects the ip address that allocations advertise as their relayed
+    /// transport address, for hosts that have multiple external ip addresses
+    /// on one interface. Rules are matched in order and the first match wins,
+    /// allocations that match no rule use the external address of the
+    /// interface.
+    #[serde(default)]
+    pub relay_addresses: Vec<RelayAddress>,
+
+    /// external host name resolve interval
+    ///
+    /// The interval in seconds at which the external addresses that are
+    /// configured as host names are resolved again, `0` only resolves them
+    /// at startup.
+    #[serde(default = "Turn::resolve_interval")]
+    pub resolve_interval: u64,
+
+    /// relay port allocator seed
+    ///
+    /// Seeds the random number generator that picks the relay ports, so that
+    /// the same sequence of allocations gets the same ports on every run.
+    /// Meant for tests and staging environments, the relay ports are
+    /// predictable when it is set.
+    #[serde(default)]
+    pub port_seed: Option<u64>,
+
+    /// relay port ranges
+    ///
+    /// Partitions the relay ports between the interfaces or the address
+    /// families, an allocation takes its port from the first range that
+    /// matches its interface and the family of its relayed address. The
+    /// allocations that match no range take their ports from all relay
+    /// ports, a last range without interface and family keeps them out of
+    /// the other ranges. The ranges have to be within 49152-65534.
+    #[serde(default)]
+    pub port_ranges: Vec<PortRange>,
+
+    /// state replay
+    ///
+    /// The path of a state dump taken from the `/state` endpoint of the api,
+    /// which is loaded at startup to replay the requests of a bug report
+    /// against the same sessions, ports, permissions, channels and timers.
+    /// Meant for diagnosis only.
+    #[serde(default)]
+    pub replay: Option<PathBuf>,
+
+    /// maximum permissions per allocation
+    ///
+    /// The maximum number of peers that one allocation can have permissions
+    /// for, CreatePermission and ChannelBind requests beyond it are rejected
+    /// with a 508 (Insufficient Capacity) error. Unlimited if not set.
+    #[serde(default)]
+    pub max_permissions: Option<usize>,
+
+    /// maximum channel bindings per allocation
+    ///
+    /// The maximum number of channels that one allocation can bind,
+    /// ChannelBind requests beyond it are rejected with a 508 (Insufficient
+    /// Capacity) error. Unlimited if not set.
+    #[serde(default)]
+    pub max_channels: Option<usize>,
+
+    /// permission lifetime
+    ///
+    /// The lifetime in seconds of a permission, a permission that is not
+    /// refreshed by a CreatePermission or ChannelBind request in time is
+    /// deleted. The permissions last as long as the allocation if not set.
+    #[serde(default)]
+    pub permission_lifetime: Option<u32>,
+
+    /// channel lifetime
+    ///
+    /// The lifetime in seconds of a channel binding, a channel that is not
+    /// bound again in time is deleted. The channels last as long as the
+    /// allocation if not set.
+    #[serde(default)]
+    pub channel_lifetime: Option<u32>,
+
+    /// allocation expiry warning
+    ///
+    /// The number of seconds before an allocation expires at which an
+    /// `expiring` event is sent once for a client that has not refreshed it,
+    /// has to be shorter than the default lifetime. Disabled if not set.
+    #[serde(default)]
+    pub expiry_warning: Option<u64>,
+
+    /// idle allocation timeout
+    ///
+    /// The number of seconds after which an allocation that has relayed no
+    /// ChannelData or Send indications is deleted, even if it keeps being
+    /// refreshed. Disabled if not set.
+    #[serde(default)]
+    pub max_idle: Option<u64>,
+
+    /// deleted allocation linger
+    ///
+    /// The number of milliseconds that an allocation deleted with a Refresh
+    /// of lifetime zero keeps forwarding the data of its peers to the
+    /// client, so that data already in flight is not lost. The requests of
+    /// the client are rejected in the meantime. Deleted right away if not
+    /// set.
+    #[serde(default)]
+    pub linger: Option<u64>,
+
+    /// binding response cache
+    ///
+    /// The number of source addresses per interface whose last Binding
+    /// response is kept, a retransmitted Binding request is answered with
+    /// the kept response. Disabled if not set.
+    #[serde(default)]
+    pub binding_cache: Option<usize>,
+
+    /// fingerprint
+    ///
+    /// When enabled, requests without a valid FINGERPRINT attribute are
+    /// discarded, and every response carries FINGERPRINT, including the
+    /// responses that are not signed with MESSAGE-INTEGRITY.
+    #[serde(default)]
+    pub fingerprint: bool,
+
+    /// mobility
+    ///
+    /// When enabled, the clients that ask for a MOBILITY-TICKET get one, and
+    /// re-claim their allocation with it from another address. Otherwise the
+    /// requests with a ticket are rejected with a 405 (Mobility Forbidden)
+    /// error.
+    #[serde(default)]
+    pub mobility: bool,
+
+    /// binding on relayed addresses
+    ///
+    /// When enabled, the Binding requests without credentials that the
+    /// sessions send to the relayed addresses of their peers are answered by
+    /// the server instead of being relayed. The connectivity checks of ICE
+    /// are always relayed.
+    #[serde(default)]
+    pub relay_binding: bool,
+
+    /// default allocation lifetime
+    ///
+    /// The lifetime in seconds that is granted to an allocation whose
+    /// Allocate or Refresh request does not ask for one, shorter lifetimes
+    /// requested by the clients are raised to it. Defaults to 600 if not set.
+    #[serde(default)]
+    pub default_lifetime: Option<u32>,
+
+    /// max allocation lifetime
+    ///
+    /// The longest lifetime in seconds that is granted to an allocation,
+    /// longer lifetimes requested with Allocate and Refresh requests are
+    /// clamped to it. Defaults to 3600 if not set.
+    #[serde(default)]
+    pub max_lifetime: Option<u32>,
+
+    /// nonce lifetime
+    ///
+    /// The lifetime in seconds of the nonces that the clients are challenged
+    /// with, the nonce of an allocation lasts as long as the allocation.
+    /// Defaults to 600 if not set.
+    #[serde(default)]
+    pub nonce_lifetime: Option<u32>,
+
+    /// allocation quota
+    ///
+    /// The maximum number of allocations that a username holds at the same
+    /// time in a realm, further Allocate requests are rejected with a 486
+    /// (Allocation Quota Reached) error. Unlimited if not set.
+    #[serde(default)]
+    pub max_allocations: Option<usize>,
+
+    /// realm settings
+    ///
+    /// The settings of the realms of the turn server and its interfaces, in
+    /// place of the settings of the turn server, see [`Realm`].
+    #[serde(default)]
+    pub realms: HashMap<String, Realm>,
+
+    /// overload shedding
+    ///
+    /// New allocations are rejected with a 508 (Insufficient Capacity) error
+    /// while the server is overloaded, refreshes, permissions, channels and
+    /// relayed data of the existing allocations are still served. Disabled if
+    /// not set.
+    #[serde(default)]
+    pub shedding: Option<Shedding>,
+
+    /// bandwidth limiting
+    ///
+    /// The data that the sessions relay to their peers in ChannelData
+    /// messages and Send indications is dropped while it exceeds the rates.
+    /// Not limited if not set.
+    #[serde(default)]
+    pub bandwidth: Option<Bandwidth>,
+
+    /// unauthenticated request limiting
+    ///
+    /// The Binding requests and the Allocate requests without
+    /// MESSAGE-INTEGRITY that a source ip address sends over UDP are
+    /// dropped while they exceed the rate, so that the server cannot be used
+    /// to reflect traffic at spoofed addresses. Not limited if not set.
+    #[serde(default)]
+    pub request_rate: Option<RequestRate>,
+
+    /// access control lists
+    ///
+    /// The peers that the sessions may relay to, and the source addresses
+    /// that the clients may authenticate from. Relaying to the private and
+    /// loopback networks is denied by default.
+    #[serde(default)]
+    pub acl: Acl,
+
+    /// mDNS advertisement
+    ///
+    /// Publish the interfaces as `_turn._udp` and `_turn._tcp` services with
+    /// mDNS/DNS-SD, for LAN-only deployments, requires the `mdns` feature.
+    /// Disabled if not set.
+    #[serde(default)]
+    pub mdns: Option<Mdns>,
 }
 
 impl Turn {
     pub fn get_externals(&self) -> Vec<SocketAddr> {
         self.interfaces.iter().map(|item| item.external).collect()
     }
+
+    /// The realm of the clients of an interface, the realm of the interface
+    /// if it has one and the realm of the turn server otherwise.
+    pub fn get_realm(&self, interface: &SocketAddr) -> &str {
+        self.interfaces
+            .iter()
+            .find(|it| it.external == *interface)
+            .and_then(|it| it.realm.as_deref())
+            .unwrap_or(&self.realm)
+    }
 }
 
 impl Turn {
@@ -95,6 +818,10 @@ impl Turn {
     fn interfaces() -> Vec<Interface> {
         vec![]
     }
+
+    fn resolve_interval() -> u64 {
+        60
+    }
 }
 
 impl Default for Turn {
@@ -102,6 +829,35 @@ impl Default for Turn {
         Self {
             realm: Self::realm(),
             interfaces: Self::interfaces(),
+            auto_interfaces: AutoInterfaces::default(),
+            strict_reverse_path: false,
+            lockout: None,
+            relay_addresses: Vec::new(),
+            resolve_interval: Self::resolve_interval(),
+            port_seed: None,
+            port_ranges: Vec::new(),
+            replay: None,
+            max_permissions: None,
+            max_channels: None,
+            permission_lifetime: None,
+            channel_lifetime: None,
+            expiry_warning: None,
+            max_idle: None,
+            linger: None,
+            binding_cache: None,
+            fingerprint: false,
+            mobility: false,
+            relay_binding: false,
+            default_lifetime: None,
+            max_lifetime: None,
+            nonce_lifetime: None,
+            max_allocations: None,
+            realms: HashMap::new(),
+            shedding: None,
+            bandwidth: None,
+            request_rate: None,
+            acl: Acl::default(),
+            mdns: None,
         }
     }
 }
@@ -128,28 +884,188 @@ pub struct Api {
     /// through this service, please do not expose it directly to an unsafe
     /// environment.
     pub hooks: Option<String>,
+    /// hooks routes
+    ///
+    /// Routes authentication and events to different hooks services based on
+    /// the realm, the username prefix or the interface of the session. Routes
+    /// are matched in order and the first match wins, sessions that do not
+    /// match any route fall back to `hooks`. Each route lists its servers in
+    /// failover order, the next server is only tried when the previous one is
+    /// unreachable or returns a server error.
+    #[serde(default)]
+    pub hooks_routes: Vec<HooksRoute>,
+    /// hooks retry queue
+    ///
+    /// The number of events per route that are kept to be pushed again while
+    /// no hooks server of the route takes them, the oldest events are dropped
+    /// when it is full. Events are not retried if it is zero.
+    #[serde(default = "Api::hooks_retry_queue")]
+    pub hooks_retry_queue: usize,
+    /// hooks password cache
+    ///
+    /// Caches the passwords that the hooks service returns, so that repeated
+    /// authentication of the same username does not request the hooks
+    /// service every time. The cache is kept in memory, and optionally in
+    /// redis so that it is shared by several servers, which requires the
+    /// `redis` feature. Not cached if not set.
+    #[serde(default)]
+    pub password_cache: Option<PasswordCache>,
+    /// session statistics
+    ///
+    /// Whether the traffic statistics of the sessions are collected. The
+    /// per-packet accounting also requires the `statistics` feature, without
+    /// it the statistics of the sessions stay at zero.
+    #[serde(default = "Api::statistics")]
+    pub statistics: bool,
+    /// traffic events interval
+    ///
+    /// The number of seconds between the `traffic` events, which carry the
+    /// bytes and packets that each session relayed since its previous one.
+    /// Requires the statistics of the sessions. Not sent if not set.
+    #[serde(default)]
+    pub traffic_interval: Option<u64>,
 }
 
 impl Api {
     fn bind() -> SocketAddr {
         "127.0.0.1:3000".parse().unwrap()
     }
+
+    fn statistics() -> bool {
+        true
+    }
+
+    fn hooks_retry_queue() -> usize {
+        1024
+    }
 }
 
 impl Default for Api {
     fn default() -> Self {
         Self {
             hooks: None,
+            hooks_routes: Vec::new(),
+            hooks_retry_queue: Self::hooks_retry_queue(),
+            password_cache: None,
             bind: Self::bind(),
+            statistics: Self::statistics(),
+            traffic_interval: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct PasswordCache {
+    /// The seconds that a password is cached for.
+    #[serde(default = "PasswordCache::ttl")]
+    pub ttl: u64,
+    /// The number of usernames whose passwords are cached in memory, the
+    /// least recently used ones are dropped when it is full.
+    #[serde(default = "PasswordCache::capacity")]
+    pub capacity: usize,
+    /// redis server url
+    ///
+    /// Example: redis://127.0.0.1:6379
+    pub redis: Option<String>,
+    /// The prefix of the redis keys that the passwords are stored at.
+    #[serde(default = "PasswordCache::prefix")]
+    pub prefix: String,
+}
+
+impl PasswordCache {
+    fn ttl() -> u64 {
+        60
+    }
+
+    fn capacity() -> usize {
+        10000
+    }
+
+    fn prefix() -> String {
+        "turn:password:".to_string()
+    }
+}
+
+impl Default for PasswordCache {
+    fn default() -> Self {
+        Self {
+            ttl: Self::ttl(),
+            capacity: Self::capacity(),
+            redis: None,
+            prefix: Self::prefix(),
+        }
+    }
+}
+
+/// Conditions on a session, shared by the rules that apply to a subset of
+/// sessions.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct SessionMatch {
+    /// Only match sessions in this realm.
+    pub realm: Option<String>,
+    /// Only match sessions whose username starts with this prefix.
+    pub username_prefix: Option<String>,
+    /// Only match sessions on this interface.
+    pub interface: Option<SocketAddr>,
+}
+
+impl SessionMatch {
+    /// Whether the session matches all the conditions, a condition that is
+    /// not set always matches.
+    pub fn matches(&self, realm: &str, interface: &SocketAddr, username: &str) -> bool {
+        self.realm.as_deref().map(|it| it == realm).unwrap_or(true)
+            && self
+                .username_prefix
+                .as_deref()
+                .map(|it| username.starts_with(it))
+                .unwrap_or(true)
+            && self.interface.as_ref().map(|it| it == interface).unwrap_or(true)
+    }
+
+    /// Prepare the realm and the username prefix in the form that the server
+    /// compares them in.
+    fn prepare(&mut self) -> anyhow::Result<()> {
+        if let Some(realm) = &mut self.realm {
+            *realm = prepare_realm(realm)
+                .map_err(|_| anyhow!("invalid realm: {}", realm))?
+                .into_owned();
+        }
+
+        if let Some(prefix) = self.username_prefix.as_mut().filter(|it| !it.is_empty()) {
+            *prefix = opaque_string(prefix)
+                .map_err(|_| anyhow!("invalid username prefix: {}", prefix))?
+                .into_owned();
         }
+
+        Ok(())
     }
 }
 
+#[derive(Deserialize, Debug, Clone)]
+pub struct HooksRoute {
+    /// The sessions that are routed to the hooks servers.
+    #[serde(flatten)]
+    pub matches: SessionMatch,
+    /// hooks server urls, in failover order.
+    pub servers: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct RelayAddress {
+    /// The sessions that use the relay address.
+    #[serde(flatten)]
+    pub matches: SessionMatch,
+    /// The ip address advertised as the relayed transport address.
+    pub address: IpAddr,
+}
+
 #[derive(Deserialize, Debug, Clone, Copy)]
 #[serde(rename_all = "lowercase")]
+#[derive(Default)]
 pub enum LogLevel {
     Error,
     Warn,
+    #[default]
     Info,
     Debug,
     Trace,
@@ -170,12 +1086,6 @@ impl FromStr for LogLevel {
     }
 }
 
-impl Default for LogLevel {
-    fn default() -> Self {
-        Self::Info
-    }
-}
-
 impl LogLevel {
     pub fn as_level(&self) -> log::Level {
         match *self {
@@ -188,6 +1098,27 @@ impl LogLevel {
     }
 }
 
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+#[derive(Default)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Ok(match value {
+            "text" => Self::Text,
+            "json" => Self::Json,
+            _ => return Err(format!("unknown log format: {value}")),
+        })
+    }
+}
+
 #[derive(Deserialize, Debug, Default)]
 pub struct Log {
     /// log level
@@ -195,6 +1126,20 @@ pub struct Log {
     /// An enum representing the available verbosity levels of the logger.
     #[serde(default)]
     pub level: LogLevel,
+    /// hot-path log sampling
+    ///
+    /// Log one in every `sample` hot-path events, such as forwarded packets
+    /// and packets dropped by permission checks, at the debug level. `0`
+    /// disables them, which is the default.
+    #[serde(default)]
+    pub sample: u32,
+    /// log format
+    ///
+    /// `text` writes the records as plain lines, `json` writes every record
+    /// as a json object. The session whose request is being processed is
+    /// attached to the records in both formats.
+    #[serde(default)]
+    pub format: LogFormat,
 }
 
 #[derive(Deserialize, Debug, Default)]
@@ -214,9 +1159,247 @@ pub struct Auth {
     /// If set, the turn server will not request external services via the HTTP
     /// Hooks API to obtain the key.
     pub static_auth_secret: Option<String>,
+    /// credential store
+    ///
+    /// The path of an embedded key-value store that persists the static
+    /// credentials managed at runtime through the api or the `credentials`
+    /// command, requires the `store` feature.
+    pub store: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct NatsSink {
+    /// nats server url
+    ///
+    /// Example: nats://127.0.0.1:4222
+    pub url: String,
+    /// The subject that the events are published to.
+    #[serde(default = "NatsSink::subject")]
+    pub subject: String,
+}
+
+impl NatsSink {
+    fn subject() -> String {
+        "turn.events".to_string()
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct KafkaSink {
+    /// kafka bootstrap brokers
+    ///
+    /// Example: ["127.0.0.1:9092"]
+    pub brokers: Vec<String>,
+    /// The topic that the events are produced to.
+    #[serde(default = "KafkaSink::topic")]
+    pub topic: String,
+    /// The partition of the topic that the events are produced to.
+    #[serde(default)]
+    pub partition: i32,
+}
+
+impl KafkaSink {
+    fn topic() -> String {
+        "turn-events".to_string()
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Sinks {
+    /// Publish session events to nats, requires the `nats` feature.
+    pub nats: Option<NatsSink>,
+    /// Produce session events to kafka, requires the `kafka` feature.
+    pub kafka: Option<KafkaSink>,
+    /// The number of events per sink that wait to be published, the events
+    /// are dropped for a sink while its queue is full.
+    #[serde(default = "Sinks::queue")]
+    pub queue: usize,
+}
+
+impl Sinks {
+    fn queue() -> usize {
+        1024
+    }
+}
+
+impl Default for Sinks {
+    fn default() -> Self {
+        Self {
+            nats: None,
+            kafka: None,
+            queue: Self::queue(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct GeoIp {
+    /// The path of a MaxMind country database, such as GeoLite2-Country or
+    /// GeoLite2-City, requires the `geoip` feature.
+    pub country: Option<PathBuf>,
+    /// The path of a MaxMind ASN database, such as GeoLite2-ASN, requires the
+    /// `geoip` feature.
+    pub asn: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Mirror {
+    /// The multicast group that the relayed data of the mirrored sessions is
+    /// sent to, mirroring is disabled without it.
+    ///
+    /// Example: 239.255.0.1:5004
+    pub group: Option<SocketAddr>,
+    /// The local address that the mirrored packets are sent from, which
+    /// selects the network interface of the multicast group.
+    #[serde(default = "Mirror::bind")]
+    pub bind: IpAddr,
+    /// The time to live of the mirrored packets, the default of 1 keeps them
+    /// in the local network.
+    #[serde(default = "Mirror::ttl")]
+    pub ttl: u32,
+    /// The token that the api requests to mirror a session have to present as
+    /// `Authorization: Bearer <token>`, the sessions cannot be mirrored
+    /// through the api without it.
+    pub token: Option<String>,
+}
+
+impl Mirror {
+    fn bind() -> IpAddr {
+        IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+    }
+
+    fn ttl() -> u32 {
+        1
+    }
+}
+
+impl Default for Mirror {
+    fn default() -> Self {
+        Self {
+            group: None,
+            bind: Self::bind(),
+            ttl: Self::ttl(),
+            token: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Ipfix {
+    /// The IPFIX collector that the flow records are exported to, the export
+    /// is disabled without it, requires the `ipfix` feature.
+    ///
+    /// Example: 127.0.0.1:4739
+    pub collector: Option<SocketAddr>,
+    /// The local address that the flow records are sent from.
+    #[serde(default = "Ipfix::bind")]
+    pub bind: IpAddr,
+    /// The seconds between two exports, every export carries the bytes and
+    /// packets that the flows relayed since the previous one.
+    #[serde(default = "Ipfix::interval")]
+    pub interval: u64,
+    /// The observation domain id in the header of the exported messages, to
+    /// tell multiple turn servers apart at the collector.
+    #[serde(default)]
+    pub observation_domain: u32,
+}
+
+impl Ipfix {
+    fn bind() -> IpAddr {
+        IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+    }
+
+    fn interval() -> u64 {
+        60
+    }
+}
+
+impl Default for Ipfix {
+    fn default() -> Self {
+        Self {
+            collector: None,
+            bind: Self::bind(),
+            interval: Self::interval(),
+            observation_domain: 0,
+        }
+    }
 }
 
 #[derive(Deserialize, Debug)]
+pub struct Profiling {
+    /// The token that the api requests for a cpu profile have to present as
+    /// `Authorization: Bearer <token>`, the server cannot be profiled through
+    /// the api without it. Requires the `pprof` feature.
+    pub token: Option<String>,
+    /// The longest profile in seconds that can be requested.
+    #[serde(default = "Profiling::max_duration")]
+    pub max_duration: u64,
+    /// The seconds that have to pass after a profile ended before the next
+    /// one can be started.
+    #[serde(default = "Profiling::cooldown")]
+    pub cooldown: u64,
+    /// The number of samples per second taken while profiling.
+    #[serde(default = "Profiling::frequency")]
+    pub frequency: i32,
+}
+
+impl Profiling {
+    fn max_duration() -> u64 {
+        30
+    }
+
+    fn cooldown() -> u64 {
+        60
+    }
+
+    fn frequency() -> i32 {
+        99
+    }
+}
+
+impl Default for Profiling {
+    fn default() -> Self {
+        Self {
+            token: None,
+            max_duration: Self::max_duration(),
+            cooldown: Self::cooldown(),
+            frequency: Self::frequency(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct Cascade {
+    /// The upstream turn server that the sessions allocate on to reach the
+    /// external peers, cascading is disabled without it.
+    ///
+    /// Example: 203.0.113.1:3478
+    pub upstream: Option<SocketAddr>,
+    /// The long-term credentials that the allocations on the upstream turn
+    /// server are authenticated with.
+    #[serde(default)]
+    pub username: String,
+    #[serde(default)]
+    pub password: String,
+    /// The local address that the allocations on the upstream turn server
+    /// are made from, the unspecified address of the family of the upstream
+    /// if not set.
+    pub bind: Option<IpAddr>,
+    /// The peers that are reached through the upstream turn server, only the
+    /// relayed addresses of the upstream itself if empty.
+    #[serde(default)]
+    pub peers: Vec<IpNet>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct Audit {
+    /// The file that the requests changing the state of the server through
+    /// the api are appended to, one json record per line. The records are
+    /// written to the log if not set.
+    pub path: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Debug, Default)]
 pub struct Config {
     #[serde(default)]
     pub turn: Turn,
@@ -226,16 +1409,65 @@ pub struct Config {
     pub log: Log,
     #[serde(default)]
     pub auth: Auth,
+    #[serde(default)]
+    pub sinks: Sinks,
+    #[serde(default)]
+    pub geoip: GeoIp,
+    #[serde(default)]
+    pub mirror: Mirror,
+    #[serde(default)]
+    pub ipfix: Ipfix,
+    #[serde(default)]
+    pub profiling: Profiling,
+    #[serde(default)]
+    pub audit: Audit,
+    #[serde(default)]
+    pub cascade: Cascade,
+}
+
+#[cfg(feature = "cli")]
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Manage the credentials in the credential store, requires the `store`
+    /// feature and `auth.store`
+    ///
+    /// The store can only be opened by one process, use the api while the
+    /// server is running.
+    #[command(subcommand)]
+    Credentials(CredentialsCommand),
 }
 
+#[cfg(feature = "cli")]
+#[derive(Subcommand, Debug)]
+pub enum CredentialsCommand {
+    /// List all usernames
+    List,
+    /// Add or replace a credential
+    Add {
+        username: String,
+        password: String,
+        /// The time to live of the credential in seconds, it does not expire
+        /// if not set
+        #[arg(long)]
+        ttl: Option<u64>,
+    },
+    /// Remove a credential
+    Remove { username: String },
+}
+
+#[cfg(feature = "cli")]
 #[derive(Parser, Debug)]
 #[command(
     about = env!("CARGO_PKG_DESCRIPTION"),
     version = env!("CARGO_PKG_VERSION"),
     author = env!("CARGO_PKG_AUTHORS"),
 )]
-struct Cli {
-    /// Specify the configuration file path
+pub struct Cli {
+    /// Run a command instead of starting the server
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    /// Specify the configuration file path, `-` reads the configuration from
+    /// stdin
     ///
     /// Example: --config /etc/turn-rs/config.toml
     #[arg(long, short)]
@@ -249,12 +1481,27 @@ struct Cli {
     /// REST API
     #[arg(long)]
     auth_static_auth_secret: Option<String>,
+    /// The path of the credential store
+    ///
+    /// Example: --auth-store /var/lib/turn-rs/credentials
+    #[arg(long)]
+    auth_store: Option<PathBuf>,
     /// An enum representing the available verbosity levels of the logger
     #[arg(
         long,
         value_parser = clap::value_parser!(LogLevel),
     )]
     log_level: Option<LogLevel>,
+    /// Log one in every N hot-path events at the debug level, 0 disables
+    /// them
+    #[arg(long)]
+    log_sample: Option<u32>,
+    /// The format of the log records, `text` or `json`
+    #[arg(
+        long,
+        value_parser = clap::value_parser!(LogFormat),
+    )]
+    log_format: Option<LogFormat>,
     /// This option specifies the http server binding address used to control
     /// the turn server
     #[arg(long)]
@@ -272,8 +1519,17 @@ struct Cli {
     /// Example: --turn-interfaces udp@127.0.0.1:3478/127.0.0.1:3478
     #[arg(long)]
     turn_interfaces: Option<Vec<Interface>>,
+    /// Seed of the relay port allocator, for reproducible port assignments
+    #[arg(long, env = "TURN_PORT_SEED")]
+    turn_port_seed: Option<u64>,
+    /// Load a state dump at startup, for diagnosis
+    ///
+    /// Example: --turn-replay state.json
+    #[arg(long)]
+    turn_replay: Option<PathBuf>,
 }
 
+#[cfg(feature = "cli")]
 impl Cli {
     // [username]:[password]
     fn parse_credential(s: &str) -> Result<(String, String), anyhow::Error> {
@@ -285,19 +1541,123 @@ impl Cli {
     }
 }
 
+/// Replace the `${NAME}` references in a configuration value with the
+/// environment variable `NAME`, `$${` is an escaped `${`.
+///
+/// It is an error to reference an environment variable that is not set, so
+/// that a missing secret does not silently become an empty string.
+///
+/// # Example
+///
+/// ```
+/// use turn_server::config::interpolate;
+///
+/// std::env::set_var("TURN_TEST_SECRET", "secret");
+///
+/// assert_eq!(interpolate("${TURN_TEST_SECRET}").unwrap(), "secret");
+/// assert_eq!(interpolate("a-${TURN_TEST_SECRET}-b").unwrap(), "a-secret-b");
+/// assert_eq!(interpolate("$${TURN_TEST_SECRET}").unwrap(), "${TURN_TEST_SECRET}");
+/// assert_eq!(interpolate("$5").unwrap(), "$5");
+/// assert!(interpolate("${TURN_TEST_NOT_SET}").is_err());
+/// assert!(interpolate("${TURN_TEST_SECRET").is_err());
+/// ```
+pub fn interpolate(value: &str) -> anyhow::Result<String> {
+    let mut output = String::with_capacity(value.len());
+    let mut rest = value;
+
+    while let Some(index) = rest.find('$') {
+        output.push_str(&rest[..index]);
+        rest = &rest[index..];
+
+        if let Some(it) = rest.strip_prefix("$${") {
+            output.push_str("${");
+            rest = it;
+        } else if let Some(it) = rest.strip_prefix("${") {
+            let (name, it) = it
+                .split_once('}')
+                .ok_or_else(|| anyhow!("unterminated environment variable reference: {}", value))?;
+
+            output.push_str(&std::env::var(name).map_err(|_| anyhow!("environment variable is not set: {}", name))?);
+
+            rest = it;
+        } else {
+            output.push('$');
+            rest = &rest[1..];
+        }
+    }
+
+    output.push_str(rest);
+    Ok(output)
+}
+
+#[cfg(feature = "cli")]
+fn interpolate_table(table: &mut toml::Table) -> anyhow::Result<()> {
+    for (_, value) in table.iter_mut() {
+        interpolate_value(value)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "cli")]
+fn interpolate_value(value: &mut toml::Value) -> anyhow::Result<()> {
+    match value {
+        toml::Value::String(it) => *it = interpolate(it)?,
+        toml::Value::Array(it) => {
+            for value in it {
+                interpolate_value(value)?;
+            }
+        }
+        toml::Value::Table(it) => interpolate_table(it)?,
+        _ => (),
+    }
+
+    Ok(())
+}
+
 impl Config {
     /// Load configure from config file and command line parameters.
     ///
     /// Load command line parameters, if the configuration file path is
     /// specified, the configuration is read from the configuration file,
     /// otherwise the default configuration is used.
+    #[cfg(feature = "cli")]
     pub fn load() -> anyhow::Result<Self> {
-        let cli = Cli::parse();
-        let mut config = toml::from_str::<Self>(
-            &cli.config
-                .and_then(|path| read_to_string(path).ok())
-                .unwrap_or("".to_string()),
-        )?;
+        Self::load_from(Cli::parse())
+    }
+
+    /// Load configure from config file and parsed command line parameters.
+    #[cfg(feature = "cli")]
+    pub fn load_from(cli: Cli) -> anyhow::Result<Self> {
+        let mut value = toml::from_str::<toml::Table>(&match cli.config.as_deref() {
+            Some("-") => {
+                let mut buf = String::new();
+                stdin().read_to_string(&mut buf)?;
+                buf
+            }
+            Some(path) => read_to_string(path).unwrap_or_default(),
+            None => String::new(),
+        })?;
+
+        // Secrets can be injected through environment variables instead of being
+        // written into the configuration file.
+        interpolate_table(&mut value)?;
+
+        // `interfaces = "auto"` is not a list of interfaces, take it out before
+        // deserializing and enumerate the interfaces of the host afterwards.
+        let auto_interfaces = value
+            .get_mut("turn")
+            .and_then(|it| it.as_table_mut())
+            .and_then(|turn| match turn.get("interfaces") {
+                Some(toml::Value::String(it)) if it == "auto" => turn.remove("interfaces"),
+                _ => None,
+            })
+            .is_some();
+
+        let mut config = Self::deserialize(value)?;
+        if auto_interfaces {
+            config.turn.interfaces = config.turn.auto_interfaces.enumerate()?;
+        }
 
         // Command line arguments have a high priority and override configuration file
         // options; here they are used to replace the configuration parsed out of the
@@ -313,10 +1673,22 @@ impl Config {
                 config.auth.static_auth_secret.replace(secret);
             }
 
+            if let Some(store) = cli.auth_store {
+                config.auth.store.replace(store);
+            }
+
             if let Some(level) = cli.log_level {
                 config.log.level = level;
             }
 
+            if let Some(sample) = cli.log_sample {
+                config.log.sample = sample;
+            }
+
+            if let Some(format) = cli.log_format {
+                config.log.format = format;
+            }
+
             if let Some(bind) = cli.api_bind {
                 config.api.bind = bind;
             }
@@ -329,6 +1701,14 @@ impl Config {
                 config.turn.realm = realm;
             }
 
+            if let Some(seed) = cli.turn_port_seed {
+                config.turn.port_seed.replace(seed);
+            }
+
+            if let Some(path) = cli.turn_replay {
+                config.turn.replay.replace(path);
+            }
+
             if let Some(interfaces) = cli.turn_interfaces {
                 for interface in interfaces {
                     config.turn.interfaces.push(interface);
@@ -336,12 +1716,127 @@ impl Config {
             }
         }
 
+        config.prepare()?;
+        Ok(config)
+    }
+
+    /// Prepare a configuration for the server.
+    ///
+    /// `load_from` calls it, a configuration that an embedding application
+    /// builds itself should be prepared as well before it is used.
+    pub fn prepare(&mut self) -> anyhow::Result<()> {
+        // The realm is sent to the clients as it is configured, and the usernames of
+        // the requests are prepared with OpaqueString before they are compared, so
+        // the realm and the username prefixes are prepared here once.
+        {
+            self.turn.realm = prepare_realm(&self.turn.realm)
+                .map_err(|_| anyhow!("invalid realm: {}", self.turn.realm))?
+                .into_owned();
+
+            for it in self.turn.interfaces.iter_mut().filter_map(|it| it.realm.as_mut()) {
+                *it = prepare_realm(it)
+                    .map_err(|_| anyhow!("invalid realm: {}", it))?
+                    .into_owned();
+            }
+
+            self.turn.realms = std::mem::take(&mut self.turn.realms)
+                .into_iter()
+                .map(|(k, v)| {
+                    prepare_realm(&k)
+                        .map(|it| (it.into_owned(), v))
+                        .map_err(|_| anyhow!("invalid realm: {}", k))
+                })
+                .collect::<anyhow::Result<_>>()?;
+
+            for it in &mut self.api.hooks_routes {
+                it.matches.prepare()?;
+            }
+
+            for it in &mut self.turn.relay_addresses {
+                it.matches.prepare()?;
+            }
+        }
+
+        // The relay port ranges are checked here, as the ports outside the relay
+        // ports would never be assigned.
+        for it in &self.turn.port_ranges {
+            let ports = PortAllocatePools::port_range();
+            if it.start > it.end || !ports.contains(&it.start) || !ports.contains(&it.end) {
+                return Err(anyhow!("invalid relay port range: {}-{}", it.start, it.end));
+            }
+        }
+
+        // The settings of the realms override the ones of the turn server, so they are
+        // checked here against the limits that the turn server applies.
+        {
+            let max_lifetime = self.turn.max_lifetime.unwrap_or(sessions::MAX_LIFETIME);
+            for lifetime in [self.turn.default_lifetime, self.turn.nonce_lifetime] {
+                if lifetime == Some(0) {
+                    return Err(anyhow!("invalid lifetime: 0"));
+                }
+            }
+
+            if self.turn.default_lifetime.unwrap_or(sessions::DEFAULT_LIFETIME) > max_lifetime {
+                return Err(anyhow!("default lifetime exceeds max lifetime: {}", max_lifetime));
+            }
+
+            if self.turn.max_allocations == Some(0) {
+                return Err(anyhow!("invalid max allocations: 0"));
+            }
+
+            for (realm, it) in &self.turn.realms {
+                if *realm != self.turn.realm && !self.turn.interfaces.iter().any(|it| it.realm.as_ref() == Some(realm))
+                {
+                    return Err(anyhow!("realm is not served by any interface: {}", realm));
+                }
+
+                if it.nonce_lifetime == Some(0) || it.default_lifetime == Some(0) {
+                    return Err(anyhow!("invalid lifetime of realm: {}", realm));
+                }
+
+                if it.default_lifetime.unwrap_or(0) > max_lifetime {
+                    return Err(anyhow!("default lifetime of realm exceeds max lifetime: {}", realm));
+                }
+
+                if it.max_allocations == Some(0) {
+                    return Err(anyhow!("invalid max allocations of realm: {}", realm));
+                }
+
+                // The length limit of the SOFTWARE attribute, see RFC 8489 section 14.14.
+                if let Some(software) = &it.software {
+                    if software.is_empty() || software.chars().count() >= 128 {
+                        return Err(anyhow!("invalid software of realm: {}", realm));
+                    }
+                }
+            }
+
+            // An allocation lasts at least the default lifetime, a warning that is not
+            // shorter than that would be sent as soon as the allocation is created.
+            if let Some(warning) = self.turn.expiry_warning {
+                let lifetime = self
+                    .turn
+                    .realms
+                    .values()
+                    .filter_map(|it| it.default_lifetime)
+                    .chain([self.turn.default_lifetime.unwrap_or(sessions::DEFAULT_LIFETIME)])
+                    .min()
+                    .unwrap_or(sessions::DEFAULT_LIFETIME);
+
+                if warning == 0 || warning >= lifetime as u64 {
+                    return Err(anyhow!(
+                        "expiry warning is not shorter than the default lifetime: {}",
+                        lifetime
+                    ));
+                }
+            }
+        }
+
         // Filters out transport protocols that are not enabled.
         {
-            let mut interfaces = Vec::with_capacity(config.turn.interfaces.len());
+            let mut interfaces = Vec::with_capacity(self.turn.interfaces.len());
 
             {
-                for it in &config.turn.interfaces {
+                for it in &self.turn.interfaces {
                     #[cfg(feature = "udp")]
                     if it.transport == Transport::UDP {
                         interfaces.push(it.clone());
@@ -351,12 +1846,17 @@ impl Config {
                     if it.transport == Transport::TCP {
                         interfaces.push(it.clone());
                     }
+
+                    #[cfg(feature = "tls")]
+                    if it.transport == Transport::TLS {
+                        interfaces.push(it.clone());
+                    }
                 }
             }
 
-            config.turn.interfaces = interfaces;
+            self.turn.interfaces = interfaces;
         }
 
-        Ok(config)
+        Ok(())
     }
 }
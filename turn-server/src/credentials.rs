@@ -0,0 +1,249 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::anyhow;
+use parking_lot::RwLock;
+use stun::auth::opaque_string;
+
+use crate::config::Auth;
+
+/// Static credentials.
+///
+/// Holds the static credentials of the configuration file and the command
+/// line, and the credentials that are managed at runtime through the api.
+/// With the `store` feature, and when `auth.store` is set, the credentials
+/// managed at runtime are persisted to an embedded key-value store and loaded
+/// again at startup.
+///
+/// Usernames are prepared with the OpaqueString profile, as the usernames of
+/// the requests are, so a username matches whichever Unicode form it was
+/// written in.
+///
+/// A credential managed at runtime can have a time to live, it expires at a
+/// unix time so that it also expires while the server is stopped. Expired
+/// credentials are not found, and are dropped from the store at startup.
+///
+/// # Example
+///
+/// ```
+/// use std::time::Duration;
+///
+/// use turn_server::{config::Auth, credentials::Credentials};
+///
+/// let credentials = Credentials::new(&Auth::default()).unwrap();
+///
+/// credentials.insert("test", "test", None).unwrap();
+/// assert_eq!(credentials.get("test"), Some("test".to_string()));
+/// assert_eq!(credentials.list(), vec!["test".to_string()]);
+///
+/// assert!(credentials.remove("test").unwrap());
+/// assert_eq!(credentials.get("test"), None);
+/// assert!(!credentials.remove("test").unwrap());
+///
+/// credentials.insert("bu\u{308}cher", "test", None).unwrap();
+/// assert_eq!(credentials.get("b\u{fc}cher"), Some("test".to_string()));
+///
+/// credentials.insert("expired", "test", Some(Duration::ZERO)).unwrap();
+/// assert_eq!(credentials.get("expired"), None);
+/// assert_eq!(credentials.list(), vec!["b\u{fc}cher".to_string()]);
+///
+/// credentials.insert("expired", "test", None).unwrap();
+/// assert_eq!(credentials.get("expired"), Some("test".to_string()));
+/// assert_eq!(credentials.expires("expired"), None);
+///
+/// credentials.insert("rotated", "test", Some(Duration::from_secs(60))).unwrap();
+/// assert!(credentials.expires("rotated").is_some());
+/// ```
+pub struct Credentials {
+    map: RwLock<HashMap<String, Credential>>,
+    #[cfg(feature = "store")]
+    store: Option<Store>,
+}
+
+struct Credential {
+    password: String,
+    /// The unix time in seconds at which the credential expires.
+    expires: Option<u64>,
+}
+
+impl Credential {
+    fn is_expired(&self, now: u64) -> bool {
+        self.expires.map(|it| it <= now).unwrap_or(false)
+    }
+}
+
+/// The passwords are stored by username, and the expiry times of the
+/// credentials that have one are stored in a separate tree, so that stores
+/// written before credentials could expire are read as they are.
+#[cfg(feature = "store")]
+struct Store {
+    db: sled::Db,
+    expires: sled::Tree,
+}
+
+impl Credentials {
+    pub fn new(config: &Auth) -> anyhow::Result<Self> {
+        #[allow(unused_mut)]
+        let mut map = config
+            .static_credentials
+            .iter()
+            .map(|(username, password)| {
+                Ok((
+                    prepare(username)?,
+                    Credential {
+                        password: password.clone(),
+                        expires: None,
+                    },
+                ))
+            })
+            .collect::<anyhow::Result<HashMap<_, _>>>()?;
+
+        // The stored credentials were added at runtime, after the configuration was
+        // written, so they take precedence.
+        #[cfg(feature = "store")]
+        let store = if let Some(path) = &config.store {
+            let db = sled::open(path)?;
+            let expires = db.open_tree("expires")?;
+
+            let now = now();
+            for item in db.iter() {
+                let (username, password) = item?;
+                let credential = Credential {
+                    password: String::from_utf8(password.to_vec())?,
+                    expires: expires
+                        .get(&username)?
+                        .and_then(|it| Some(u64::from_be_bytes(it.as_ref().try_into().ok()?))),
+                };
+
+                if credential.is_expired(now) {
+                    db.remove(&username)?;
+                    expires.remove(&username)?;
+                    continue;
+                }
+
+                map.insert(prepare(std::str::from_utf8(&username)?)?, credential);
+            }
+
+            db.flush()?;
+            Some(Store { db, expires })
+        } else {
+            None
+        };
+
+        Ok(Self {
+            map: RwLock::new(map),
+            #[cfg(feature = "store")]
+            store,
+        })
+    }
+
+    pub fn get(&self, username: &str) -> Option<String> {
+        self.map
+            .read()
+            .get(&*opaque_string(username).ok()?)
+            .filter(|it| !it.is_expired(now()))
+            .map(|it| it.password.clone())
+    }
+
+    /// Get the unix time in seconds at which the credential expires, `None` if
+    /// it does not exist or never expires.
+    pub fn expires(&self, username: &str) -> Option<u64> {
+        self.map
+            .read()
+            .get(&*opaque_string(username).ok()?)
+            .filter(|it| !it.is_expired(now()))
+            .and_then(|it| it.expires)
+    }
+
+    /// Get all usernames that have not expired, in alphabetical order.
+    pub fn list(&self) -> Vec<String> {
+        let now = now();
+        let mut usernames = self
+            .map
+            .read()
+            .iter()
+            .filter(|(_, it)| !it.is_expired(now))
+            .map(|(username, _)| username.clone())
+            .collect::<Vec<_>>();
+
+        usernames.sort();
+        usernames
+    }
+
+    /// Add or replace a credential, which expires after the time to live if
+    /// there is one.
+    ///
+    /// Replacing the password of a username rotates it, the expiry of the
+    /// previous credential does not carry over.
+    pub fn insert(&self, username: &str, password: &str, ttl: Option<Duration>) -> anyhow::Result<()> {
+        let username = prepare(username)?;
+        let expires = ttl.map(|it| now().saturating_add(it.as_secs()));
+
+        #[cfg(feature = "store")]
+        if let Some(store) = &self.store {
+            store.db.insert(username.as_str(), password)?;
+            if let Some(expires) = expires {
+                store.expires.insert(username.as_str(), &expires.to_be_bytes())?;
+            } else {
+                store.expires.remove(username.as_str())?;
+            }
+
+            store.db.flush()?;
+        }
+
+        // Expired credentials are dropped on the way, so that they do not pile up in
+        // memory while credentials are rotated with a time to live.
+        let mut map = self.map.write();
+        let now = now();
+        map.retain(|_, it| !it.is_expired(now));
+        map.insert(
+            username,
+            Credential {
+                password: password.to_string(),
+                expires,
+            },
+        );
+
+        Ok(())
+    }
+
+    /// Remove a credential, returns whether the credential existed.
+    ///
+    /// Credentials of the configuration file are only removed until the
+    /// server is restarted.
+    pub fn remove(&self, username: &str) -> anyhow::Result<bool> {
+        let username = match opaque_string(username) {
+            Ok(it) => it.into_owned(),
+            Err(_) => return Ok(false),
+        };
+
+        #[cfg(feature = "store")]
+        if let Some(store) = &self.store {
+            store.db.remove(username.as_str())?;
+            store.expires.remove(username.as_str())?;
+            store.db.flush()?;
+        }
+
+        Ok(self
+            .map
+            .write()
+            .remove(&username)
+            .filter(|it| !it.is_expired(now()))
+            .is_some())
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|it| it.as_secs())
+        .unwrap_or(0)
+}
+
+fn prepare(username: &str) -> anyhow::Result<String> {
+    opaque_string(username)
+        .map(|it| it.into_owned())
+        .map_err(|_| anyhow!("invalid username: {}", username))
+}
@@ -0,0 +1,114 @@
+use std::net::IpAddr;
+
+use serde::Serialize;
+
+use crate::config::GeoIp;
+
+/// The geographic location of a client address.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Location {
+    /// ISO 3166-1 alpha-2 code of the country, such as `DE`.
+    pub country: Option<String>,
+    /// The number of the autonomous system that the address belongs to.
+    pub asn: Option<u32>,
+    /// The organization that operates the autonomous system.
+    pub asn_org: Option<String>,
+}
+
+/// Looks up the location of the client addresses.
+///
+/// With the `geoip` feature, the country and the autonomous system of an
+/// address are looked up in MaxMind databases, such as GeoLite2-Country and
+/// GeoLite2-ASN. The databases are loaded into memory at startup, a lookup
+/// does not touch the disk. Without the feature, or if no database is
+/// configured, nothing is looked up.
+///
+/// # Example
+///
+/// ```
+/// use turn_server::{config::GeoIp, geoip::Geolocator};
+///
+/// let geolocator = Geolocator::new(&GeoIp::default()).unwrap();
+///
+/// assert!(!geolocator.is_enabled());
+/// assert_eq!(geolocator.lookup("1.1.1.1".parse().unwrap()), None);
+/// ```
+pub struct Geolocator {
+    #[cfg(feature = "geoip")]
+    country: Option<maxminddb::Reader<Vec<u8>>>,
+    #[cfg(feature = "geoip")]
+    asn: Option<maxminddb::Reader<Vec<u8>>>,
+}
+
+impl Geolocator {
+    #[allow(unused_variables)]
+    pub fn new(config: &GeoIp) -> anyhow::Result<Self> {
+        #[cfg(feature = "geoip")]
+        {
+            Ok(Self {
+                country: config
+                    .country
+                    .as_ref()
+                    .map(maxminddb::Reader::open_readfile)
+                    .transpose()?,
+                asn: config.asn.as_ref().map(maxminddb::Reader::open_readfile).transpose()?,
+            })
+        }
+
+        #[cfg(not(feature = "geoip"))]
+        {
+            if config.country.is_some() || config.asn.is_some() {
+                log::warn!("geoip databases are configured, but the geoip feature is not enabled");
+            }
+
+            Ok(Self {})
+        }
+    }
+
+    /// Whether any database is loaded.
+    pub fn is_enabled(&self) -> bool {
+        #[cfg(feature = "geoip")]
+        {
+            self.country.is_some() || self.asn.is_some()
+        }
+
+        #[cfg(not(feature = "geoip"))]
+        {
+            false
+        }
+    }
+
+    /// Get the location of the address, `None` if no database is loaded.
+    ///
+    /// An address that is not found in a database, such as a private
+    /// address, leaves the fields of that database unset.
+    #[allow(unused_variables)]
+    pub fn lookup(&self, ip: IpAddr) -> Option<Location> {
+        if !self.is_enabled() {
+            return None;
+        }
+
+        #[allow(unused_mut)]
+        let mut location = Location::default();
+
+        #[cfg(feature = "geoip")]
+        {
+            use maxminddb::geoip2;
+
+            if let Some(reader) = &self.country {
+                if let Ok(it) = reader.lookup::<geoip2::Country>(ip) {
+                    location.country = it.country.and_then(|it| it.iso_code).map(|it| it.to_string());
+                }
+            }
+
+            if let Some(reader) = &self.asn {
+                if let Ok(it) = reader.lookup::<geoip2::Asn>(ip) {
+                    location.asn = it.autonomous_system_number;
+                    location.asn_org = it.autonomous_system_organization.map(|it| it.to_string());
+                }
+            }
+        }
+
+        Some(location)
+    }
+}
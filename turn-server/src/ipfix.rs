@@ -0,0 +1,426 @@
+use std::net::{IpAddr, SocketAddr};
+
+use stun::Transport;
+use turn::{RelayPort, SessionAddr};
+
+#[cfg(feature = "ipfix")]
+use std::{
+    net::UdpSocket,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+#[cfg(feature = "ipfix")]
+use ahash::AHashMap;
+#[cfg(feature = "ipfix")]
+use parking_lot::{Mutex, RwLock};
+
+use crate::config;
+
+#[cfg(feature = "ipfix")]
+use crate::sampling::sampled;
+
+/// The id of the template of the flows between ipv4 addresses.
+pub const TEMPLATE_V4: u16 = 256;
+
+/// The id of the template of the flows that involve an ipv6 address, the
+/// ipv4 addresses of these flows are mapped into ipv6.
+pub const TEMPLATE_V6: u16 = 257;
+
+// The information elements of the templates, the ip addresses are replaced by
+// their ipv6 counterparts in the ipv6 template.
+//
+// sourceIPv4Address, sourceTransportPort, postNATSourceIPv4Address,
+// postNAPTSourceTransportPort, destinationIPv4Address,
+// destinationTransportPort, protocolIdentifier, octetDeltaCount,
+// packetDeltaCount, flowStartMilliseconds, flowEndMilliseconds
+const FIELDS_V4: [(u16, u16); 11] = [
+    (8, 4),
+    (7, 2),
+    (225, 4),
+    (227, 2),
+    (12, 4),
+    (11, 2),
+    (4, 1),
+    (1, 8),
+    (2, 8),
+    (152, 8),
+    (153, 8),
+];
+
+const FIELDS_V6: [(u16, u16); 11] = [
+    (27, 16),
+    (7, 2),
+    (281, 16),
+    (227, 2),
+    (28, 16),
+    (11, 2),
+    (4, 1),
+    (1, 8),
+    (2, 8),
+    (152, 8),
+    (153, 8),
+];
+
+/// The most records in one message, which keeps the messages of ipv6 flows
+/// below the usual mtu.
+pub const MAX_RECORDS: usize = 12;
+
+/// The traffic that a session relayed to one peer during an export interval.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlowRecord {
+    /// The client address of the session that relayed the data.
+    pub client: SocketAddr,
+    /// The relayed transport address of the session, which is the address
+    /// that the peer receives the data from.
+    pub relay: SocketAddr,
+    /// The client address of the peer that the data was relayed to.
+    pub peer: SocketAddr,
+    /// The transport between the client and the turn server.
+    pub transport: Transport,
+    pub bytes: u64,
+    pub packets: u64,
+    /// The milliseconds since the unix epoch of the first packet.
+    pub start: u64,
+    /// The milliseconds since the unix epoch of the last packet.
+    pub end: u64,
+}
+
+impl FlowRecord {
+    fn is_v4(&self) -> bool {
+        self.client.is_ipv4() && self.relay.is_ipv4() && self.peer.is_ipv4()
+    }
+}
+
+/// Put the flow records into an IPFIX message of [RFC 7011].
+///
+/// The message carries the templates in front of the data sets, so that the
+/// collector can decode every message on its own, the way exporters over UDP
+/// are expected to refresh their templates. The sequence number is the number
+/// of data records that were exported before this message.
+///
+/// [RFC 7011]: https://datatracker.ietf.org/doc/html/rfc7011
+///
+/// # Example
+///
+/// ```
+/// use stun::Transport;
+/// use turn_server::ipfix::*;
+///
+/// let mut bytes = Vec::new();
+/// encode(
+///     &mut bytes,
+///     1_700_000_000,
+///     0,
+///     1,
+///     &[FlowRecord {
+///         client: "127.0.0.1:1000".parse().unwrap(),
+///         relay: "127.0.0.1:50000".parse().unwrap(),
+///         peer: "127.0.0.1:2000".parse().unwrap(),
+///         transport: Transport::UDP,
+///         bytes: 1200,
+///         packets: 10,
+///         start: 1_700_000_000_000,
+///         end: 1_700_000_000_500,
+///     }],
+/// );
+///
+/// assert_eq!(&bytes[..2], &[0, 10]);
+/// assert_eq!(u16::from_be_bytes([bytes[2], bytes[3]]) as usize, bytes.len());
+/// assert_eq!(&bytes[16..18], &[0, 2]);
+/// assert_eq!(&bytes[116..118], &TEMPLATE_V4.to_be_bytes());
+/// assert_eq!(&bytes[120..126], &[127, 0, 0, 1, 0x03, 0xe8]);
+/// assert_eq!(bytes.len(), 16 + 100 + 4 + 51);
+/// ```
+pub fn encode(bytes: &mut Vec<u8>, export_time: u32, sequence: u32, domain: u32, records: &[FlowRecord]) {
+    bytes.clear();
+    bytes.extend_from_slice(&10u16.to_be_bytes());
+    bytes.extend_from_slice(&[0, 0]);
+    bytes.extend_from_slice(&export_time.to_be_bytes());
+    bytes.extend_from_slice(&sequence.to_be_bytes());
+    bytes.extend_from_slice(&domain.to_be_bytes());
+
+    // The template set.
+    let set = bytes.len();
+    bytes.extend_from_slice(&2u16.to_be_bytes());
+    bytes.extend_from_slice(&[0, 0]);
+    for (id, fields) in [(TEMPLATE_V4, &FIELDS_V4), (TEMPLATE_V6, &FIELDS_V6)] {
+        bytes.extend_from_slice(&id.to_be_bytes());
+        bytes.extend_from_slice(&(fields.len() as u16).to_be_bytes());
+
+        for (element, size) in fields {
+            bytes.extend_from_slice(&element.to_be_bytes());
+            bytes.extend_from_slice(&size.to_be_bytes());
+        }
+    }
+
+    finish_set(bytes, set);
+
+    for (id, is_v4) in [(TEMPLATE_V4, true), (TEMPLATE_V6, false)] {
+        if !records.iter().any(|it| it.is_v4() == is_v4) {
+            continue;
+        }
+
+        let set = bytes.len();
+        bytes.extend_from_slice(&id.to_be_bytes());
+        bytes.extend_from_slice(&[0, 0]);
+
+        for record in records.iter().filter(|it| it.is_v4() == is_v4) {
+            put_ip(bytes, record.client.ip(), is_v4);
+            bytes.extend_from_slice(&record.client.port().to_be_bytes());
+            put_ip(bytes, record.relay.ip(), is_v4);
+            bytes.extend_from_slice(&record.relay.port().to_be_bytes());
+            put_ip(bytes, record.peer.ip(), is_v4);
+            bytes.extend_from_slice(&record.peer.port().to_be_bytes());
+            bytes.push(match record.transport {
+                Transport::TCP => 6,
+                Transport::UDP => 17,
+            });
+
+            bytes.extend_from_slice(&record.bytes.to_be_bytes());
+            bytes.extend_from_slice(&record.packets.to_be_bytes());
+            bytes.extend_from_slice(&record.start.to_be_bytes());
+            bytes.extend_from_slice(&record.end.to_be_bytes());
+        }
+
+        finish_set(bytes, set);
+    }
+
+    let size = bytes.len() as u16;
+    bytes[2..4].copy_from_slice(&size.to_be_bytes());
+}
+
+fn finish_set(bytes: &mut [u8], offset: usize) {
+    let size = (bytes.len() - offset) as u16;
+    bytes[offset + 2..offset + 4].copy_from_slice(&size.to_be_bytes());
+}
+
+fn put_ip(bytes: &mut Vec<u8>, ip: IpAddr, is_v4: bool) {
+    match ip {
+        IpAddr::V4(ip) if is_v4 => bytes.extend_from_slice(&ip.octets()),
+        IpAddr::V4(ip) => bytes.extend_from_slice(&ip.to_ipv6_mapped().octets()),
+        IpAddr::V6(ip) => bytes.extend_from_slice(&ip.octets()),
+    }
+}
+
+#[cfg(feature = "ipfix")]
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|it| it.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(feature = "ipfix")]
+struct Flow {
+    bytes: u64,
+    packets: u64,
+    start: u64,
+    end: u64,
+}
+
+/// Exports the traffic of the relayed flows to an IPFIX collector.
+///
+/// A flow is the data that a session relays to one peer, which the turn
+/// server only relays after the peer has been permitted. At every interval
+/// the bytes and packets of the flows since the previous export are sent to
+/// the collector, the remaining traffic of a session is exported when it is
+/// closed. Like the mirror, the export is best effort, messages that cannot
+/// be sent right away are dropped. Without the `ipfix` feature, or if no
+/// collector is configured, nothing is recorded.
+#[derive(Clone, Default)]
+pub struct FlowExporter {
+    #[cfg(feature = "ipfix")]
+    inner: Option<Arc<FlowExporterInner>>,
+}
+
+#[cfg(feature = "ipfix")]
+struct FlowExporterInner {
+    socket: UdpSocket,
+    collector: SocketAddr,
+    domain: u32,
+    sequence: AtomicU32,
+    relays: RwLock<AHashMap<SessionAddr, (RelayPort, Transport)>>,
+    flows: Mutex<AHashMap<(SessionAddr, SocketAddr), Flow>>,
+}
+
+impl FlowExporter {
+    pub fn new(config: &config::Ipfix) -> anyhow::Result<Self> {
+        let collector = match config.collector {
+            Some(it) => it,
+            None => return Ok(Self::default()),
+        };
+
+        #[cfg(feature = "ipfix")]
+        {
+            let socket = UdpSocket::bind(SocketAddr::new(config.bind, 0))?;
+            socket.set_nonblocking(true)?;
+
+            log::info!("ipfix enabled: collector={}, bind={}", collector, config.bind);
+
+            let inner = Arc::new(FlowExporterInner {
+                relays: RwLock::new(AHashMap::with_capacity(1024)),
+                flows: Mutex::new(AHashMap::with_capacity(1024)),
+                sequence: AtomicU32::new(0),
+                domain: config.observation_domain,
+                collector,
+                socket,
+            });
+
+            inner.start(Duration::from_secs(config.interval.max(1)));
+            Ok(Self { inner: Some(inner) })
+        }
+
+        #[cfg(not(feature = "ipfix"))]
+        {
+            log::warn!(
+                "ipfix collector {} is configured, but the ipfix feature is not enabled",
+                collector
+            );
+
+            Ok(Self::default())
+        }
+    }
+
+    /// Whether a collector is configured.
+    pub fn is_enabled(&self) -> bool {
+        #[cfg(feature = "ipfix")]
+        {
+            self.inner.is_some()
+        }
+
+        #[cfg(not(feature = "ipfix"))]
+        {
+            false
+        }
+    }
+
+    /// Remember the relayed transport address of a new allocation.
+    #[allow(unused_variables)]
+    pub fn allocated(&self, addr: &SessionAddr, port: RelayPort, transport: Transport) {
+        #[cfg(feature = "ipfix")]
+        if let Some(inner) = &self.inner {
+            inner.relays.write().insert(*addr, (port, transport));
+        }
+    }
+
+    /// Export the remaining traffic of a closed session.
+    #[allow(unused_variables)]
+    pub fn closed(&self, addr: &SessionAddr) {
+        #[cfg(feature = "ipfix")]
+        if let Some(inner) = &self.inner {
+            let flows = {
+                let mut flows = inner.flows.lock();
+                let keys = flows.keys().filter(|(it, _)| it == addr).copied().collect::<Vec<_>>();
+                keys.into_iter()
+                    .filter_map(|key| flows.remove(&key).map(|it| (key, it)))
+                    .collect::<Vec<_>>()
+            };
+
+            inner.export(flows);
+            inner.relays.write().remove(addr);
+        }
+    }
+
+    /// Keep accounting a session that has moved to another address.
+    #[allow(unused_variables)]
+    pub fn transfer(&self, from: &SessionAddr, to: &SessionAddr) {
+        #[cfg(feature = "ipfix")]
+        if let Some(inner) = &self.inner {
+            let mut relays = inner.relays.write();
+            if let Some(it) = relays.remove(from) {
+                relays.insert(*to, it);
+            }
+        }
+    }
+
+    /// Account data that the session relayed to the peer, `size` is the size
+    /// of the ChannelData message or the Data indication.
+    #[inline]
+    #[allow(unused_variables)]
+    pub fn record(&self, addr: &SessionAddr, peer: &SocketAddr, size: usize) {
+        #[cfg(feature = "ipfix")]
+        if let Some(inner) = &self.inner {
+            let now = now();
+            let mut flows = inner.flows.lock();
+            let flow = flows.entry((*addr, *peer)).or_insert(Flow {
+                bytes: 0,
+                packets: 0,
+                start: now,
+                end: now,
+            });
+
+            flow.bytes += size as u64;
+            flow.packets += 1;
+            flow.end = now;
+        }
+    }
+}
+
+#[cfg(feature = "ipfix")]
+impl FlowExporterInner {
+    /// Export the traffic of all flows at every interval in a background task.
+    fn start(self: &Arc<Self>, interval: Duration) {
+        let this = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(interval);
+            interval.tick().await;
+
+            loop {
+                interval.tick().await;
+
+                if let Some(this) = this.upgrade() {
+                    let flows = std::mem::take(&mut *this.flows.lock());
+                    this.export(flows.into_iter().collect());
+                } else {
+                    break;
+                }
+            }
+        });
+    }
+
+    fn export(&self, flows: Vec<((SessionAddr, SocketAddr), Flow)>) {
+        if flows.is_empty() {
+            return;
+        }
+
+        let records = {
+            let relays = self.relays.read();
+            flows
+                .into_iter()
+                .filter_map(|((addr, peer), flow)| {
+                    let (port, transport) = relays.get(&addr)?;
+                    Some(FlowRecord {
+                        client: addr.address,
+                        relay: SocketAddr::new(addr.interface.ip(), port.get()),
+                        transport: *transport,
+                        bytes: flow.bytes,
+                        packets: flow.packets,
+                        start: flow.start,
+                        end: flow.end,
+                        peer,
+                    })
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let export_time = (now() / 1000) as u32;
+        let mut bytes = Vec::with_capacity(1500);
+        for chunk in records.chunks(MAX_RECORDS) {
+            let sequence = self.sequence.fetch_add(chunk.len() as u32, Ordering::Relaxed);
+            encode(&mut bytes, export_time, sequence, self.domain, chunk);
+
+            if let Err(e) = self.socket.send_to(&bytes, self.collector) {
+                sampled!(
+                    log::Level::Warn,
+                    "ipfix send failed: collector={}, err={}",
+                    self.collector,
+                    e
+                );
+            }
+        }
+    }
+}
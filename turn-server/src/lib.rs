@@ -1,40 +1,302 @@
+pub mod audit;
+pub mod cache;
+pub mod cascade;
 pub mod config;
+pub mod credentials;
+pub mod geoip;
+pub mod ipfix;
+pub mod limiter;
+pub mod logger;
+pub mod mdns;
+pub mod memory;
+pub mod mirror;
 pub mod observer;
+pub mod profiling;
 pub mod publicly;
+pub mod resolver;
 pub mod router;
+pub mod sampling;
 pub mod server;
+pub mod shedding;
 pub mod statistics;
+pub mod trace;
 
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
-use turn::Service;
+use turn::{Service, SessionsOptions};
 
-use self::{config::Config, observer::Observer, statistics::Statistics};
+use self::{
+    config::{Config, Interface},
+    mdns::Advertiser,
+    observer::Observer,
+    router::Router,
+    statistics::Statistics,
+};
 
-/// In order to let the integration test directly use the turn-server crate and
-/// start the server, a function is opened to replace the main function to
-/// directly start the server.
-pub async fn startup(config: Arc<Config>) -> anyhow::Result<()> {
-    let statistics = Statistics::default();
-    let service = Service::new(
-        config.turn.realm.clone(),
-        config.turn.get_externals(),
-        Observer::new(config.clone(), statistics.clone()).await?,
-    );
+/// How long the events of the sessions closed at shutdown are waited for.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Builds the parts of the turn server, for embedding the server into
+/// another application.
+///
+/// Every part that is not given is created from the configuration, the same
+/// as [`startup`] does. The api server can be left out to only run the
+/// relay.
+///
+/// # Example
+///
+/// ```no_run
+/// use std::sync::Arc;
+///
+/// use turn_server::{config::Config, statistics::Statistics, ServerBuilder};
+///
+/// #[tokio::main]
+/// async fn main() -> anyhow::Result<()> {
+///     let config = Arc::new(Config::load()?);
+///     let statistics = Statistics::new(true);
+///
+///     let server = ServerBuilder::new(config)
+///         .with_statistics(statistics.clone())
+///         .with_api(false)
+///         .build()
+///         .await?;
+///
+///     server.start().await?;
+///
+///     // The relay runs in the background, the statistics of the sessions can
+///     // be read from `statistics` by the embedding application.
+///     std::future::pending::<()>().await;
+///     Ok(())
+/// }
+/// ```
+pub struct ServerBuilder {
+    config: Arc<Config>,
+    options: Option<SessionsOptions>,
+    statistics: Option<Statistics>,
+    router: Option<Router>,
+    api: bool,
+}
+
+impl ServerBuilder {
+    pub fn new(config: Arc<Config>) -> Self {
+        Self {
+            api: cfg!(feature = "api"),
+            statistics: None,
+            options: None,
+            router: None,
+            config,
+        }
+    }
+
+    /// Use these session options instead of the ones of `turn` in the
+    /// configuration.
+    pub fn with_sessions_options(mut self, options: SessionsOptions) -> Self {
+        self.options = Some(options);
+        self
+    }
+
+    /// Use this statistics instead of creating one, so that the statistics
+    /// can be shared with the embedding application.
+    pub fn with_statistics(mut self, statistics: Statistics) -> Self {
+        self.statistics = Some(statistics);
+        self
+    }
 
-    server::start(&config, &statistics, &service).await?;
+    /// Use this router instead of creating one.
+    pub fn with_router(mut self, router: Router) -> Self {
+        self.router = Some(router);
+        self
+    }
 
-    #[cfg(feature = "api")]
-    {
-        publicly::api::start_server(config, service, statistics).await?;
+    /// Whether to start the api server, enabled by default if the `api`
+    /// feature is enabled, and has no effect without it.
+    pub fn with_api(mut self, enable: bool) -> Self {
+        self.api = enable;
+        self
     }
 
-    // The turn server is non-blocking after it runs and needs to be kept from
-    // exiting immediately if the api server is not enabled.
-    #[cfg(not(feature = "api"))]
-    {
-        std::future::pending::<()>().await;
+    /// Create the turn service, the interfaces are not bound yet.
+    pub async fn build(self) -> anyhow::Result<TurnServer> {
+        sampling::set_rate(self.config.log.sample);
+
+        let config = self.config;
+        let router = self.router.unwrap_or_default();
+        let statistics = self
+            .statistics
+            .unwrap_or_else(|| Statistics::new(config.api.statistics));
+
+        let observer = Observer::new(config.clone(), statistics.clone(), router.clone()).await?;
+        let mut service = Service::with_options(
+            config.turn.realm.clone(),
+            config.turn.get_externals(),
+            observer.clone(),
+            self.options.unwrap_or_else(|| SessionsOptions {
+                strict_reverse_path: config.turn.strict_reverse_path,
+                lockout: config.turn.lockout.as_ref().map(Into::into),
+                port_seed: config.turn.port_seed,
+                port_ranges: config.turn.port_ranges.iter().map(Into::into).collect(),
+                max_permissions: config.turn.max_permissions,
+                max_channels: config.turn.max_channels,
+                permission_lifetime: config.turn.permission_lifetime,
+                channel_lifetime: config.turn.channel_lifetime,
+                expiry_warning: config.turn.expiry_warning,
+                max_idle: config.turn.max_idle,
+                linger: config.turn.linger.map(Duration::from_millis),
+                binding_cache: config.turn.binding_cache,
+                fingerprint: config.turn.fingerprint,
+                mobility: config.turn.mobility,
+                relay_binding: config.turn.relay_binding,
+                max_lifetime: config.turn.max_lifetime,
+                default_lifetime: config.turn.default_lifetime,
+                nonce_lifetime: config.turn.nonce_lifetime,
+                max_allocations: config.turn.max_allocations,
+                realms: config.turn.realms.iter().map(|(k, v)| (k.clone(), v.into())).collect(),
+            }),
+        );
+
+        for it in &config.turn.interfaces {
+            if let Some(realm) = &it.realm {
+                service.set_realm(it.external, realm.clone());
+            }
+
+            let realm = config.turn.get_realm(&it.external);
+            if let Some(software) = config.turn.realms.get(realm).and_then(|it| it.software.as_ref()) {
+                service.set_software(it.external, software.clone());
+            }
+        }
+
+        service.set_relay_ips(config.turn.relay_addresses.iter().map(|it| it.address).collect());
+
+        observer.get_cascade().attach(&service.get_sessions());
+        observer.start_traffic_reports(&service.get_sessions());
+
+        // The state is loaded before any interface is bound, the loaded sessions are
+        // not announced to the hooks and the sinks.
+        if let Some(path) = &config.turn.replay {
+            let dump = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+            service.get_sessions().load(dump)?;
+
+            log::warn!(
+                "diagnostic mode, the state is loaded from {:?}, do not use it in production",
+                path
+            );
+        }
+
+        Ok(TurnServer {
+            advertiser: Advertiser::new(&config)?,
+            api: self.api,
+            statistics,
+            observer,
+            service,
+            router,
+            config,
+        })
     }
+}
 
-    Ok(())
+/// The turn server built by [`ServerBuilder`].
+pub struct TurnServer {
+    config: Arc<Config>,
+    router: Router,
+    statistics: Statistics,
+    observer: Observer,
+    service: Service<Observer>,
+    advertiser: Advertiser,
+    #[allow(unused)]
+    api: bool,
+}
+
+impl TurnServer {
+    pub fn get_service(&self) -> &Service<Observer> {
+        &self.service
+    }
+
+    pub fn get_observer(&self) -> &Observer {
+        &self.observer
+    }
+
+    pub fn get_statistics(&self) -> &Statistics {
+        &self.statistics
+    }
+
+    /// Bind the interfaces and start relaying, the relay keeps running in the
+    /// background after this returns.
+    pub async fn start(&self) -> anyhow::Result<()> {
+        server::start(
+            &self.config,
+            &self.statistics,
+            &self.router,
+            self.observer.get_mirror(),
+            self.observer.get_flows(),
+            self.observer.get_tracer(),
+            self.observer.get_shedder(),
+            self.observer.get_limiter(),
+            self.observer.get_request_limiter(),
+            &self.service,
+        )
+        .await?;
+
+        self.advertiser.advertise(&self.config)
+    }
+
+    /// Bind one interface and start relaying on it, to start an interface
+    /// again that has stopped. The external address of the interface has to
+    /// be one of the interfaces of the configuration.
+    pub async fn start_interface(&self, interface: &Interface) -> anyhow::Result<()> {
+        server::start_interface(
+            interface,
+            &self.statistics,
+            &self.router,
+            self.observer.get_mirror(),
+            self.observer.get_flows(),
+            self.observer.get_tracer(),
+            self.observer.get_shedder(),
+            self.observer.get_limiter(),
+            self.observer.get_request_limiter(),
+            &self.service,
+        )
+        .await
+    }
+
+    /// Start relaying and the api server if it is enabled, and run until the
+    /// api server stops, or forever without it.
+    ///
+    /// On ctrl-c the sessions are closed, and the `closed` events are given a
+    /// few seconds to be pushed before this returns.
+    pub async fn run(self) -> anyhow::Result<()> {
+        self.start().await?;
+
+        let sessions = self.service.get_sessions();
+        let observer = self.observer.clone();
+        let serve = async move {
+            #[cfg(feature = "api")]
+            if self.api {
+                return publicly::api::start_server(self.config, self.service, self.observer, self.statistics).await;
+            }
+
+            // The turn server is non-blocking after it runs and needs to be kept from
+            // exiting immediately if the api server is not enabled.
+            std::future::pending::<()>().await;
+            Ok(())
+        };
+
+        tokio::select! {
+            ret = serve => ret,
+            ret = tokio::signal::ctrl_c() => {
+                ret?;
+
+                log::info!("shutting down, closing all sessions");
+                sessions.shutdown();
+                observer.flush_events(SHUTDOWN_TIMEOUT).await;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// In order to let the integration test directly use the turn-server crate and
+/// start the server, a function is opened to replace the main function to
+/// directly start the server.
+pub async fn startup(config: Arc<Config>) -> anyhow::Result<()> {
+    ServerBuilder::new(config).build().await?.run().await
 }
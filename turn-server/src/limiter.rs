@@ -0,0 +1,326 @@
+use std::{
+    net::IpAddr,
+    num::NonZeroUsize,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+
+use ahash::AHashMap;
+use lru::LruCache;
+use parking_lot::Mutex;
+use stun::{util, AttrKind, Kind, Method};
+use turn::SessionAddr;
+
+use crate::config::{Bandwidth, RequestRate};
+
+/// The number of source ip addresses that are tracked, the least recently
+/// seen one is forgotten to make room for a new one.
+const MAX_SOURCES: usize = 65536;
+
+/// A token bucket that holds up to one second of the rate in bytes, or the
+/// burst.
+struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: u64) -> Self {
+        Self::with_capacity(rate, rate)
+    }
+
+    fn with_capacity(rate: u64, capacity: u64) -> Self {
+        Self {
+            rate: rate as f64,
+            capacity: capacity as f64,
+            tokens: capacity as f64,
+            updated: Instant::now(),
+        }
+    }
+
+    /// Refill the bucket for the time since the last update, and check if it
+    /// holds enough tokens for the size.
+    fn refill(&mut self, now: Instant, size: usize) -> bool {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.updated = now;
+        self.tokens >= size as f64
+    }
+
+    fn take(&mut self, size: usize) {
+        self.tokens -= size as f64;
+    }
+}
+
+/// Limits the bandwidth of the data that the sessions relay to their peers.
+///
+/// Each session has a token bucket of the per session rate, and all sessions
+/// share a token bucket of the global rate, the buckets hold up to one second
+/// of their rate. ChannelData messages and Send indications that exceed one
+/// of the rates are dropped and counted. Without a configuration nothing is
+/// limited.
+///
+/// # Example
+///
+/// ```
+/// use turn::SessionAddr;
+/// use turn_server::{config::Bandwidth, limiter::RateLimiter};
+///
+/// let limiter = RateLimiter::new(Some(&Bandwidth {
+///     session: Some(1000),
+///     global: Some(1500),
+/// }));
+///
+/// let addr = SessionAddr {
+///     address: "127.0.0.1:8080".parse().unwrap(),
+///     interface: "127.0.0.1:3478".parse().unwrap(),
+/// };
+///
+/// let peer_addr = SessionAddr {
+///     address: "127.0.0.1:8081".parse().unwrap(),
+///     interface: "127.0.0.1:3478".parse().unwrap(),
+/// };
+///
+/// assert!(limiter.allow(&addr, 800));
+/// assert!(!limiter.allow(&addr, 800));
+/// assert!(!limiter.allow(&peer_addr, 800));
+/// assert!(limiter.allow(&peer_addr, 700));
+/// assert_eq!(limiter.get_dropped(), 2);
+///
+/// assert!(RateLimiter::default().allow(&addr, 1 << 20));
+/// ```
+#[derive(Clone, Default)]
+pub struct RateLimiter(Option<Arc<RateLimiterInner>>);
+
+struct RateLimiterInner {
+    session: Option<u64>,
+    global: Option<Mutex<TokenBucket>>,
+    sessions: Mutex<AHashMap<SessionAddr, TokenBucket>>,
+    dropped: AtomicU64,
+}
+
+impl RateLimiter {
+    pub fn new(config: Option<&Bandwidth>) -> Self {
+        Self(
+            config
+                .filter(|it| it.session.is_some() || it.global.is_some())
+                .map(|it| {
+                    Arc::new(RateLimiterInner {
+                        session: it.session,
+                        global: it.global.map(|it| Mutex::new(TokenBucket::new(it))),
+                        sessions: Mutex::new(AHashMap::with_capacity(1024)),
+                        dropped: AtomicU64::new(0),
+                    })
+                }),
+        )
+    }
+
+    /// Whether the session may relay data of the size to a peer, the data
+    /// is taken from the buckets if it may and counted as dropped otherwise.
+    pub fn allow(&self, addr: &SessionAddr, size: usize) -> bool {
+        let inner = if let Some(it) = &self.0 {
+            it
+        } else {
+            return true;
+        };
+
+        let now = Instant::now();
+        let mut global = inner.global.as_ref().map(|it| it.lock());
+        let is_allowed = match inner.session {
+            Some(rate) => {
+                let mut sessions = inner.sessions.lock();
+                let bucket = sessions.entry(*addr).or_insert_with(|| TokenBucket::new(rate));
+
+                let is_allowed =
+                    bucket.refill(now, size) && global.as_mut().map(|it| it.refill(now, size)).unwrap_or(true);
+
+                if is_allowed {
+                    bucket.take(size);
+                }
+
+                is_allowed
+            }
+            None => global.as_mut().map(|it| it.refill(now, size)).unwrap_or(true),
+        };
+
+        if is_allowed {
+            if let Some(it) = global.as_mut() {
+                it.take(size);
+            }
+        } else {
+            inner.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+
+        is_allowed
+    }
+
+    /// Forget the bucket of a closed session.
+    pub fn remove(&self, addr: &SessionAddr) {
+        if let Some(inner) = &self.0 {
+            inner.sessions.lock().remove(addr);
+        }
+    }
+
+    /// The number of packets that were dropped.
+    pub fn get_dropped(&self) -> u64 {
+        self.0
+            .as_ref()
+            .map(|it| it.dropped.load(Ordering::Relaxed))
+            .unwrap_or(0)
+    }
+}
+
+/// Whether the message is a request that the server answers without
+/// authenticating it, a Binding request or an Allocate request without
+/// MESSAGE-INTEGRITY, whose response is larger than the request.
+///
+/// # Example
+///
+/// ```
+/// use bytes::BytesMut;
+/// use stun::{attribute::UserName, Kind, MessageWriter, Method};
+/// use turn_server::limiter::is_unauthenticated;
+///
+/// let mut buf = BytesMut::new();
+/// let mut message = MessageWriter::new(Method::Allocate(Kind::Request), &[0u8; 12], &mut buf);
+/// message.append::<UserName>("test");
+/// message.flush(None).unwrap();
+/// assert!(is_unauthenticated(&buf));
+///
+/// let mut buf = BytesMut::new();
+/// let mut message = MessageWriter::new(Method::Allocate(Kind::Request), &[0u8; 12], &mut buf);
+/// message.append::<UserName>("test");
+/// message.flush(Some(&[0u8; 16])).unwrap();
+/// assert!(!is_unauthenticated(&buf));
+///
+/// let mut buf = BytesMut::new();
+/// MessageWriter::new(Method::Binding(Kind::Request), &[0u8; 12], &mut buf)
+///     .flush(None)
+///     .unwrap();
+/// assert!(is_unauthenticated(&buf));
+/// assert!(!is_unauthenticated(&[0x40, 0x00, 0x00, 0x04]));
+/// ```
+pub fn is_unauthenticated(bytes: &[u8]) -> bool {
+    if bytes.len() < 20 {
+        return false;
+    }
+
+    match Method::try_from(u16::from_be_bytes([bytes[0], bytes[1]])) {
+        Ok(Method::Binding(Kind::Request)) => true,
+        Ok(Method::Allocate(Kind::Request)) => {
+            let mut offset = 20;
+            while bytes.len() - offset >= 4 {
+                let kind = u16::from_be_bytes([bytes[offset], bytes[offset + 1]]);
+                if kind == AttrKind::MessageIntegrity as u16 || kind == AttrKind::MessageIntegritySha256 as u16 {
+                    return false;
+                }
+
+                let size = u16::from_be_bytes([bytes[offset + 2], bytes[offset + 3]]) as usize;
+                offset += 4 + size + util::pad_size(size);
+                if offset > bytes.len() {
+                    break;
+                }
+            }
+
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Limits the rate of the unauthenticated requests per source ip address.
+///
+/// The responses to Binding requests and to the Allocate requests that are
+/// challenged for credentials are larger than the requests, and are sent
+/// without checking the source address, so spoofed requests can reflect
+/// amplified traffic at a victim. Each source ip address has a token bucket
+/// of packets, with the configured rate and burst, the requests that exceed
+/// it are dropped without a response and counted. The other packets, which
+/// are either authenticated or belong to an allocation, are never limited.
+/// Without a configuration nothing is limited.
+///
+/// # Example
+///
+/// ```
+/// use turn_server::{config::RequestRate, limiter::RequestLimiter};
+///
+/// let limiter = RequestLimiter::new(Some(&RequestRate {
+///     rate: 1,
+///     burst: Some(2),
+/// }));
+///
+/// let ip = "127.0.0.1".parse().unwrap();
+/// assert!(limiter.allow(ip));
+/// assert!(limiter.allow(ip));
+/// assert!(!limiter.allow(ip));
+/// assert!(limiter.allow("127.0.0.2".parse().unwrap()));
+/// assert_eq!(limiter.get_dropped(), 1);
+///
+/// assert!(RequestLimiter::default().allow(ip));
+/// ```
+#[derive(Clone, Default)]
+pub struct RequestLimiter(Option<Arc<RequestLimiterInner>>);
+
+struct RequestLimiterInner {
+    rate: u64,
+    burst: u64,
+    sources: Mutex<LruCache<IpAddr, TokenBucket>>,
+    dropped: AtomicU64,
+}
+
+impl RequestLimiter {
+    pub fn new(config: Option<&RequestRate>) -> Self {
+        Self(config.map(|it| {
+            Arc::new(RequestLimiterInner {
+                rate: it.rate as u64,
+                burst: it.burst.unwrap_or(it.rate).max(1) as u64,
+                sources: Mutex::new(LruCache::new(NonZeroUsize::new(MAX_SOURCES).unwrap())),
+                dropped: AtomicU64::new(0),
+            })
+        }))
+    }
+
+    /// Whether the source ip address may send another unauthenticated
+    /// request, the request is counted as dropped if it may not.
+    pub fn allow(&self, ip: IpAddr) -> bool {
+        let inner = if let Some(it) = &self.0 {
+            it
+        } else {
+            return true;
+        };
+
+        let now = Instant::now();
+        let mut sources = inner.sources.lock();
+
+        // Spoofed requests come from any number of addresses, the least recently
+        // seen address is evicted for a new one, which bounds the memory and costs
+        // the same for every request.
+        let bucket = sources.get_or_insert_mut(ip, || TokenBucket::with_capacity(inner.rate, inner.burst));
+
+        if bucket.refill(now, 1) {
+            bucket.take(1);
+            return true;
+        }
+
+        drop(sources);
+        inner.dropped.fetch_add(1, Ordering::Relaxed);
+
+        #[cfg(feature = "prometheus")]
+        crate::statistics::prometheus::METRICS.limited_requests.inc();
+
+        false
+    }
+
+    /// The number of requests that were dropped.
+    pub fn get_dropped(&self) -> u64 {
+        self.0
+            .as_ref()
+            .map(|it| it.dropped.load(Ordering::Relaxed))
+            .unwrap_or(0)
+    }
+}
@@ -0,0 +1,126 @@
+use std::{
+    future::Future,
+    io::Write,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use log::{Level, Log, Metadata, Record};
+use serde_json::json;
+use turn::SessionAddr;
+
+tokio::task_local! {
+    static SESSION: SessionAddr;
+}
+
+/// Attach the session to the log records emitted while the future runs.
+pub async fn scope<F: Future>(addr: SessionAddr, future: F) -> F::Output {
+    SESSION.scope(addr, future).await
+}
+
+/// Attach the session to the log records emitted while the closure runs.
+pub fn sync_scope<T>(addr: SessionAddr, func: impl FnOnce() -> T) -> T {
+    SESSION.sync_scope(addr, func)
+}
+
+/// The session whose request is being processed, if any.
+///
+/// # Example
+///
+/// ```
+/// use turn::SessionAddr;
+/// use turn_server::logger::*;
+///
+/// let addr = SessionAddr {
+///     address: "127.0.0.1:8080".parse().unwrap(),
+///     interface: "127.0.0.1:3478".parse().unwrap(),
+/// };
+///
+/// assert_eq!(current_session(), None);
+/// assert_eq!(sync_scope(addr, current_session), Some(addr));
+/// ```
+pub fn current_session() -> Option<SessionAddr> {
+    SESSION.try_with(|addr| *addr).ok()
+}
+
+/// Appends the current session to the messages of the records that are
+/// passed on to the wrapped logger.
+pub struct SessionLogger<T>(pub T);
+
+impl<T: Log> Log for SessionLogger<T> {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.0.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        match current_session() {
+            Some(addr) => self.0.log(
+                &Record::builder()
+                    .args(format_args!(
+                        "{}, session={{address={:?}, interface={:?}}}",
+                        record.args(),
+                        addr.address,
+                        addr.interface
+                    ))
+                    .metadata(record.metadata().clone())
+                    .module_path(record.module_path())
+                    .file(record.file())
+                    .line(record.line())
+                    .build(),
+            ),
+            None => self.0.log(record),
+        }
+    }
+
+    fn flush(&self) {
+        self.0.flush()
+    }
+}
+
+/// Writes every record as a line of json to stdout, the session is
+/// included as a field, so that the records of a session can be filtered by
+/// log collectors.
+pub struct JsonLogger {
+    level: Level,
+}
+
+impl JsonLogger {
+    pub fn new(level: Level) -> Self {
+        Self { level }
+    }
+}
+
+impl Log for JsonLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let mut line = json!({
+            "timestamp": SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|it| it.as_millis() as u64)
+                .unwrap_or_default(),
+            "level": record.level().as_str(),
+            "target": record.target(),
+            "message": record.args().to_string(),
+        });
+
+        if let Some(addr) = current_session() {
+            line["session"] = json!({
+                "address": addr.address,
+                "interface": addr.interface,
+            });
+        }
+
+        // A record that cannot be written has nowhere else to go.
+        let _ = writeln!(std::io::stdout().lock(), "{}", line);
+    }
+
+    fn flush(&self) {
+        let _ = std::io::stdout().flush();
+    }
+}
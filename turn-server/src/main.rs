@@ -2,14 +2,29 @@
 #[global_allocator]
 static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
 
+#[cfg(all(feature = "jemalloc", not(feature = "mimalloc"), not(target_env = "msvc")))]
+#[global_allocator]
+static GLOBAL: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+
 use std::sync::Arc;
 
-use turn_server::config::Config;
+use clap::Parser;
+use turn_server::{
+    config::{Cli, Command, Config, Log, LogFormat},
+    logger::{JsonLogger, SessionLogger},
+};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let config = Arc::new(Config::load()?);
-    simple_logger::init_with_level(config.log.level.as_level())?;
+    let mut cli = Cli::parse();
+    let command = cli.command.take();
+
+    let config = Arc::new(Config::load_from(cli)?);
+    init_logger(&config.log)?;
+
+    if let Some(command) = command {
+        return run_command(&config, command);
+    }
 
     if config.turn.interfaces.is_empty() {
         log::warn!(
@@ -21,3 +36,61 @@ async fn main() -> anyhow::Result<()> {
 
     turn_server::startup(config).await
 }
+
+fn init_logger(config: &Log) -> anyhow::Result<()> {
+    let level = config.level.as_level();
+    match config.format {
+        LogFormat::Text => log::set_boxed_logger(Box::new(SessionLogger(
+            simple_logger::SimpleLogger::new().with_level(level.to_level_filter()),
+        )))?,
+        LogFormat::Json => log::set_boxed_logger(Box::new(JsonLogger::new(level)))?,
+    }
+
+    log::set_max_level(level.to_level_filter());
+    Ok(())
+}
+
+#[cfg(feature = "store")]
+fn run_command(config: &Config, command: Command) -> anyhow::Result<()> {
+    use std::time::Duration;
+
+    use turn_server::{config::CredentialsCommand, credentials::Credentials};
+
+    match command {
+        Command::Credentials(command) => {
+            if config.auth.store.is_none() {
+                anyhow::bail!("the credential store is not configured, set `auth.store` or `--auth-store`");
+            }
+
+            let credentials = Credentials::new(&config.auth)?;
+            match command {
+                CredentialsCommand::List => {
+                    for username in credentials.list() {
+                        println!("{}", username);
+                    }
+                }
+                CredentialsCommand::Add {
+                    username,
+                    password,
+                    ttl,
+                } => {
+                    credentials.insert(&username, &password, ttl.map(Duration::from_secs))?;
+                }
+                CredentialsCommand::Remove { username } => {
+                    if !credentials.remove(&username)? {
+                        anyhow::bail!("credential not found: {}", username);
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(not(feature = "store"))]
+fn run_command(_: &Config, command: Command) -> anyhow::Result<()> {
+    match command {
+        Command::Credentials(_) => anyhow::bail!("the credential store requires the `store` feature"),
+    }
+}
@@ -0,0 +1,123 @@
+use crate::config::Config;
+
+/// Advertises the turn service in the local network with mDNS/DNS-SD.
+///
+/// Each interface is published as a `_turn._udp` or `_turn._tcp` service at
+/// its external address, the TXT record carries the realm, the relay address
+/// and the range of the relay ports. Clients and test harnesses in the local
+/// network can discover the relay without hardcoding ip addresses. mDNS does
+/// not leave the local network, this is meant for LAN-only deployments.
+///
+/// With the `mdns` feature, and when `turn.mdns` is set, the services are
+/// published when the interfaces are started and withdrawn when the
+/// advertiser is dropped. Otherwise nothing is published.
+///
+/// # Example
+///
+/// ```
+/// use turn_server::{config::Config, mdns::Advertiser};
+///
+/// let advertiser = Advertiser::new(&Config::default()).unwrap();
+///
+/// assert!(!advertiser.is_enabled());
+/// advertiser.advertise(&Config::default()).unwrap();
+/// ```
+pub struct Advertiser {
+    #[cfg(feature = "mdns")]
+    daemon: Option<mdns_sd::ServiceDaemon>,
+}
+
+impl Advertiser {
+    #[allow(unused_variables)]
+    pub fn new(config: &Config) -> anyhow::Result<Self> {
+        #[cfg(feature = "mdns")]
+        {
+            Ok(Self {
+                daemon: config
+                    .turn
+                    .mdns
+                    .as_ref()
+                    .map(|_| mdns_sd::ServiceDaemon::new())
+                    .transpose()?,
+            })
+        }
+
+        #[cfg(not(feature = "mdns"))]
+        {
+            if config.turn.mdns.is_some() {
+                log::warn!("mdns is configured, but the mdns feature is not enabled");
+            }
+
+            Ok(Self {})
+        }
+    }
+
+    /// Whether the services are published.
+    pub fn is_enabled(&self) -> bool {
+        #[cfg(feature = "mdns")]
+        {
+            self.daemon.is_some()
+        }
+
+        #[cfg(not(feature = "mdns"))]
+        {
+            false
+        }
+    }
+
+    /// Publish a service for each interface of the configuration.
+    #[allow(unused_variables)]
+    pub fn advertise(&self, config: &Config) -> anyhow::Result<()> {
+        #[cfg(feature = "mdns")]
+        if let (Some(daemon), Some(mdns)) = (&self.daemon, &config.turn.mdns) {
+            use crate::config::Transport;
+
+            let range = turn::PortAllocatePools::port_range();
+            let ports = format!("{}-{}", range.start, range.end - 1);
+
+            for it in &config.turn.interfaces {
+                let kind = match it.transport {
+                    Transport::UDP => "_turn._udp.local.",
+                    Transport::TCP => "_turn._tcp.local.",
+                    Transport::TLS => "_turns._tcp.local.",
+                };
+
+                let relay = it.external.ip().to_string();
+                let properties = [
+                    ("realm", config.turn.get_realm(&it.external)),
+                    ("relay", relay.as_str()),
+                    ("ports", ports.as_str()),
+                ];
+
+                daemon.register(mdns_sd::ServiceInfo::new(
+                    kind,
+                    &format!("{}-{}", mdns.name, it.external.port()),
+                    &format!("{}.local.", mdns.name),
+                    it.external.ip(),
+                    it.external.port(),
+                    &properties[..],
+                )?)?;
+
+                log::info!(
+                    "mdns advertised: type={}, name={}, external={}",
+                    kind,
+                    mdns.name,
+                    it.external
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "mdns")]
+impl Drop for Advertiser {
+    fn drop(&mut self) {
+        // Shutting down the daemon withdraws the published services, so that the clients
+        // do not keep discovering a relay that is gone.
+        if let Some(daemon) = self.daemon.take() {
+            let _ = daemon.shutdown();
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use serde::Serialize;
+
+/// The global allocator of the turn server and what it reports.
+///
+/// The allocator is selected with the `mimalloc` or `jemalloc` feature,
+/// `mimalloc` takes precedence if both are enabled, and jemalloc is not
+/// available on MSVC targets. Only jemalloc reports its statistics, the
+/// numbers are `None` for the other allocators.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct AllocatorStats {
+    /// "mimalloc", "jemalloc" or "system".
+    pub name: &'static str,
+    /// The bytes allocated by the application.
+    pub allocated: Option<usize>,
+    /// The bytes of physical memory that the allocator has mapped, including
+    /// the memory that it keeps cached for later allocations.
+    pub resident: Option<usize>,
+}
+
+/// Read the statistics of the global allocator.
+///
+/// # Example
+///
+/// ```
+/// use turn_server::memory::allocator;
+///
+/// let stats = allocator();
+/// assert!(["mimalloc", "jemalloc", "system"].contains(&stats.name));
+/// ```
+pub fn allocator() -> AllocatorStats {
+    #[cfg(feature = "mimalloc")]
+    {
+        AllocatorStats {
+            name: "mimalloc",
+            allocated: None,
+            resident: None,
+        }
+    }
+
+    #[cfg(all(feature = "jemalloc", not(feature = "mimalloc"), not(target_env = "msvc")))]
+    {
+        use tikv_jemalloc_ctl::{epoch, stats};
+
+        // The statistics are cached by jemalloc until the epoch is advanced.
+        let _ = epoch::advance();
+
+        AllocatorStats {
+            name: "jemalloc",
+            allocated: stats::allocated::read().ok(),
+            resident: stats::resident::read().ok(),
+        }
+    }
+
+    #[cfg(not(any(feature = "mimalloc", all(feature = "jemalloc", not(target_env = "msvc")))))]
+    {
+        AllocatorStats {
+            name: "system",
+            allocated: None,
+            resident: None,
+        }
+    }
+}
@@ -0,0 +1,279 @@
+use std::{
+    net::{IpAddr, SocketAddr, UdpSocket},
+    sync::Arc,
+};
+
+use ahash::AHashSet;
+use parking_lot::RwLock;
+use stun::{attribute::Data, Decoder, Method, Payload};
+use turn::{ResponseMethod, SessionAddr};
+
+use crate::{config, sampling::sampled};
+
+/// The first bytes of every mirrored packet.
+pub const MAGIC: [u8; 4] = *b"TRNM";
+
+/// The version of the encapsulation of the mirrored packets.
+pub const VERSION: u8 = 1;
+
+/// Put the relayed data into the encapsulation of the mirrored packets.
+///
+/// All numbers are in network byte order:
+///
+/// ```text
+/// +--------------------+-------------+--------------+----------------+
+/// | magic "TRNM" (4)   | version (1) | reserved (1) | length (2)     |
+/// +--------------------+-------------+--------------+----------------+
+/// | session address: family (1), ip (4 or 16), port (2)              |
+/// +------------------------------------------------------------------+
+/// | peer address: family (1), ip (4 or 16), port (2)                 |
+/// +------------------------------------------------------------------+
+/// | payload (length)                                                 |
+/// +------------------------------------------------------------------+
+/// ```
+///
+/// The family is `4` or `6`. The session address is the client address of
+/// the mirrored session that sent the data, the peer address is the client
+/// address of the session that it was relayed to, and the payload is the
+/// application data without the ChannelData or Data indication framing.
+///
+/// # Example
+///
+/// ```
+/// use turn_server::mirror::*;
+///
+/// let mut bytes = Vec::new();
+/// encapsulate(
+///     &mut bytes,
+///     &"127.0.0.1:1000".parse().unwrap(),
+///     &"127.0.0.1:2000".parse().unwrap(),
+///     b"hello",
+/// );
+///
+/// assert_eq!(&bytes[..4], &MAGIC);
+/// assert_eq!(bytes[4], VERSION);
+/// assert_eq!(&bytes[6..8], &[0, 5]);
+/// assert_eq!(&bytes[8..15], &[4, 127, 0, 0, 1, 0x03, 0xe8]);
+/// assert_eq!(&bytes[15..22], &[4, 127, 0, 0, 1, 0x07, 0xd0]);
+/// assert_eq!(&bytes[22..], b"hello");
+/// ```
+pub fn encapsulate(bytes: &mut Vec<u8>, session: &SocketAddr, peer: &SocketAddr, payload: &[u8]) {
+    bytes.clear();
+    bytes.extend_from_slice(&MAGIC);
+    bytes.push(VERSION);
+    bytes.push(0);
+    bytes.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+
+    for addr in [session, peer] {
+        match addr.ip() {
+            IpAddr::V4(ip) => {
+                bytes.push(4);
+                bytes.extend_from_slice(&ip.octets());
+            }
+            IpAddr::V6(ip) => {
+                bytes.push(6);
+                bytes.extend_from_slice(&ip.octets());
+            }
+        }
+
+        bytes.extend_from_slice(&addr.port().to_be_bytes());
+    }
+
+    bytes.extend_from_slice(payload);
+}
+
+/// Sends the relayed data of selected sessions to a multicast group.
+///
+/// Passive recorders in the local network can join the group and capture the
+/// media of a conference without a tap in the relay path. Only the data that
+/// a mirrored session sends is mirrored, to capture both directions of a
+/// call, both sessions have to be mirrored. Mirroring is best effort, packets
+/// that cannot be sent right away are dropped and never slow down the relay.
+///
+/// # Example
+///
+/// ```
+/// use std::net::UdpSocket;
+///
+/// use turn::{ResponseMethod, SessionAddr};
+/// use turn_server::{config, mirror::*};
+///
+/// let recorder = UdpSocket::bind("127.0.0.1:0").unwrap();
+/// let mirror = Mirror::new(&config::Mirror {
+///     group: Some(recorder.local_addr().unwrap()),
+///     bind: "127.0.0.1".parse().unwrap(),
+///     ..Default::default()
+/// })
+/// .unwrap();
+///
+/// let addr = SessionAddr {
+///     address: "127.0.0.1:1000".parse().unwrap(),
+///     interface: "127.0.0.1:3478".parse().unwrap(),
+/// };
+///
+/// let peer = "127.0.0.1:2000".parse().unwrap();
+/// let channel_data = [0x40, 0x00, 0x00, 0x05, b'h', b'e', b'l', b'l', b'o', 0, 0, 0];
+///
+/// assert!(mirror.is_enabled());
+/// assert!(mirror.start(&addr));
+/// assert!(mirror.is_mirrored(&addr));
+///
+/// mirror.send(&addr, &peer, ResponseMethod::ChannelData, &channel_data);
+///
+/// let mut buf = [0u8; 1500];
+/// let size = recorder.recv(&mut buf).unwrap();
+/// assert_eq!(&buf[..4], &MAGIC);
+/// assert_eq!(&buf[22..size], b"hello");
+///
+/// assert!(mirror.stop(&addr));
+/// assert!(!mirror.is_mirrored(&addr));
+/// ```
+#[derive(Clone, Default)]
+pub struct Mirror(Option<Arc<MirrorInner>>);
+
+struct MirrorInner {
+    socket: UdpSocket,
+    group: SocketAddr,
+    sessions: RwLock<AHashSet<SessionAddr>>,
+}
+
+impl Mirror {
+    pub fn new(config: &config::Mirror) -> anyhow::Result<Self> {
+        let group = match config.group {
+            Some(it) => it,
+            None => return Ok(Self(None)),
+        };
+
+        let socket = UdpSocket::bind(SocketAddr::new(config.bind, 0))?;
+        socket.set_nonblocking(true)?;
+
+        if group.ip().is_multicast() {
+            match group.ip() {
+                IpAddr::V4(_) => {
+                    socket.set_multicast_ttl_v4(config.ttl)?;
+                    socket.set_multicast_loop_v4(true)?;
+                }
+                IpAddr::V6(_) => {
+                    socket.set_multicast_loop_v6(true)?;
+                }
+            }
+        }
+
+        log::info!("mirror enabled: group={}, bind={}", group, config.bind);
+
+        Ok(Self(Some(Arc::new(MirrorInner {
+            sessions: RwLock::new(AHashSet::with_capacity(64)),
+            socket,
+            group,
+        }))))
+    }
+
+    /// Whether a multicast group is configured.
+    pub fn is_enabled(&self) -> bool {
+        self.0.is_some()
+    }
+
+    /// Start mirroring the session, returns `false` if mirroring is not
+    /// enabled.
+    pub fn start(&self, addr: &SessionAddr) -> bool {
+        if let Some(inner) = &self.0 {
+            inner.sessions.write().insert(*addr);
+            log::info!(
+                "mirror start: address={:?}, interface={:?}",
+                addr.address,
+                addr.interface
+            );
+
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Stop mirroring the session, returns `false` if the session was not
+    /// mirrored.
+    pub fn stop(&self, addr: &SessionAddr) -> bool {
+        let removed = self
+            .0
+            .as_ref()
+            .map(|it| it.sessions.write().remove(addr))
+            .unwrap_or(false);
+
+        if removed {
+            log::info!(
+                "mirror stop: address={:?}, interface={:?}",
+                addr.address,
+                addr.interface
+            );
+        }
+
+        removed
+    }
+
+    /// Keep mirroring a session that has moved to another address.
+    pub fn transfer(&self, from: &SessionAddr, to: &SessionAddr) {
+        if let Some(inner) = &self.0 {
+            let mut sessions = inner.sessions.write();
+            if sessions.remove(from) {
+                sessions.insert(*to);
+            }
+        }
+    }
+
+    pub fn is_mirrored(&self, addr: &SessionAddr) -> bool {
+        self.0
+            .as_ref()
+            .map(|it| it.sessions.read().contains(addr))
+            .unwrap_or(false)
+    }
+
+    /// Mirror data that the session relayed to the peer.
+    ///
+    /// `bytes` is the ChannelData message or the Data indication that is sent
+    /// to the peer, nothing is sent if the session is not mirrored.
+    pub fn send(&self, addr: &SessionAddr, peer: &SocketAddr, method: ResponseMethod, bytes: &[u8]) {
+        let inner = match &self.0 {
+            Some(it) => it,
+            None => return,
+        };
+
+        {
+            let sessions = inner.sessions.read();
+            if sessions.is_empty() || !sessions.contains(addr) {
+                return;
+            }
+        }
+
+        let mut decoder = Decoder::default();
+        let payload = match method {
+            ResponseMethod::ChannelData => match decoder.decode(bytes) {
+                Ok(Payload::ChannelData(it)) => {
+                    let size = u16::from_be_bytes([bytes[2], bytes[3]]) as usize;
+                    &it.bytes[..size]
+                }
+                _ => return,
+            },
+            ResponseMethod::Stun(Method::DataIndication) => match decoder.decode(bytes) {
+                Ok(Payload::Message(it)) => match it.get::<Data>() {
+                    Some(it) => it,
+                    None => return,
+                },
+                _ => return,
+            },
+            _ => return,
+        };
+
+        let mut packet = Vec::with_capacity(payload.len() + 48);
+        encapsulate(&mut packet, &addr.address, peer, payload);
+
+        if let Err(e) = inner.socket.send_to(&packet, inner.group) {
+            sampled!(
+                log::Level::Warn,
+                "mirror send failed: address={:?}, group={}, err={}",
+                addr.address,
+                inner.group,
+                e
+            );
+        }
+    }
+}
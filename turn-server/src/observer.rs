@@ -1,73 +1,538 @@
-use std::{future::Future, sync::Arc};
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
-use crate::{config::Config, statistics::Statistics};
+use crate::{
+    cascade::Cascade,
+    config::Config,
+    credentials::Credentials,
+    geoip::{Geolocator, Location},
+    ipfix::FlowExporter,
+    limiter::{RateLimiter, RequestLimiter},
+    mirror::Mirror,
+    resolver::Resolver,
+    router::Router,
+    sampling::sampled,
+    shedding::LoadShedder,
+    statistics::Statistics,
+    trace::Tracer,
+};
 
 #[cfg(feature = "hooks")]
 use crate::publicly::hooks::HooksService;
 
-#[cfg(feature = "hooks")]
-use serde_json::json;
+#[cfg(any(feature = "nats", feature = "kafka"))]
+use crate::publicly::sinks::EventSinks;
+
+#[cfg(feature = "api")]
+use crate::publicly::api::EventStream;
+
+#[cfg(any(feature = "api", feature = "hooks", feature = "nats", feature = "kafka"))]
+use parking_lot::Mutex;
 
+#[cfg(any(feature = "api", feature = "hooks", feature = "nats", feature = "kafka"))]
+use serde_json::{json, Value};
+
+use ahash::AHashMap;
 use anyhow::Result;
-use base64::{prelude::BASE64_STANDARD, Engine};
-use turn::SessionAddr;
+use parking_lot::RwLock;
+use serde::Serialize;
+use stun::{Method, Transport};
+use turn::{
+    lockout::{AuthFailure, LockoutKey},
+    sessions::Endpoint,
+    ChannelNumber, CloseReason, Credential, PasswordError, PeerPort, RelayPort, ResponseMethod, SessionAddr, Sessions,
+};
+
+/// The mechanism that a session was authenticated with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthMethod {
+    /// The static credentials, including the ones managed at runtime.
+    StaticCredentials,
+    /// The static auth secret of the TURN REST API.
+    StaticAuthSecret,
+    /// The password of the hooks service.
+    Hooks,
+}
+
+/// The state of an interface as reported by the server, see
+/// [`Observer::get_interface_state`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InterfaceState {
+    /// The socket of the interface is bound and receiving.
+    Started,
+    /// The socket of the interface could not be bound.
+    Failed,
+    /// The socket of the interface stopped receiving.
+    Stopped,
+}
 
 #[derive(Clone)]
 pub struct Observer {
     config: Arc<Config>,
+    credentials: Arc<Credentials>,
+    auth_methods: Arc<RwLock<AHashMap<SessionAddr, AuthMethod>>>,
+    // The state of each interface by its external address and the transport of its sessions.
+    interfaces: Arc<RwLock<AHashMap<(SocketAddr, Transport), InterfaceState>>>,
+    resolver: Arc<Resolver>,
+    geolocator: Arc<Geolocator>,
+    mirror: Mirror,
+    flows: FlowExporter,
+    tracer: Tracer,
+    shedder: LoadShedder,
+    limiter: RateLimiter,
+    request_limiter: RequestLimiter,
+    cascade: Cascade,
+    router: Router,
     #[cfg(feature = "hooks")]
     hooks: Arc<HooksService>,
-    #[cfg(feature = "api")]
+    #[cfg(any(feature = "nats", feature = "kafka"))]
+    sinks: Arc<EventSinks>,
+    #[cfg(any(feature = "api", feature = "hooks", feature = "nats", feature = "kafka"))]
     statistics: Statistics,
+    // The traffic of each session when its last `traffic` event was sent.
+    #[cfg(any(feature = "api", feature = "hooks", feature = "nats", feature = "kafka"))]
+    reported: Arc<Mutex<AHashMap<SessionAddr, [u64; 4]>>>,
+    #[cfg(feature = "api")]
+    stream: EventStream,
 }
 
 impl Observer {
     #[allow(unused_variables)]
-    pub async fn new(config: Arc<Config>, statistics: Statistics) -> Result<Self> {
+    pub async fn new(config: Arc<Config>, statistics: Statistics, router: Router) -> Result<Self> {
+        let resolver = Arc::new(Resolver::new(&config.turn.interfaces));
+        resolver.start(Duration::from_secs(config.turn.resolve_interval));
+
         Ok(Self {
+            credentials: Arc::new(Credentials::new(&config.auth)?),
+            auth_methods: Default::default(),
+            interfaces: Default::default(),
+            resolver,
+            geolocator: Arc::new(Geolocator::new(&config.geoip)?),
+            mirror: Mirror::new(&config.mirror)?,
+            flows: FlowExporter::new(&config.ipfix)?,
+            tracer: Tracer::default(),
+            shedder: LoadShedder::new(config.turn.shedding.as_ref(), router.clone()),
+            limiter: RateLimiter::new(config.turn.bandwidth.as_ref()),
+            request_limiter: RequestLimiter::new(config.turn.request_rate.as_ref()),
+            cascade: Cascade::new(&config.cascade, router.clone()),
+            router,
             #[cfg(feature = "hooks")]
             hooks: Arc::new(HooksService::new(config.clone())?),
-            #[cfg(feature = "api")]
+            #[cfg(any(feature = "nats", feature = "kafka"))]
+            sinks: Arc::new(EventSinks::new(config.clone())),
+            #[cfg(any(feature = "api", feature = "hooks", feature = "nats", feature = "kafka"))]
             statistics,
+            #[cfg(any(feature = "api", feature = "hooks", feature = "nats", feature = "kafka"))]
+            reported: Default::default(),
+            #[cfg(feature = "api")]
+            stream: EventStream::default(),
             config,
         })
     }
+
+    /// The static credentials, including the ones managed at runtime.
+    pub fn get_credentials(&self) -> &Credentials {
+        &self.credentials
+    }
+
+    /// The mechanism that the session was authenticated with.
+    pub fn get_auth_method(&self, addr: &SessionAddr) -> Option<AuthMethod> {
+        self.auth_methods.read().get(addr).copied()
+    }
+
+    // The mechanism that the password of the username is looked up with, in the
+    // order of the lookup.
+    fn auth_method(&self, username: &str) -> AuthMethod {
+        if self.credentials.get(username).is_some() {
+            AuthMethod::StaticCredentials
+        } else if self.config.auth.static_auth_secret.is_some() {
+            AuthMethod::StaticAuthSecret
+        } else {
+            AuthMethod::Hooks
+        }
+    }
+
+    /// The state of the interface with the external address over the
+    /// transport, `None` if the server has not tried to start it yet.
+    pub fn get_interface_state(&self, interface: &SocketAddr, transport: Transport) -> Option<InterfaceState> {
+        self.interfaces.read().get(&(*interface, transport)).copied()
+    }
+
+    /// The location of the session, `None` if no geoip database is loaded.
+    pub fn get_location(&self, addr: &SessionAddr) -> Option<Location> {
+        self.geolocator.lookup(addr.address.ip())
+    }
+
+    /// The sessions whose relayed data is mirrored to a multicast group.
+    pub fn get_mirror(&self) -> &Mirror {
+        &self.mirror
+    }
+
+    /// Exports the traffic of the relayed flows to an IPFIX collector.
+    pub fn get_flows(&self) -> &FlowExporter {
+        &self.flows
+    }
+
+    /// The sessions whose forwarded packets are traced.
+    pub fn get_tracer(&self) -> &Tracer {
+        &self.tracer
+    }
+
+    /// Decides whether new allocations are shed.
+    pub fn get_shedder(&self) -> &LoadShedder {
+        &self.shedder
+    }
+
+    /// Limits the bandwidth of the relayed data.
+    pub fn get_limiter(&self) -> &RateLimiter {
+        &self.limiter
+    }
+
+    /// Limits the rate of the unauthenticated requests.
+    pub fn get_request_limiter(&self) -> &RequestLimiter {
+        &self.request_limiter
+    }
+
+    /// Bridges the sessions to the peers of an upstream turn server.
+    pub fn get_cascade(&self) -> &Cascade {
+        &self.cascade
+    }
+
+    /// Forwards the data between the sockets of the interfaces.
+    pub fn get_router(&self) -> &Router {
+        &self.router
+    }
+
+    /// The events of the sessions for the clients of the api.
+    #[cfg(feature = "api")]
+    pub fn get_event_stream(&self) -> &EventStream {
+        &self.stream
+    }
+
+    /// The number of bytes held by the events waiting to be pushed to the
+    /// hooks services and the event sinks.
+    pub fn get_queued_event_bytes(&self) -> usize {
+        #[allow(unused_mut)]
+        let mut bytes = 0;
+
+        #[cfg(feature = "hooks")]
+        {
+            bytes += self.hooks.queued_bytes();
+        }
+
+        #[cfg(any(feature = "nats", feature = "kafka"))]
+        {
+            bytes += self.sinks.queued_bytes();
+        }
+
+        bytes
+    }
+
+    /// Wait for the queued events to be taken by the hooks services and the
+    /// event sinks, for at most the timeout.
+    pub async fn flush_events(&self, timeout: Duration) {
+        let deadline = Instant::now() + timeout;
+        while self.get_queued_event_bytes() > 0 && Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+
+    /// Send a `traffic` event for every session that relayed data since the
+    /// previous one, every `api.traffic_interval` seconds, in a background
+    /// task that stops with the sessions.
+    #[allow(unused_variables)]
+    pub fn start_traffic_reports(&self, sessions: &Arc<Sessions<Self>>) {
+        #[cfg(any(feature = "api", feature = "hooks", feature = "nats", feature = "kafka"))]
+        if let Some(interval) = self.config.api.traffic_interval.filter(|it| *it > 0) {
+            let this = self.clone();
+            let sessions = Arc::downgrade(sessions);
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(Duration::from_secs(interval));
+                interval.tick().await;
+
+                loop {
+                    interval.tick().await;
+
+                    if let Some(sessions) = sessions.upgrade() {
+                        this.report_traffic(&sessions);
+                    } else {
+                        break;
+                    }
+                }
+            });
+        }
+    }
+
+    #[cfg(any(feature = "api", feature = "hooks", feature = "nats", feature = "kafka"))]
+    fn report_traffic(&self, sessions: &Sessions<Self>) {
+        // The table of the sessions is not locked while the events are sent.
+        let allocations = sessions
+            .get_all_sessions()
+            .iter()
+            .filter(|(_, v)| v.allocate.port.is_some())
+            .map(|(k, v)| (*k, v.auth.username.clone()))
+            .collect::<Vec<_>>();
+
+        for (addr, name) in allocations {
+            let traffic = match self.take_traffic(&addr) {
+                Some(it) if it.iter().any(|it| *it > 0) => it,
+                _ => continue,
+            };
+
+            self.emit(
+                &addr,
+                &name,
+                json!({
+                    "kind": "traffic",
+                    "session": {
+                        "address": addr.address,
+                        "interface": addr.interface,
+                    },
+                    "username": name,
+                    "traffic": traffic_to_json(&traffic),
+                }),
+            );
+        }
+    }
+
+    // The traffic of the session since its last `traffic` event, which is counted as
+    // reported from now on.
+    #[cfg(any(feature = "api", feature = "hooks", feature = "nats", feature = "kafka"))]
+    fn take_traffic(&self, addr: &SessionAddr) -> Option<[u64; 4]> {
+        let counts = self.statistics.get(addr)?;
+        let current = [
+            counts.received_bytes,
+            counts.send_bytes,
+            counts.received_pkts,
+            counts.send_pkts,
+        ];
+
+        let reported = self.reported.lock().insert(*addr, current).unwrap_or_default();
+        Some([0, 1, 2, 3].map(|i| current[i].saturating_sub(reported[i])))
+    }
+
+    /// Whether the hooks services can be reached, there is no need to check
+    /// when the hooks feature is not enabled.
+    pub async fn is_hooks_reachable(&self) -> bool {
+        #[cfg(feature = "hooks")]
+        {
+            self.hooks.is_reachable().await
+        }
+
+        #[cfg(not(feature = "hooks"))]
+        {
+            true
+        }
+    }
+
+    // Push the session event to the api event stream, the hooks service and the
+    // event sinks.
+    #[cfg(any(feature = "api", feature = "hooks", feature = "nats", feature = "kafka"))]
+    #[allow(unused_variables)]
+    fn emit(&self, addr: &SessionAddr, username: &str, event: Value) {
+        #[cfg(feature = "api")]
+        {
+            self.stream.emit(&event);
+        }
+
+        #[cfg(any(feature = "nats", feature = "kafka"))]
+        {
+            self.sinks.emit(addr, &event);
+        }
+
+        #[cfg(feature = "hooks")]
+        {
+            self.hooks.emit(addr, username, event);
+        }
+    }
+
+    // Push the change of the client address to interface mappings, for the
+    // load balancers in front of the interfaces.
+    #[cfg(any(feature = "api", feature = "hooks", feature = "nats", feature = "kafka"))]
+    fn emit_affinity(&self, addr: &SessionAddr, username: &str, added: &[SessionAddr], removed: &[SessionAddr]) {
+        let sessions = |it: &[SessionAddr]| {
+            it.iter()
+                .map(|it| {
+                    json!({
+                        "address": it.address,
+                        "interface": it.interface,
+                    })
+                })
+                .collect::<Vec<_>>()
+        };
+
+        self.emit(
+            addr,
+            username,
+            json!({
+                "kind": "affinity",
+                "session": {
+                    "address": addr.address,
+                    "interface": addr.interface,
+                },
+                "username": username,
+                "added": sessions(added),
+                "removed": sessions(removed),
+            }),
+        );
+    }
 }
 
 impl turn::Observer for Observer {
-    fn get_password(&self, addr: &SessionAddr, username: &str) -> impl Future<Output = Option<String>> + Send {
-        async move {
+    /// The origin is only passed on to the hooks service, static credentials
+    /// and the static auth secret apply to every origin. The clients that
+    /// `turn.acl` does not allow are refused before any lookup, otherwise
+    /// only the hooks service can refuse a session or fail to look up the
+    /// password. The passwords of the hooks service are cached as their
+    /// digests, see [`crate::cache::CredentialCache`].
+    #[allow(unused_variables)]
+    async fn try_get_credential(
+        &self,
+        addr: &SessionAddr,
+        username: &str,
+        realm: &str,
+        origin: Option<&str>,
+    ) -> Result<Option<Credential>, PasswordError> {
+        log::info!(
+            "auth: address={:?}, interface={:?}, username={:?}, origin={:?}",
+            addr.address,
+            addr.interface,
+            username,
+            origin,
+        );
+
+        if !self.config.turn.acl.is_client_allowed(username, &addr.address.ip()) {
             log::info!(
-                "auth: address={:?}, interface={:?}, username={:?}",
+                "client denied: address={:?}, interface={:?}, username={:?}",
                 addr.address,
                 addr.interface,
                 username,
             );
 
-            // Match the static authentication information first.
-            if let Some(it) = self.config.auth.static_credentials.get(username) {
-                return Some(it.clone());
-            }
+            return Err(PasswordError::Forbidden);
+        }
+
+        // Match the static authentication information first.
+        if let Some(it) = self.credentials.get(username) {
+            return Ok(Some(Credential::Password(it)));
+        }
+
+        // Try again to match the static authentication key.
+        if let Some(it) = &self.config.auth.static_auth_secret {
+            // The username carries the time at which the credential expires, a username
+            // without it would never expire, so it is refused like an expired one.
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|it| it.as_secs())
+                .unwrap_or(0);
 
-            // Try again to match the static authentication key.
-            if let Some(it) = &self.config.auth.static_auth_secret {
-                // Because (TURN REST api) this RFC does not mandate the format of the username,
-                // only suggested values. In principle, the RFC also indicates that the
-                // timestamp part of username can be set at will, so the timestamp is not
-                // verified here, and the external web service guarantees its security by
-                // itself.
-                return encode_password(it, username);
+            match stun::auth::static_auth_secret_expires(username) {
+                Some(expires) if expires >= now => (),
+                _ => {
+                    log::info!(
+                        "auth secret expired: address={:?}, interface={:?}, username={:?}",
+                        addr.address,
+                        addr.interface,
+                        username,
+                    );
+
+                    return Ok(None);
+                }
             }
 
-            #[cfg(feature = "hooks")]
+            let password = match stun::auth::static_auth_secret_password(it, username) {
+                Ok(it) => it,
+                Err(_) => return Ok(None),
+            };
+
+            return Ok(Some(Credential::Password(password)));
+        }
+
+        #[cfg(feature = "hooks")]
+        {
+            let location = self.get_location(addr);
+            if let Some(it) = self
+                .hooks
+                .get_credential(addr, username, realm, origin, location.as_ref())
+                .await?
             {
-                if let Some(it) = self.hooks.get_password(addr, username).await {
-                    return Some(it);
-                }
+                return Ok(Some(it));
             }
+        }
+
+        Ok(None)
+    }
+
+    /// password lifetime
+    ///
+    /// The static credentials added with a time to live and the credentials
+    /// of the static auth secret, which expire at the unix time in their
+    /// username, close the sessions authenticated with them when they expire,
+    /// like coturn does, however often the sessions are refreshed.
+    fn get_password_lifetime(&self, _: &SessionAddr, username: &str) -> Option<u64> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|it| it.as_secs())
+            .unwrap_or(0);
 
+        // The password was looked up the same way, static credentials first.
+        let expires = if self.credentials.get(username).is_some() {
+            self.credentials.expires(username)
+        } else if self.config.auth.static_auth_secret.is_some() {
+            stun::auth::static_auth_secret_expires(username)
+        } else {
             None
+        };
+
+        expires.map(|it| it.saturating_sub(now))
+    }
+
+    /// select relay address
+    ///
+    /// The first matching rule of `turn.relay_addresses` decides the relay
+    /// address of the allocation, otherwise an interface whose external
+    /// address is a host name uses the latest resolved address of the host
+    /// name.
+    fn get_relay_ip(&self, addr: &SessionAddr, name: &str) -> Option<IpAddr> {
+        self.config
+            .turn
+            .relay_addresses
+            .iter()
+            .find(|it| {
+                it.matches
+                    .matches(self.config.turn.get_realm(&addr.interface), &addr.interface, name)
+            })
+            .map(|it| it.address)
+            .or_else(|| self.resolver.get(&addr.interface))
+    }
+
+    /// shed allocate request
+    ///
+    /// New allocations are shed while the router queue or the processing time
+    /// of the requests exceeds the thresholds of `turn.shedding`.
+    fn shed_allocate(&self, addr: &SessionAddr) -> bool {
+        if !self.shedder.shed() {
+            return false;
         }
+
+        sampled!(
+            log::Level::Warn,
+            "allocate shed: address={:?}, interface={:?}, queue={}, latency={:?}",
+            addr.address,
+            addr.interface,
+            self.router.queue_depth(),
+            self.shedder.latency()
+        );
+
+        #[cfg(feature = "prometheus")]
+        crate::statistics::prometheus::METRICS.shed.inc();
+
+        true
     }
 
     /// allocate request
@@ -87,31 +552,46 @@ impl turn::Observer for Observer {
     /// Known Port range) to discourage clients from using TURN to run
     /// standard services.
     #[allow(clippy::let_underscore_future)]
-    fn allocated(&self, addr: &SessionAddr, name: &str, port: u16) {
+    fn allocated(&self, addr: &SessionAddr, name: &str, port: RelayPort, transport: Transport) {
         log::info!(
-            "allocate: address={:?}, interface={:?}, username={:?}, port={}",
+            "allocate: address={:?}, interface={:?}, username={:?}, port={}, transport={:?}",
             addr.address,
             addr.interface,
             name,
-            port
+            port,
+            transport
         );
 
-        #[cfg(feature = "api")]
+        // The password lookup does not know whether the client can prove that it knows
+        // the password, the mechanism is recorded once the allocation has been
+        // authenticated.
+        self.auth_methods.write().insert(*addr, self.auth_method(name));
+        self.flows.allocated(addr, port, transport);
+
+        #[cfg(any(feature = "api", feature = "hooks", feature = "nats", feature = "kafka"))]
         {
             self.statistics.register(*addr);
         }
 
-        #[cfg(feature = "hooks")]
+        #[cfg(any(feature = "api", feature = "hooks", feature = "nats", feature = "kafka"))]
         {
-            self.hooks.emit(json!({
-                "kind": "allocated",
-                "session": {
-                    "address": addr.address,
-                    "interface": addr.interface,
-                },
-                "username": name,
-                "port": port,
-            }));
+            self.emit(
+                addr,
+                name,
+                json!({
+                    "kind": "allocated",
+                    "session": {
+                        "address": addr.address,
+                        "interface": addr.interface,
+                    },
+                    "username": name,
+                    "port": port.get(),
+                    "transport": transport,
+                    "location": self.get_location(addr),
+                }),
+            );
+
+            self.emit_affinity(addr, name, &[*addr], &[]);
         }
     }
 
@@ -146,7 +626,7 @@ impl turn::Observer for Observer {
     /// transaction would initially fail but succeed on a
     /// retransmission.
     #[allow(clippy::let_underscore_future)]
-    fn channel_bind(&self, addr: &SessionAddr, name: &str, channel: u16) {
+    fn channel_bind(&self, addr: &SessionAddr, name: &str, channel: ChannelNumber) {
         log::info!(
             "channel bind: address={:?}, interface={:?}, username={:?}, channel={}",
             addr.address,
@@ -155,17 +635,21 @@ impl turn::Observer for Observer {
             channel
         );
 
-        #[cfg(feature = "hooks")]
+        #[cfg(any(feature = "api", feature = "hooks", feature = "nats", feature = "kafka"))]
         {
-            self.hooks.emit(json!({
-                "kind": "channel_bind",
-                "session": {
-                    "address": addr.address,
-                    "interface": addr.interface,
-                },
-                "username": name,
-                "channel": channel,
-            }));
+            self.emit(
+                addr,
+                name,
+                json!({
+                    "kind": "channel_bind",
+                    "session": {
+                        "address": addr.address,
+                        "interface": addr.interface,
+                    },
+                    "username": name,
+                    "channel": channel.get(),
+                }),
+            );
         }
     }
 
@@ -205,11 +689,13 @@ impl turn::Observer for Observer {
     /// There are no mandatory attributes in the success response.
     ///
     /// > NOTE: A server need not do anything special to implement
-    /// idempotency of CreatePermission requests over UDP using the
-    /// "stateless stack approach".  Retransmitted CreatePermission
-    /// requests will simply refresh the permissions.
+    /// > idempotency of CreatePermission requests over UDP using the
+    /// > "stateless stack approach".  Retransmitted CreatePermission
+    /// > requests will simply refresh the permissions.
     #[allow(clippy::let_underscore_future)]
-    fn create_permission(&self, addr: &SessionAddr, name: &str, ports: &[u16]) {
+    fn create_permission(&self, addr: &SessionAddr, name: &str, ports: &[PeerPort]) {
+        let ports = ports.iter().map(|it| it.get()).collect::<Vec<_>>();
+
         log::info!(
             "create permission: address={:?}, interface={:?}, username={:?}, ports={:?}",
             addr.address,
@@ -218,20 +704,85 @@ impl turn::Observer for Observer {
             ports
         );
 
+        #[cfg(any(feature = "api", feature = "hooks", feature = "nats", feature = "kafka"))]
+        {
+            self.emit(
+                addr,
+                name,
+                json!({
+                    "kind": "create_permission",
+                    "session": {
+                        "address": addr.address,
+                        "interface": addr.interface,
+                    },
+                    "username": name,
+                    "ports": ports,
+                }),
+            );
+        }
+    }
+
+    fn is_external(&self, peer: &SocketAddr) -> bool {
+        self.cascade.is_external(peer)
+    }
+
+    /// The peers that are relay ports of this server are sessions of this
+    /// server and reach no other host, only the external peers are checked
+    /// against `turn.acl` for the username. The hooks service is asked about
+    /// every peer that `turn.acl` allows.
+    async fn is_peer_allowed(&self, addr: &SessionAddr, name: &str, peer: &SocketAddr) -> bool {
+        if self.cascade.is_external(peer) && !self.config.turn.acl.is_peer_allowed(name, &peer.ip()) {
+            log::info!(
+                "peer denied: address={:?}, interface={:?}, username={:?}, peer={:?}",
+                addr.address,
+                addr.interface,
+                name,
+                peer
+            );
+
+            return false;
+        }
+
         #[cfg(feature = "hooks")]
         {
-            self.hooks.emit(json!({
-                "kind": "create_permission",
-                "session": {
-                    "address": addr.address,
-                    "interface": addr.interface,
-                },
-                "username": name,
-                "ports": ports,
-            }));
+            self.hooks.allow_peer(addr, name, peer).await
+        }
+
+        #[cfg(not(feature = "hooks"))]
+        {
+            true
         }
     }
 
+    fn external_permission(&self, addr: &SessionAddr, name: &str, peers: &[SocketAddr]) {
+        log::info!(
+            "external permission: address={:?}, interface={:?}, username={:?}, peers={:?}",
+            addr.address,
+            addr.interface,
+            name,
+            peers
+        );
+
+        self.cascade.permit(addr, peers);
+    }
+
+    fn relay_external(&self, addr: &SessionAddr, peer: &SocketAddr, data: &[u8]) {
+        self.cascade.relay(addr, peer, data);
+    }
+
+    /// The relays of the TCP allocations forward to the connections of their
+    /// clients, which are the routes of the connections.
+    fn connection_attempt(&self, peer: &Endpoint, message: &[u8]) {
+        log::info!("connection attempt: address={:?}, port={}", peer.address, peer.port);
+
+        self.router.send(
+            &peer.endpoint,
+            ResponseMethod::Stun(Method::ConnectionAttemptIndication),
+            &peer.address,
+            message,
+        );
+    }
+
     /// refresh request
     ///
     /// If the server receives a Refresh Request with a REQUESTED-ADDRESS-
@@ -251,17 +802,17 @@ impl turn::Observer for Observer {
     /// Subsequent processing depends on the "desired lifetime" value:
     ///
     /// * If the "desired lifetime" is zero, then the request succeeds and
-    /// the allocation is deleted.
+    ///   the allocation is deleted.
     ///
     /// * If the "desired lifetime" is non-zero, then the request succeeds
-    /// and the allocation's time-to-expiry is set to the "desired
-    /// lifetime".
+    ///   and the allocation's time-to-expiry is set to the "desired
+    ///   lifetime".
     ///
     /// If the request succeeds, then the server sends a success response
     /// containing:
     ///
     /// * A LIFETIME attribute containing the current value of the time-to-
-    /// expiry timer.
+    ///   expiry timer.
     ///
     /// NOTE: A server need not do anything special to implement
     /// idempotency of Refresh requests over UDP using the "stateless
@@ -281,61 +832,359 @@ impl turn::Observer for Observer {
             lifetime
         );
 
-        #[cfg(feature = "hooks")]
+        #[cfg(any(feature = "api", feature = "hooks", feature = "nats", feature = "kafka"))]
         {
-            self.hooks.emit(json!({
-                "kind": "refresh",
-                "session": {
-                    "address": addr.address,
-                    "interface": addr.interface,
-                },
-                "username": name,
-                "lifetime": lifetime,
-            }));
+            self.emit(
+                addr,
+                name,
+                json!({
+                    "kind": "refresh",
+                    "session": {
+                        "address": addr.address,
+                        "interface": addr.interface,
+                    },
+                    "username": name,
+                    "lifetime": lifetime,
+                }),
+            );
         }
     }
 
     /// session closed
     ///
     /// Triggered when the session leaves from the turn. Possible reasons: the
-    /// session life cycle has expired, external active deletion, or active
-    /// exit of the session.
+    /// session life cycle has expired, external active deletion, active exit
+    /// of the session, the allocation has been idle for too long, or the
+    /// server is shutting down.
     #[allow(clippy::let_underscore_future)]
-    fn closed(&self, addr: &SessionAddr, name: &str) {
+    fn closed(&self, addr: &SessionAddr, name: &str, reason: CloseReason) {
+        let reason = match reason {
+            CloseReason::Expired => "expired",
+            CloseReason::Client => "client",
+            CloseReason::Removed => "removed",
+            CloseReason::Idle => "idle",
+            CloseReason::Shutdown => "shutdown",
+            CloseReason::Revoked => "revoked",
+        };
+
         log::info!(
-            "closed: address={:?}, interface={:?}, username={:?}",
+            "closed: address={:?}, interface={:?}, username={:?}, reason={}",
             addr.address,
             addr.interface,
-            name
+            name,
+            reason
         );
 
-        #[cfg(feature = "api")]
-        {
-            self.statistics.unregister(&addr);
-        }
+        self.auth_methods.write().remove(addr);
+        self.mirror.stop(addr);
+        self.flows.closed(addr);
+        self.tracer.stop(addr);
+        self.limiter.remove(addr);
+        self.cascade.close(addr);
 
-        #[cfg(feature = "hooks")]
+        #[cfg(any(feature = "api", feature = "hooks", feature = "nats", feature = "kafka"))]
         {
-            self.hooks.emit(json!({
+            let mut event = json!({
                 "kind": "closed",
                 "session": {
                     "address": addr.address,
                     "interface": addr.interface,
                 },
                 "username": name,
-            }));
+                "reason": reason,
+            });
+
+            // The traffic since the last `traffic` event completes the traffic events of
+            // the session.
+            if let Some(traffic) = self.take_traffic(addr) {
+                event["traffic"] = traffic_to_json(&traffic);
+            }
+
+            if let Some(counts) = self.statistics.get(addr) {
+                event["usage"] = json!({
+                    "received_bytes": counts.received_bytes,
+                    "send_bytes": counts.send_bytes,
+                    "received_pkts": counts.received_pkts,
+                    "send_pkts": counts.send_pkts,
+                    "error_pkts": counts.error_pkts,
+                    "rejected_pkts": counts.rejected_pkts,
+                    "retransmitted_pkts": counts.retransmitted_pkts,
+                    "dropped_pkts": counts.dropped_pkts,
+                });
+            }
+
+            self.emit(addr, name, event);
+            self.emit_affinity(addr, name, &[], &[*addr]);
+        }
+
+        #[cfg(any(feature = "api", feature = "hooks", feature = "nats", feature = "kafka"))]
+        {
+            self.statistics.unregister(addr);
+            self.reported.lock().remove(addr);
+        }
+    }
+
+    /// session transferred
+    ///
+    /// Triggered when a client that reconnected from another address re-claims
+    /// its existing allocation, the session moved from `from` to `addr`.
+    fn transferred(&self, addr: &SessionAddr, name: &str, from: &SessionAddr) {
+        log::info!(
+            "transferred: address={:?}, interface={:?}, username={:?}, from address={:?}, from interface={:?}",
+            addr.address,
+            addr.interface,
+            name,
+            from.address,
+            from.interface
+        );
+
+        {
+            let mut auth_methods = self.auth_methods.write();
+            if let Some(it) = auth_methods.remove(from) {
+                auth_methods.insert(*addr, it);
+            }
+        }
+
+        self.mirror.transfer(from, addr);
+        self.flows.transfer(from, addr);
+        self.tracer.transfer(from, addr);
+        self.cascade.transfer(from, addr);
+
+        #[cfg(any(feature = "api", feature = "hooks", feature = "nats", feature = "kafka"))]
+        {
+            self.statistics.transfer(from, *addr);
+
+            let mut reported = self.reported.lock();
+            if let Some(it) = reported.remove(from) {
+                reported.insert(*addr, it);
+            }
+        }
+
+        #[cfg(any(feature = "api", feature = "hooks", feature = "nats", feature = "kafka"))]
+        {
+            self.emit(
+                addr,
+                name,
+                json!({
+                    "kind": "transferred",
+                    "session": {
+                        "address": addr.address,
+                        "interface": addr.interface,
+                    },
+                    "username": name,
+                    "from": {
+                        "address": from.address,
+                        "interface": from.interface,
+                    },
+                }),
+            );
+
+            self.emit_affinity(addr, name, &[*addr], &[*from]);
+        }
+    }
+
+    /// data rejected
+    ///
+    /// Triggered when strict reverse path verification drops data sent from
+    /// the relay port `port` to the session.
+    #[allow(unused_variables)]
+    fn rejected(&self, addr: &SessionAddr, port: PeerPort, transport: Transport) {
+        sampled!(
+            log::Level::Debug,
+            "rejected: address={:?}, interface={:?}, port={}",
+            addr.address,
+            addr.interface,
+            port
+        );
+
+        #[cfg(any(feature = "api", feature = "hooks", feature = "nats", feature = "kafka"))]
+        {
+            self.statistics
+                .get_reporter(transport)
+                .send(addr, &[crate::statistics::Stats::RejectedPkts(1)]);
+        }
+    }
+
+    /// request retransmitted
+    ///
+    /// Triggered when the TRANSACTION-TRANSMIT-COUNTER attribute of a request
+    /// says that the client has sent it `count` times before without getting
+    /// a response through.
+    #[allow(unused_variables)]
+    fn retransmitted(&self, addr: &SessionAddr, method: Method, count: u8, transport: Transport) {
+        sampled!(
+            log::Level::Debug,
+            "retransmitted: address={:?}, interface={:?}, method={:?}, count={}",
+            addr.address,
+            addr.interface,
+            method,
+            count
+        );
+
+        #[cfg(any(feature = "api", feature = "hooks", feature = "nats", feature = "kafka"))]
+        {
+            self.statistics
+                .get_reporter(transport)
+                .send(addr, &[crate::statistics::Stats::RetransmittedPkts(count as u32)]);
+        }
+    }
+
+    /// session expiring
+    ///
+    /// Triggered once before the allocation of a session that has not
+    /// refreshed it expires. The clients are not notified, a Data indication
+    /// has to carry data of a peer, the warning is logged, counted and sent
+    /// to the hooks and the sinks.
+    fn expiring(&self, addr: &SessionAddr, name: &str, _: Transport, _: SocketAddr, lifetime: u64) {
+        log::info!(
+            "expiring: address={:?}, interface={:?}, username={:?}, lifetime={}",
+            addr.address,
+            addr.interface,
+            name,
+            lifetime
+        );
+
+        #[cfg(feature = "prometheus")]
+        crate::statistics::prometheus::METRICS.expiring.inc();
+
+        #[cfg(any(feature = "api", feature = "hooks", feature = "nats", feature = "kafka"))]
+        {
+            self.emit(
+                addr,
+                name,
+                json!({
+                    "kind": "expiring",
+                    "session": {
+                        "address": addr.address,
+                        "interface": addr.interface,
+                    },
+                    "username": name,
+                    "lifetime": lifetime,
+                }),
+            );
+        }
+    }
+
+    /// interface started
+    ///
+    /// Starting and stopping interfaces is already logged by the server, the
+    /// state of the interface is kept for the readiness of the server.
+    fn interface_started(&self, interface: SocketAddr, _: SocketAddr, transport: Transport) {
+        self.interfaces
+            .write()
+            .insert((interface, transport), InterfaceState::Started);
+    }
+
+    /// interface bind failed
+    ///
+    /// The startup fails with the error.
+    fn interface_failed(&self, interface: SocketAddr, bind: SocketAddr, transport: Transport, error: &std::io::Error) {
+        log::error!(
+            "interface bind failed: bind={}, external={}, transport={:?}, err={}",
+            bind,
+            interface,
+            transport,
+            error
+        );
+
+        self.interfaces
+            .write()
+            .insert((interface, transport), InterfaceState::Failed);
+    }
+
+    /// interface stopped
+    fn interface_stopped(&self, interface: SocketAddr, transport: Transport) {
+        self.interfaces
+            .write()
+            .insert((interface, transport), InterfaceState::Stopped);
+    }
+
+    /// credentials locked out
+    ///
+    /// Triggered when a username or a source address has failed
+    /// authentication too many times and is locked out.
+    fn locked(&self, addr: &SessionAddr, name: &str, key: &LockoutKey, lockout: u64) {
+        let target = match key {
+            LockoutKey::Username(_) => "username",
+            LockoutKey::Address(_) => "address",
+        };
+
+        log::warn!(
+            "locked: address={:?}, interface={:?}, username={:?}, target={}, lockout={}",
+            addr.address,
+            addr.interface,
+            name,
+            target,
+            lockout
+        );
+
+        #[cfg(any(feature = "api", feature = "hooks", feature = "nats", feature = "kafka"))]
+        {
+            self.emit(
+                addr,
+                name,
+                json!({
+                    "kind": "locked",
+                    "session": {
+                        "address": addr.address,
+                        "interface": addr.interface,
+                    },
+                    "username": name,
+                    "target": target,
+                    "lockout": lockout,
+                }),
+            );
+        }
+    }
+
+    /// authentication failed
+    ///
+    /// Triggered for every request that presents an unknown username or fails
+    /// the integrity check, tools such as fail2ban can block abusive sources
+    /// with the events.
+    fn auth_failed(&self, addr: &SessionAddr, name: &str, reason: AuthFailure) {
+        let reason = match reason {
+            AuthFailure::UnknownUsername => "unknown_username",
+            AuthFailure::Integrity => "integrity",
+        };
+
+        sampled!(
+            log::Level::Warn,
+            "auth failed: address={:?}, interface={:?}, username={:?}, reason={}",
+            addr.address,
+            addr.interface,
+            name,
+            reason
+        );
+
+        #[cfg(feature = "prometheus")]
+        crate::statistics::prometheus::METRICS.auth_failures.inc();
+
+        #[cfg(any(feature = "api", feature = "hooks", feature = "nats", feature = "kafka"))]
+        {
+            self.emit(
+                addr,
+                name,
+                json!({
+                    "kind": "auth_failed",
+                    "session": {
+                        "address": addr.address,
+                        "interface": addr.interface,
+                    },
+                    "username": name,
+                    "reason": reason,
+                }),
+            );
         }
     }
 }
 
-// https://datatracker.ietf.org/doc/html/draft-uberti-behave-turn-rest-00#section-2.2
-fn encode_password(key: &str, username: &str) -> Option<String> {
-    Some(
-        BASE64_STANDARD.encode(
-            stun::util::hmac_sha1(key.as_bytes(), &[username.as_bytes()])
-                .ok()?
-                .into_bytes()
-                .as_slice(),
-        ),
-    )
+#[cfg(any(feature = "api", feature = "hooks", feature = "nats", feature = "kafka"))]
+fn traffic_to_json(traffic: &[u64; 4]) -> Value {
+    json!({
+        "received_bytes": traffic[0],
+        "send_bytes": traffic[1],
+        "received_pkts": traffic[2],
+        "send_pkts": traffic[3],
+    })
 }
@@ -0,0 +1,168 @@
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+
+use crate::config;
+
+/// Why a cpu profile is not taken.
+#[derive(Debug)]
+pub enum ProfileError {
+    /// The turn server is built without the `pprof` feature, or not for a
+    /// unix target.
+    Unsupported,
+    /// The duration is zero or longer than the configured maximum.
+    InvalidDuration,
+    /// A profile is running or has ended within the cooldown, the next one
+    /// can be started after the duration.
+    Busy(Duration),
+    Failed(String),
+}
+
+impl fmt::Display for ProfileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unsupported => write!(f, "profiling is not supported"),
+            Self::InvalidDuration => write!(f, "invalid duration"),
+            Self::Busy(it) => write!(f, "busy, retry after {:?}", it),
+            Self::Failed(it) => write!(f, "profiling failed: {}", it),
+        }
+    }
+}
+
+impl std::error::Error for ProfileError {}
+
+/// Takes cpu profiles of the running turn server.
+///
+/// Sampling the threads costs cpu time that the relay is missing, the
+/// profiles are therefore kept within a budget: a profile is at most
+/// `max_duration` long, only one is taken at a time, and after it has ended
+/// the next one can only be started when the cooldown has passed.
+///
+/// # Example
+///
+/// ```
+/// use std::time::Duration;
+///
+/// use turn_server::{config, profiling::*};
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let profiler = Profiler::new(&config::Profiling::default());
+///
+///     let ret = profiler.flamegraph(Duration::ZERO).await;
+///     assert!(matches!(
+///         ret,
+///         Err(ProfileError::InvalidDuration | ProfileError::Unsupported)
+///     ));
+///
+///     let ret = profiler.flamegraph(Duration::from_secs(3600)).await;
+///     assert!(matches!(
+///         ret,
+///         Err(ProfileError::InvalidDuration | ProfileError::Unsupported)
+///     ));
+/// }
+/// ```
+pub struct Profiler {
+    max_duration: Duration,
+    cooldown: Duration,
+    frequency: i32,
+    // When the next profile can be started.
+    next: Mutex<Option<Instant>>,
+}
+
+impl Profiler {
+    pub fn new(config: &config::Profiling) -> Self {
+        Self {
+            max_duration: Duration::from_secs(config.max_duration),
+            cooldown: Duration::from_secs(config.cooldown),
+            frequency: config.frequency.clamp(1, 1000),
+            next: Mutex::new(None),
+        }
+    }
+
+    /// Whether the turn server is built with the `pprof` feature for a unix
+    /// target.
+    pub fn is_supported() -> bool {
+        cfg!(all(feature = "pprof", unix))
+    }
+
+    /// Profile the turn server for `duration` and render the samples as a
+    /// flame graph in SVG.
+    pub async fn flamegraph(&self, duration: Duration) -> Result<Vec<u8>, ProfileError> {
+        if !Self::is_supported() {
+            return Err(ProfileError::Unsupported);
+        }
+
+        if duration.is_zero() || duration > self.max_duration {
+            return Err(ProfileError::InvalidDuration);
+        }
+
+        {
+            let now = Instant::now();
+            let mut next = self.next.lock();
+            if let Some(it) = *next {
+                if it > now {
+                    return Err(ProfileError::Busy(it - now));
+                }
+            }
+
+            // Taken while the profile is running, and moved to the end of the
+            // cooldown once it has ended.
+            next.replace(now + duration + self.cooldown);
+        }
+
+        log::info!(
+            "profiling started: duration={:?}, frequency={}",
+            duration,
+            self.frequency
+        );
+
+        let ret = profile(duration, self.frequency).await;
+        self.next.lock().replace(Instant::now() + self.cooldown);
+
+        match &ret {
+            Ok(_) => log::info!("profiling finished"),
+            Err(e) => log::error!("{}", e),
+        }
+
+        ret
+    }
+}
+
+#[cfg(all(feature = "pprof", unix))]
+async fn profile(duration: Duration, frequency: i32) -> Result<Vec<u8>, ProfileError> {
+    let failed = |e: pprof::Error| ProfileError::Failed(e.to_string());
+
+    // The samples are taken by a signal handler, the thread only waits for the
+    // duration and renders the report, which is kept off the runtime.
+    tokio::task::spawn_blocking(move || {
+        let guard = pprof::ProfilerGuardBuilder::default()
+            .frequency(frequency)
+            .blocklist(&["libc", "libgcc", "pthread", "vdso"])
+            .build()
+            .map_err(failed)?;
+
+        std::thread::sleep(duration);
+
+        let mut svg = Vec::with_capacity(64 * 1024);
+        guard
+            .report()
+            .build()
+            .map_err(failed)?
+            .flamegraph(&mut svg)
+            .map_err(failed)?;
+
+        Ok(svg)
+    })
+    .await
+    .map_err(|e| ProfileError::Failed(e.to_string()))?
+}
+
+#[cfg(not(all(feature = "pprof", unix)))]
+#[allow(unused_variables)]
+async fn profile(duration: Duration, frequency: i32) -> Result<Vec<u8>, ProfileError> {
+    Err(ProfileError::Unsupported)
+}
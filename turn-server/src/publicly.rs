@@ -13,30 +13,101 @@ static NONCE: Lazy<String> = Lazy::new(|| {
 
 #[cfg(feature = "api")]
 pub mod api {
-    use std::{net::SocketAddr, sync::Arc, time::Instant};
+    use std::{
+        convert::Infallible,
+        net::SocketAddr,
+        pin::Pin,
+        sync::Arc,
+        time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+    };
 
     use axum::{
-        extract::{Query, State},
-        http::HeaderValue,
-        middleware,
-        response::{IntoResponse, Response},
-        routing::{delete, get},
+        body::{to_bytes, Body},
+        extract::{ConnectInfo, Path, Query, Request, State},
+        http::{
+            header::{AUTHORIZATION, CONTENT_TYPE, RETRY_AFTER},
+            HeaderMap, HeaderValue, Method, StatusCode,
+        },
+        middleware::{self, Next},
+        response::{
+            sse::{Event, KeepAlive, Sse},
+            IntoResponse, Response,
+        },
+        routing::{delete, get, put},
         Json, Router,
     };
 
-    use reqwest::StatusCode;
     use serde::Deserialize;
-    use serde_json::json;
-    use tokio::net::TcpListener;
-    use turn::{PortAllocatePools, Service, SessionAddr};
+    use serde_json::{json, Value};
+    use stun::{auth::opaque_string, Transport};
+    use tokio::{net::TcpListener, sync::broadcast, time::interval};
+    use tokio_stream::{
+        wrappers::{errors::BroadcastStreamRecvError, BroadcastStream, IntervalStream},
+        Stream, StreamExt,
+    };
+    use turn::{CloseReason, PortAllocatePools, Service, Session, SessionAddr, Sessions};
 
     use super::NONCE;
-    use crate::{config::Config, observer::Observer, statistics::Statistics};
+    use crate::{
+        audit::{redact, AuditRecord, Auditor},
+        config::Config,
+        memory::allocator,
+        observer::{InterfaceState, Observer},
+        profiling::{ProfileError, Profiler},
+        statistics::Statistics,
+    };
+
+    /// The number of events that a client of the event stream can fall behind
+    /// by before it misses events.
+    const EVENT_STREAM_CAPACITY: usize = 1024;
+
+    // An event of a session, encoded once for all clients of the event stream.
+    struct StreamEvent {
+        kind: String,
+        data: String,
+    }
+
+    /// The events of the sessions as they happen, for the clients of
+    /// `/events/stream`.
+    ///
+    /// The events are the same as the events of the hooks service, they are
+    /// only encoded while there are clients. A client that falls behind by
+    /// more than the capacity of the stream misses the oldest events, and is
+    /// told how many it missed.
+    #[derive(Clone)]
+    pub struct EventStream(broadcast::Sender<Arc<StreamEvent>>);
+
+    impl Default for EventStream {
+        fn default() -> Self {
+            Self(broadcast::channel(EVENT_STREAM_CAPACITY).0)
+        }
+    }
+
+    impl EventStream {
+        pub fn emit(&self, event: &Value) {
+            if self.0.receiver_count() == 0 {
+                return;
+            }
+
+            match serde_json::to_string(event) {
+                Ok(data) => {
+                    let _ = self.0.send(Arc::new(StreamEvent {
+                        kind: event["kind"].as_str().unwrap_or("unknown").to_string(),
+                        data,
+                    }));
+                }
+                Err(e) => log::error!("failed to encode event, err={}", e),
+            }
+        }
+    }
 
     struct AppState {
         config: Arc<Config>,
         service: Service<Observer>,
+        observer: Observer,
         statistics: Statistics,
+        profiler: Profiler,
+        auditor: Auditor,
         uptime: Instant,
     }
 
@@ -46,15 +117,285 @@ pub mod api {
         interface: SocketAddr,
     }
 
-    impl Into<SessionAddr> for SessionQueryFilter {
-        fn into(self) -> SessionAddr {
+    impl From<SessionQueryFilter> for SessionAddr {
+        fn from(val: SessionQueryFilter) -> Self {
             SessionAddr {
-                address: self.address,
-                interface: self.interface,
+                address: val.address,
+                interface: val.interface,
             }
         }
     }
 
+    #[derive(Deserialize)]
+    struct TraceQueryFilter {
+        address: SocketAddr,
+        interface: SocketAddr,
+        /// How long the session is traced, in seconds.
+        duration: Option<u64>,
+    }
+
+    #[derive(Deserialize)]
+    struct ProfileQueryFilter {
+        /// How long the server is profiled, in seconds.
+        seconds: Option<u64>,
+    }
+
+    #[derive(Deserialize)]
+    struct Credential {
+        username: String,
+        password: String,
+        /// The time to live of the credential, in seconds.
+        ttl: Option<u64>,
+    }
+
+    #[derive(Deserialize)]
+    struct SessionsQueryFilter {
+        transport: Option<Transport>,
+        username: Option<String>,
+        interface: Option<SocketAddr>,
+        /// The minimum of the bytes that the session has received and sent.
+        min_bytes: Option<u64>,
+        #[serde(default)]
+        offset: usize,
+        limit: Option<usize>,
+    }
+
+    #[derive(Deserialize)]
+    struct EventStreamQueryFilter {
+        /// The seconds between the traffic events, no traffic events if zero.
+        interval: Option<u64>,
+    }
+
+    #[derive(Deserialize, Default)]
+    #[serde(rename_all = "lowercase")]
+    enum AffinityFormat {
+        #[default]
+        Json,
+        Text,
+    }
+
+    #[derive(Deserialize)]
+    struct AffinityQueryFilter {
+        #[serde(default)]
+        format: AffinityFormat,
+    }
+
+    fn session_to_json(
+        state: &AppState,
+        sessions: &Sessions<Observer>,
+        addr: &SessionAddr,
+        session: &Session,
+    ) -> Value {
+        // The times of the session are relative to the timer of the session manager,
+        // they are converted to unix timestamps.
+        let now = sessions.now();
+        let unix = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|it| it.as_secs())
+            .unwrap_or(0);
+        let timestamp = |time: u64| unix.saturating_sub(now.saturating_sub(time));
+
+        // The permissions and channels without a lifetime last as long as the allocation.
+        let (permission_lifetimes, channel_lifetimes) = sessions.get_lifetimes(addr);
+
+        json!({
+            "username": session.auth.username,
+            "password": session.auth.password,
+            "permissions": session.permissions.iter().map(|it| it.get()).collect::<Vec<_>>(),
+            "channels": session.allocate.channels.iter().map(|it| it.get()).collect::<Vec<_>>(),
+            "permissions_count": session.permissions.len(),
+            "channels_count": session.allocate.channels.len(),
+            "permission_lifetimes": session
+                .permissions
+                .iter()
+                .map(|it| json!({ "port": it.get(), "lifetime": permission_lifetimes.get(it) }))
+                .collect::<Vec<_>>(),
+            "channel_lifetimes": session
+                .allocate
+                .channels
+                .iter()
+                .map(|it| json!({ "channel": it.get(), "lifetime": channel_lifetimes.get(it) }))
+                .collect::<Vec<_>>(),
+            "port": session.allocate.port.map(|it| it.get()),
+            "relay_ip": session.allocate.ip,
+            "additional_port": session.allocate.additional_port.map(|it| it.get()),
+            "additional_relay_ip": session.allocate.additional_ip,
+            "origin": session.auth.origin,
+            "expires": session.expires,
+            "transport": session.transport,
+            "auth_method": state.observer.get_auth_method(addr),
+            "created_at": timestamp(session.created),
+            "last_request_at": timestamp(session.activity.last_request()),
+            "last_data_at": session.activity.last_data().map(timestamp),
+            "nonce_issued_at": sessions.get_nonce_issued(addr).map(timestamp),
+            "location": state.observer.get_location(addr),
+            "mirrored": state.observer.get_mirror().is_mirrored(addr),
+            "traced": state.observer.get_tracer().is_traced(addr),
+            "upstream_relay": state.observer.get_cascade().get_relay(addr),
+        })
+    }
+
+    // Usernames are stored in the prepared form, the same as the usernames of the
+    // requests.
+    fn prepare_username(username: &str) -> String {
+        opaque_string(username)
+            .map(|it| it.into_owned())
+            .unwrap_or_else(|_| username.to_string())
+    }
+
+    /// The addresses of the sessions of the username.
+    fn get_user_sessions(state: &AppState, username: &str) -> Vec<SessionAddr> {
+        let username = prepare_username(username);
+        state
+            .service
+            .get_sessions()
+            .get_all_sessions()
+            .iter()
+            .filter(|(_, session)| session.auth.username == username)
+            .map(|(addr, _)| *addr)
+            .collect()
+    }
+
+    // The traffic of all sessions, the same counts as `/session/statistics`.
+    fn traffic_to_json(state: &AppState) -> Value {
+        let sessions = state.service.get_sessions();
+        let sessions = sessions
+            .get_all_sessions()
+            .iter()
+            .filter_map(|(addr, session)| {
+                let counts = state.statistics.get(addr)?;
+                Some(json!({
+                    "address": addr.address,
+                    "interface": addr.interface,
+                    "username": session.auth.username,
+                    "received_bytes": counts.received_bytes,
+                    "send_bytes": counts.send_bytes,
+                    "received_pkts": counts.received_pkts,
+                    "send_pkts": counts.send_pkts,
+                    "error_pkts": counts.error_pkts,
+                    "rejected_pkts": counts.rejected_pkts,
+                    "retransmitted_pkts": counts.retransmitted_pkts,
+                    "dropped_pkts": counts.dropped_pkts,
+                }))
+            })
+            .collect::<Vec<_>>();
+
+        json!({
+            "kind": "traffic",
+            "sessions": sessions,
+        })
+    }
+
+    async fn put_credential(State(state): State<Arc<AppState>>, Json(credential): Json<Credential>) -> StatusCode {
+        if opaque_string(&credential.username).is_err() {
+            return StatusCode::BAD_REQUEST;
+        }
+
+        match state.observer.get_credentials().insert(
+            &credential.username,
+            &credential.password,
+            credential.ttl.map(Duration::from_secs),
+        ) {
+            Ok(_) => StatusCode::OK,
+            Err(e) => {
+                log::error!("failed to store credential: {}", e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+
+    /// The sessions that were authenticated with the credential are closed, as
+    /// they would be when it expires.
+    fn remove_credential(state: &AppState, username: &str) -> StatusCode {
+        match state.observer.get_credentials().remove(username) {
+            Ok(true) => {
+                let sessions = state.service.get_sessions();
+                for addr in get_user_sessions(state, username) {
+                    sessions.close(&addr, CloseReason::Revoked);
+                }
+
+                StatusCode::OK
+            }
+            Ok(false) => StatusCode::NOT_FOUND,
+            Err(e) => {
+                log::error!("failed to remove credential: {}", e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+
+    /// Whether the request presents the bearer token, the endpoints guarded
+    /// by a token cannot be used if no token is configured.
+    fn is_authorized(token: Option<&str>, headers: &HeaderMap) -> bool {
+        let token = match token {
+            Some(it) => it,
+            None => return false,
+        };
+
+        headers
+            .get(AUTHORIZATION)
+            .and_then(|it| it.to_str().ok())
+            .and_then(|it| it.strip_prefix("Bearer "))
+            .map(|it| it == token)
+            .unwrap_or(false)
+    }
+
+    /// The name of the bearer token that the request presents, if any.
+    fn get_actor(state: &AppState, headers: &HeaderMap) -> Option<&'static str> {
+        if is_authorized(state.config.mirror.token.as_deref(), headers) {
+            Some("mirror")
+        } else if is_authorized(state.config.profiling.token.as_deref(), headers) {
+            Some("profiling")
+        } else {
+            None
+        }
+    }
+
+    /// Writes an audit record for every request that changes the state of the
+    /// server, and for the debugging endpoints.
+    async fn audit(
+        State(state): State<Arc<AppState>>,
+        ConnectInfo(remote): ConnectInfo<SocketAddr>,
+        req: Request,
+        next: Next,
+    ) -> Response {
+        let method = req.method().clone();
+        if matches!(method, Method::GET | Method::HEAD | Method::OPTIONS) && !req.uri().path().starts_with("/debug/") {
+            return next.run(req).await;
+        }
+
+        let actor = get_actor(&state, req.headers());
+        let path = req.uri().path().to_string();
+        let query = req.uri().query().map(ToString::to_string);
+
+        // The bodies of the api are small json documents, they are read to be audited
+        // and handed on to the handler.
+        let (parts, body) = req.into_parts();
+        let bytes = match to_bytes(body, 64 * 1024).await {
+            Ok(it) => it,
+            Err(_) => return StatusCode::PAYLOAD_TOO_LARGE.into_response(),
+        };
+
+        let body = serde_json::from_slice::<Value>(&bytes).ok().map(|mut it| {
+            redact(&mut it);
+            it
+        });
+
+        let res = next.run(Request::from_parts(parts, Body::from(bytes))).await;
+        state.auditor.record(&AuditRecord {
+            timestamp: AuditRecord::timestamp(),
+            method: method.to_string(),
+            status: res.status().as_u16(),
+            remote,
+            actor,
+            path,
+            query,
+            body,
+        });
+
+        res
+    }
+
     /// start http server
     ///
     /// Create an http server and start it, and you can access the controller
@@ -67,17 +408,77 @@ pub mod api {
     pub async fn start_server(
         config: Arc<Config>,
         service: Service<Observer>,
+        observer: Observer,
         statistics: Statistics,
     ) -> anyhow::Result<()> {
         let state = Arc::new(AppState {
+            profiler: Profiler::new(&config.profiling),
+            auditor: Auditor::new(&config.audit)?,
             config: config.clone(),
             uptime: Instant::now(),
+            observer,
             service,
             statistics,
         });
 
         #[allow(unused_mut)]
         let mut app = Router::new()
+            .route("/healthz", get(|| async { Json(json!({ "status": "ok" })) }))
+            .route(
+                "/readyz",
+                get(|State(state): State<Arc<AppState>>| async move {
+                    // The interfaces are ready once the server has bound them, and stop being
+                    // ready when their sockets stop receiving.
+                    let mut bound = 0;
+                    let mut failed = Vec::new();
+                    for it in &state.config.turn.interfaces {
+                        match state
+                            .observer
+                            .get_interface_state(&it.external, it.transport.session_transport())
+                        {
+                            Some(InterfaceState::Started) => bound += 1,
+                            other => failed.push(json!({
+                                "external": it.external,
+                                "transport": it.transport,
+                                "state": other,
+                            })),
+                        }
+                    }
+
+                    let interfaces = bound > 0 && failed.is_empty();
+                    let hooks = state.observer.is_hooks_reachable().await;
+                    let capacity = PortAllocatePools::capacity();
+                    let allocated = state.service.get_sessions().allocated();
+                    let port_pool = allocated < capacity;
+
+                    let ready = interfaces && hooks && port_pool;
+                    (
+                        if ready {
+                            StatusCode::OK
+                        } else {
+                            StatusCode::SERVICE_UNAVAILABLE
+                        },
+                        Json(json!({
+                            "status": if ready { "ok" } else { "unavailable" },
+                            "checks": {
+                                "interfaces": {
+                                    "ok": interfaces,
+                                    "bound": bound,
+                                    "failed": failed,
+                                },
+                                "hooks": {
+                                    "ok": hooks,
+                                },
+                                "port_pool": {
+                                    "ok": port_pool,
+                                    "allocated": allocated,
+                                    "capacity": capacity,
+                                },
+                            },
+                        })),
+                    )
+                }),
+            )
             .route(
                 "/info",
                 get(|State(app_state): State<Arc<AppState>>| async move {
@@ -88,6 +489,27 @@ pub mod api {
                         "interfaces": app_state.config.turn.interfaces,
                         "port_capacity": PortAllocatePools::capacity(),
                         "port_allocated": sessions.allocated(),
+                        "port_ranges": sessions
+                            .get_port_ranges()
+                            .into_iter()
+                            .map(|(range, allocated)| {
+                                json!({
+                                    "interface": range.interface,
+                                    "family": range.ipv6.map(|it| if it { "ipv6" } else { "ipv4" }),
+                                    "start": range.ports.start,
+                                    "end": range.ports.end.saturating_sub(1),
+                                    "capacity": range.ports.len(),
+                                    "allocated": allocated,
+                                    "remaining": range.ports.len().saturating_sub(allocated),
+                                })
+                            })
+                            .collect::<Vec<_>>(),
+                        "memory": {
+                            "sessions": sessions.memory_usage(),
+                            "router": app_state.observer.get_router().queued_bytes(),
+                            "events": app_state.observer.get_queued_event_bytes(),
+                            "allocator": allocator(),
+                        },
                     }))
                 }),
             )
@@ -95,22 +517,174 @@ pub mod api {
                 "/session",
                 get(
                     |Query(query): Query<SessionQueryFilter>, State(state): State<Arc<AppState>>| async move {
-                        if let Some(session) = state.service.get_sessions().get_session(&query.into()).get_ref() {
-                            Json(json!({
-                                "username": session.auth.username,
-                                "password": session.auth.password,
-                                "permissions": session.permissions,
-                                "channels": session.allocate.channels,
-                                "port": session.allocate.port,
-                                "expires": session.expires,
-                            }))
-                            .into_response()
+                        let addr: SessionAddr = query.into();
+                        let sessions = state.service.get_sessions();
+                        let lock = sessions.get_session(&addr);
+                        if let Some(session) = lock.get_ref() {
+                            Json(session_to_json(&state, &sessions, &addr, session)).into_response()
                         } else {
                             StatusCode::NOT_FOUND.into_response()
                         }
                     },
                 ),
             )
+            .route(
+                "/sessions",
+                get(
+                    |Query(query): Query<SessionsQueryFilter>, State(state): State<Arc<AppState>>| async move {
+                        let username = query.username.as_deref().map(prepare_username);
+
+                        let sessions = state.service.get_sessions();
+                        let table = sessions.get_all_sessions();
+                        let mut entries = table
+                            .iter()
+                            .filter(|(_, session)| query.transport.map(|it| it == session.transport).unwrap_or(true))
+                            .filter(|(_, session)| {
+                                username.as_ref().map(|it| it == &session.auth.username).unwrap_or(true)
+                            })
+                            .filter(|(addr, _)| query.interface.map(|it| it == addr.interface).unwrap_or(true))
+                            .filter(|(addr, _)| {
+                                query
+                                    .min_bytes
+                                    .map(|min| {
+                                        state
+                                            .statistics
+                                            .get(addr)
+                                            .map(|it| it.received_bytes + it.send_bytes >= min)
+                                            .unwrap_or(min == 0)
+                                    })
+                                    .unwrap_or(true)
+                            })
+                            .collect::<Vec<_>>();
+
+                        // The sessions are not kept in any order, they are sorted by the time they
+                        // were created so that the pages stay stable while sessions come and go.
+                        entries.sort_by_key(|(addr, session)| (session.created, addr.address, addr.interface));
+
+                        let total = entries.len();
+                        let entries = entries
+                            .into_iter()
+                            .skip(query.offset)
+                            .take(query.limit.unwrap_or(usize::MAX))
+                            .map(|(addr, session)| {
+                                let mut it = session_to_json(&state, &sessions, addr, session);
+                                it["address"] = json!(addr.address);
+                                it["interface"] = json!(addr.interface);
+                                it
+                            })
+                            .collect::<Vec<_>>();
+
+                        ([("X-Total-Count", total.to_string())], Json(entries))
+                    },
+                ),
+            )
+            .route(
+                "/sessions/by-user/:username",
+                get(
+                    |Path(username): Path<String>, State(state): State<Arc<AppState>>| async move {
+                        let sessions = state.service.get_sessions();
+                        let entries = get_user_sessions(&state, &username)
+                            .iter()
+                            .filter_map(|addr| {
+                                let mut it =
+                                    session_to_json(&state, &sessions, addr, sessions.get_session(addr).get_ref()?);
+                                it["address"] = json!(addr.address);
+                                it["interface"] = json!(addr.interface);
+                                Some(it)
+                            })
+                            .collect::<Vec<_>>();
+
+                        Json(entries)
+                    },
+                )
+                .delete(
+                    |Path(username): Path<String>, State(state): State<Arc<AppState>>| async move {
+                        // The sessions are collected before they are removed, the table of the
+                        // sessions cannot be locked while the sessions are removed from it.
+                        let sessions = state.service.get_sessions();
+                        let removed = get_user_sessions(&state, &username)
+                            .into_iter()
+                            .filter(|addr| sessions.close(addr, CloseReason::Removed))
+                            .collect::<Vec<_>>();
+
+                        if removed.is_empty() {
+                            StatusCode::NOT_FOUND.into_response()
+                        } else {
+                            Json(removed).into_response()
+                        }
+                    },
+                ),
+            )
+            .route(
+                "/events/stream",
+                get(
+                    |Query(query): Query<EventStreamQueryFilter>, State(state): State<Arc<AppState>>| async move {
+                        let events =
+                            BroadcastStream::new(state.observer.get_event_stream().0.subscribe()).map(|it| match it {
+                                Ok(it) => Event::default().event(&it.kind).data(&it.data),
+                                Err(BroadcastStreamRecvError::Lagged(missed)) => Event::default()
+                                    .event("lagged")
+                                    .data(json!({ "missed": missed }).to_string()),
+                            });
+
+                        // The traffic is not an event of its own, a snapshot of the statistics of
+                        // all sessions is sent to each client at the interval.
+                        let traffic: Pin<Box<dyn Stream<Item = Event> + Send>> = match query.interval.unwrap_or(5) {
+                            0 => Box::pin(tokio_stream::empty()),
+                            secs => Box::pin(IntervalStream::new(interval(Duration::from_secs(secs))).map(move |_| {
+                                Event::default()
+                                    .event("traffic")
+                                    .data(traffic_to_json(&state).to_string())
+                            })),
+                        };
+
+                        Sse::new(events.merge(traffic).map(Ok::<_, Infallible>)).keep_alive(KeepAlive::default())
+                    },
+                ),
+            )
+            .route(
+                "/affinity",
+                get(
+                    |Query(query): Query<AffinityQueryFilter>, State(state): State<Arc<AppState>>| async move {
+                        // Only the sessions that hold an allocation are pinned to their interface,
+                        // the others can be moved by the load balancer without losing anything.
+                        let sessions = state.service.get_sessions();
+                        let sessions = sessions.get_all_sessions();
+                        let entries = sessions
+                            .iter()
+                            .filter(|(_, session)| session.allocate.port.is_some())
+                            .map(|(addr, session)| (addr, session.transport));
+
+                        match query.format {
+                            AffinityFormat::Json => Json(
+                                entries
+                                    .map(|(addr, transport)| {
+                                        json!({
+                                            "address": addr.address,
+                                            "interface": addr.interface,
+                                            "transport": transport,
+                                        })
+                                    })
+                                    .collect::<Vec<_>>(),
+                            )
+                            .into_response(),
+                            AffinityFormat::Text => (
+                                [(CONTENT_TYPE, "text/plain")],
+                                entries
+                                    .map(|(addr, transport)| {
+                                        format!("{} {} {}\n", addr.address, addr.interface, transport)
+                                    })
+                                    .collect::<String>(),
+                            )
+                                .into_response(),
+                        }
+                    },
+                ),
+            )
+            .route(
+                "/state",
+                get(|State(state): State<Arc<AppState>>| async move { Json(state.service.get_sessions().dump()) }),
+            )
             .route(
                 "/session/statistics",
                 get(
@@ -123,6 +697,9 @@ pub mod api {
                                 "received_pkts": counts.received_pkts,
                                 "send_pkts": counts.send_pkts,
                                 "error_pkts": counts.error_pkts,
+                                "rejected_pkts": counts.rejected_pkts,
+                                "retransmitted_pkts": counts.retransmitted_pkts,
+                                "dropped_pkts": counts.dropped_pkts,
                             }))
                             .into_response()
                         } else {
@@ -131,24 +708,139 @@ pub mod api {
                     },
                 ),
             )
+            .route(
+                "/credentials",
+                get(|State(state): State<Arc<AppState>>| async move { Json(state.observer.get_credentials().list()) })
+                    .post(put_credential),
+            )
+            .route(
+                "/credentials/:username",
+                delete(
+                    |Path(username): Path<String>, State(state): State<Arc<AppState>>| async move {
+                        remove_credential(&state, &username)
+                    },
+                ),
+            )
             .route(
                 "/session",
                 delete(
                     |Query(query): Query<SessionQueryFilter>, State(state): State<Arc<AppState>>| async move {
-                        if state.service.get_sessions().refresh(&query.into(), 0) {
+                        if state.service.get_sessions().close(&query.into(), CloseReason::Removed) {
                             StatusCode::OK
                         } else {
                             StatusCode::EXPECTATION_FAILED
                         }
                     },
                 ),
+            )
+            .route(
+                "/session/mirror",
+                put(
+                    |Query(query): Query<SessionQueryFilter>,
+                     State(state): State<Arc<AppState>>,
+                     headers: HeaderMap| async move {
+                        if !is_authorized(state.config.mirror.token.as_deref(), &headers) {
+                            return StatusCode::UNAUTHORIZED;
+                        }
+
+                        let mirror = state.observer.get_mirror();
+                        if !mirror.is_enabled() {
+                            return StatusCode::NOT_IMPLEMENTED;
+                        }
+
+                        let addr: SessionAddr = query.into();
+                        if state.service.get_sessions().get_session(&addr).get_ref().is_none() {
+                            return StatusCode::NOT_FOUND;
+                        }
+
+                        mirror.start(&addr);
+                        StatusCode::OK
+                    },
+                )
+                .delete(
+                    |Query(query): Query<SessionQueryFilter>,
+                     State(state): State<Arc<AppState>>,
+                     headers: HeaderMap| async move {
+                        if !is_authorized(state.config.mirror.token.as_deref(), &headers) {
+                            return StatusCode::UNAUTHORIZED;
+                        }
+
+                        if state.observer.get_mirror().stop(&query.into()) {
+                            StatusCode::OK
+                        } else {
+                            StatusCode::NOT_FOUND
+                        }
+                    },
+                ),
+            )
+            .route(
+                "/session/trace",
+                get(
+                    |Query(query): Query<SessionQueryFilter>, State(state): State<Arc<AppState>>| async move {
+                        if let Some(trace) = state.observer.get_tracer().get(&query.into()) {
+                            Json(trace).into_response()
+                        } else {
+                            StatusCode::NOT_FOUND.into_response()
+                        }
+                    },
+                )
+                .put(
+                    |Query(query): Query<TraceQueryFilter>, State(state): State<Arc<AppState>>| async move {
+                        let addr = SessionAddr {
+                            address: query.address,
+                            interface: query.interface,
+                        };
+
+                        if state.service.get_sessions().get_session(&addr).get_ref().is_none() {
+                            return StatusCode::NOT_FOUND;
+                        }
+
+                        state
+                            .observer
+                            .get_tracer()
+                            .start(&addr, Duration::from_secs(query.duration.unwrap_or(60)));
+                        StatusCode::OK
+                    },
+                )
+                .delete(
+                    |Query(query): Query<SessionQueryFilter>, State(state): State<Arc<AppState>>| async move {
+                        if state.observer.get_tracer().stop(&query.into()) {
+                            StatusCode::OK
+                        } else {
+                            StatusCode::NOT_FOUND
+                        }
+                    },
+                ),
+            )
+            .route(
+                "/debug/pprof/profile",
+                get(
+                    |Query(query): Query<ProfileQueryFilter>,
+                     State(state): State<Arc<AppState>>,
+                     headers: HeaderMap| async move {
+                        if !is_authorized(state.config.profiling.token.as_deref(), &headers) {
+                            return StatusCode::UNAUTHORIZED.into_response();
+                        }
+
+                        let duration = Duration::from_secs(query.seconds.unwrap_or(10));
+                        match state.profiler.flamegraph(duration).await {
+                            Ok(svg) => ([(CONTENT_TYPE, "image/svg+xml")], svg).into_response(),
+                            Err(ProfileError::Unsupported) => StatusCode::NOT_IMPLEMENTED.into_response(),
+                            Err(ProfileError::InvalidDuration) => StatusCode::BAD_REQUEST.into_response(),
+                            Err(ProfileError::Busy(retry)) => (
+                                StatusCode::TOO_MANY_REQUESTS,
+                                [(RETRY_AFTER, (retry.as_secs() + 1).to_string())],
+                            )
+                                .into_response(),
+                            Err(ProfileError::Failed(_)) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+                        }
+                    },
+                ),
             );
 
         #[cfg(feature = "prometheus")]
         {
             use crate::statistics::prometheus::generate_metrics;
-            use axum::http::header::CONTENT_TYPE;
-
             let mut metrics_bytes = Vec::with_capacity(4096);
 
             app = app.route(
@@ -176,10 +868,15 @@ pub mod api {
                     res
                 },
             ))
+            .route_layer(middleware::from_fn_with_state(state.clone(), audit))
             .with_state(state);
 
         log::info!("api server listening={:?}", &config.api.bind);
-        axum::serve(TcpListener::bind(config.api.bind).await?, app).await?;
+        axum::serve(
+            TcpListener::bind(config.api.bind).await?,
+            app.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .await?;
 
         Ok(())
     }
@@ -187,21 +884,195 @@ pub mod api {
 
 #[cfg(feature = "hooks")]
 pub mod hooks {
-    use std::{sync::Arc, time::Duration};
+    use std::{
+        collections::{HashMap, HashSet, VecDeque},
+        net::SocketAddr,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
 
-    use axum::http::{HeaderMap, HeaderValue};
-    use reqwest::{Client, ClientBuilder};
+    use bytes::Bytes;
+    use parking_lot::RwLock;
+    use reqwest::{
+        header::{HeaderMap, HeaderValue, CONTENT_TYPE},
+        Client, ClientBuilder, RequestBuilder, Response, StatusCode,
+    };
     use serde_json::Value;
-    use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
-    use turn::SessionAddr;
+    use tokio::{
+        sync::mpsc::{unbounded_channel, UnboundedSender},
+        time::{sleep_until, Instant},
+    };
+    use turn::{Credential, PasswordDigest, PasswordError, SessionAddr};
 
     use super::NONCE;
-    use crate::config::Config;
+    use crate::{cache::CredentialCache, config::Config, geoip::Location};
+
+    /// The delay before the events of a route are pushed again after no hooks
+    /// server of the route took them, doubled after every failed attempt up to
+    /// the maximum.
+    const RETRY_BACKOFF: Duration = Duration::from_secs(1);
+    const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(60);
+
+    // The events of a route that no hooks server of the route took, in the order
+    // that they were emitted. While a route has pending events, new events of the
+    // route are queued behind them, so that they are delivered in order.
+    struct Pending {
+        events: VecDeque<(String, Bytes)>,
+        backoff: Duration,
+        next: Instant,
+    }
+
+    impl Pending {
+        fn new() -> Self {
+            Self {
+                events: VecDeque::new(),
+                backoff: RETRY_BACKOFF,
+                next: Instant::now(),
+            }
+        }
+
+        fn failed(&mut self) {
+            self.next = Instant::now() + self.backoff;
+            self.backoff = (self.backoff * 2).min(MAX_RETRY_BACKOFF);
+        }
+
+        fn succeeded(&mut self) {
+            self.backoff = RETRY_BACKOFF;
+        }
+
+        // Queue the event, the oldest event is dropped if the queue is full, which is
+        // returned.
+        fn push(&mut self, kind: String, body: Bytes, capacity: usize) -> Option<Bytes> {
+            self.events.push_back((kind, body));
+            if self.events.len() > capacity {
+                self.events.pop_front().map(|(_, it)| it)
+            } else {
+                None
+            }
+        }
+    }
+
+    /// The version of the hooks contract implemented by this server, it is
+    /// sent to the hooks servers in the `Hooks-Version` header.
+    pub const HOOKS_VERSION: u32 = 1;
+
+    /// What a hooks server has declared to support.
+    ///
+    /// Hooks servers declare their capabilities in the `Hooks-Capabilities`
+    /// response header, a comma separated list of `password`, which means that
+    /// the server answers password requests, `peer`, which means that it
+    /// answers peer requests, and the kinds of the events that it accepts, or
+    /// `events` for all of them. A hooks server that does not send the header
+    /// predates the negotiation and is assumed to support everything.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use turn_server::publicly::hooks::Capabilities;
+    ///
+    /// let capabilities = Capabilities::parse(Some("1"), Some("password, allocated,closed"));
+    /// assert_eq!(capabilities.version, 1);
+    /// assert!(capabilities.supports("password"));
+    /// assert!(capabilities.supports("closed"));
+    /// assert!(!capabilities.supports("transferred"));
+    ///
+    /// let capabilities = Capabilities::parse(Some("1"), Some("events"));
+    /// assert!(capabilities.supports("transferred"));
+    /// assert!(!capabilities.supports("password"));
+    /// assert!(!capabilities.supports("peer"));
+    ///
+    /// let capabilities = Capabilities::parse(None, None);
+    /// assert_eq!(capabilities.version, 0);
+    /// assert!(capabilities.supports("password"));
+    /// assert!(capabilities.supports("transferred"));
+    /// ```
+    #[derive(Debug, Clone, Default)]
+    pub struct Capabilities {
+        /// The version of the hooks contract implemented by the hooks server,
+        /// `0` if it did not declare one.
+        pub version: u32,
+        tokens: Option<HashSet<String>>,
+    }
+
+    impl Capabilities {
+        pub fn parse(version: Option<&str>, capabilities: Option<&str>) -> Self {
+            Self {
+                version: version.and_then(|it| it.trim().parse().ok()).unwrap_or(0),
+                tokens: capabilities.map(|it| {
+                    it.split(',')
+                        .map(|it| it.trim().to_string())
+                        .filter(|it| !it.is_empty())
+                        .collect()
+                }),
+            }
+        }
+
+        fn from_headers(headers: &HeaderMap) -> Self {
+            Self::parse(
+                headers.get("Hooks-Version").and_then(|it| it.to_str().ok()),
+                headers.get("Hooks-Capabilities").and_then(|it| it.to_str().ok()),
+            )
+        }
+
+        /// Whether the hooks server supports password requests, for the
+        /// capability `password`, peer requests, for the capability `peer`,
+        /// or the event of the kind.
+        pub fn supports(&self, capability: &str) -> bool {
+            match &self.tokens {
+                Some(tokens) => {
+                    tokens.contains(capability)
+                        || (!matches!(capability, "password" | "peer") && tokens.contains("events"))
+                }
+                None => true,
+            }
+        }
+    }
+
+    // The capabilities of the hooks servers by url, learned from their responses. A
+    // server that has not responded yet is assumed to support everything, so that the
+    // first request to it is made either way.
+    #[derive(Default)]
+    struct Negotiated(RwLock<HashMap<String, Capabilities>>);
+
+    impl Negotiated {
+        fn supports(&self, server: &str, capability: Option<&str>) -> bool {
+            match (capability, self.0.read().get(server)) {
+                (Some(capability), Some(it)) => it.supports(capability),
+                _ => true,
+            }
+        }
+
+        fn update(&self, server: &str, headers: &HeaderMap) {
+            let capabilities = Capabilities::from_headers(headers);
+            let mut table = self.0.write();
+            if table.get(server).map(|it| it.version) != Some(capabilities.version) {
+                log::info!(
+                    "hooks server negotiated, server={}, version={}, capabilities={:?}",
+                    server,
+                    capabilities.version,
+                    capabilities.tokens
+                );
+            }
+
+            table.insert(server.to_string(), capabilities);
+        }
+    }
 
     pub struct HooksService {
         client: Arc<Client>,
-        tx: UnboundedSender<Value>,
+        // The route, the kind and the encoded body of the queued events.
+        tx: UnboundedSender<(usize, String, Bytes)>,
+        queued: Arc<AtomicUsize>,
         config: Arc<Config>,
+        // The hooks servers of each route in failover order, the same order as
+        // `api.hooks_routes`, followed by the default route built from
+        // `api.hooks`.
+        servers: Arc<Vec<Vec<String>>>,
+        negotiated: Arc<Negotiated>,
+        cache: CredentialCache,
     }
 
     impl HooksService {
@@ -209,6 +1080,7 @@ pub mod hooks {
             let mut headers = HeaderMap::new();
             headers.insert("Realm", HeaderValue::from_str(&config.turn.realm)?);
             headers.insert("Nonce", HeaderValue::from_str(&NONCE)?);
+            headers.insert("Hooks-Version", HeaderValue::from(HOOKS_VERSION));
 
             let client = Arc::new(
                 ClientBuilder::new()
@@ -217,55 +1089,607 @@ pub mod hooks {
                     .build()?,
             );
 
+            let servers = Arc::new(
+                config
+                    .api
+                    .hooks_routes
+                    .iter()
+                    .map(|it| it.servers.clone())
+                    .chain(std::iter::once(config.api.hooks.iter().cloned().collect()))
+                    .collect::<Vec<Vec<String>>>(),
+            );
+
+            // Learn the capabilities of all hooks servers before the first sessions
+            // arrive, the same as any other request to them.
+            let negotiated = Arc::new(Negotiated::default());
+            {
+                let client = client.clone();
+                let servers = servers.clone();
+                let negotiated = negotiated.clone();
+                tokio::spawn(async move {
+                    for server in servers.iter().flatten() {
+                        let _ = request(std::slice::from_ref(server), &negotiated, None, |server| {
+                            client.head(server)
+                        })
+                        .await;
+                    }
+                });
+            }
+
             // It keeps taking queued events from the queue and sending them to an external
-            // hook service.
-            let config_ = config.clone();
+            // hook service. Events of a kind that a hooks server has not declared are not
+            // sent to it, as it could not understand them. Events that no hooks server of
+            // the route takes are kept and pushed again with an exponential backoff, so
+            // that they are delivered at least once if the hooks servers come back before
+            // the retry queue of the route overflows.
             let client_ = client.clone();
-            let (tx, mut rx) = unbounded_channel::<Value>();
+            let servers_ = servers.clone();
+            let negotiated_ = negotiated.clone();
+            let queued = Arc::new(AtomicUsize::new(0));
+            let queued_ = queued.clone();
+            let capacity = config.api.hooks_retry_queue;
+            let (tx, mut rx) = unbounded_channel::<(usize, String, Bytes)>();
             tokio::spawn(async move {
-                if let Some(server) = &config_.api.hooks {
-                    let uri = format!("{}/events", server);
+                let mut pending = servers_.iter().map(|_| Pending::new()).collect::<Vec<_>>();
+                loop {
+                    let next = pending
+                        .iter()
+                        .filter(|it| !it.events.is_empty())
+                        .map(|it| it.next)
+                        .min();
+
+                    let event = tokio::select! {
+                        it = rx.recv() => match it {
+                            Some(it) => Some(it),
+                            None => break,
+                        },
+                        _ = sleep_until(next.unwrap_or_else(Instant::now)), if next.is_some() => None,
+                    };
+
+                    if let Some((route, kind, body)) = event {
+                        #[cfg(feature = "prometheus")]
+                        crate::statistics::prometheus::METRICS.queues.hooks.dec();
+
+                        if !servers_[route].iter().any(|it| negotiated_.supports(it, Some(&kind))) {
+                            queued_.fetch_sub(body.len(), Ordering::Relaxed);
+                            continue;
+                        }
+
+                        if pending[route].events.is_empty() {
+                            if push_event(&client_, &servers_[route], &negotiated_, &kind, &body).await {
+                                queued_.fetch_sub(body.len(), Ordering::Relaxed);
+                                continue;
+                            }
+
+                            log::error!("failed to request hooks server, all servers are unavailable");
+                            pending[route].failed();
+                        }
+
+                        if capacity == 0 {
+                            queued_.fetch_sub(body.len(), Ordering::Relaxed);
+                            continue;
+                        }
+
+                        // The event stays counted in the queued bytes while it waits for the
+                        // retry.
+                        if let Some(dropped) = pending[route].push(kind, body, capacity) {
+                            queued_.fetch_sub(dropped.len(), Ordering::Relaxed);
+                            log::warn!("hooks retry queue is full, the oldest event is dropped");
 
-                    while let Some(signal) = rx.recv().await {
-                        if let Err(e) = client_.post(&uri).json(&signal).send().await {
-                            log::error!("failed to request hooks server, err={}", e);
+                            #[cfg(feature = "prometheus")]
+                            crate::statistics::prometheus::METRICS.hooks_dropped_events.inc();
+                        }
+
+                        continue;
+                    }
+
+                    // Push the pending events of the routes whose backoff has passed, in
+                    // order, until one of them fails again.
+                    let now = Instant::now();
+                    for (route, it) in pending.iter_mut().enumerate() {
+                        if it.events.is_empty() || it.next > now {
+                            continue;
+                        }
+
+                        while let Some((kind, body)) = it.events.front() {
+                            if !push_event(&client_, &servers_[route], &negotiated_, kind, body).await {
+                                it.failed();
+                                break;
+                            }
+
+                            queued_.fetch_sub(body.len(), Ordering::Relaxed);
+                            it.events.pop_front();
+                            it.succeeded();
                         }
                     }
                 }
             });
 
-            Ok(Self { client, config, tx })
+            Ok(Self {
+                cache: CredentialCache::new(config.api.password_cache.as_ref()),
+                negotiated,
+                client,
+                config,
+                servers,
+                queued,
+                tx,
+            })
+        }
+
+        // Find the route of the session, the first matching route wins, otherwise the
+        // default route is used. Returns `None` if there is no hooks server to send to.
+        fn route(&self, addr: &SessionAddr, username: &str) -> Option<usize> {
+            let route = self
+                .config
+                .api
+                .hooks_routes
+                .iter()
+                .position(|it| {
+                    it.matches
+                        .matches(self.config.turn.get_realm(&addr.interface), &addr.interface, username)
+                })
+                .unwrap_or(self.servers.len() - 1);
+
+            if self.servers[route].is_empty() {
+                None
+            } else {
+                Some(route)
+            }
         }
 
         // There are no matching static entries, get the password from an external hook
-        // service.
-        pub async fn get_password(&self, addr: &SessionAddr, username: &str) -> Option<String> {
-            if let Some(server) = &self.config.api.hooks {
-                if let Ok(res) = self
-                    .client
-                    .get(format!(
-                        "{}/password?address={}&interface={}&username={}",
-                        server, addr.address, addr.interface, username
-                    ))
-                    .send()
-                    .await
-                {
-                    if let Ok(password) = res.text().await {
-                        return Some(password);
+        // service. Hooks servers that only take events are skipped. A hooks server
+        // refuses the session with 403, and the lookup fails if no hooks server of
+        // the route responds without a server error. The password is cached as its
+        // digest, so a cached password is only known by its digest.
+        pub async fn get_credential(
+            &self,
+            addr: &SessionAddr,
+            username: &str,
+            realm: &str,
+            origin: Option<&str>,
+            location: Option<&Location>,
+        ) -> Result<Option<Credential>, PasswordError> {
+            let route = match self.route(addr, username) {
+                Some(it) => it,
+                None => return Ok(None),
+            };
+
+            if !self.servers[route]
+                .iter()
+                .any(|it| self.negotiated.supports(it, Some("password")))
+            {
+                return Ok(None);
+            }
+
+            // The same username can have different passwords in different realms and
+            // routes, for example for different tenants. The realm is prefixed with its
+            // length, so that a realm and a username that contain the separator do not
+            // collide with another realm and username.
+            let key = format!("{}:{}:{}:{}", route, realm.len(), realm, username);
+            if let Some(it) = self.cache.get(&key).await {
+                #[cfg(feature = "prometheus")]
+                crate::statistics::prometheus::METRICS.password_cache_hits.inc();
+
+                return Ok(Some(Credential::Digest(it)));
+            }
+
+            let res = request(&self.servers[route], &self.negotiated, Some("password"), |server| {
+                let req = self.client.get(format!(
+                    "{}/password?address={}&interface={}&username={}",
+                    server, addr.address, addr.interface, username
+                ));
+
+                // The realm of the interface, which may differ from the realm header.
+                let req = req.query(&[("realm", realm)]);
+
+                // The origin is a url, so it has to be encoded.
+                let req = if let Some(origin) = origin {
+                    req.query(&[("origin", origin)])
+                } else {
+                    req
+                };
+
+                // The location allows a policy per region, fields that were not found are
+                // left out.
+                if let Some(location) = location {
+                    let mut query = Vec::with_capacity(2);
+                    if let Some(country) = &location.country {
+                        query.push(("country", country.clone()));
                     }
+
+                    if let Some(asn) = location.asn {
+                        query.push(("asn", asn.to_string()));
+                    }
+
+                    req.query(&query)
+                } else {
+                    req
+                }
+            })
+            .await
+            .ok_or(PasswordError::Unavailable)?;
+
+            if res.status() == StatusCode::FORBIDDEN {
+                log::info!(
+                    "hooks denied: address={:?}, interface={:?}, username={:?}, reason={:?}",
+                    addr.address,
+                    addr.interface,
+                    username,
+                    res.text().await.unwrap_or_default()
+                );
+
+                Err(PasswordError::Forbidden)
+            } else if res.status().is_success() {
+                let password = res.text().await.map_err(|_| PasswordError::Unavailable)?;
+                if let Ok(digest) = PasswordDigest::new(username, &password, realm) {
+                    self.cache.insert(&key, digest).await;
                 }
+
+                Ok(Some(Credential::Password(password)))
+            } else {
+                Ok(None)
             }
+        }
 
-            None
+        // Ask the hooks service whether the session may relay to the peer, before the
+        // permission is installed. Only hooks servers that declared `peer` are asked,
+        // a hooks server that does not know the request answers 404, which allows the
+        // peer like any answer other than 403. The peer is denied if no hooks server
+        // of the route responds, a policy that cannot be checked is not bypassed.
+        pub async fn allow_peer(&self, addr: &SessionAddr, username: &str, peer: &SocketAddr) -> bool {
+            let route = match self.route(addr, username) {
+                Some(it) => it,
+                None => return true,
+            };
+
+            if !self.servers[route]
+                .iter()
+                .any(|it| self.negotiated.supports(it, Some("peer")))
+            {
+                return true;
+            }
+
+            let res = match request(&self.servers[route], &self.negotiated, Some("peer"), |server| {
+                self.client.get(format!(
+                    "{}/peer?address={}&interface={}&username={}&peer={}",
+                    server, addr.address, addr.interface, username, peer
+                ))
+            })
+            .await
+            {
+                Some(it) => it,
+                None => return false,
+            };
+
+            if res.status() == StatusCode::FORBIDDEN {
+                log::info!(
+                    "hooks denied peer: address={:?}, interface={:?}, username={:?}, peer={:?}, reason={:?}",
+                    addr.address,
+                    addr.interface,
+                    username,
+                    peer,
+                    res.text().await.unwrap_or_default()
+                );
+
+                return false;
+            }
+
+            true
+        }
+
+        // Check that every route has at least one hooks server that responds, any
+        // response that is not a server error is fine here.
+        pub async fn is_reachable(&self) -> bool {
+            for servers in self.servers.iter().filter(|it| !it.is_empty()) {
+                if request(servers, &self.negotiated, None, |server| {
+                    self.client.head(server).timeout(Duration::from_secs(1))
+                })
+                .await
+                .is_none()
+                {
+                    return false;
+                }
+            }
+
+            true
         }
 
         // Notifications for all events are all added to the queue, which has the
         // advantage of not blocking the current call, which is useful for scenarios
         // requiring high real-time performance.
-        pub fn emit(&self, event: Value) {
-            if self.config.api.hooks.is_some() {
-                if let Err(e) = self.tx.send(event) {
+        pub fn emit(&self, addr: &SessionAddr, username: &str, event: Value) {
+            if let Some(route) = self.route(addr, username) {
+                let kind = event["kind"].as_str().unwrap_or("unknown").to_string();
+                let body = match serde_json::to_vec(&event) {
+                    Ok(it) => Bytes::from(it),
+                    Err(e) => return log::error!("failed to encode event, err={}", e),
+                };
+
+                let size = body.len();
+                self.queued.fetch_add(size, Ordering::Relaxed);
+
+                if let Err(e) = self.tx.send((route, kind, body)) {
+                    self.queued.fetch_sub(size, Ordering::Relaxed);
                     log::error!("failed to send event, err={}", e)
+                } else {
+                    #[cfg(feature = "prometheus")]
+                    crate::statistics::prometheus::METRICS.queues.hooks.inc();
+                }
+            }
+        }
+
+        /// The number of bytes held by the events waiting to be pushed to the
+        /// hooks services.
+        pub fn queued_bytes(&self) -> usize {
+            self.queued.load(Ordering::Relaxed)
+        }
+    }
+
+    // Push an event to the first hooks server of the route that takes it.
+    async fn push_event(
+        client: &Client,
+        servers: &[String],
+        negotiated: &Negotiated,
+        kind: &str,
+        body: &Bytes,
+    ) -> bool {
+        request(servers, negotiated, Some(kind), |server| {
+            client
+                .post(format!("{}/events", server))
+                .header(CONTENT_TYPE, "application/json")
+                .body(body.clone())
+        })
+        .await
+        .is_some()
+    }
+
+    // Send the request to the servers in order until one of them responds, a server
+    // that cannot be reached or responds with a server error is skipped, as well as
+    // a server that does not support the capability. Every response updates the
+    // capabilities of the server, so a hooks server that is upgraded or downgraded
+    // is picked up without restarting.
+    async fn request<F>(
+        servers: &[String],
+        negotiated: &Negotiated,
+        capability: Option<&str>,
+        builder: F,
+    ) -> Option<Response>
+    where
+        F: Fn(&str) -> RequestBuilder,
+    {
+        for server in servers.iter().filter(|it| negotiated.supports(it, capability)) {
+            match builder(server).send().await {
+                Ok(res) if !res.status().is_server_error() => {
+                    negotiated.update(server, res.headers());
+                    return Some(res);
+                }
+                Ok(res) => {
+                    log::warn!("hooks server error, server={}, status={}", server, res.status())
+                }
+                Err(e) => log::warn!("failed to request hooks server, server={}, err={}", server, e),
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(any(feature = "nats", feature = "kafka"))]
+pub mod sinks {
+    use std::{
+        sync::{
+            atomic::{AtomicU64, AtomicUsize, Ordering},
+            Arc,
+        },
+        time::SystemTime,
+    };
+
+    use bytes::Bytes;
+    use serde_json::{json, Value};
+    use tokio::sync::mpsc::{channel, error::TrySendError, Receiver, Sender};
+    use turn::SessionAddr;
+
+    use super::NONCE;
+    use crate::config::Config;
+
+    /// Publishes session events to streaming platforms as CloudEvents in the
+    /// structured JSON format.
+    ///
+    /// Every sink has its own queue and publishes from its own task, so a
+    /// sink that is slow or unreachable does not hold up the other sinks.
+    /// While the queue of a sink is full, the events are dropped for that
+    /// sink.
+    pub struct EventSinks {
+        sinks: Vec<(&'static str, Sender<(String, Bytes)>)>,
+        config: Arc<Config>,
+        sequence: AtomicU64,
+        queued: Arc<AtomicUsize>,
+    }
+
+    impl EventSinks {
+        pub fn new(config: Arc<Config>) -> Self {
+            let queued = Arc::new(AtomicUsize::new(0));
+
+            #[allow(unused_mut)]
+            let mut sinks = Vec::with_capacity(2);
+
+            #[cfg(feature = "nats")]
+            if config.sinks.nats.is_some() {
+                let (tx, rx) = channel(config.sinks.queue.max(1));
+                tokio::spawn(publish_nats(config.clone(), rx, queued.clone()));
+                sinks.push(("nats", tx));
+            }
+
+            #[cfg(feature = "kafka")]
+            if config.sinks.kafka.is_some() {
+                let (tx, rx) = channel(config.sinks.queue.max(1));
+                tokio::spawn(publish_kafka(config.clone(), rx, queued.clone()));
+                sinks.push(("kafka", tx));
+            }
+
+            Self {
+                sequence: AtomicU64::new(0),
+                queued,
+                config,
+                sinks,
+            }
+        }
+
+        // Wrap the event into a CloudEvent, the event kind is used as the event type and
+        // the session address as the subject, which is also used as the message key so
+        // that the events of a session stay in order.
+        pub fn emit(&self, addr: &SessionAddr, event: &Value) {
+            if self.sinks.is_empty() {
+                return;
+            }
+
+            let subject = addr.address.to_string();
+            let cloud_event = json!({
+                "specversion": "1.0",
+                "id": format!("{}-{}", *NONCE, self.sequence.fetch_add(1, Ordering::Relaxed)),
+                "source": format!("turn-rs://{}/{}", self.config.turn.get_realm(&addr.interface), addr.interface),
+                "type": format!("rs.turn.session.{}", event["kind"].as_str().unwrap_or("unknown")),
+                "subject": subject,
+                "time": humantime::format_rfc3339_millis(SystemTime::now()).to_string(),
+                "datacontenttype": "application/json",
+                "data": event,
+            });
+
+            let bytes = match serde_json::to_vec(&cloud_event) {
+                Ok(it) => Bytes::from(it),
+                Err(e) => {
+                    log::error!("failed to encode event, err={}", e);
+                    return;
+                }
+            };
+
+            // The queued bytes count the event once for every sink that it waits for.
+            for (name, tx) in &self.sinks {
+                self.queued.fetch_add(bytes.len(), Ordering::Relaxed);
+
+                match tx.try_send((subject.clone(), bytes.clone())) {
+                    Ok(_) => {
+                        #[cfg(feature = "prometheus")]
+                        crate::statistics::prometheus::METRICS.queues.sinks.inc();
+                    }
+                    Err(e) => {
+                        self.queued.fetch_sub(bytes.len(), Ordering::Relaxed);
+
+                        if let TrySendError::Full(_) = e {
+                            log::warn!("event sink queue is full, the event is dropped: sink={}", name);
+
+                            #[cfg(feature = "prometheus")]
+                            crate::statistics::prometheus::METRICS.sinks_dropped_events.inc();
+                        } else {
+                            log::error!("failed to send event: sink={}, err={}", name, e);
+                        }
+                    }
+                }
+            }
+        }
+
+        /// The number of bytes held by the events waiting to be published to
+        /// the sinks.
+        pub fn queued_bytes(&self) -> usize {
+            self.queued.load(Ordering::Relaxed)
+        }
+    }
+
+    // Take the next event off the queue of a sink.
+    async fn next(rx: &mut Receiver<(String, Bytes)>, queued: &AtomicUsize) -> Option<(String, Bytes)> {
+        let (key, event) = rx.recv().await?;
+        queued.fetch_sub(event.len(), Ordering::Relaxed);
+
+        #[cfg(feature = "prometheus")]
+        crate::statistics::prometheus::METRICS.queues.sinks.dec();
+
+        Some((key, event))
+    }
+
+    // Connects to the nats server and keeps publishing the queued events to it. If it
+    // cannot be connected, the sink is disabled and logged, and its events are
+    // discarded.
+    #[cfg(feature = "nats")]
+    async fn publish_nats(config: Arc<Config>, mut rx: Receiver<(String, Bytes)>, queued: Arc<AtomicUsize>) {
+        let sink = match &config.sinks.nats {
+            Some(it) => it,
+            None => return,
+        };
+
+        let client = match async_nats::ConnectOptions::new()
+            .retry_on_initial_connect()
+            .connect(&sink.url)
+            .await
+        {
+            Ok(client) => Some(client),
+            Err(e) => {
+                log::error!("failed to connect nats, url={}, err={}", sink.url, e);
+                None
+            }
+        };
+
+        while let Some((_, event)) = next(&mut rx, &queued).await {
+            if let Some(client) = &client {
+                if let Err(e) = client.publish(sink.subject.clone(), event).await {
+                    log::error!("failed to publish event to nats, err={}", e);
+                }
+            }
+        }
+    }
+
+    // Connects to the kafka brokers and keeps producing the queued events to them. If
+    // they cannot be connected, the sink is disabled and logged, and its events are
+    // discarded.
+    #[cfg(feature = "kafka")]
+    async fn publish_kafka(config: Arc<Config>, mut rx: Receiver<(String, Bytes)>, queued: Arc<AtomicUsize>) {
+        use rskafka::{
+            chrono::DateTime,
+            client::{
+                partition::{Compression, UnknownTopicHandling},
+                ClientBuilder,
+            },
+            record::Record,
+        };
+
+        let sink = match &config.sinks.kafka {
+            Some(it) => it,
+            None => return,
+        };
+
+        let client = match ClientBuilder::new(sink.brokers.clone()).build().await {
+            Ok(client) => {
+                client
+                    .partition_client(sink.topic.clone(), sink.partition, UnknownTopicHandling::Retry)
+                    .await
+            }
+            Err(e) => Err(e),
+        };
+
+        let client = match client {
+            Ok(client) => Some(client),
+            Err(e) => {
+                log::error!("failed to connect kafka, brokers={:?}, err={}", sink.brokers, e);
+                None
+            }
+        };
+
+        while let Some((key, event)) = next(&mut rx, &queued).await {
+            if let Some(client) = &client {
+                let timestamp = SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .map(|it| it.as_millis() as i64)
+                    .unwrap_or_default();
+
+                let record = Record {
+                    key: Some(key.into_bytes()),
+                    value: Some(event.to_vec()),
+                    headers: [("content-type".to_string(), b"application/cloudevents+json".to_vec())].into(),
+                    timestamp: DateTime::from_timestamp_millis(timestamp).unwrap_or_default(),
+                };
+
+                if let Err(e) = client.produce(vec![record], Compression::NoCompression).await {
+                    log::error!("failed to produce event to kafka, err={}", e);
                 }
             }
         }
@@ -0,0 +1,104 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use parking_lot::RwLock;
+use tokio::net::lookup_host;
+
+use crate::config::Interface;
+
+/// Resolves the external host names of the interfaces.
+///
+/// The external address of an interface is resolved once when the
+/// configuration is loaded and keeps identifying the interface. On cloud
+/// instances the public ip address behind a stable host name can change, so
+/// the host names are resolved again periodically, and allocations advertise
+/// the latest ip address as their relayed address.
+///
+/// # Example
+///
+/// ```
+/// use turn_server::{config::Interface, resolver::Resolver};
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let interface = "udp@127.0.0.1:3478/localhost:3478".parse::<Interface>().unwrap();
+///     let resolver = Resolver::new(&[interface.clone()]);
+///
+///     assert_eq!(resolver.get(&interface.external), None);
+///
+///     resolver.resolve().await;
+///     assert_eq!(resolver.get(&interface.external), Some(interface.external.ip()));
+/// }
+/// ```
+pub struct Resolver {
+    hosts: Vec<(SocketAddr, String)>,
+    addresses: RwLock<HashMap<SocketAddr, IpAddr>>,
+}
+
+impl Resolver {
+    pub fn new(interfaces: &[Interface]) -> Self {
+        Self {
+            hosts: interfaces
+                .iter()
+                .filter_map(|it| Some((it.external, it.external_host.clone()?)))
+                .collect(),
+            addresses: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Get the latest resolved ip address of the interface, `None` if the
+    /// external address of the interface is not a host name or has not been
+    /// resolved again yet.
+    pub fn get(&self, interface: &SocketAddr) -> Option<IpAddr> {
+        self.addresses.read().get(interface).copied()
+    }
+
+    /// Resolve all host names once.
+    ///
+    /// Only addresses of the same family as the external address are used,
+    /// if a host name fails to resolve, its last resolved address is kept.
+    pub async fn resolve(&self) {
+        for (external, host) in &self.hosts {
+            let ip = match lookup_host((host.as_str(), external.port())).await {
+                Ok(mut addrs) => addrs.find(|it| it.is_ipv4() == external.is_ipv4()).map(|it| it.ip()),
+                Err(e) => {
+                    log::warn!("failed to resolve external host name, host={}, err={}", host, e);
+                    None
+                }
+            };
+
+            if let Some(ip) = ip {
+                if self.addresses.write().insert(*external, ip) != Some(ip) && ip != external.ip() {
+                    log::info!("external address changed: host={}, ip={}", host, ip);
+                }
+            }
+        }
+    }
+
+    /// Resolve the host names again at every interval in a background task.
+    pub fn start(self: &Arc<Self>, interval: Duration) {
+        if self.hosts.is_empty() || interval.is_zero() {
+            return;
+        }
+
+        let this = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(interval);
+            interval.tick().await;
+
+            loop {
+                interval.tick().await;
+
+                if let Some(this) = this.upgrade() {
+                    this.resolve().await;
+                } else {
+                    break;
+                }
+            }
+        });
+    }
+}
@@ -1,19 +1,86 @@
-use std::{net::SocketAddr, sync::Arc};
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
 
 use ahash::AHashMap;
+use bytes::Bytes;
 use parking_lot::RwLock;
 use tokio::sync::mpsc::*;
 use turn::ResponseMethod;
 
-type Receiver = UnboundedSender<(Vec<u8>, ResponseMethod, SocketAddr)>;
+type Receiver = UnboundedSender<(Bytes, ResponseMethod, SocketAddr)>;
+
+// The packets waiting in all routes and the bytes that they hold.
+#[derive(Default)]
+struct Queued {
+    packets: AtomicUsize,
+    bytes: AtomicUsize,
+}
+
+impl Queued {
+    fn add(&self, bytes: usize) {
+        self.packets.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    fn sub(&self, bytes: usize) {
+        self.packets.fetch_sub(1, Ordering::Relaxed);
+        self.bytes.fetch_sub(bytes, Ordering::Relaxed);
+    }
+}
+
+/// The receiving side of a route.
+///
+/// The data waiting in the route is counted in the depth of the router queue,
+/// with the `prometheus` feature it is also reported in the metrics.
+pub struct RouterReceiver {
+    receiver: UnboundedReceiver<(Bytes, ResponseMethod, SocketAddr)>,
+    queued: Arc<Queued>,
+}
+
+impl RouterReceiver {
+    pub async fn recv(&mut self) -> Option<(Bytes, ResponseMethod, SocketAddr)> {
+        let ret = self.receiver.recv().await;
+        if let Some((bytes, _, _)) = &ret {
+            self.queued.sub(bytes.len());
+
+            #[cfg(feature = "prometheus")]
+            crate::statistics::prometheus::METRICS.queues.router.dec();
+        }
+
+        ret
+    }
+}
+
+impl Drop for RouterReceiver {
+    fn drop(&mut self) {
+        self.receiver.close();
+        while let Ok((bytes, _, _)) = self.receiver.try_recv() {
+            self.queued.sub(bytes.len());
+
+            #[cfg(feature = "prometheus")]
+            crate::statistics::prometheus::METRICS.queues.router.dec();
+        }
+    }
+}
 
 /// Handles packet forwarding between transport protocols.
 #[derive(Clone)]
-pub struct Router(Arc<RwLock<AHashMap<SocketAddr, Receiver>>>);
+pub struct Router {
+    routes: Arc<RwLock<AHashMap<SocketAddr, Receiver>>>,
+    queued: Arc<Queued>,
+}
 
 impl Default for Router {
     fn default() -> Self {
-        Self(Arc::new(RwLock::new(AHashMap::with_capacity(1024))))
+        Self {
+            routes: Arc::new(RwLock::new(AHashMap::with_capacity(1024))),
+            queued: Default::default(),
+        }
     }
 }
 
@@ -43,10 +110,70 @@ impl Router {
     ///     assert_eq!(ret.2, addr);
     /// }
     /// ```
-    pub fn get_receiver(&self, interface: SocketAddr) -> UnboundedReceiver<(Vec<u8>, ResponseMethod, SocketAddr)> {
+    pub fn get_receiver(&self, interface: SocketAddr) -> RouterReceiver {
         let (sender, receiver) = unbounded_channel();
-        self.0.write().insert(interface, sender);
-        receiver
+        self.routes.write().insert(interface, sender);
+        RouterReceiver {
+            queued: self.queued.clone(),
+            receiver,
+        }
+    }
+
+    /// The number of packets of all routes that are waiting to be sent.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::net::SocketAddr;
+    /// use turn::ResponseMethod;
+    /// use turn_server::router::*;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let addr = "127.0.0.1:8080".parse::<SocketAddr>().unwrap();
+    ///     let router = Router::default();
+    ///     let mut receiver = router.get_receiver(addr);
+    ///
+    ///     router.send(&addr, ResponseMethod::ChannelData, &addr, &[1, 2, 3]);
+    ///     router.send(&addr, ResponseMethod::ChannelData, &addr, &[1, 2, 3]);
+    ///     assert_eq!(router.queue_depth(), 2);
+    ///
+    ///     receiver.recv().await.unwrap();
+    ///     assert_eq!(router.queue_depth(), 1);
+    ///
+    ///     drop(receiver);
+    ///     assert_eq!(router.queue_depth(), 0);
+    /// }
+    /// ```
+    pub fn queue_depth(&self) -> usize {
+        self.queued.packets.load(Ordering::Relaxed)
+    }
+
+    /// The number of bytes held by the packets of all routes that are waiting
+    /// to be sent.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::net::SocketAddr;
+    /// use turn::ResponseMethod;
+    /// use turn_server::router::*;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let addr = "127.0.0.1:8080".parse::<SocketAddr>().unwrap();
+    ///     let router = Router::default();
+    ///     let mut receiver = router.get_receiver(addr);
+    ///
+    ///     router.send(&addr, ResponseMethod::ChannelData, &addr, &[1, 2, 3]);
+    ///     assert_eq!(router.queued_bytes(), 3);
+    ///
+    ///     receiver.recv().await.unwrap();
+    ///     assert_eq!(router.queued_bytes(), 0);
+    /// }
+    /// ```
+    pub fn queued_bytes(&self) -> usize {
+        self.queued.bytes.load(Ordering::Relaxed)
     }
 
     /// Send data to router.
@@ -77,12 +204,55 @@ impl Router {
     /// }
     /// ```
     pub fn send(&self, interface: &SocketAddr, method: ResponseMethod, addr: &SocketAddr, data: &[u8]) {
+        self.send_bytes(interface, method, addr, Bytes::copy_from_slice(data))
+    }
+
+    /// Send data that is already owned to router, without copying it.
+    ///
+    /// The same as [`Router::send`], for data that is a part of a receive
+    /// buffer, such as a ChannelData message that is forwarded as it was
+    /// received. The data keeps the whole buffer that it is a part of until it
+    /// is sent, which is not counted in the queued bytes.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::net::SocketAddr;
+    /// use bytes::Bytes;
+    /// use turn::ResponseMethod;
+    /// use turn_server::router::*;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let addr = "127.0.0.1:8080".parse::<SocketAddr>().unwrap();
+    ///     let router = Router::default();
+    ///     let mut receiver = router.get_receiver(addr);
+    ///
+    ///     let buf = Bytes::from_static(&[0, 1, 2, 3, 4]);
+    ///     router.send_bytes(&addr, ResponseMethod::ChannelData, &addr, buf.slice(1..4));
+    ///     assert_eq!(router.queued_bytes(), 3);
+    ///
+    ///     let ret = receiver.recv().await.unwrap();
+    ///     assert_eq!(ret.0, vec![1, 2, 3]);
+    ///     assert_eq!(ret.0.as_ptr(), buf[1..].as_ptr());
+    /// }
+    /// ```
+    pub fn send_bytes(&self, interface: &SocketAddr, method: ResponseMethod, addr: &SocketAddr, bytes: Bytes) {
         let mut is_destroy = false;
 
         {
-            if let Some(sender) = self.0.read().get(interface) {
-                if sender.send((data.to_vec(), method, *addr)).is_err() {
+            if let Some(sender) = self.routes.read().get(interface) {
+                // Counted before it is sent, so that the receiver never takes it out of
+                // the queue before it was counted.
+                let size = bytes.len();
+                self.queued.add(size);
+
+                if sender.send((bytes, method, *addr)).is_err() {
+                    self.queued.sub(size);
                     is_destroy = true;
+                } else {
+                    #[cfg(feature = "prometheus")]
+                    crate::statistics::prometheus::METRICS.queues.router.inc();
                 }
             }
         }
@@ -118,6 +288,6 @@ impl Router {
     /// }
     /// ```
     pub fn remove(&self, interface: &SocketAddr) {
-        drop(self.0.write().remove(interface))
+        drop(self.routes.write().remove(interface))
     }
 }
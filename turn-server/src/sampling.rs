@@ -0,0 +1,60 @@
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+static RATE: AtomicU32 = AtomicU32::new(0);
+
+/// Set the sampling rate of hot-path logs, one in `rate` events is logged, and
+/// `0` disables them.
+pub fn set_rate(rate: u32) {
+    RATE.store(rate, Ordering::Relaxed);
+}
+
+/// Counts the events of one call site.
+///
+/// Checking a sample only costs two relaxed atomic operations, the log message
+/// is neither formatted nor allocated when the event is skipped.
+///
+/// # Example
+///
+/// ```
+/// use turn_server::sampling::*;
+///
+/// let sampler = Sampler::new();
+/// assert!(!sampler.sample());
+///
+/// set_rate(3);
+/// assert_eq!((0..6).filter(|_| sampler.sample()).count(), 2);
+///
+/// set_rate(0);
+/// assert!(!sampler.sample());
+/// ```
+pub struct Sampler(AtomicU64);
+
+impl Sampler {
+    #[allow(clippy::new_without_default)]
+    pub const fn new() -> Self {
+        Self(AtomicU64::new(0))
+    }
+
+    /// Whether the current event is logged.
+    pub fn sample(&self) -> bool {
+        match RATE.load(Ordering::Relaxed) {
+            0 => false,
+            rate => self.0.fetch_add(1, Ordering::Relaxed).is_multiple_of(rate as u64),
+        }
+    }
+}
+
+/// Log one in every `log.sample` events of the call site at the given level.
+///
+/// The arguments are the same as those of `log::log!`.
+macro_rules! sampled {
+    ($level:expr, $($arg:tt)+) => {{
+        static SAMPLER: $crate::sampling::Sampler = $crate::sampling::Sampler::new();
+
+        if log::log_enabled!($level) && SAMPLER.sample() {
+            log::log!($level, $($arg)+);
+        }
+    }};
+}
+
+pub(crate) use sampled;
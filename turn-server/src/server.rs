@@ -1,10 +1,15 @@
 use crate::{
     config::{Config, Interface},
+    ipfix::FlowExporter,
+    limiter::{RateLimiter, RequestLimiter},
+    mirror::Mirror,
     router::Router,
+    shedding::LoadShedder,
     statistics::Statistics,
+    trace::Tracer,
 };
 
-use std::net::SocketAddr;
+use std::{net::SocketAddr, path::PathBuf};
 
 use turn::{Observer, Service};
 
@@ -14,7 +19,15 @@ struct ServerStartOptions<T> {
     external: SocketAddr,
     service: Service<T>,
     router: Router,
+    mirror: Mirror,
+    flows: FlowExporter,
+    tracer: Tracer,
+    shedder: LoadShedder,
+    limiter: RateLimiter,
+    request_limiter: RequestLimiter,
     statistics: Statistics,
+    certificate: Option<PathBuf>,
+    private_key: Option<PathBuf>,
 }
 
 #[allow(unused)]
@@ -27,16 +40,40 @@ trait Server {
 #[cfg(feature = "udp")]
 mod udp {
     use super::{Server as ServerExt, ServerStartOptions};
-    use crate::statistics::Stats;
+    use crate::{limiter::is_unauthenticated, logger, sampling::sampled, statistics::Stats};
 
-    use std::{io::ErrorKind::ConnectionReset, ops::Deref, sync::Arc};
+    use std::{
+        io::ErrorKind::ConnectionReset,
+        ops::{Deref, Range},
+        sync::Arc,
+    };
 
+    use bytes::{Bytes, BytesMut};
     use once_cell::sync::Lazy;
     use stun::Transport;
     use tokio::net::UdpSocket;
     use turn::{Observer, ResponseMethod, SessionAddr};
 
-    static NUM_CPUS: Lazy<usize> = Lazy::new(|| num_cpus::get());
+    static NUM_CPUS: Lazy<usize> = Lazy::new(num_cpus::get);
+
+    /// The size of the receive buffer of a worker, the datagrams are received
+    /// into it one after the other.
+    const RECV_BUFFER_SIZE: usize = 64 * 1024;
+
+    /// The space that is kept free in the receive buffer for the next
+    /// datagram.
+    const MAX_DATAGRAM_SIZE: usize = 2048;
+
+    // The range of the slice in the buffer, if the slice is a part of the buffer.
+    fn subslice_range(buf: &[u8], slice: &[u8]) -> Option<Range<usize>> {
+        let start = (slice.as_ptr() as usize).checked_sub(buf.as_ptr() as usize)?;
+        let end = start + slice.len();
+        if end <= buf.len() {
+            Some(start..end)
+        } else {
+            None
+        }
+    }
 
     /// udp socket process thread.
     ///
@@ -52,21 +89,43 @@ mod udp {
                 external,
                 service,
                 router,
+                mirror,
+                flows,
+                tracer,
+                shedder,
+                limiter,
+                request_limiter,
                 statistics,
+                ..
             }: ServerStartOptions<T>,
         ) -> Result<(), anyhow::Error>
         where
             T: Clone + Observer + 'static,
         {
-            let socket = Arc::new(UdpSocket::bind(bind).await?);
+            let observer = service.get_observer().clone();
+            let socket = match UdpSocket::bind(bind).await {
+                Ok(it) => Arc::new(it),
+                Err(e) => {
+                    observer.interface_failed(external, bind, Transport::UDP, &e);
+                    return Err(e.into());
+                }
+            };
+
             let local_addr = socket.local_addr()?;
+            observer.interface_started(external, local_addr, Transport::UDP);
 
             tokio::spawn(async move {
                 for _ in 0..*NUM_CPUS.deref() {
                     let socket = socket.clone();
                     let router = router.clone();
+                    let mirror = mirror.clone();
+                    let flows = flows.clone();
+                    let tracer = tracer.clone();
+                    let shedder = shedder.clone();
+                    let limiter = limiter.clone();
+                    let request_limiter = request_limiter.clone();
                     let reporter = statistics.get_reporter(Transport::UDP);
-                    let mut operationer = service.get_operationer(external, external);
+                    let mut operationer = service.get_operationer(external, external, Transport::UDP);
 
                     let mut session_addr = SessionAddr {
                         address: external,
@@ -74,13 +133,19 @@ mod udp {
                     };
 
                     tokio::spawn(async move {
-                        let mut buf = vec![0u8; 2048];
+                        let mut buf = BytesMut::with_capacity(RECV_BUFFER_SIZE);
 
                         loop {
+                            // The packets that are forwarded to another interface are handed
+                            // over as parts of the buffer, so the buffer is only reallocated
+                            // once its space is used up while some of them are still queued.
+                            buf.clear();
+                            buf.reserve(MAX_DATAGRAM_SIZE);
+
                             // Note: An error will also be reported when the remote host is
                             // shut down, which is not processed yet, but a
                             // warning will be issued.
-                            let (size, addr) = match socket.recv_from(&mut buf).await {
+                            let (size, addr) = match socket.recv_buf_from(&mut buf).await {
                                 Err(e) if e.kind() != ConnectionReset => break,
                                 Ok(s) => s,
                                 _ => continue,
@@ -93,14 +158,54 @@ mod udp {
                                 &[Stats::ReceivedBytes(size as u32), Stats::ReceivedPkts(1)],
                             );
 
+                            // The unauthenticated requests of a source that exceeds the
+                            // request rate are dropped without a response, so that the
+                            // server does not reflect larger responses at spoofed sources.
+                            if is_unauthenticated(&buf) && !request_limiter.allow(addr.ip()) {
+                                continue;
+                            }
+
                             // The stun message requires at least 4 bytes. (currently the
                             // smallest stun message is channel data,
                             // excluding content)
                             if size >= 4 {
-                                if let Ok(Some(res)) = operationer.route(&buf[..size], addr).await {
+                                let started = shedder.start();
+                                let ret = logger::scope(session_addr, operationer.route(&buf, addr)).await;
+                                shedder.finish(started);
+
+                                if let Ok(Some(res)) = ret {
+                                    if let Some(ref relay) = res.relay {
+                                        if !limiter.allow(&session_addr, res.bytes.len()) {
+                                            reporter.send(&session_addr, &[Stats::DroppedPkts(1)]);
+                                            continue;
+                                        }
+
+                                        mirror.send(&session_addr, relay, res.method, res.bytes);
+                                        flows.record(&session_addr, relay, res.bytes.len());
+                                        tracer.record(&session_addr, relay, res.method, res.bytes);
+                                    }
+
                                     let target = res.relay.as_ref().unwrap_or(&addr);
                                     if let Some(ref endpoint) = res.endpoint {
-                                        router.send(endpoint, res.method, target, res.bytes);
+                                        sampled!(
+                                            log::Level::Debug,
+                                            "forward: address={:?}, interface={:?}, target={:?}, method={:?}, size={}",
+                                            addr,
+                                            external,
+                                            target,
+                                            res.method,
+                                            res.bytes.len()
+                                        );
+
+                                        // ChannelData messages are forwarded as they were received, they
+                                        // are sent from the buffer without copying them.
+                                        let (endpoint, method, target) = (*endpoint, res.method, *target);
+                                        let bytes = match subslice_range(&buf, res.bytes) {
+                                            Some(range) => buf.split().freeze().slice(range),
+                                            None => Bytes::copy_from_slice(res.bytes),
+                                        };
+
+                                        router.send_bytes(&endpoint, method, &target, bytes);
                                     } else {
                                         if let Err(e) = socket.send_to(res.bytes, target).await {
                                             if e.kind() != ConnectionReset {
@@ -152,6 +257,7 @@ mod udp {
                 }
 
                 log::error!("udp server close: interface={:?}", local_addr);
+                observer.interface_stopped(external, Transport::UDP);
             });
 
             log::info!(
@@ -168,16 +274,29 @@ mod udp {
 #[cfg(feature = "tcp")]
 mod tcp {
     use super::{Server as ServerExt, ServerStartOptions};
-    use crate::statistics::Stats;
+    use crate::{
+        logger,
+        router::Router,
+        sampling::sampled,
+        statistics::{StatisticsReporter, Stats},
+    };
 
     use std::{
+        future::Future,
+        io,
         ops::{Deref, DerefMut},
         sync::Arc,
+        time::Duration,
     };
 
-    use stun::{Decoder, Transport};
-    use tokio::{io::AsyncReadExt, io::AsyncWriteExt, net::TcpListener, sync::Mutex};
-    use turn::{Observer, ResponseMethod, SessionAddr};
+    use stun::{Decoder, Kind, Method, Transport};
+    use tokio::{
+        io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+        sync::Mutex,
+        time::{interval, sleep},
+    };
+    use turn::{Observer, ResponseMethod, SessionAddr, Sessions};
 
     static ZERO_BYTES: [u8; 8] = [0u8; 8];
 
@@ -246,7 +365,7 @@ mod tcp {
         }
 
         fn split(&mut self, len: usize) -> &[u8] {
-            let (ref current_bytes, current_len) = self.buffers[self.index];
+            let current_len = self.buffers[self.index].1;
 
             // The length of the separation cannot be greater than the length of the data.
             assert!(len <= current_len);
@@ -254,27 +373,158 @@ mod tcp {
             // Length of unconsumed data
             let remaining = current_len - len;
 
-            {
-                // The current buffer is no longer in use, resetting the content length.
-                self.buffers[self.index].1 = 0;
+            // Borrow both buffers at once, the current buffer is read while the free buffer is
+            // written.
+            let (first, second) = self.buffers.split_at_mut(1);
+            let (current, free) = if self.index == 0 {
+                (&mut first[0], &mut second[0])
+            } else {
+                (&mut second[0], &mut first[0])
+            };
 
-                // Invert the buffer.
-                self.index = if self.index == 0 { 1 } else { 0 };
+            // The current buffer is no longer in use, resetting the content length.
+            current.1 = 0;
 
-                // The length of unconsumed data needs to be updated into the reversed
-                // completion buffer.
-                self.buffers[self.index].1 = remaining;
-            }
+            // The length of unconsumed data needs to be updated into the reversed completion
+            // buffer.
+            free.1 = remaining;
 
             // Unconsumed data exists and is copied to the free buffer.
-            #[allow(mutable_transmutes)]
             if remaining > 0 {
-                unsafe { std::mem::transmute::<&[u8], &mut [u8]>(&self.buffers[self.index].0[..remaining]) }
-                    .copy_from_slice(&current_bytes[len..current_len]);
+                free.0[..remaining].copy_from_slice(&current.0[len..current_len]);
+            }
+
+            // Invert the buffer.
+            self.index = if self.index == 0 { 1 } else { 0 };
+
+            &current.0[..len]
+        }
+    }
+
+    /// Completes the handshake of an accepted connection before any message
+    /// is read from it, there is none for plain TCP.
+    trait Handshake: Clone + Send + Sync + 'static {
+        type Stream: AsyncRead + AsyncWrite + Send + Unpin + 'static;
+
+        fn handshake(&self, socket: TcpStream) -> impl Future<Output = io::Result<Self::Stream>> + Send;
+    }
+
+    #[derive(Clone)]
+    struct Plain;
+
+    impl Handshake for Plain {
+        type Stream = TcpStream;
+
+        async fn handshake(&self, socket: TcpStream) -> io::Result<Self::Stream> {
+            Ok(socket)
+        }
+    }
+
+    #[cfg(feature = "tls")]
+    impl Handshake for tokio_rustls::TlsAcceptor {
+        type Stream = tokio_rustls::server::TlsStream<TcpStream>;
+
+        fn handshake(&self, socket: TcpStream) -> impl Future<Output = io::Result<Self::Stream>> + Send {
+            self.accept(socket)
+        }
+    }
+
+    // Load the certificate chain and the private key of a TLS interface.
+    #[cfg(feature = "tls")]
+    fn tls_acceptor(
+        certificate: &std::path::Path,
+        private_key: &std::path::Path,
+    ) -> anyhow::Result<tokio_rustls::TlsAcceptor> {
+        use std::{fs::File, io::BufReader};
+
+        use tokio_rustls::rustls::{crypto::ring::default_provider, ServerConfig};
+
+        let certs =
+            rustls_pemfile::certs(&mut BufReader::new(File::open(certificate)?)).collect::<Result<Vec<_>, _>>()?;
+        let key = rustls_pemfile::private_key(&mut BufReader::new(File::open(private_key)?))?
+            .ok_or_else(|| anyhow::anyhow!("no private key in {:?}", private_key))?;
+
+        let config = ServerConfig::builder_with_provider(Arc::new(default_provider()))
+            .with_safe_default_protocol_versions()?
+            .with_no_client_auth()
+            .with_single_cert(certs, key)?;
+
+        Ok(tokio_rustls::TlsAcceptor::from(Arc::new(config)))
+    }
+
+    /// Relay the data of a client data connection to the data connection of
+    /// the peer, as is, see [RFC6062](https://tools.ietf.org/html/rfc6062).
+    ///
+    /// Nothing is read from the connection until the peer has bound its data
+    /// connection too, the data waits in the socket in the meantime. The
+    /// connection ends when the client closes it, or when the connection
+    /// of the allocations is closed.
+    async fn relay_connection<T, R>(
+        sessions: &Sessions<T>,
+        router: &Router,
+        reporter: &StatisticsReporter,
+        session_addr: &SessionAddr,
+        reader: &mut R,
+        buffer: &mut ExchangeBuffer,
+    ) where
+        T: Observer + 'static,
+        R: AsyncRead + Unpin,
+    {
+        // The success response to the ConnectionBind request has been sent.
+        sessions.connection_ready(session_addr);
+
+        let peer = loop {
+            if let Some(it) = sessions.get_connection_peer(session_addr) {
+                break it;
+            }
+
+            if !sessions.is_connection_alive(session_addr) {
+                return;
             }
 
-            &current_bytes[..len]
+            sleep(Duration::from_millis(100)).await;
+        };
+
+        // The data that the client sent right after the ConnectionBind request.
+        let size = buffer.len();
+        if size > 0 {
+            router.send(
+                &peer.address,
+                ResponseMethod::ConnectionData,
+                &peer.address,
+                buffer.split(size),
+            );
         }
+
+        let mut bytes = vec![0u8; 4096];
+        let mut ticker = interval(Duration::from_secs(1));
+
+        loop {
+            tokio::select! {
+                ret = reader.read(&mut bytes) => {
+                    let size = match ret {
+                        Ok(size) if size > 0 => size,
+                        _ => break,
+                    };
+
+                    reporter.send(session_addr, &[Stats::ReceivedBytes(size as u32)]);
+                    router.send(
+                        &peer.address,
+                        ResponseMethod::ConnectionData,
+                        &peer.address,
+                        &bytes[..size],
+                    );
+                }
+                _ = ticker.tick() => {
+                    if !sessions.is_connection_alive(session_addr) {
+                        break;
+                    }
+                }
+            }
+        }
+
+        // The data connection of the peer is closed with this one.
+        router.remove(&peer.address);
     }
 
     /// tcp socket process thread.
@@ -284,45 +534,115 @@ mod tcp {
     pub struct Server;
 
     impl ServerExt for Server {
-        async fn start<T>(
-            ServerStartOptions {
-                bind,
-                external,
-                service,
-                router,
-                statistics,
-            }: ServerStartOptions<T>,
-        ) -> Result<(), anyhow::Error>
+        async fn start<T>(options: ServerStartOptions<T>) -> Result<(), anyhow::Error>
         where
             T: Clone + Observer + 'static,
         {
-            let listener = TcpListener::bind(bind).await?;
-            let local_addr = listener.local_addr()?;
+            listen(options, Plain, "TCP").await
+        }
+    }
 
-            tokio::spawn(async move {
-                // Accept all connections on the current listener, but exit the entire
-                // process when an error occurs.
-                while let Ok((socket, address)) = listener.accept().await {
-                    let router = router.clone();
-                    let reporter = statistics.get_reporter(Transport::TCP);
-                    let mut receiver = router.get_receiver(address);
-                    let mut operationer = service.get_operationer(address, external);
+    /// tls socket process thread.
+    ///
+    /// The connections are the same as the ones of tcp once the TLS handshake
+    /// has completed, which runs on the task of the connection.
+    #[cfg(feature = "tls")]
+    pub struct TlsServer;
+
+    #[cfg(feature = "tls")]
+    impl ServerExt for TlsServer {
+        async fn start<T>(options: ServerStartOptions<T>) -> Result<(), anyhow::Error>
+        where
+            T: Clone + Observer + 'static,
+        {
+            let acceptor = match (&options.certificate, &options.private_key) {
+                (Some(certificate), Some(private_key)) => tls_acceptor(certificate, private_key)?,
+                _ => {
+                    return Err(anyhow::anyhow!(
+                        "tls interface without a certificate: {}",
+                        options.external
+                    ))
+                }
+            };
 
-                    log::info!("tcp socket accept: addr={:?}, interface={:?}", address, local_addr,);
+            listen(options, acceptor, "TLS").await
+        }
+    }
 
-                    // Disable the Nagle algorithm.
-                    // because to maintain real-time, any received data should be processed
-                    // as soon as possible.
-                    if let Err(e) = socket.set_nodelay(true) {
-                        log::error!("tcp socket set nodelay failed!: addr={}, err={}", address, e);
-                    }
+    async fn listen<T, H>(
+        ServerStartOptions {
+            bind,
+            external,
+            service,
+            router,
+            mirror,
+            flows,
+            tracer,
+            shedder,
+            limiter,
+            statistics,
+            ..
+        }: ServerStartOptions<T>,
+        handshake: H,
+        kind: &'static str,
+    ) -> Result<(), anyhow::Error>
+    where
+        T: Clone + Observer + 'static,
+        H: Handshake,
+    {
+        let observer = service.get_observer().clone();
+        let listener = match TcpListener::bind(bind).await {
+            Ok(it) => it,
+            Err(e) => {
+                observer.interface_failed(external, bind, Transport::TCP, &e);
+                return Err(e.into());
+            }
+        };
 
-                    let session_addr = SessionAddr {
-                        interface: external,
-                        address,
+        let local_addr = listener.local_addr()?;
+        observer.interface_started(external, local_addr, Transport::TCP);
+
+        tokio::spawn(async move {
+            // Accept all connections on the current listener, but exit the entire
+            // process when an error occurs.
+            while let Ok((socket, address)) = listener.accept().await {
+                let router = router.clone();
+                let mirror = mirror.clone();
+                let flows = flows.clone();
+                let tracer = tracer.clone();
+                let shedder = shedder.clone();
+                let limiter = limiter.clone();
+                let handshake = handshake.clone();
+                let reporter = statistics.get_reporter(Transport::TCP);
+                let mut operationer = service.get_operationer(address, external, Transport::TCP);
+
+                log::info!("tcp socket accept: addr={:?}, interface={:?}", address, local_addr,);
+
+                // Disable the Nagle algorithm.
+                // because to maintain real-time, any received data should be processed
+                // as soon as possible.
+                if let Err(e) = socket.set_nodelay(true) {
+                    log::error!("tcp socket set nodelay failed!: addr={}, err={}", address, e);
+                }
+
+                let session_addr = SessionAddr {
+                    interface: external,
+                    address,
+                };
+
+                let sessions = service.get_sessions();
+                tokio::spawn(async move {
+                    // A connection whose handshake fails never becomes a route.
+                    let socket = match handshake.handshake(socket).await {
+                        Ok(it) => it,
+                        Err(e) => {
+                            log::warn!("{} handshake failed: addr={:?}, err={}", kind, address, e);
+                            return;
+                        }
                     };
 
-                    let (mut reader, writer) = socket.into_split();
+                    let mut receiver = router.get_receiver(address);
+                    let (mut reader, writer) = tokio::io::split(socket);
                     let writer = Arc::new(Mutex::new(writer));
 
                     // Use a separate task to handle messages forwarded to this socket.
@@ -331,7 +651,7 @@ mod tcp {
                     tokio::spawn(async move {
                         while let Some((bytes, method, _)) = receiver.recv().await {
                             let mut writer = writer_.lock().await;
-                            if writer.write_all(bytes.as_slice()).await.is_err() {
+                            if writer.write_all(&bytes).await.is_err() {
                                 break;
                             } else {
                                 reporter_.send(
@@ -352,112 +672,152 @@ mod tcp {
                                 }
                             }
                         }
+
+                        // The route of a data connection is also removed when the data
+                        // connection of its peer is closed, the client is told so.
+                        let _ = writer_.lock().await.shutdown().await;
                     });
 
-                    let sessions = service.get_sessions();
-                    tokio::spawn(async move {
-                        let mut buffer = ExchangeBuffer::default();
+                    let mut buffer = ExchangeBuffer::default();
+                    let mut is_data_connection = false;
 
-                        'a: while let Ok(size) = reader.read(&mut buffer).await {
-                            // When the received message is 0, it means that the socket
-                            // has been closed.
-                            if size == 0 {
+                    'a: while let Ok(size) = reader.read(&mut buffer).await {
+                        // When the received message is 0, it means that the socket
+                        // has been closed.
+                        if size == 0 {
+                            break;
+                        } else {
+                            reporter.send(&session_addr, &[Stats::ReceivedBytes(size as u32)]);
+                            buffer.advance(size);
+                        }
+
+                        // The minimum length of a stun message will not be less
+                        // than 4.
+                        if buffer.len() < 4 {
+                            continue;
+                        }
+
+                        loop {
+                            if buffer.len() <= 4 {
                                 break;
-                            } else {
-                                reporter.send(&session_addr, &[Stats::ReceivedBytes(size as u32)]);
-                                buffer.advance(size);
                             }
 
-                            // The minimum length of a stun message will not be less
-                            // than 4.
-                            if buffer.len() < 4 {
-                                continue;
-                            }
+                            // Try to get the message length, if the currently
+                            // received data is less than the message length, jump
+                            // out of the current loop and continue to receive more
+                            // data.
+                            let size = match Decoder::message_size(&buffer, true) {
+                                Err(_) => break,
+                                Ok(s) => {
+                                    // Limit the maximum length of messages to 2048, this is to prevent buffer
+                                    // overflow attacks.
+                                    if s > 2048 {
+                                        break 'a;
+                                    }
 
-                            loop {
-                                if buffer.len() <= 4 {
-                                    break;
+                                    if s > buffer.len() {
+                                        break;
+                                    }
+
+                                    reporter.send(&session_addr, &[Stats::ReceivedPkts(1)]);
+
+                                    s
                                 }
+                            };
 
-                                // Try to get the message length, if the currently
-                                // received data is less than the message length, jump
-                                // out of the current loop and continue to receive more
-                                // data.
-                                let size = match Decoder::message_size(&buffer, true) {
-                                    Err(_) => break,
-                                    Ok(s) => {
-                                        // Limit the maximum length of messages to 2048, this is to prevent buffer
-                                        // overflow attacks.
-                                        if s > 2048 {
-                                            break 'a;
+                            let chunk = buffer.split(size);
+                            let started = shedder.start();
+                            let ret = logger::scope(session_addr, operationer.route(chunk, address)).await;
+                            shedder.finish(started);
+
+                            if let Ok(ret) = ret {
+                                if let Some(res) = ret {
+                                    if let Some(ref relay) = res.relay {
+                                        if !limiter.allow(&session_addr, res.bytes.len()) {
+                                            reporter.send(&session_addr, &[Stats::DroppedPkts(1)]);
+                                            continue;
                                         }
 
-                                        if s > buffer.len() {
-                                            break;
-                                        }
+                                        mirror.send(&session_addr, relay, res.method, res.bytes);
+                                        flows.record(&session_addr, relay, res.bytes.len());
+                                        tracer.record(&session_addr, relay, res.method, res.bytes);
+                                    }
 
-                                        reporter.send(&session_addr, &[Stats::ReceivedPkts(1)]);
+                                    if let Some(ref inerface) = res.endpoint {
+                                        sampled!(
+                                            log::Level::Debug,
+                                            "forward: address={:?}, interface={:?}, target={:?}, method={:?}, size={}",
+                                            address,
+                                            external,
+                                            res.relay.as_ref().unwrap_or(&address),
+                                            res.method,
+                                            res.bytes.len()
+                                        );
 
-                                        s
-                                    }
-                                };
-
-                                let chunk = buffer.split(size);
-                                if let Ok(ret) = operationer.route(chunk, address).await {
-                                    if let Some(res) = ret {
-                                        if let Some(ref inerface) = res.endpoint {
-                                            router.send(
-                                                inerface,
-                                                res.method,
-                                                res.relay.as_ref().unwrap_or(&address),
-                                                res.bytes,
-                                            );
-                                        } else {
-                                            if writer.lock().await.write_all(res.bytes).await.is_err() {
-                                                break 'a;
-                                            }
+                                        router.send(
+                                            inerface,
+                                            res.method,
+                                            res.relay.as_ref().unwrap_or(&address),
+                                            res.bytes,
+                                        );
+                                    } else {
+                                        if writer.lock().await.write_all(res.bytes).await.is_err() {
+                                            break 'a;
+                                        }
 
-                                            reporter.send(
-                                                &session_addr,
-                                                &[Stats::SendBytes(res.bytes.len() as u32), Stats::SendPkts(1)],
-                                            );
+                                        reporter.send(
+                                            &session_addr,
+                                            &[Stats::SendBytes(res.bytes.len() as u32), Stats::SendPkts(1)],
+                                        );
 
-                                            if let ResponseMethod::Stun(method) = res.method {
-                                                if method.is_error() {
-                                                    reporter.send(&session_addr, &[Stats::ErrorPkts(1)]);
-                                                }
+                                        if let ResponseMethod::Stun(method) = res.method {
+                                            if method.is_error() {
+                                                reporter.send(&session_addr, &[Stats::ErrorPkts(1)]);
                                             }
                                         }
+
+                                        // The connection is a client data connection from now on.
+                                        if res.method == ResponseMethod::Stun(Method::ConnectionBind(Kind::Response)) {
+                                            is_data_connection = true;
+                                            break 'a;
+                                        }
                                     }
-                                } else {
-                                    break 'a;
                                 }
+                            } else {
+                                break 'a;
                             }
                         }
+                    }
 
-                        // When the tcp connection is closed, the procedure to close the session is
-                        // process directly once, avoiding the connection being disconnected
-                        // directly without going through the closing
-                        // process.
-                        sessions.refresh(&session_addr, 0);
+                    if is_data_connection {
+                        relay_connection(&sessions, &router, &reporter, &session_addr, &mut reader, &mut buffer).await;
+                        sessions.close_connection(&session_addr);
+                    }
 
-                        router.remove(&address);
+                    // When the tcp connection is closed, the procedure to close the session is
+                    // process directly once, avoiding the connection being disconnected
+                    // directly without going through the closing
+                    // process.
+                    logger::sync_scope(session_addr, || sessions.refresh(&session_addr, 0));
 
-                        log::info!("tcp socket disconnect: addr={:?}, interface={:?}", address, local_addr);
-                    });
-                }
+                    router.remove(&address);
 
-                log::error!("tcp server close: interface={:?}", local_addr);
-            });
+                    log::info!("tcp socket disconnect: addr={:?}, interface={:?}", address, local_addr);
+                });
+            }
 
-            log::info!(
-                "turn server listening: bind={}, external={}, transport=TCP",
-                bind,
-                external,
-            );
+            log::error!("tcp server close: interface={:?}", local_addr);
+            observer.interface_stopped(external, Transport::TCP);
+        });
 
-            Ok(())
-        }
+        log::info!(
+            "turn server listening: bind={}, external={}, transport={}",
+            bind,
+            external,
+            kind,
+        );
+
+        Ok(())
     }
 }
 
@@ -465,38 +825,94 @@ mod tcp {
 ///
 /// create a specified number of threads,
 /// each thread processes udp data separately.
-pub async fn start<T>(config: &Config, statistics: &Statistics, service: &Service<T>) -> anyhow::Result<()>
+#[allow(clippy::too_many_arguments)]
+pub async fn start<T>(
+    config: &Config,
+    statistics: &Statistics,
+    router: &Router,
+    mirror: &Mirror,
+    flows: &FlowExporter,
+    tracer: &Tracer,
+    shedder: &LoadShedder,
+    limiter: &RateLimiter,
+    request_limiter: &RequestLimiter,
+    service: &Service<T>,
+) -> anyhow::Result<()>
+where
+    T: Clone + Observer + 'static,
+{
+    for interface in config.turn.interfaces.iter() {
+        start_interface(
+            interface,
+            statistics,
+            router,
+            mirror,
+            flows,
+            tracer,
+            shedder,
+            limiter,
+            request_limiter,
+            service,
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// start one interface of the turn server.
+///
+/// The observer of the service is notified when the interface is started,
+/// fails to bind, or stops, so an interface that stopped can be started
+/// again, for example when the network it was bound to comes back. The
+/// external address has to be one of the interfaces that the service was
+/// created with.
+#[allow(clippy::too_many_arguments)]
+pub async fn start_interface<T>(
+    interface: &Interface,
+    statistics: &Statistics,
+    router: &Router,
+    mirror: &Mirror,
+    flows: &FlowExporter,
+    tracer: &Tracer,
+    shedder: &LoadShedder,
+    limiter: &RateLimiter,
+    request_limiter: &RequestLimiter,
+    service: &Service<T>,
+) -> anyhow::Result<()>
 where
     T: Clone + Observer + 'static,
 {
     #[allow(unused)]
     use crate::config::Transport;
 
-    let router = Router::default();
-    for Interface {
-        transport,
-        external,
-        bind,
-    } in config.turn.interfaces.iter().cloned()
-    {
-        #[allow(unused)]
-        let options = ServerStartOptions {
-            statistics: statistics.clone(),
-            service: service.clone(),
-            router: router.clone(),
-            external,
-            bind,
-        };
+    #[allow(unused)]
+    let options = ServerStartOptions {
+        statistics: statistics.clone(),
+        service: service.clone(),
+        router: router.clone(),
+        mirror: mirror.clone(),
+        flows: flows.clone(),
+        tracer: tracer.clone(),
+        shedder: shedder.clone(),
+        limiter: limiter.clone(),
+        request_limiter: request_limiter.clone(),
+        certificate: interface.certificate.clone(),
+        private_key: interface.private_key.clone(),
+        external: interface.external,
+        bind: interface.bind,
+    };
 
-        match transport {
-            #[cfg(feature = "udp")]
-            Transport::UDP => udp::Server::start(options).await?,
-            #[cfg(feature = "tcp")]
-            Transport::TCP => tcp::Server::start(options).await?,
-            #[allow(unreachable_patterns)]
-            _ => (),
-        };
-    }
+    match interface.transport {
+        #[cfg(feature = "udp")]
+        Transport::UDP => udp::Server::start(options).await?,
+        #[cfg(feature = "tcp")]
+        Transport::TCP => tcp::Server::start(options).await?,
+        #[cfg(feature = "tls")]
+        Transport::TLS => tcp::TlsServer::start(options).await?,
+        #[allow(unreachable_patterns)]
+        _ => (),
+    };
 
     Ok(())
 }
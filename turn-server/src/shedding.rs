@@ -0,0 +1,149 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{config::Shedding, router::Router};
+
+/// Decides whether new allocations are shed because the server is overloaded.
+///
+/// The server is overloaded while more packets than `max_queue_depth` are
+/// waiting in the router queue, or while the average processing time of the
+/// requests exceeds the `deadline` budget. The processing time is a moving
+/// average over the recent requests, it also includes the time that new
+/// allocations wait for the hooks server. Without a configuration nothing is
+/// measured and nothing is shed.
+///
+/// # Example
+///
+/// ```
+/// use std::time::Duration;
+///
+/// use turn_server::{config::Shedding, router::Router, shedding::LoadShedder};
+///
+/// let shedder = LoadShedder::new(
+///     Some(&Shedding {
+///         max_queue_depth: None,
+///         deadline: Some(10),
+///     }),
+///     Router::default(),
+/// );
+///
+/// assert!(!shedder.is_overloaded());
+///
+/// for _ in 0..64 {
+///     shedder.record(Duration::from_millis(50));
+/// }
+///
+/// assert!(shedder.is_overloaded());
+/// assert!(shedder.shed());
+/// assert_eq!(shedder.get_shed(), 1);
+///
+/// for _ in 0..64 {
+///     shedder.record(Duration::from_micros(10));
+/// }
+///
+/// assert!(!shedder.is_overloaded());
+/// assert!(!shedder.shed());
+/// assert_eq!(shedder.get_shed(), 1);
+/// ```
+#[derive(Clone, Default)]
+pub struct LoadShedder(Option<Arc<LoadShedderInner>>);
+
+struct LoadShedderInner {
+    max_queue_depth: Option<usize>,
+    deadline: Option<u64>,
+    router: Router,
+    // The moving average of the processing time in microseconds.
+    latency: AtomicU64,
+    shed: AtomicU64,
+}
+
+impl LoadShedder {
+    pub fn new(config: Option<&Shedding>, router: Router) -> Self {
+        Self(config.map(|it| {
+            Arc::new(LoadShedderInner {
+                max_queue_depth: it.max_queue_depth,
+                deadline: it.deadline.map(|it| it * 1000),
+                latency: AtomicU64::new(0),
+                shed: AtomicU64::new(0),
+                router,
+            })
+        }))
+    }
+
+    /// Start measuring the processing time of a request, `None` if there is
+    /// no deadline budget to check it against.
+    #[inline]
+    pub fn start(&self) -> Option<Instant> {
+        self.0.as_ref().and_then(|it| it.deadline).map(|_| Instant::now())
+    }
+
+    /// Finish measuring the processing time of a request.
+    #[inline]
+    pub fn finish(&self, started: Option<Instant>) {
+        if let Some(started) = started {
+            self.record(started.elapsed());
+        }
+    }
+
+    /// Add the processing time of a request to the moving average.
+    ///
+    /// Concurrent requests may overwrite each other's update, which only
+    /// loses single samples of the average.
+    pub fn record(&self, elapsed: Duration) {
+        if let Some(inner) = &self.0 {
+            let sample = elapsed.as_micros() as u64;
+            let average = inner.latency.load(Ordering::Relaxed);
+            inner
+                .latency
+                .store(average - average / 8 + sample / 8, Ordering::Relaxed);
+        }
+    }
+
+    /// The moving average of the processing time of the requests.
+    pub fn latency(&self) -> Duration {
+        Duration::from_micros(
+            self.0
+                .as_ref()
+                .map(|it| it.latency.load(Ordering::Relaxed))
+                .unwrap_or(0),
+        )
+    }
+
+    pub fn is_overloaded(&self) -> bool {
+        if let Some(inner) = &self.0 {
+            if let Some(max) = inner.max_queue_depth {
+                if inner.router.queue_depth() > max {
+                    return true;
+                }
+            }
+
+            if let Some(deadline) = inner.deadline {
+                if inner.latency.load(Ordering::Relaxed) > deadline {
+                    return true;
+                }
+            }
+        }
+
+        false
+    }
+
+    /// Whether a new allocation is shed, shed allocations are counted.
+    pub fn shed(&self) -> bool {
+        let overloaded = self.is_overloaded();
+        if let (true, Some(inner)) = (overloaded, &self.0) {
+            inner.shed.fetch_add(1, Ordering::Relaxed);
+        }
+
+        overloaded
+    }
+
+    /// The number of allocations that were shed.
+    pub fn get_shed(&self) -> u64 {
+        self.0.as_ref().map(|it| it.shed.load(Ordering::Relaxed)).unwrap_or(0)
+    }
+}
@@ -11,10 +11,14 @@ use turn::SessionAddr;
 /// [issue](https://github.com/mycrl/turn-rs/issues/101)
 ///
 /// Integrated Prometheus Metrics Exporter
+#[cfg(feature = "prometheus")]
 pub mod prometheus {
     use anyhow::Result;
     use once_cell::sync::Lazy;
-    use prometheus::{register_int_counter, register_int_gauge, Encoder, IntCounter, IntGauge, TextEncoder};
+    use prometheus::{
+        register_int_counter, register_int_gauge, register_int_gauge_vec, Encoder, IntCounter, IntGauge, TextEncoder,
+    };
+    use tokio::runtime::{Handle, RuntimeMetrics};
 
     use super::{Counts, Number, Stats};
 
@@ -32,7 +36,7 @@ pub mod prometheus {
         };
     }
 
-    pub static METRICS: Lazy<Metrics> = Lazy::new(|| Metrics::default());
+    pub static METRICS: Lazy<Metrics> = Lazy::new(Metrics::default);
 
     /// # Example
     ///
@@ -66,16 +70,154 @@ pub mod prometheus {
                 received_pkts: counter!(prefix, "received", "packets")?,
                 send_pkts: counter!(prefix, "sent", "packets")?,
                 error_pkts: counter!(prefix, "error", "packets")?,
+                rejected_pkts: counter!(prefix, "rejected", "packets")?,
+                retransmitted_pkts: counter!(prefix, "retransmitted", "packets")?,
+                dropped_pkts: counter!(prefix, "dropped", "packets")?,
             })
         }
     }
 
+    /// The number of messages waiting in the internal queues.
+    ///
+    /// Each gauge is increased when a message is queued and decreased when it
+    /// is taken out of the queue.
+    pub struct Queues {
+        /// Data forwarded between the sockets of the interfaces.
+        pub router: IntGauge,
+        /// Events waiting to be pushed to the hooks services.
+        pub hooks: IntGauge,
+        /// Events waiting to be published to the event sinks.
+        pub sinks: IntGauge,
+    }
+
+    impl Queues {
+        fn new() -> Result<Self> {
+            let queues = register_int_gauge_vec!(
+                "queue_depth",
+                "The number of messages waiting in an internal queue",
+                &["queue"]
+            )?;
+
+            Ok(Self {
+                router: queues.get_metric_with_label_values(&["router"])?,
+                hooks: queues.get_metric_with_label_values(&["hooks"])?,
+                sinks: queues.get_metric_with_label_values(&["sinks"])?,
+            })
+        }
+    }
+
+    /// Metrics of the tokio runtime, sampled when the metrics are generated.
+    ///
+    /// The poll counts, the local queue depths and the blocking pool usage are
+    /// only available when the turn server is built with
+    /// `RUSTFLAGS="--cfg tokio_unstable"`, they stay at zero otherwise.
+    pub struct Runtime {
+        pub workers: IntGauge,
+        pub alive_tasks: IntGauge,
+        pub global_queue_depth: IntGauge,
+        pub local_queue_depth: IntGauge,
+        pub worker_polls: IntGauge,
+        pub blocking_threads: IntGauge,
+        pub idle_blocking_threads: IntGauge,
+        pub blocking_queue_depth: IntGauge,
+    }
+
+    impl Runtime {
+        fn new() -> Result<Self> {
+            Ok(Self {
+                workers: register_int_gauge!("runtime_workers", "The number of worker threads of the runtime")?,
+                alive_tasks: register_int_gauge!("runtime_alive_tasks", "The number of alive tasks in the runtime")?,
+                global_queue_depth: register_int_gauge!(
+                    "runtime_global_queue_depth",
+                    "The number of tasks in the global queue of the runtime"
+                )?,
+                local_queue_depth: register_int_gauge!(
+                    "runtime_local_queue_depth",
+                    "The number of tasks in the local queues of all worker threads"
+                )?,
+                worker_polls: register_int_gauge!(
+                    "runtime_worker_polls",
+                    "The number of tasks polled by all worker threads"
+                )?,
+                blocking_threads: register_int_gauge!(
+                    "runtime_blocking_threads",
+                    "The number of threads in the blocking pool"
+                )?,
+                idle_blocking_threads: register_int_gauge!(
+                    "runtime_idle_blocking_threads",
+                    "The number of idle threads in the blocking pool"
+                )?,
+                blocking_queue_depth: register_int_gauge!(
+                    "runtime_blocking_queue_depth",
+                    "The number of tasks waiting for a thread of the blocking pool"
+                )?,
+            })
+        }
+
+        /// # Example
+        ///
+        /// ```
+        /// use turn_server::statistics::prometheus::*;
+        ///
+        /// #[tokio::main(flavor = "multi_thread", worker_threads = 2)]
+        /// async fn main() {
+        ///     METRICS.runtime.update(&tokio::runtime::Handle::current().metrics());
+        ///     assert_eq!(METRICS.runtime.workers.get(), 2);
+        /// }
+        /// ```
+        pub fn update(&self, metrics: &RuntimeMetrics) {
+            self.workers.set(metrics.num_workers() as i64);
+            self.alive_tasks.set(metrics.num_alive_tasks() as i64);
+            self.global_queue_depth.set(metrics.global_queue_depth() as i64);
+
+            #[cfg(tokio_unstable)]
+            {
+                let workers = 0..metrics.num_workers();
+                self.local_queue_depth.set(
+                    workers
+                        .clone()
+                        .map(|it| metrics.worker_local_queue_depth(it) as i64)
+                        .sum(),
+                );
+
+                self.worker_polls
+                    .set(workers.map(|it| metrics.worker_poll_count(it) as i64).sum());
+                self.blocking_threads.set(metrics.num_blocking_threads() as i64);
+                self.idle_blocking_threads
+                    .set(metrics.num_idle_blocking_threads() as i64);
+                self.blocking_queue_depth.set(metrics.blocking_queue_depth() as i64);
+            }
+        }
+    }
+
     /// Summarized metrics data for Global/TCP/UDP.
     pub struct Metrics {
         pub allocated: IntGauge,
+        /// Sessions that were warned because they did not refresh their
+        /// allocation before it was about to expire.
+        pub expiring: IntCounter,
+        /// New allocations that were rejected because the server was
+        /// overloaded.
+        pub shed: IntCounter,
+        /// Requests that presented an unknown username or failed the
+        /// integrity check.
+        pub auth_failures: IntCounter,
+        /// Unauthenticated requests that were dropped because their source
+        /// exceeded the request rate.
+        pub limited_requests: IntCounter,
+        /// Events that were dropped from the full retry queue of the hooks.
+        pub hooks_dropped_events: IntCounter,
+        /// Events that were dropped because the queue of an event sink was
+        /// full.
+        pub sinks_dropped_events: IntCounter,
+        /// Password lookups that were answered by the password cache instead
+        /// of the hooks service.
+        pub password_cache_hits: IntCounter,
         pub total: Counts<IntCounter>,
         pub tcp: Counts<IntCounter>,
         pub udp: Counts<IntCounter>,
+        pub queues: Queues,
+        pub runtime: Runtime,
     }
 
     impl Default for Metrics {
@@ -91,6 +233,36 @@ pub mod prometheus {
                 tcp: Counts::new("tcp")?,
                 udp: Counts::new("udp")?,
                 allocated: register_int_gauge!("allocated", "The number of allocated ports, count = 16383")?,
+                expiring: register_int_counter!(
+                    "expiring_sessions",
+                    "The number of sessions that did not refresh their allocation before the expiry warning"
+                )?,
+                shed: register_int_counter!(
+                    "shed_allocations",
+                    "The number of new allocations that were shed because the server was overloaded"
+                )?,
+                auth_failures: register_int_counter!(
+                    "auth_failures",
+                    "The number of requests that presented an unknown username or failed the integrity check"
+                )?,
+                limited_requests: register_int_counter!(
+                    "limited_requests",
+                    "The number of unauthenticated requests that were dropped because their source exceeded the request rate"
+                )?,
+                hooks_dropped_events: register_int_counter!(
+                    "hooks_dropped_events",
+                    "The number of events that were dropped because the retry queue of the hooks was full"
+                )?,
+                sinks_dropped_events: register_int_counter!(
+                    "sinks_dropped_events",
+                    "The number of events that were dropped because the queue of an event sink was full"
+                )?,
+                password_cache_hits: register_int_counter!(
+                    "password_cache_hits",
+                    "The number of password lookups that were answered by the password cache instead of the hooks"
+                )?,
+                queues: Queues::new()?,
+                runtime: Runtime::new()?,
             })
         }
 
@@ -119,6 +291,10 @@ pub mod prometheus {
     /// Generate prometheus metrics data that externally needs to be exposed to
     /// the `/metrics` route.
     pub fn generate_metrics(buf: &mut Vec<u8>) -> Result<()> {
+        if let Ok(handle) = Handle::try_current() {
+            METRICS.runtime.update(&handle.metrics());
+        }
+
         TextEncoder::new().encode(&prometheus::gather(), buf)?;
         Ok(())
     }
@@ -132,6 +308,9 @@ pub enum Stats {
     ReceivedPkts(u32),
     SendPkts(u32),
     ErrorPkts(u32),
+    RejectedPkts(u32),
+    RetransmittedPkts(u32),
+    DroppedPkts(u32),
 }
 
 pub trait Number {
@@ -159,6 +338,13 @@ pub struct Counts<T> {
     pub received_pkts: T,
     pub send_pkts: T,
     pub error_pkts: T,
+    /// Packets toward the session dropped by strict reverse path verification.
+    pub rejected_pkts: T,
+    /// Transmissions of requests from the session that did not get through,
+    /// as counted by the clients in TRANSACTION-TRANSMIT-COUNTER attributes.
+    pub retransmitted_pkts: T,
+    /// Data from the session dropped because it exceeded the bandwidth limits.
+    pub dropped_pkts: T,
 }
 
 impl<T: Number> Counts<T> {
@@ -173,6 +359,9 @@ impl<T: Number> Counts<T> {
     ///     received_pkts: Count::default(),
     ///     send_pkts: Count::default(),
     ///     error_pkts: Count::default(),
+    ///     rejected_pkts: Count::default(),
+    ///     retransmitted_pkts: Count::default(),
+    ///     dropped_pkts: Count::default(),
     /// };
     ///
     /// counts.add(&Stats::ReceivedBytes(1));
@@ -186,6 +375,15 @@ impl<T: Number> Counts<T> {
     ///
     /// counts.add(&Stats::SendPkts(1));
     /// assert_eq!(counts.send_pkts.get(), 1);
+    ///
+    /// counts.add(&Stats::RejectedPkts(1));
+    /// assert_eq!(counts.rejected_pkts.get(), 1);
+    ///
+    /// counts.add(&Stats::RetransmittedPkts(2));
+    /// assert_eq!(counts.retransmitted_pkts.get(), 2);
+    ///
+    /// counts.add(&Stats::DroppedPkts(1));
+    /// assert_eq!(counts.dropped_pkts.get(), 1);
     /// ```
     pub fn add(&self, payload: &Stats) {
         match payload {
@@ -194,28 +392,65 @@ impl<T: Number> Counts<T> {
             Stats::SendBytes(v) => self.send_bytes.add(*v as u64),
             Stats::SendPkts(v) => self.send_pkts.add(*v as u64),
             Stats::ErrorPkts(v) => self.error_pkts.add(*v as u64),
+            Stats::RejectedPkts(v) => self.rejected_pkts.add(*v as u64),
+            Stats::RetransmittedPkts(v) => self.retransmitted_pkts.add(*v as u64),
+            Stats::DroppedPkts(v) => self.dropped_pkts.add(*v as u64),
         }
     }
 }
 
+type Table = Arc<RwLock<AHashMap<SessionAddr, Counts<Count>>>>;
+
 /// worker cluster statistics
+///
+/// The per-packet accounting of the reporters is only compiled in with the
+/// `statistics` feature, and the statistics can also be disabled at runtime,
+/// in which case nothing is recorded at all.
 #[derive(Clone)]
-pub struct Statistics(Arc<RwLock<AHashMap<SessionAddr, Counts<Count>>>>);
+pub struct Statistics(Option<Table>);
 
 impl Default for Statistics {
-    #[cfg(feature = "api")]
-    fn default() -> Self {
-        Self(Arc::new(RwLock::new(AHashMap::with_capacity(1024))))
-    }
-
-    // There's no need to take up so much memory when you don't have stats enabled.
-    #[cfg(not(feature = "api"))]
     fn default() -> Self {
-        Self(Default::default())
+        Self::new(true)
     }
 }
 
 impl Statistics {
+    /// Create the statistics, nothing is recorded if `enabled` is not set.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use turn::*;
+    /// use turn_server::statistics::*;
+    ///
+    /// let statistics = Statistics::new(false);
+    ///
+    /// let addr = SessionAddr {
+    ///     address: "127.0.0.1:8080".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// statistics.register(addr.clone());
+    /// assert_eq!(statistics.get(&addr).is_some(), false);
+    /// ```
+    pub fn new(enabled: bool) -> Self {
+        if !enabled {
+            return Self(None);
+        }
+
+        #[cfg(feature = "api")]
+        {
+            Self(Some(Arc::new(RwLock::new(AHashMap::with_capacity(1024)))))
+        }
+
+        // There's no need to take up so much memory when you don't have stats enabled.
+        #[cfg(not(feature = "api"))]
+        {
+            Self(Some(Default::default()))
+        }
+    }
+
     /// get signal sender
     ///
     /// The signal sender can notify the statisticsing instance to update
@@ -266,12 +501,18 @@ impl Statistics {
     /// assert_eq!(statistics.get(&addr).is_some(), true);
     /// ```
     pub fn register(&self, addr: SessionAddr) {
+        let table = if let Some(it) = &self.0 {
+            it
+        } else {
+            return;
+        };
+
         #[cfg(feature = "prometheus")]
         {
             self::prometheus::METRICS.allocated.inc();
         }
 
-        self.0.write().insert(
+        table.write().insert(
             addr,
             Counts {
                 received_bytes: Count::default(),
@@ -279,6 +520,9 @@ impl Statistics {
                 received_pkts: Count::default(),
                 send_pkts: Count::default(),
                 error_pkts: Count::default(),
+                rejected_pkts: Count::default(),
+                retransmitted_pkts: Count::default(),
+                dropped_pkts: Count::default(),
             },
         );
     }
@@ -306,12 +550,58 @@ impl Statistics {
     /// assert_eq!(statistics.get(&addr).is_some(), false);
     /// ```
     pub fn unregister(&self, addr: &SessionAddr) {
+        let table = if let Some(it) = &self.0 {
+            it
+        } else {
+            return;
+        };
+
         #[cfg(feature = "prometheus")]
         {
             self::prometheus::METRICS.allocated.dec();
         }
 
-        self.0.write().remove(addr);
+        table.write().remove(addr);
+    }
+
+    /// Move the statistics of a session to its new address
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::net::SocketAddr;
+    /// use stun::Transport;
+    /// use turn::*;
+    /// use turn_server::statistics::*;
+    ///
+    /// let statistics = Statistics::default();
+    ///
+    /// let addr = SessionAddr {
+    ///     address: "127.0.0.1:8080".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let moved_addr = SessionAddr {
+    ///     address: "127.0.0.1:9090".parse().unwrap(),
+    ///     interface: "127.0.0.1:443".parse().unwrap(),
+    /// };
+    ///
+    /// statistics.register(addr.clone());
+    ///
+    /// statistics.transfer(&addr, moved_addr.clone());
+    /// assert_eq!(statistics.get(&addr).is_some(), false);
+    /// assert_eq!(statistics.get(&moved_addr).is_some(), true);
+    /// ```
+    pub fn transfer(&self, from: &SessionAddr, to: SessionAddr) {
+        let mut table = if let Some(it) = &self.0 {
+            it.write()
+        } else {
+            return;
+        };
+
+        if let Some(counts) = table.remove(from) {
+            table.insert(to, counts);
+        }
     }
 
     /// Obtain a list of statistics from statisticsing
@@ -336,12 +626,15 @@ impl Statistics {
     /// assert_eq!(statistics.get(&addr).is_some(), true);
     /// ```
     pub fn get(&self, addr: &SessionAddr) -> Option<Counts<u64>> {
-        self.0.read().get(addr).map(|counts| Counts {
+        self.0.as_ref()?.read().get(addr).map(|counts| Counts {
             received_bytes: counts.received_bytes.get(),
             received_pkts: counts.received_pkts.get(),
             send_bytes: counts.send_bytes.get(),
             send_pkts: counts.send_pkts.get(),
             error_pkts: counts.error_pkts.get(),
+            rejected_pkts: counts.rejected_pkts.get(),
+            retransmitted_pkts: counts.retransmitted_pkts.get(),
+            dropped_pkts: counts.dropped_pkts.get(),
         })
     }
 }
@@ -354,15 +647,15 @@ impl Statistics {
 #[derive(Clone)]
 #[allow(unused)]
 pub struct StatisticsReporter {
-    map: Arc<RwLock<AHashMap<SessionAddr, Counts<Count>>>>,
+    map: Option<Table>,
     transport: Transport,
 }
 
 impl StatisticsReporter {
     #[allow(unused_variables)]
     pub fn send(&self, addr: &SessionAddr, reports: &[Stats]) {
-        #[cfg(feature = "api")]
-        {
+        #[cfg(feature = "statistics")]
+        if let Some(map) = &self.map {
             #[cfg(feature = "prometheus")]
             {
                 for report in reports {
@@ -370,7 +663,7 @@ impl StatisticsReporter {
                 }
             }
 
-            if let Some(counts) = self.map.read().get(addr) {
+            if let Some(counts) = map.read().get(addr) {
                 for item in reports {
                     counts.add(item);
                 }
@@ -0,0 +1,232 @@
+use std::{
+    collections::VecDeque,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use ahash::AHashMap;
+use parking_lot::{Mutex, RwLock};
+use serde::Serialize;
+use stun::Method;
+use turn::{ResponseMethod, SessionAddr};
+
+/// The number of packets that the trace of a session keeps, the oldest
+/// packets are dropped first.
+pub const CAPACITY: usize = 4096;
+
+/// The longest time that a session is traced for.
+pub const MAX_DURATION: Duration = Duration::from_secs(600);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    /// Sent by the traced session to the peer.
+    Sent,
+    /// Received by the traced session from the peer.
+    Received,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Framing {
+    ChannelData,
+    Indication,
+}
+
+/// The metadata of a forwarded packet, the data itself is not kept.
+#[derive(Debug, Clone, Serialize)]
+pub struct TracedPacket {
+    pub direction: Direction,
+    /// The client address of the session on the other side.
+    pub peer: SocketAddr,
+    pub framing: Framing,
+    /// The channel number of ChannelData messages.
+    pub channel: Option<u16>,
+    /// The size of the ChannelData message or the Data indication.
+    pub size: usize,
+    /// When the packet was forwarded, a unix timestamp in milliseconds.
+    pub timestamp: u64,
+}
+
+/// The packets recorded for a session, see [`Tracer::get`].
+#[derive(Debug, Clone, Serialize)]
+pub struct TraceDump {
+    /// Whether packets are still recorded.
+    pub active: bool,
+    /// The packets that did not fit into the trace.
+    pub dropped: u64,
+    pub packets: Vec<TracedPacket>,
+}
+
+struct Trace {
+    until: Instant,
+    dropped: u64,
+    packets: VecDeque<TracedPacket>,
+}
+
+impl Trace {
+    fn push(&mut self, now: Instant, packet: TracedPacket) {
+        if now >= self.until {
+            return;
+        }
+
+        if self.packets.len() >= CAPACITY {
+            self.packets.pop_front();
+            self.dropped += 1;
+        }
+
+        self.packets.push_back(packet);
+    }
+}
+
+/// Records the metadata of the packets that selected sessions forward.
+///
+/// A trace shows the packets that the session sends to its peers and the
+/// packets that its peers send to it, so that a call where the media only
+/// flows in one direction can be diagnosed without capturing the packets on
+/// the host. A session is traced for a limited time, and the trace is kept
+/// until it is stopped or the session is closed.
+///
+/// # Example
+///
+/// ```
+/// use std::time::Duration;
+///
+/// use turn::{ResponseMethod, SessionAddr};
+/// use turn_server::trace::*;
+///
+/// let tracer = Tracer::default();
+///
+/// let addr = SessionAddr {
+///     address: "127.0.0.1:1000".parse().unwrap(),
+///     interface: "127.0.0.1:3478".parse().unwrap(),
+/// };
+///
+/// let peer = SessionAddr {
+///     address: "127.0.0.1:2000".parse().unwrap(),
+///     interface: "127.0.0.1:3478".parse().unwrap(),
+/// };
+///
+/// let channel_data = [0x40, 0x00, 0x00, 0x05, b'h', b'e', b'l', b'l', b'o', 0, 0, 0];
+///
+/// tracer.start(&addr, Duration::from_secs(60));
+/// assert!(tracer.is_traced(&addr));
+///
+/// tracer.record(&addr, &peer.address, ResponseMethod::ChannelData, &channel_data);
+/// tracer.record(&peer, &addr.address, ResponseMethod::ChannelData, &channel_data);
+///
+/// let trace = tracer.get(&addr).unwrap();
+/// assert!(trace.active);
+/// assert_eq!(trace.packets.len(), 2);
+/// assert_eq!(trace.packets[0].direction, Direction::Sent);
+/// assert_eq!(trace.packets[0].peer, peer.address);
+/// assert_eq!(trace.packets[0].channel, Some(0x4000));
+/// assert_eq!(trace.packets[1].direction, Direction::Received);
+/// assert_eq!(trace.packets[1].size, 12);
+///
+/// assert!(tracer.stop(&addr));
+/// assert!(tracer.get(&addr).is_none());
+/// ```
+#[derive(Clone, Default)]
+pub struct Tracer(Arc<RwLock<AHashMap<SessionAddr, Mutex<Trace>>>>);
+
+impl Tracer {
+    /// Start tracing the session for `duration`, at most for
+    /// [`MAX_DURATION`]. The packets of an earlier trace of the session are
+    /// dropped.
+    pub fn start(&self, addr: &SessionAddr, duration: Duration) {
+        let duration = duration.min(MAX_DURATION);
+        self.0.write().insert(
+            *addr,
+            Mutex::new(Trace {
+                until: Instant::now() + duration,
+                dropped: 0,
+                packets: VecDeque::with_capacity(256),
+            }),
+        );
+
+        log::info!(
+            "trace start: address={:?}, interface={:?}, duration={:?}",
+            addr.address,
+            addr.interface,
+            duration
+        );
+    }
+
+    /// Stop tracing the session and drop its trace, returns `false` if the
+    /// session was not traced.
+    pub fn stop(&self, addr: &SessionAddr) -> bool {
+        let removed = self.0.write().remove(addr).is_some();
+        if removed {
+            log::info!("trace stop: address={:?}, interface={:?}", addr.address, addr.interface);
+        }
+
+        removed
+    }
+
+    /// Keep the trace of a session that has moved to another address.
+    pub fn transfer(&self, from: &SessionAddr, to: &SessionAddr) {
+        let mut traces = self.0.write();
+        if let Some(trace) = traces.remove(from) {
+            traces.insert(*to, trace);
+        }
+    }
+
+    pub fn is_traced(&self, addr: &SessionAddr) -> bool {
+        self.0.read().contains_key(addr)
+    }
+
+    pub fn get(&self, addr: &SessionAddr) -> Option<TraceDump> {
+        let traces = self.0.read();
+        let trace = traces.get(addr)?.lock();
+        Some(TraceDump {
+            active: Instant::now() < trace.until,
+            dropped: trace.dropped,
+            packets: trace.packets.iter().cloned().collect(),
+        })
+    }
+
+    /// Record a packet that the session of `addr` forwards to the session of
+    /// `peer`, in the traces of both sessions.
+    ///
+    /// `bytes` is the ChannelData message or the Data indication, the session
+    /// of the peer is looked up by its client address.
+    pub fn record(&self, addr: &SessionAddr, peer: &SocketAddr, method: ResponseMethod, bytes: &[u8]) {
+        let traces = self.0.read();
+        if traces.is_empty() {
+            return;
+        }
+
+        let (framing, channel) = match method {
+            ResponseMethod::ChannelData if bytes.len() >= 2 => {
+                (Framing::ChannelData, Some(u16::from_be_bytes([bytes[0], bytes[1]])))
+            }
+            ResponseMethod::Stun(Method::DataIndication) => (Framing::Indication, None),
+            _ => return,
+        };
+
+        let now = Instant::now();
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|it| it.as_millis() as u64)
+            .unwrap_or(0);
+
+        let packet = |direction, peer| TracedPacket {
+            size: bytes.len(),
+            direction,
+            peer,
+            framing,
+            channel,
+            timestamp,
+        };
+
+        if let Some(trace) = traces.get(addr) {
+            trace.lock().push(now, packet(Direction::Sent, *peer));
+        }
+
+        for (_, trace) in traces.iter().filter(|(it, _)| it.address == *peer) {
+            trace.lock().push(now, packet(Direction::Received, addr.address));
+        }
+    }
+}
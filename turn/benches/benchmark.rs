@@ -0,0 +1,50 @@
+use std::net::SocketAddr;
+
+use bytes::BytesMut;
+use criterion::*;
+use mycrl_turn::*;
+use stun::{attribute::Transport, Kind, MessageWriter, Method};
+
+#[derive(Clone)]
+struct ObserverTest;
+
+impl Observer for ObserverTest {}
+
+fn criterion_benchmark(c: &mut Criterion) {
+    let mut binding = c.benchmark_group("binding");
+    let addr = "127.0.0.1:3478".parse::<SocketAddr>().unwrap();
+    let source = "127.0.0.1:1000".parse::<SocketAddr>().unwrap();
+
+    let mut request = BytesMut::new();
+    MessageWriter::new(Method::Binding(Kind::Request), &[0u8; 12], &mut request)
+        .flush(None)
+        .unwrap();
+
+    binding.throughput(Throughput::Elements(1));
+    for (name, binding_cache) in [("binding_uncached", None), ("binding_cached", Some(1024))] {
+        let service = Service::with_options(
+            "test".to_string(),
+            vec![addr],
+            ObserverTest,
+            SessionsOptions {
+                binding_cache,
+                ..Default::default()
+            },
+        );
+
+        let mut operationer = service.get_operationer(addr, addr, Transport::UDP);
+        binding.bench_function(name, |b| {
+            b.iter(|| {
+                operationer
+                    .process_blocking(&request, source)
+                    .unwrap()
+                    .unwrap();
+            })
+        });
+    }
+
+    binding.finish();
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
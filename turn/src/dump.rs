@@ -0,0 +1,142 @@
+//! Snapshots of the state of the session manager.
+//!
+//! A dump holds everything that decides how the session manager answers the
+//! next request, so that the state of a server that misbehaves can be
+//! attached to a bug report and loaded into another session manager to
+//! replay the requests against it. The lists are sorted, the same state
+//! always gives the same dump.
+//!
+//! The passwords of the sessions are not dumped, only their long-term
+//! credential digests, which are enough to check the integrity of the
+//! requests. The digests still authenticate the users, a dump has to be
+//! handled like the credentials themselves.
+//!
+//! The connections of the TCP allocations are not dumped, they are bound to
+//! TCP connections of the clients that a replay does not have.
+
+use std::{fmt, net::IpAddr};
+
+use stun::attribute::Transport;
+
+use crate::{
+    sessions::{Endpoint, SessionAddr},
+    types::{ChannelNumber, PeerPort, RelayPort},
+};
+
+/// The state of the session manager, see [`Sessions::dump`].
+///
+/// [`Sessions::dump`]: crate::Sessions::dump
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StateDump {
+    /// The time of the timer of the session manager, which the expiry and the
+    /// activity of the sessions and the nonces are counted in.
+    pub timer: u64,
+    pub sessions: Vec<SessionDump>,
+    pub nonces: Vec<NonceDump>,
+    /// The sessions that the data arriving on a relay port is forwarded to,
+    /// which the permissions install.
+    pub relays: Vec<RelayDump>,
+    pub channels: Vec<ChannelDump>,
+    /// The peer ports that the session bound to a relay port has sent data
+    /// to, only recorded with strict reverse path verification.
+    pub outbound: Vec<OutboundDump>,
+    /// The sessions that were deleted with a refresh of lifetime zero and are
+    /// still lingering.
+    pub lingering: Vec<SessionAddr>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SessionDump {
+    pub addr: SessionAddr,
+    pub transport: Transport,
+    pub username: String,
+    /// The realm of the session, the dumps of older versions have none,
+    /// their sessions count against the quota of the empty realm.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub realm: String,
+    pub digest: [u8; 16],
+    /// The key of the SHA-256 password algorithm, the dumps of older
+    /// versions have none, their sessions can only use MD5.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub digest_sha256: [u8; 32],
+    pub origin: Option<String>,
+    /// When the password of the session runs out, see
+    /// [`Auth::expires`](crate::sessions::Auth::expires).
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub credential_expires: Option<u64>,
+    pub port: Option<RelayPort>,
+    pub ip: Option<IpAddr>,
+    /// The transport protocol of the relayed transport address.
+    pub relay_transport: Transport,
+    /// The relay port and the ip address of the other address family of a
+    /// dual allocation.
+    pub additional_port: Option<RelayPort>,
+    pub additional_ip: Option<IpAddr>,
+    pub channels: Vec<ChannelNumber>,
+    pub permissions: Vec<PeerPort>,
+    pub expires: u64,
+    pub created: u64,
+    pub last_request: u64,
+    pub last_data: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NonceDump {
+    pub addr: SessionAddr,
+    pub nonce: String,
+    pub expires: u64,
+    pub issued: u64,
+}
+
+/// The data that arrives on `port` for the session of `addr` is forwarded
+/// from `endpoint`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RelayDump {
+    pub addr: SessionAddr,
+    pub port: RelayPort,
+    pub endpoint: Endpoint,
+}
+
+/// The data that the session of `addr` sends to `channel` is forwarded to
+/// `endpoint`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ChannelDump {
+    pub addr: SessionAddr,
+    pub channel: ChannelNumber,
+    pub endpoint: Endpoint,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OutboundDump {
+    pub port: RelayPort,
+    pub peers: Vec<RelayPort>,
+}
+
+/// Why a dump cannot be loaded, see [`Sessions::load`].
+///
+/// [`Sessions::load`]: crate::Sessions::load
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadError {
+    /// The session manager already has sessions, a dump is only loaded into
+    /// a new session manager.
+    NotEmpty,
+    /// The relay port is allocated to more than one session of the dump.
+    PortInUse(RelayPort),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotEmpty => write!(f, "the sessions are not empty"),
+            Self::PortInUse(port) => write!(f, "port in use: {}", port),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
@@ -1,14 +1,35 @@
+pub mod blocking;
+pub mod dump;
+pub mod lockout;
 pub mod operations;
+pub mod runtime;
 pub mod sessions;
+pub mod types;
 
-use self::operations::ServiceContext;
+use self::{
+    lockout::{AuthFailure, LockoutKey},
+    operations::{Handler, Handlers, ServiceContext},
+};
 
 pub use self::{
     operations::{Operationer, ResponseMethod},
-    sessions::{PortAllocatePools, Session, SessionAddr, Sessions},
+    runtime::{Runtime, Task, ThreadRuntime},
+    sessions::{CloseReason, PortAllocatePools, Session, SessionAddr, Sessions, SessionsOptions},
+    types::{ChannelNumber, OutOfRange, PeerPort, RelayPort},
 };
 
-use std::{future::Future, net::SocketAddr, sync::Arc};
+use std::{
+    future::Future,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+
+use ahash::HashMap;
+use stun::{
+    attribute::{Algorithm, ErrorKind, Transport},
+    auth::{long_term_credential_digest, long_term_credential_key, Key},
+    Method, StunError,
+};
 
 #[rustfmt::skip]
 static SOFTWARE: &str = concat!(
@@ -16,14 +37,162 @@ static SOFTWARE: &str = concat!(
     env!("CARGO_PKG_VERSION")
 );
 
+/// The reason why the password of a session is not given, see
+/// [`Observer::try_get_password`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PasswordError {
+    /// The session must not be authenticated, the request is rejected with
+    /// 403 Forbidden.
+    Forbidden,
+    /// The password could not be looked up, for example because the backend
+    /// that stores it is down. The request is rejected with 500 Server Error,
+    /// which clients may retry.
+    Unavailable,
+}
+
+impl From<PasswordError> for ErrorKind {
+    fn from(value: PasswordError) -> Self {
+        match value {
+            PasswordError::Forbidden => ErrorKind::Forbidden,
+            PasswordError::Unavailable => ErrorKind::ServerError,
+        }
+    }
+}
+
+/// The keys of a password for the MD5 and SHA-256 password algorithms.
+///
+/// The keys authenticate the user as well as the password does, but the
+/// password cannot be recovered from them, see
+/// [`stun::auth::long_term_credential_key`].
+///
+/// # Test
+///
+/// ```
+/// use mycrl_turn::PasswordDigest;
+/// use stun::{
+///     attribute::Algorithm,
+///     auth::{long_term_credential_key, Key},
+/// };
+///
+/// let digest = PasswordDigest::new("panda", "panda", "raspberry").unwrap();
+/// assert_eq!(
+///     Key::Md5(digest.md5),
+///     long_term_credential_key("panda", "panda", "raspberry", Algorithm::Md5).unwrap()
+/// );
+/// assert_eq!(
+///     Key::Sha256(digest.sha256),
+///     long_term_credential_key("panda", "panda", "raspberry", Algorithm::Sha256).unwrap()
+/// );
+///
+/// assert!(PasswordDigest::new("panda", "", "raspberry").is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PasswordDigest {
+    pub md5: [u8; 16],
+    pub sha256: [u8; 32],
+}
+
+impl PasswordDigest {
+    /// The keys of the password of the username in the realm, fails if the
+    /// strings cannot be prepared with the OpaqueString profile.
+    pub fn new(username: &str, password: &str, realm: &str) -> Result<Self, StunError> {
+        Ok(Self {
+            md5: long_term_credential_digest(username, password, realm)?,
+            sha256: match long_term_credential_key(username, password, realm, Algorithm::Sha256)? {
+                Key::Sha256(it) => it,
+                Key::Md5(_) => unreachable!(),
+            },
+        })
+    }
+}
+
+/// The long-term credential of a username, see
+/// [`Observer::try_get_credential`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Credential {
+    Password(String),
+    /// Only the keys of the password, for observers that do not keep the
+    /// passwords themselves.
+    Digest(PasswordDigest),
+}
+
 #[allow(unused)]
 pub trait Observer: Send + Sync {
+    /// Get the password without blocking on an async runtime.
+    ///
+    /// This is the synchronous variant of `get_password`, for embedders that
+    /// drive the service from their own event loop or from plain threads, see
+    /// [`Operationer::process_blocking`]. `get_password` defaults to it.
+    fn get_password_blocking(&self, addr: &SessionAddr, username: &str) -> Option<String> {
+        None
+    }
+
     fn get_password(
         &self,
         addr: &SessionAddr,
         username: &str,
     ) -> impl Future<Output = Option<String>> + Send {
-        async { None }
+        let password = self.get_password_blocking(addr, username);
+        async move { password }
+    }
+
+    /// Get the password, for a request that carries the ORIGIN attribute.
+    ///
+    /// `origin` is the web origin of the application that sent the request,
+    /// browsers add it on behalf of web applications. This allows a policy
+    /// per origin, for example only authenticating sessions of known web
+    /// applications. Defaults to `get_password`, which ignores the origin.
+    fn get_password_with_origin(
+        &self,
+        addr: &SessionAddr,
+        username: &str,
+        origin: Option<&str>,
+    ) -> impl Future<Output = Option<String>> + Send {
+        self.get_password(addr, username)
+    }
+
+    /// Get the password, or the reason why the session is not authenticated.
+    ///
+    /// `Ok(None)` is an unknown username, which is rejected with 401
+    /// Unauthorized like a `None` of `get_password_with_origin`. The errors
+    /// tell a refused session from a lookup that failed, neither counts as a
+    /// failed authentication for the lockouts. Defaults to
+    /// `get_password_with_origin`, which never fails.
+    fn try_get_password(
+        &self,
+        addr: &SessionAddr,
+        username: &str,
+        origin: Option<&str>,
+    ) -> impl Future<Output = Result<Option<String>, PasswordError>> + Send {
+        let password = self.get_password_with_origin(addr, username, origin);
+        async move { Ok(password.await) }
+    }
+
+    /// Get the password or its keys in the realm, or the reason why the
+    /// session is not authenticated.
+    ///
+    /// This allows an observer to keep the keys of the passwords rather than
+    /// the passwords, for example in a cache that is shared with other
+    /// servers. The password of a session that was authenticated with the
+    /// keys is not known. Defaults to `try_get_password`.
+    fn try_get_credential(
+        &self,
+        addr: &SessionAddr,
+        username: &str,
+        realm: &str,
+        origin: Option<&str>,
+    ) -> impl Future<Output = Result<Option<Credential>, PasswordError>> + Send {
+        let password = self.try_get_password(addr, username, origin);
+        async move { Ok(password.await?.map(Credential::Password)) }
+    }
+
+    /// Get the number of seconds that the password of the username stays
+    /// valid, the sessions that were authenticated with it are closed with
+    /// [`CloseReason::Revoked`] when it runs out, however often they are
+    /// refreshed. Called once when a session is authenticated. Defaults to
+    /// `None`, the password never runs out.
+    fn get_password_lifetime(&self, addr: &SessionAddr, username: &str) -> Option<u64> {
+        None
     }
 
     /// allocate request
@@ -42,7 +211,35 @@ pub trait Observer: Send + Sync {
     /// server SHOULD NOT allocate ports in the range 0 - 1023 (the Well-
     /// Known Port range) to discourage clients from using TURN to run
     /// standard services.
-    fn allocated(&self, addr: &SessionAddr, username: &str, port: u16) {}
+    ///
+    /// `transport` is the transport protocol that the client is connected to
+    /// the server over.
+    fn allocated(&self, addr: &SessionAddr, username: &str, port: RelayPort, transport: Transport) {
+    }
+
+    /// select relay address
+    ///
+    /// Called before allocating a port for the session, returns the ip
+    /// address that the relayed transport address of the allocation is
+    /// advertised with. The external ip address of the interface is used if
+    /// `None` is returned, which is the default.
+    ///
+    /// This allows hosts with multiple external ip addresses on one interface
+    /// to dedicate addresses to users.
+    fn get_relay_ip(&self, addr: &SessionAddr, username: &str) -> Option<IpAddr> {
+        None
+    }
+
+    /// shed allocate request
+    ///
+    /// Called for a new allocation before the request is authenticated, the
+    /// request is rejected with a 508 (Insufficient Capacity) error if `true`
+    /// is returned. This allows an overloaded server to shed new allocations
+    /// predictably, while it keeps serving the existing ones: refreshes,
+    /// permissions, channels and relayed data are never shed.
+    fn shed_allocate(&self, addr: &SessionAddr) -> bool {
+        false
+    }
 
     /// channel binding request
     ///
@@ -74,7 +271,7 @@ pub trait Observer: Send + Sync {
     /// different channel, eliminating the possibility that the
     /// transaction would initially fail but succeed on a
     /// retransmission.
-    fn channel_bind(&self, addr: &SessionAddr, username: &str, channel: u16) {}
+    fn channel_bind(&self, addr: &SessionAddr, username: &str, channel: ChannelNumber) {}
 
     /// create permission request
     ///
@@ -112,10 +309,64 @@ pub trait Observer: Send + Sync {
     /// There are no mandatory attributes in the success response.
     ///
     /// > NOTE: A server need not do anything special to implement
-    /// idempotency of CreatePermission requests over UDP using the
-    /// "stateless stack approach".  Retransmitted CreatePermission
-    /// requests will simply refresh the permissions.
-    fn create_permission(&self, addr: &SessionAddr, username: &str, ports: &[u16]) {}
+    /// > idempotency of CreatePermission requests over UDP using the
+    /// > "stateless stack approach".  Retransmitted CreatePermission
+    /// > requests will simply refresh the permissions.
+    fn create_permission(&self, addr: &SessionAddr, username: &str, ports: &[PeerPort]) {}
+
+    /// external peer
+    ///
+    /// Whether the peer is not a session of this server, but is reached
+    /// through another relay that the server has allocated on, such as an
+    /// upstream turn server. The permissions and the data of the sessions
+    /// for external peers are handed to [`Observer::external_permission`]
+    /// and [`Observer::relay_external`] instead of being relayed by the
+    /// server. No peer is external by default.
+    fn is_external(&self, peer: &SocketAddr) -> bool {
+        false
+    }
+
+    /// external permission
+    ///
+    /// Triggered when the session has permitted external peers with a
+    /// CreatePermission request, or has bound a channel to one with a
+    /// ChannelBind request. The data that the peers send to the session is
+    /// only delivered after this.
+    fn external_permission(&self, addr: &SessionAddr, username: &str, peers: &[SocketAddr]) {}
+
+    /// relay to an external peer
+    ///
+    /// Called for the data that the session sends to a permitted external
+    /// peer, in a Send indication or on a channel bound to the peer. This is
+    /// on the path of the relayed data, the data has to be handed on without
+    /// blocking.
+    fn relay_external(&self, addr: &SessionAddr, peer: &SocketAddr, data: &[u8]) {}
+
+    /// peer allowed
+    ///
+    /// Whether the session may relay to the peer, checked for every peer of
+    /// a CreatePermission or ChannelBind request before the permission is
+    /// installed, the request is rejected with a 403 (Forbidden) error if
+    /// one of its peers is not allowed. This can ask an external policy
+    /// engine. Every peer is allowed by default.
+    fn is_peer_allowed(
+        &self,
+        addr: &SessionAddr,
+        username: &str,
+        peer: &SocketAddr,
+    ) -> impl Future<Output = bool> + Send {
+        async { true }
+    }
+
+    /// connection attempt
+    ///
+    /// Called when another session has connected to the TCP allocation that
+    /// `peer` forwards to, with the ConnectionAttempt indication that has to
+    /// be sent to the client of the allocation. The indication does not
+    /// answer a request of that client, so it is not a response of the
+    /// operationer. Only servers that allow TCP allocations have to deliver
+    /// it.
+    fn connection_attempt(&self, peer: &sessions::Endpoint, message: &[u8]) {}
 
     /// refresh request
     ///
@@ -136,17 +387,17 @@ pub trait Observer: Send + Sync {
     /// Subsequent processing depends on the "desired lifetime" value:
     ///
     /// * If the "desired lifetime" is zero, then the request succeeds and
-    /// the allocation is deleted.
+    ///   the allocation is deleted.
     ///
     /// * If the "desired lifetime" is non-zero, then the request succeeds
-    /// and the allocation's time-to-expiry is set to the "desired
-    /// lifetime".
+    ///   and the allocation's time-to-expiry is set to the "desired
+    ///   lifetime".
     ///
     /// If the request succeeds, then the server sends a success response
     /// containing:
     ///
     /// * A LIFETIME attribute containing the current value of the time-to-
-    /// expiry timer.
+    ///   expiry timer.
     ///
     /// NOTE: A server need not do anything special to implement
     /// idempotency of Refresh requests over UDP using the "stateless
@@ -161,9 +412,152 @@ pub trait Observer: Send + Sync {
     /// session closed
     ///
     /// Triggered when the session leaves from the turn. Possible reasons: the
-    /// session life cycle has expired, external active deletion, or active
-    /// exit of the session.
-    fn closed(&self, addr: &SessionAddr, username: &str) {}
+    /// session life cycle has expired, external active deletion, active exit
+    /// of the session, or the allocation has been idle for too long, see
+    /// [`CloseReason`].
+    fn closed(&self, addr: &SessionAddr, username: &str, reason: CloseReason) {}
+
+    /// session transferred
+    ///
+    /// Triggered when a client that reconnected from another address, over
+    /// another interface or transport, re-claims its existing allocation. The
+    /// session moved from `from` to `addr`.
+    fn transferred(&self, addr: &SessionAddr, username: &str, from: &SessionAddr) {}
+
+    /// session expiring
+    ///
+    /// Triggered once when the allocation of the session expires in
+    /// [`SessionsOptions::expiry_warning`] seconds or less and the session has
+    /// not refreshed it by then, `lifetime` is the seconds that are left.
+    /// `relay` is the relayed transport address of the allocation. The
+    /// session is warned again if it refreshes the allocation and lets it run
+    /// out again.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use std::{
+    ///     net::SocketAddr,
+    ///     sync::{
+    ///         atomic::{AtomicUsize, Ordering},
+    ///         Arc,
+    ///     },
+    ///     thread::sleep,
+    ///     time::Duration,
+    /// };
+    ///
+    /// use mycrl_turn::*;
+    /// use stun::attribute::Transport;
+    ///
+    /// #[derive(Clone, Default)]
+    /// struct ObserverTest(Arc<AtomicUsize>);
+    ///
+    /// impl Observer for ObserverTest {
+    ///     fn get_password_blocking(&self, _: &SessionAddr, _: &str) -> Option<String> {
+    ///         Some("test".to_string())
+    ///     }
+    ///
+    ///     fn expiring(&self, _: &SessionAddr, _: &str, _: Transport, _: SocketAddr, lifetime: u64) {
+    ///         assert!(lifetime <= 599);
+    ///         self.0.fetch_add(1, Ordering::Relaxed);
+    ///     }
+    /// }
+    ///
+    /// let addr = SessionAddr {
+    ///     address: "127.0.0.1:8080".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let observer = ObserverTest::default();
+    /// let sessions = Sessions::with_options(
+    ///     observer.clone(),
+    ///     SessionsOptions {
+    ///         expiry_warning: Some(599),
+    ///         ..Default::default()
+    ///     },
+    /// );
+    ///
+    /// pollster::block_on(sessions.get_digest(&addr, Transport::UDP, "test", "test"));
+    /// sessions.allocate(&addr).unwrap();
+    ///
+    /// // The session is warned once, however many ticks it stays in the window.
+    /// sleep(Duration::from_millis(2500));
+    /// assert_eq!(observer.0.load(Ordering::Relaxed), 1);
+    ///
+    /// // A refresh moves the expiry, the session is warned again.
+    /// assert!(sessions.refresh(&addr, 600));
+    /// sleep(Duration::from_millis(2500));
+    /// assert_eq!(observer.0.load(Ordering::Relaxed), 2);
+    /// ```
+    fn expiring(
+        &self,
+        addr: &SessionAddr,
+        username: &str,
+        transport: Transport,
+        relay: SocketAddr,
+        lifetime: u64,
+    ) {
+    }
+
+    /// data rejected
+    ///
+    /// Triggered when strict reverse path verification drops data sent from
+    /// the relay port `port` to the session, because the session has never
+    /// sent anything to that port.
+    fn rejected(&self, addr: &SessionAddr, port: PeerPort, transport: Transport) {}
+
+    /// request retransmitted
+    ///
+    /// Triggered for a request that carries a TRANSACTION-TRANSMIT-COUNTER
+    /// attribute saying that the client has transmitted it more than once,
+    /// `count` is the number of transmissions before this one. The earlier
+    /// transmissions or their responses were lost between the client and the
+    /// server.
+    fn retransmitted(&self, addr: &SessionAddr, method: Method, count: u8, transport: Transport) {}
+
+    /// credentials locked out
+    ///
+    /// Triggered when a username or a source address has failed
+    /// authentication too many times and is locked out for `lockout`
+    /// seconds. `username` is the username presented in the failed request.
+    fn locked(&self, addr: &SessionAddr, username: &str, key: &LockoutKey, lockout: u64) {}
+
+    /// authentication failed
+    ///
+    /// Triggered for every request that presents an unknown username or
+    /// fails the integrity check, whether or not lockouts are enabled.
+    /// Requests that are rejected because they are already locked out do not
+    /// trigger it.
+    fn auth_failed(&self, addr: &SessionAddr, username: &str, reason: AuthFailure) {}
+
+    /// interface started
+    ///
+    /// Triggered by the server when the socket of an interface is bound and
+    /// starts receiving. `interface` is the external address of the
+    /// interface and `bind` the local address of the socket.
+    fn interface_started(&self, interface: SocketAddr, bind: SocketAddr, transport: Transport) {}
+
+    /// interface bind failed
+    ///
+    /// Triggered by the server when the socket of an interface cannot be
+    /// bound, for example because the address is not available on the host
+    /// (yet), or is already in use.
+    fn interface_failed(
+        &self,
+        interface: SocketAddr,
+        bind: SocketAddr,
+        transport: Transport,
+        error: &std::io::Error,
+    ) {
+    }
+
+    /// interface stopped
+    ///
+    /// Triggered by the server when the socket of an interface stops
+    /// receiving, for example because the network it was bound to went
+    /// away. The sessions of the interface are not closed, they expire
+    /// unless the interface is started again.
+    fn interface_stopped(&self, interface: SocketAddr, transport: Transport) {}
 }
 
 /// Turn service.
@@ -171,7 +565,16 @@ pub trait Observer: Send + Sync {
 pub struct Service<T> {
     interfaces: Arc<Vec<SocketAddr>>,
     sessions: Arc<Sessions<T>>,
+    handlers: Arc<Handlers<T>>,
     realm: Arc<String>,
+    // The realms of the interfaces that do not use the realm of the service.
+    realms: Arc<HashMap<SocketAddr, Arc<String>>>,
+    software: Arc<String>,
+    // The SOFTWARE of the interfaces that do not use the one of the service.
+    softwares: Arc<HashMap<SocketAddr, Arc<String>>>,
+    // The ip addresses that allocations may be advertised with in addition to
+    // the ones of the interfaces.
+    relay_ips: Arc<Vec<IpAddr>>,
     observer: T,
 }
 
@@ -183,6 +586,10 @@ where
         self.sessions.clone()
     }
 
+    pub fn get_observer(&self) -> &T {
+        &self.observer
+    }
+
     /// Create turn service.
     ///
     /// # Test
@@ -198,22 +605,166 @@ where
     /// Service::new("test".to_string(), vec![], ObserverTest);
     /// ```
     pub fn new(realm: String, interfaces: Vec<SocketAddr>, observer: T) -> Self {
+        Self::with_options(realm, interfaces, observer, SessionsOptions::default())
+    }
+
+    /// Create turn service with session options.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use mycrl_turn::*;
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {}
+    ///
+    /// Service::with_options(
+    ///     "test".to_string(),
+    ///     vec![],
+    ///     ObserverTest,
+    ///     SessionsOptions {
+    ///         strict_reverse_path: true,
+    ///         ..Default::default()
+    ///     },
+    /// );
+    /// ```
+    pub fn with_options(
+        realm: String,
+        interfaces: Vec<SocketAddr>,
+        observer: T,
+        options: SessionsOptions,
+    ) -> Self {
+        Self::with_runtime(realm, interfaces, observer, options, ThreadRuntime)
+    }
+
+    /// Create turn service with session options, whose background tasks run
+    /// on the runtime, see [`Runtime`].
+    pub fn with_runtime<R: Runtime>(
+        realm: String,
+        interfaces: Vec<SocketAddr>,
+        observer: T,
+        options: SessionsOptions,
+        runtime: R,
+    ) -> Self {
         Self {
-            sessions: Sessions::new(observer.clone()),
+            sessions: Sessions::with_runtime(observer.clone(), options, runtime),
+            handlers: Arc::new(Handlers::default()),
             interfaces: Arc::new(interfaces),
             realm: Arc::new(realm),
+            realms: Default::default(),
+            software: Arc::new(SOFTWARE.to_string()),
+            softwares: Default::default(),
+            relay_ips: Default::default(),
             observer,
         }
     }
 
+    /// Set the realm of an interface, in place of the realm of the service.
+    ///
+    /// The clients of the interface are challenged with the realm, and their
+    /// long-term credentials are computed with it, so that one service can
+    /// serve the tenants of several realms. Only the operationers created
+    /// after the call use the realm.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use std::net::SocketAddr;
+    /// use mycrl_turn::*;
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {}
+    ///
+    /// let addr = "127.0.0.1:8080".parse::<SocketAddr>().unwrap();
+    /// let interface = "127.0.0.1:3478".parse::<SocketAddr>().unwrap();
+    ///
+    /// let mut service = Service::new("test".to_string(), vec![interface], ObserverTest);
+    /// assert_eq!(service.get_realm(&interface), "test");
+    ///
+    /// service.set_realm(interface, "tenant".to_string());
+    /// assert_eq!(service.get_realm(&interface), "tenant");
+    /// assert_eq!(service.get_realm(&addr), "test");
+    /// ```
+    pub fn set_realm(&mut self, interface: SocketAddr, realm: String) {
+        Arc::make_mut(&mut self.realms).insert(interface, Arc::new(realm));
+    }
+
+    /// The realm of the interface, see [`Service::set_realm`].
+    pub fn get_realm(&self, interface: &SocketAddr) -> &str {
+        self.realms.get(interface).unwrap_or(&self.realm)
+    }
+
+    /// Set the SOFTWARE attribute that the responses on an interface carry, in
+    /// place of the name and version of this crate.
+    ///
+    /// This is usually set together with the realm of the interface, see
+    /// [`Service::set_realm`]. Only the operationers created after the call
+    /// use it.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use std::net::SocketAddr;
+    /// use mycrl_turn::*;
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {}
+    ///
+    /// let addr = "127.0.0.1:8080".parse::<SocketAddr>().unwrap();
+    /// let interface = "127.0.0.1:3478".parse::<SocketAddr>().unwrap();
+    ///
+    /// let mut service = Service::new("test".to_string(), vec![interface], ObserverTest);
+    /// assert!(service.get_software(&interface).starts_with("turn-rs."));
+    ///
+    /// service.set_software(interface, "tenant".to_string());
+    /// assert_eq!(service.get_software(&interface), "tenant");
+    /// assert!(service.get_software(&addr).starts_with("turn-rs."));
+    /// ```
+    pub fn set_software(&mut self, interface: SocketAddr, software: String) {
+        Arc::make_mut(&mut self.softwares).insert(interface, Arc::new(software));
+    }
+
+    /// The SOFTWARE of the interface, see [`Service::set_software`].
+    pub fn get_software(&self, interface: &SocketAddr) -> &str {
+        self.softwares.get(interface).unwrap_or(&self.software)
+    }
+
+    /// Set the ip addresses that [`Observer::get_relay_ip`] may advertise
+    /// allocations with, in addition to the ones of the interfaces.
+    ///
+    /// The peers at these addresses are relayed by this server, so that the
+    /// clients can create permissions and bind channels for each other. Only
+    /// the operationers created after the call use the addresses.
+    pub fn set_relay_ips(&mut self, ips: Vec<IpAddr>) {
+        self.relay_ips = Arc::new(ips);
+    }
+
+    /// Set the handler of the requests of a method, in place of the handler
+    /// that the service has for it.
+    ///
+    /// Only the operationers created after the call use the handler, a
+    /// method has to be one that the stun crate can decode.
+    pub fn register<H>(&mut self, method: Method, handler: H)
+    where
+        H: Handler<T> + 'static,
+    {
+        Arc::make_mut(&mut self.handlers).insert(method, handler);
+    }
+
     /// Get operationer.
     ///
     /// # Test
     ///
     /// ```
     /// use std::net::SocketAddr;
-    /// use stun::attribute::Transport;
     /// use mycrl_turn::*;
+    /// use stun::attribute::Transport;
     ///
     /// #[derive(Clone)]
     /// struct ObserverTest;
@@ -223,16 +774,30 @@ where
     /// let addr = "127.0.0.1:8080".parse::<SocketAddr>().unwrap();
     /// let service = Service::new("test".to_string(), vec![], ObserverTest);
     ///
-    /// service.get_operationer(addr, addr);
+    /// service.get_operationer(addr, addr, Transport::UDP);
     /// ```
-    pub fn get_operationer(&self, endpoint: SocketAddr, interface: SocketAddr) -> Operationer<T> {
+    pub fn get_operationer(
+        &self,
+        endpoint: SocketAddr,
+        interface: SocketAddr,
+        transport: Transport,
+    ) -> Operationer<T> {
         Operationer::new(ServiceContext {
             interfaces: self.interfaces.clone(),
+            relay_ips: self.relay_ips.clone(),
             observer: self.observer.clone(),
             sessions: self.sessions.clone(),
-            realm: self.realm.clone(),
+            handlers: self.handlers.clone(),
+            fingerprint: self.sessions.get_options().fingerprint,
+            realm: self.realms.get(&interface).unwrap_or(&self.realm).clone(),
+            software: self
+                .softwares
+                .get(&interface)
+                .unwrap_or(&self.software)
+                .clone(),
             interface,
             endpoint,
+            transport,
         })
     }
 }
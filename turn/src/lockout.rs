@@ -0,0 +1,139 @@
+use std::{net::IpAddr, num::NonZeroUsize};
+
+use lru::LruCache;
+
+/// Options for locking out credentials that repeatedly fail authentication.
+#[derive(Debug, Clone)]
+pub struct LockoutOptions {
+    /// The maximum number of usernames and addresses tracked at the same
+    /// time, the least recently failed ones are forgotten first.
+    pub capacity: usize,
+    /// The number of failures allowed before a username or an address is
+    /// locked out.
+    pub max_failures: u32,
+    /// The lockout time in seconds after reaching the failure limit, it is
+    /// doubled for each further failure.
+    pub lockout: u64,
+    /// The upper limit of the lockout time in seconds.
+    pub max_lockout: u64,
+}
+
+impl Default for LockoutOptions {
+    fn default() -> Self {
+        Self {
+            capacity: 10000,
+            max_failures: 5,
+            lockout: 10,
+            max_lockout: 600,
+        }
+    }
+}
+
+/// Why a request failed authentication.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthFailure {
+    /// No password is known for the username.
+    UnknownUsername,
+    /// The MESSAGE-INTEGRITY of the request does not match the password.
+    Integrity,
+}
+
+/// What a lockout applies to.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum LockoutKey {
+    Username(String),
+    Address(IpAddr),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Failures {
+    count: u32,
+    locked_until: u64,
+}
+
+/// Recently failed credentials.
+///
+/// Failures are counted per username and per source address, once a key has
+/// failed more than `max_failures` times it is locked out for an increasing
+/// amount of time, and requests for it are rejected without verifying the
+/// message integrity.
+///
+/// # Test
+///
+/// ```
+/// use mycrl_turn::lockout::*;
+///
+/// let mut lockouts = Lockouts::new(LockoutOptions {
+///     max_failures: 2,
+///     lockout: 10,
+///     ..Default::default()
+/// });
+///
+/// let key = LockoutKey::Username("test".to_string());
+///
+/// assert_eq!(lockouts.failed(key.clone(), 0), None);
+/// assert_eq!(lockouts.failed(key.clone(), 0), None);
+/// assert!(!lockouts.is_locked(&key, 0));
+///
+/// assert_eq!(lockouts.failed(key.clone(), 0), Some(10));
+/// assert!(lockouts.is_locked(&key, 9));
+/// assert!(!lockouts.is_locked(&key, 10));
+///
+/// assert_eq!(lockouts.failed(key.clone(), 10), Some(20));
+///
+/// lockouts.succeeded(&key);
+/// assert!(!lockouts.is_locked(&key, 10));
+/// ```
+pub struct Lockouts {
+    options: LockoutOptions,
+    failures: LruCache<LockoutKey, Failures>,
+}
+
+impl Lockouts {
+    pub fn new(options: LockoutOptions) -> Self {
+        Self {
+            failures: LruCache::new(
+                NonZeroUsize::new(options.capacity).unwrap_or(NonZeroUsize::MIN),
+            ),
+            options,
+        }
+    }
+
+    /// Whether the key is locked out at the time `now`.
+    pub fn is_locked(&self, key: &LockoutKey, now: u64) -> bool {
+        self.failures
+            .peek(key)
+            .map(|it| it.locked_until > now)
+            .unwrap_or(false)
+    }
+
+    /// Record a failure of the key at the time `now`.
+    ///
+    /// Returns the lockout time in seconds if this failure locks the key out.
+    pub fn failed(&mut self, key: LockoutKey, now: u64) -> Option<u64> {
+        let failures = self.failures.get_or_insert_mut(key, || Failures {
+            count: 0,
+            locked_until: 0,
+        });
+
+        failures.count = failures.count.saturating_add(1);
+        if failures.count <= self.options.max_failures {
+            return None;
+        }
+
+        let exponent = (failures.count - self.options.max_failures - 1).min(63);
+        let lockout = self
+            .options
+            .lockout
+            .saturating_mul(1u64.checked_shl(exponent).unwrap_or(u64::MAX))
+            .min(self.options.max_lockout);
+
+        failures.locked_until = now + lockout;
+        Some(lockout)
+    }
+
+    /// Forget the failures of the key after a successful authentication.
+    pub fn succeeded(&mut self, key: &LockoutKey) {
+        self.failures.pop(key);
+    }
+}
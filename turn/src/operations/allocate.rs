@@ -1,14 +1,17 @@
-use super::{Requet, Response, ResponseMethod};
-use crate::{Observer, SOFTWARE};
+use super::{Handler, HandlerFuture, Requet, Response, ResponseMethod, PASSWORD_ALGORITHMS};
+use crate::{lockout::AuthFailure, Observer, SessionAddr};
 
 use std::net::SocketAddr;
 
 use stun::{
     attribute::{
-        Error, ErrorCode, ErrorKind, Lifetime, Nonce, Realm, ReqeestedTransport, Software,
-        XorMappedAddress, XorRelayedAddress,
+        AdditionalAddressFamily, AddressErrorCode, Algorithm, Error, ErrorCode, ErrorKind,
+        EvenPort, IpFamily, Lifetime, MessageIntegritySha256, MobilityTicket, Nonce,
+        PasswordAlgorithm, PasswordAlgorithms, Realm, ReqeestedTransport, RequestedAddressFamily,
+        ReservationToken, Software, UserName, XorMappedAddress, XorRelayedAddress,
     },
-    Kind, MessageReader, MessageWriter, Method,
+    auth::{opaque_string, Key},
+    Integrity, Kind, MessageReader, MessageWriter, Method, StunError, Transport,
 };
 
 /// return allocate error response
@@ -20,10 +23,18 @@ fn reject<'a, T: Observer>(
     {
         let mut message =
             MessageWriter::extend(Method::Allocate(Kind::Error), req.message, req.bytes);
+        message.fingerprint(req.service.fingerprint);
 
         message.append::<ErrorCode>(Error::from(err));
-        message.append::<Nonce>(&req.service.sessions.get_nonce(&req.address).get_ref()?.0);
+        message.append::<Nonce>(
+            &req.service
+                .sessions
+                .get_realm_nonce(req.address, &req.service.realm)
+                .get_ref()?
+                .0,
+        );
         message.append::<Realm>(&req.service.realm);
+        message.append::<PasswordAlgorithms>(PASSWORD_ALGORITHMS.to_vec());
         message.flush(None).ok()?;
     }
 
@@ -45,21 +56,47 @@ fn reject<'a, T: Observer>(
 /// underlying OS and then later assign them to allocations; for
 /// example, a server may choose this technique to implement the
 /// EVEN-PORT attribute.
+///
+/// The relayed transport address of the additional address family of a dual
+/// allocation is returned in a second XOR-RELAYED-ADDRESS attribute, or the
+/// reason why it was not allocated in the ADDRESS-ERROR-CODE attribute.
+///
+/// The token of the port that is held in reserve for an allocation with an
+/// even port is returned in the RESERVATION-TOKEN attribute, and the mobility
+/// ticket of the allocation in the MOBILITY-TICKET attribute.
 #[inline(always)]
 fn resolve<'a, T: Observer>(
     req: Requet<'_, 'a, T, MessageReader<'_>>,
-    digest: &[u8; 16],
-    port: u16,
+    digest: &Integrity,
+    relay: SocketAddr,
+    additional: Option<Result<SocketAddr, ErrorKind>>,
+    token: Option<u64>,
+    ticket: Option<[u8; 16]>,
+    lifetime: u32,
 ) -> Option<Response<'a>> {
     {
         let mut message =
             MessageWriter::extend(Method::Allocate(Kind::Response), req.message, req.bytes);
 
-        message.append::<XorRelayedAddress>(SocketAddr::new(req.service.interface.ip(), port));
+        message.append::<XorRelayedAddress>(relay);
+        match additional {
+            Some(Ok(it)) => message.append::<XorRelayedAddress>(it),
+            Some(Err(e)) => message.append::<AddressErrorCode>((IpFamily::V6, Error::from(e))),
+            None => (),
+        }
+
+        if let Some(token) = token {
+            message.append::<ReservationToken>(token);
+        }
+
+        if let Some(ticket) = &ticket {
+            message.append::<MobilityTicket>(ticket);
+        }
+
         message.append::<XorMappedAddress>(req.address.address);
-        message.append::<Lifetime>(600);
-        message.append::<Software>(SOFTWARE);
-        message.flush(Some(digest)).ok()?;
+        message.append::<Lifetime>(lifetime);
+        message.append::<Software>(&req.service.software);
+        message.flush_integrity(Some(digest)).ok()?;
     }
 
     Some(Response {
@@ -70,6 +107,125 @@ fn resolve<'a, T: Observer>(
     })
 }
 
+/// The transport protocols that relayed transport addresses can be allocated
+/// with.
+///
+/// The TCP allocations of [RFC6062](https://tools.ietf.org/html/rfc6062) are
+/// only allowed to clients that are connected over TCP, the connections of
+/// the allocation are bound to other connections of the client.
+fn is_supported_transport(transport: Transport, control: Transport) -> bool {
+    transport == Transport::UDP || control == Transport::TCP
+}
+
+/// A client that reconnected from another address presents the nonce of its
+/// previous session to re-claim the allocation of that session.
+fn find_transferable<T: Observer>(
+    req: &Requet<'_, '_, T, MessageReader<'_>>,
+) -> Option<SessionAddr> {
+    let username = opaque_string(req.message.get::<UserName>()?).ok()?;
+    let nonce = req.message.get::<Nonce>()?;
+
+    if req
+        .service
+        .sessions
+        .get_realm_nonce(req.address, &req.service.realm)
+        .get_ref()?
+        .0
+        == nonce
+    {
+        return None;
+    }
+
+    req.service
+        .sessions
+        .find_transferable(req.address, &username, &req.service.realm, nonce)
+}
+
+/// The request proves that the client owns the session of the nonce, but it
+/// may have been captured and replayed from another address. The client is
+/// challenged with the nonce of its new address, and the session is only
+/// transferred once the client has authenticated with it.
+fn prepare_transfer<'a, T: Observer>(
+    req: Requet<'_, 'a, T, MessageReader<'_>>,
+    from: SessionAddr,
+) -> Option<Response<'a>> {
+    let sessions = &req.service.sessions;
+    let (username, key) = {
+        let lock = sessions.get_session(&from);
+        let session = lock.get_ref()?;
+        (
+            session.auth.username.clone(),
+            match req.message.get::<PasswordAlgorithm>() {
+                Some(Algorithm::Sha256) => Key::Sha256(session.auth.digest_sha256),
+                _ => Key::Md5(session.auth.digest),
+            },
+        )
+    };
+
+    if sessions.is_locked(req.address, &username) {
+        return reject(req, ErrorKind::Unauthorized);
+    }
+
+    let digest = if req.message.get::<MessageIntegritySha256>().is_some() {
+        Integrity::Sha256(key)
+    } else {
+        Integrity::Sha1(key)
+    };
+
+    if req.message.check_integrity(&digest).is_err() {
+        sessions.auth_failed(req.address, &username, AuthFailure::Integrity);
+        return reject(req, ErrorKind::Unauthorized);
+    }
+
+    sessions.prepare_transfer(req.address, &from);
+    reject(req, ErrorKind::Unauthorized)
+}
+
+fn transfer<'a, T: Observer>(
+    req: Requet<'_, 'a, T, MessageReader<'_>>,
+    from: SessionAddr,
+    username: &str,
+    digest: &Integrity,
+) -> Option<Response<'a>> {
+    let sessions = &req.service.sessions;
+    let (relay, additional, lifetime) = {
+        let lock = sessions.get_session(&from);
+        let session = lock.get_ref()?;
+        let relay = SocketAddr::new(
+            session
+                .allocate
+                .ip
+                .unwrap_or_else(|| req.service.interface.ip()),
+            session.allocate.port?.get(),
+        );
+
+        let additional = session
+            .allocate
+            .additional_ip
+            .zip(session.allocate.additional_port)
+            .map(|(ip, port)| Ok(SocketAddr::new(ip, port.get())));
+
+        // The allocation keeps its time-to-expiry, the lifetime of the request
+        // is not applied to it.
+        let lifetime = session.expires.saturating_sub(sessions.now()) as u32;
+        (relay, additional, lifetime)
+    };
+
+    if !sessions.transfer(
+        &from,
+        req.address,
+        &req.service.endpoint,
+        req.service.transport,
+    ) {
+        return reject(req, ErrorKind::AllocationMismatch);
+    }
+
+    req.service
+        .observer
+        .transferred(req.address, username, &from);
+    resolve(req, digest, relay, additional, None, None, lifetime)
+}
+
 /// process allocate request
 ///
 /// [rfc8489](https://tools.ietf.org/html/rfc8489)
@@ -86,23 +242,249 @@ fn resolve<'a, T: Observer>(
 /// server SHOULD NOT allocate ports in the range 0 - 1023 (the Well-
 /// Known Port range) to discourage clients from using TURN to run
 /// standard services.
+///
+/// The server checks if the request contains a REQUESTED-TRANSPORT
+/// attribute.  If the REQUESTED-TRANSPORT attribute is not included or is
+/// malformed, the server rejects the request with a 400 (Bad Request)
+/// error.  Otherwise, if the attribute indicates an unsupported transport
+/// protocol, the server rejects the request with a 442 (Unsupported
+/// Transport Protocol) error.
+///
+/// The server computes the lifetime of the allocation the same way as for a
+/// Refresh request, the minimum of the requested LIFETIME and the maximum
+/// allowed lifetime if it is greater than the default lifetime, and the
+/// default lifetime otherwise, see [`Sessions::granted_lifetime`]. The
+/// granted lifetime is returned in the LIFETIME attribute of the response.
+///
+/// [`Sessions::granted_lifetime`]: crate::Sessions::granted_lifetime
+///
+/// [rfc8656](https://tools.ietf.org/html/rfc8656)
+///
+/// If the request contains both a REQUESTED-ADDRESS-FAMILY and an
+/// ADDITIONAL-ADDRESS-FAMILY attribute, or if the ADDITIONAL-ADDRESS-FAMILY
+/// attribute does not specify IPv6, the server rejects the request with a
+/// 400 (Bad Request) error.  If the server cannot allocate the relayed
+/// transport address of the additional address family, the allocation still
+/// succeeds with the address of the first one, and the success response
+/// contains an ADDRESS-ERROR-CODE attribute instead.
+///
+/// The ip address of the IPv6 relayed transport address is the one of an
+/// IPv6 interface of the server, see [`Sessions::allocate_additional`].
+///
+/// [`Sessions::allocate_additional`]: crate::Sessions::allocate_additional
+///
+/// A server that is overloaded rejects new allocations with a 508
+/// (Insufficient Capacity) error, see [`Observer::shed_allocate`].
+///
+/// [rfc8656](https://tools.ietf.org/html/rfc8656)
+///
+/// If the request contains both a RESERVATION-TOKEN and an EVEN-PORT
+/// attribute, or a RESERVATION-TOKEN and a REQUESTED-ADDRESS-FAMILY or
+/// ADDITIONAL-ADDRESS-FAMILY attribute, the server rejects the request with
+/// a 400 (Bad Request) error. The TCP allocations of
+/// [rfc6062](https://tools.ietf.org/html/rfc6062) take neither of them.
+///
+/// If the request contains an EVEN-PORT attribute, the relayed transport
+/// address gets an even port, and if its R bit is set the next-higher port
+/// is held in reserve and its token returned in a RESERVATION-TOKEN
+/// attribute, see [`Sessions::allocate_even`]. If the request contains a
+/// RESERVATION-TOKEN attribute, the relayed transport address is the one
+/// held in reserve, see [`Sessions::allocate_reserved`]. If the server
+/// cannot satisfy either of them, it rejects the request with a 508
+/// (Insufficient Capacity) error, or with a 486 (Allocation Quota Reached)
+/// error if the quota of the user is reached.
+///
+/// [`Sessions::allocate_even`]: crate::Sessions::allocate_even
+/// [`Sessions::allocate_reserved`]: crate::Sessions::allocate_reserved
+///
+/// [rfc8016](https://tools.ietf.org/html/rfc8016)
+///
+/// A client that wants to keep its allocation when its address changes
+/// includes an empty MOBILITY-TICKET attribute. If the server does not allow
+/// mobility, it rejects the request with a 405 (Mobility Forbidden) error,
+/// and a MOBILITY-TICKET attribute that is not empty is rejected with a 400
+/// (Bad Request) error. Otherwise the success response contains the ticket
+/// that the client presents in a Refresh request from its new address, see
+/// [`Sessions::issue_ticket`].
+///
+/// [`Sessions::issue_ticket`]: crate::Sessions::issue_ticket
+///
+/// A client that reconnects from another address re-claims the allocation of
+/// its previous session with the nonce of that session. The request is
+/// answered with a 401 (Unauthorized) error and the nonce of the new address,
+/// and the allocation is only transferred once the client authenticates with
+/// that nonce under the same username and realm, see
+/// [`Sessions::prepare_transfer`].
+///
+/// [`Sessions::prepare_transfer`]: crate::Sessions::prepare_transfer
 pub async fn process<'a, T: Observer>(
     req: Requet<'_, 'a, T, MessageReader<'_>>,
 ) -> Option<Response<'a>> {
-    if req.message.get::<ReqeestedTransport>().is_none() {
-        return reject(req, ErrorKind::ServerError);
+    if req.service.sessions.is_lingering(req.address) {
+        return reject(req, ErrorKind::AllocationMismatch);
+    }
+
+    let transport = match req.message.try_get::<ReqeestedTransport>() {
+        Some(Ok(it)) if is_supported_transport(it, req.service.transport) => it,
+        Some(Ok(_)) | Some(Err(StunError::UnsupportedTransport(_))) => {
+            return reject(req, ErrorKind::UnsupportedTransportAddress)
+        }
+        _ => return reject(req, ErrorKind::BadRequest),
+    };
+
+    let is_dual = match req.message.try_get::<AdditionalAddressFamily>() {
+        None => false,
+        Some(Ok(IpFamily::V6)) if req.message.get::<RequestedAddressFamily>().is_none() => true,
+        _ => return reject(req, ErrorKind::BadRequest),
+    };
+
+    let even_port = match req.message.try_get::<EvenPort>() {
+        None => None,
+        Some(Ok(it)) if transport == Transport::UDP => Some(it),
+        _ => return reject(req, ErrorKind::BadRequest),
+    };
+
+    let token = match req.message.try_get::<ReservationToken>() {
+        None => None,
+        Some(Ok(it))
+            if transport == Transport::UDP
+                && even_port.is_none()
+                && !is_dual
+                && req.message.get::<RequestedAddressFamily>().is_none() =>
+        {
+            Some(it)
+        }
+        _ => return reject(req, ErrorKind::BadRequest),
+    };
+
+    let is_mobile = match req.message.get::<MobilityTicket>() {
+        None => false,
+        Some([]) => true,
+        Some(_) => return reject(req, ErrorKind::BadRequest),
+    };
+
+    if is_mobile && !req.service.sessions.get_options().mobility {
+        return reject(req, ErrorKind::MobilityForbidden);
+    }
+
+    if let Some(from) = find_transferable(&req) {
+        return prepare_transfer(req, from);
+    }
+
+    // Shed before the authentication, which may have to ask the hooks server.
+    if req.service.observer.shed_allocate(req.address) {
+        return reject(req, ErrorKind::InsufficientCapacity);
     }
 
     let (username, digest) = match req.auth().await {
-        Some(it) => it,
-        None => return reject(req, ErrorKind::Unauthorized),
+        Ok(it) => it,
+        Err(e) => return reject(req, e),
+    };
+
+    if let Some(from) =
+        req.service
+            .sessions
+            .take_transfer(req.address, &username, &req.service.realm)
+    {
+        return transfer(req, from, &username, &digest);
+    }
+
+    let mut ip = req
+        .service
+        .observer
+        .get_relay_ip(req.address, &username)
+        .unwrap_or_else(|| req.service.interface.ip());
+
+    let sessions = &req.service.sessions;
+    if sessions.is_quota_reached(req.address) {
+        return reject(req, ErrorKind::AllocationQuotaReached);
+    }
+
+    // The quota may have been reached by another allocation of the user in the
+    // meantime, which is not a lack of capacity.
+    let exhausted = || {
+        if sessions.is_quota_reached(req.address) {
+            ErrorKind::AllocationQuotaReached
+        } else {
+            ErrorKind::InsufficientCapacity
+        }
     };
 
-    let port = match req.service.sessions.allocate(req.address) {
-        Some(it) => it,
-        None => return reject(req, ErrorKind::AllocationQuotaReached),
+    let (port, reservation) = match (even_port, token) {
+        (Some(reserve), _) => match sessions.allocate_even(req.address, ip, transport, reserve) {
+            Some(it) => it,
+            None => return reject(req, exhausted()),
+        },
+        (None, Some(token)) => match sessions.allocate_reserved(req.address, token, transport) {
+            Some((port, reserved_ip)) => {
+                ip = reserved_ip;
+                (port, None)
+            }
+            None => return reject(req, exhausted()),
+        },
+        (None, None) => match sessions.allocate_with_transport(req.address, ip, transport) {
+            Some(it) => (it, None),
+            None => return reject(req, exhausted()),
+        },
     };
 
-    req.service.observer.allocated(&req.address, username, port);
-    resolve(req, &digest, port)
+    // An IPv6 relayed transport address is only added to an IPv4 one.
+    let additional = if is_dual {
+        let ipv6 = req
+            .service
+            .interfaces
+            .iter()
+            .map(|it| it.ip())
+            .find(|it| it.is_ipv6() && ip.is_ipv4());
+
+        Some(match ipv6 {
+            Some(ip) => req
+                .service
+                .sessions
+                .allocate_additional(req.address, ip)
+                .map(|it| SocketAddr::new(ip, it.get()))
+                .ok_or(ErrorKind::InsufficientCapacity),
+            None => Err(ErrorKind::AddressFamilyNotSupported),
+        })
+    } else {
+        None
+    };
+
+    // The allocation is created with the default lifetime.
+    let lifetime = req
+        .service
+        .sessions
+        .granted_lifetime(req.address, req.message.get::<Lifetime>());
+    req.service.sessions.refresh(req.address, lifetime);
+
+    let ticket = if is_mobile {
+        req.service.sessions.issue_ticket(req.address)
+    } else {
+        None
+    };
+
+    req.service
+        .observer
+        .allocated(req.address, &username, port, req.service.transport);
+    resolve(
+        req,
+        &digest,
+        SocketAddr::new(ip, port.get()),
+        additional,
+        reservation,
+        ticket,
+        lifetime,
+    )
+}
+
+/// The handler of the Allocate requests, see [`process`].
+pub struct Allocate;
+
+impl<T: Observer + 'static> Handler<T> for Allocate {
+    fn process<'a, 'b: 'a>(
+        &'a self,
+        req: Requet<'a, 'b, T, MessageReader<'a>>,
+    ) -> HandlerFuture<'a, 'b> {
+        Box::pin(process(req))
+    }
 }
@@ -1,9 +1,15 @@
-use super::{Requet, Response, ResponseMethod};
+use super::{Handler, HandlerFuture, Requet, Response, ResponseMethod};
 use crate::{Observer, SOFTWARE};
 
+use std::net::SocketAddr;
+
+use bytes::BytesMut;
 use stun::{
-    attribute::{MappedAddress, ResponseOrigin, Software, XorMappedAddress},
-    Kind, MessageReader, MessageWriter, Method,
+    attribute::{
+        MappedAddress, Priority, ResponseOrigin, Software, TransactionTransmitCounter,
+        TransmitCounter, UserName, XorMappedAddress,
+    },
+    Attributes, Kind, MessageReader, MessageWriter, Method,
 };
 
 /// process binding request
@@ -31,12 +37,23 @@ use stun::{
 pub fn process<'a, T: Observer>(req: Requet<'_, 'a, T, MessageReader<'_>>) -> Option<Response<'a>> {
     {
         let mut message =
-            MessageWriter::extend(Method::Binding(Kind::Response), &req.message, req.bytes);
+            MessageWriter::extend(Method::Binding(Kind::Response), req.message, req.bytes);
+        message.fingerprint(req.service.fingerprint);
 
         message.append::<XorMappedAddress>(req.address.address);
         message.append::<MappedAddress>(req.address.address);
         message.append::<ResponseOrigin>(req.service.interface);
-        message.append::<Software>(SOFTWARE);
+
+        // Every transmission of the request is answered on its own, this
+        // response is the only one the server knows of for the transaction.
+        if let Some(counter) = req.message.get::<TransactionTransmitCounter>() {
+            message.append::<TransactionTransmitCounter>(TransmitCounter {
+                req: counter.req,
+                resp: 1,
+            });
+        }
+
+        message.append::<Software>(&req.service.software);
         message.flush(None).ok()?;
     }
 
@@ -47,3 +64,96 @@ pub fn process<'a, T: Observer>(req: Requet<'_, 'a, T, MessageReader<'_>>) -> Op
         relay: None,
     })
 }
+
+/// Answer a Binding request that a session sent to the relayed transport
+/// address of a peer, see [`SessionsOptions::relay_binding`].
+///
+/// The response is the one that the relayed transport address of the peer
+/// would send: the relayed transport address of the session is the mapped
+/// address, and the relayed transport address of the peer the origin. Only
+/// the Binding requests without USERNAME and PRIORITY are answered, which
+/// are the keepalives and reachability checks of the clients. The
+/// connectivity checks of ICE carry them, and only the peer can answer them,
+/// so they are relayed as usual. Returns `None` if the data is not such a
+/// request.
+///
+/// [`SessionsOptions::relay_binding`]: crate::SessionsOptions::relay_binding
+///
+/// # Test
+///
+/// ```
+/// use bytes::BytesMut;
+/// use mycrl_turn::operations::binding::relayed;
+/// use stun::{attribute::*, Decoder, Kind, MessageWriter, Method, Payload};
+///
+/// let mapped = "127.0.0.1:49152".parse().unwrap();
+/// let origin = "127.0.0.1:49153".parse().unwrap();
+///
+/// let mut request = BytesMut::new();
+/// MessageWriter::new(Method::Binding(Kind::Request), &[1; 12], &mut request)
+///     .flush(None)
+///     .unwrap();
+///
+/// let mut bytes = BytesMut::new();
+/// relayed(&request, mapped, origin, &mut bytes).unwrap();
+///
+/// let mut decoder = Decoder::default();
+/// if let Payload::Message(message) = decoder.decode(&bytes).unwrap() {
+///     assert_eq!(message.method, Method::Binding(Kind::Response));
+///     assert_eq!(message.token, &[1; 12]);
+///     assert_eq!(message.get::<XorMappedAddress>(), Some(mapped));
+///     assert_eq!(message.get::<ResponseOrigin>(), Some(origin));
+/// } else {
+///     panic!("not a message");
+/// }
+///
+/// // The connectivity checks of ICE are left to the peer.
+/// let mut request = BytesMut::new();
+/// let mut message = MessageWriter::new(Method::Binding(Kind::Request), &[1; 12], &mut request);
+/// message.append::<UserName>("peer:client");
+/// message.append::<Priority>(1);
+/// message.flush(Some(&[0u8; 16])).unwrap();
+///
+/// assert!(relayed(&request, mapped, origin, &mut bytes).is_none());
+/// assert!(relayed(b"not a stun message", mapped, origin, &mut bytes).is_none());
+/// ```
+pub fn relayed(
+    data: &[u8],
+    mapped: SocketAddr,
+    origin: SocketAddr,
+    bytes: &mut BytesMut,
+) -> Option<()> {
+    // Most of the relayed data is media, which is told apart by its first bytes
+    // without decoding it.
+    if data.len() < 20 || data[..2] != [0x00, 0x01] {
+        return None;
+    }
+
+    let mut attributes = Attributes::default();
+    let message = MessageReader::decode(data, &mut attributes).ok()?;
+    if message.method != Method::Binding(Kind::Request)
+        || message.get::<UserName>().is_some()
+        || message.get::<Priority>().is_some()
+    {
+        return None;
+    }
+
+    let token: &[u8; 12] = message.token.try_into().ok()?;
+    let mut message = MessageWriter::new(Method::Binding(Kind::Response), token, bytes);
+    message.append::<XorMappedAddress>(mapped);
+    message.append::<ResponseOrigin>(origin);
+    message.append::<Software>(SOFTWARE);
+    message.flush(None).ok()
+}
+
+/// The handler of the Binding requests, see [`process`].
+pub struct Binding;
+
+impl<T: Observer + 'static> Handler<T> for Binding {
+    fn process<'a, 'b: 'a>(
+        &'a self,
+        req: Requet<'a, 'b, T, MessageReader<'a>>,
+    ) -> HandlerFuture<'a, 'b> {
+        Box::pin(std::future::ready(process(req)))
+    }
+}
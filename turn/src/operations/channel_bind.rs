@@ -1,9 +1,9 @@
-use super::{Requet, Response, ResponseMethod};
+use super::{Handler, HandlerFuture, Requet, Response, ResponseMethod};
 use crate::Observer;
 
 use stun::{
     attribute::{ChannelNumber, Error, ErrorCode, ErrorKind, Realm, XorPeerAddress},
-    Kind, MessageReader, MessageWriter, Method,
+    Integrity, Kind, MessageReader, MessageWriter, Method,
 };
 
 /// return channel binding error response
@@ -15,6 +15,7 @@ fn reject<'a, T: Observer>(
     {
         let mut message =
             MessageWriter::extend(Method::ChannelBind(Kind::Error), req.message, req.bytes);
+        message.fingerprint(req.service.fingerprint);
 
         message.append::<ErrorCode>(Error::from(err));
         message.append::<Realm>(&req.service.realm);
@@ -33,11 +34,11 @@ fn reject<'a, T: Observer>(
 #[inline(always)]
 fn resolve<'a, T: Observer>(
     req: Requet<'_, 'a, T, MessageReader<'_>>,
-    digest: &[u8; 16],
+    digest: &Integrity,
 ) -> Option<Response<'a>> {
     {
         MessageWriter::extend(Method::ChannelBind(Kind::Response), req.message, req.bytes)
-            .flush(Some(digest))
+            .flush_integrity(Some(digest))
             .ok()?;
     }
 
@@ -79,42 +80,245 @@ fn resolve<'a, T: Observer>(
 /// different channel, eliminating the possibility that the
 /// transaction would initially fail but succeed on a
 /// retransmission.
+///
+/// The XOR-PEER-ADDRESS has to be the relayed transport address of another
+/// session on this server, of the address family of the relayed transport
+/// address of the allocation, the server rejects other addresses with a 443
+/// (Peer Address Family Mismatch) error and malformed ones with a 400 (Bad
+/// Request) error.
+///
+/// # Test
+///
+/// ```
+/// use std::net::{IpAddr, SocketAddr};
+///
+/// use bytes::BytesMut;
+/// use mycrl_turn::*;
+/// use rand::{thread_rng, Rng};
+/// use stun::{
+///     attribute::{
+///         AttrKind, Attribute, ChannelNumber as Channel, ErrorCode, ErrorKind, Realm, Transport,
+///         UserName, XorPeerAddress,
+///     },
+///     Decoder, Kind, MessageWriter, Method, Payload, StunError,
+/// };
+///
+/// #[derive(Clone)]
+/// struct ObserverTest;
+///
+/// impl Observer for ObserverTest {
+///     fn get_password_blocking(&self, _: &SessionAddr, _: &str) -> Option<String> {
+///         Some("test".to_string())
+///     }
+/// }
+///
+/// // An XOR-PEER-ADDRESS attribute with arbitrary content.
+/// struct RawPeerAddress;
+///
+/// impl<'a> Attribute<'a> for RawPeerAddress {
+///     type Error = StunError;
+///     type Item = &'a [u8];
+///
+///     const KIND: AttrKind = AttrKind::XorPeerAddress;
+///
+///     fn encode(value: Self::Item, bytes: &mut BytesMut, _: &'a [u8]) {
+///         bytes.extend_from_slice(value);
+///     }
+///
+///     fn decode(bytes: &'a [u8], _: &'a [u8]) -> Result<Self::Item, Self::Error> {
+///         Ok(bytes)
+///     }
+/// }
+///
+/// let interface: SocketAddr = "127.0.0.1:3478".parse().unwrap();
+/// let service = Service::new(
+///     "test".to_string(),
+///     vec![interface, "[::1]:3478".parse().unwrap()],
+///     ObserverTest,
+/// );
+///
+/// let addr = SessionAddr {
+///     address: "127.0.0.1:8080".parse().unwrap(),
+///     interface,
+/// };
+///
+/// let peer_addr = SessionAddr {
+///     address: "127.0.0.1:8081".parse().unwrap(),
+///     interface,
+/// };
+///
+/// let digest = [
+///     174, 238, 187, 253, 117, 209, 73, 157, 36, 56, 143, 91, 155, 16, 224,
+///     239,
+/// ];
+///
+/// let sessions = service.get_sessions();
+/// pollster::block_on(sessions.get_digest(&addr, Transport::UDP, "test", "test"));
+/// pollster::block_on(sessions.get_digest(&peer_addr, Transport::UDP, "test", "test"));
+///
+/// sessions.allocate(&addr).unwrap();
+/// let peer_port = sessions.allocate(&peer_addr).unwrap().get();
+///
+/// let mut operationer = service.get_operationer(interface, interface, Transport::UDP);
+/// let mut decoder = Decoder::default();
+///
+/// // Binds the channel to the peer address, or to the raw content of the
+/// // attribute, and returns the error code of the response.
+/// let mut bind = |channel: u16, peer: Result<SocketAddr, &[u8]>| -> Option<u16> {
+///     let mut bytes = BytesMut::new();
+///     {
+///         let mut message =
+///             MessageWriter::new(Method::ChannelBind(Kind::Request), &[0u8; 12], &mut bytes);
+///         message.append::<Channel>(channel);
+///         match peer {
+///             Ok(it) => message.append::<XorPeerAddress>(it),
+///             Err(it) => message.append::<RawPeerAddress>(it),
+///         }
+///
+///         message.append::<UserName>("test");
+///         message.append::<Realm>("test");
+///         message.flush(Some(&digest)).unwrap();
+///     }
+///
+///     let res = operationer
+///         .process_blocking(&bytes, addr.address)
+///         .unwrap()
+///         .unwrap();
+///
+///     match decoder.decode(res.bytes).unwrap() {
+///         Payload::Message(it) if it.method == Method::ChannelBind(Kind::Response) => None,
+///         Payload::Message(it) => Some(it.get::<ErrorCode>().unwrap().code),
+///         _ => unreachable!(),
+///     }
+/// };
+///
+/// // The relay of the peer on the other interface is of another family.
+/// let peer = SocketAddr::new("::1".parse().unwrap(), peer_port);
+/// assert_eq!(bind(0x4000, Ok(peer)), Some(ErrorKind::PeerAddressFamilyMismatch as u16));
+///
+/// let peer = SocketAddr::new("::ffff:127.0.0.1".parse().unwrap(), peer_port);
+/// assert_eq!(bind(0x4000, Ok(peer)), Some(ErrorKind::PeerAddressFamilyMismatch as u16));
+///
+/// let peer = SocketAddr::new("127.0.0.1".parse().unwrap(), peer_port);
+/// assert_eq!(bind(0x4000, Ok(peer)), None);
+///
+/// let mut rng = thread_rng();
+/// for channel in 0x4001..0x4401 {
+///     // Malformed attributes, which are never 8 or 20 bytes long.
+///     let mut raw = vec![0u8; [0, 1, 4, 7, 9, 16, 19, 21, 24][rng.gen_range(0..9)]];
+///     rng.fill(&mut raw[..]);
+///     assert_eq!(bind(channel, Err(&raw)), Some(ErrorKind::BadRequest as u16));
+///
+///     // Only the relays of the sessions on the interfaces of the server, of
+///     // the family of the allocation, are bound.
+///     let ip: IpAddr = match rng.gen_range(0..4) {
+///         0 => interface.ip(),
+///         1 => "::1".parse().unwrap(),
+///         2 => rng.gen::<[u8; 4]>().into(),
+///         _ => rng.gen::<[u8; 16]>().into(),
+///     };
+///
+///     let port = if rng.gen() { peer_port } else { rng.gen_range(1..49152) };
+///     if bind(channel, Ok(SocketAddr::new(ip, port))).is_none() {
+///         assert_eq!(ip, interface.ip());
+///         assert_eq!(port, peer_port);
+///     }
+/// }
+/// ```
 pub async fn process<'a, T: Observer>(
     req: Requet<'_, 'a, T, MessageReader<'_>>,
 ) -> Option<Response<'a>> {
+    if req.service.sessions.is_lingering(req.address) {
+        return reject(req, ErrorKind::AllocationMismatch);
+    }
+
     let peer = match req.message.get::<XorPeerAddress>() {
         None => return reject(req, ErrorKind::BadRequest),
         Some(it) => it,
     };
 
-    if !req.verify_ip(&peer) {
+    // The peers of the upstream turn server are not addresses of this server.
+    if !req.verify_ip(&peer) && !req.service.observer.is_external(&peer) {
         return reject(req, ErrorKind::PeerAddressFamilyMismatch);
     }
 
-    let number = match req.message.get::<ChannelNumber>() {
+    // Channel numbers outside of 0x4000 through 0x7FFF are invalid.
+    let number = match req
+        .message
+        .get::<ChannelNumber>()
+        .and_then(crate::ChannelNumber::new)
+    {
         None => return reject(req, ErrorKind::BadRequest),
         Some(it) => it,
     };
 
-    if !(0x4000..=0x7FFF).contains(&number) {
-        return reject(req, ErrorKind::BadRequest);
-    }
-
     let (username, digest) = match req.auth().await {
-        None => return reject(req, ErrorKind::Unauthorized),
-        Some(it) => it,
+        Err(e) => return reject(req, e),
+        Ok(it) => it,
     };
 
+    if !req.verify_family(&peer) {
+        return reject(req, ErrorKind::PeerAddressFamilyMismatch);
+    }
+
     if !req
         .service
-        .sessions
-        .bind_channel(&req.address, &req.service.endpoint, peer.port(), number)
+        .observer
+        .is_peer_allowed(req.address, &username, &peer)
+        .await
+    {
+        return reject(req, ErrorKind::Forbidden);
+    }
+
+    // Binding a channel also installs a permission for the peer.
+    let sessions = &req.service.sessions;
+    if req.service.observer.is_external(&peer) {
+        if !sessions.check_channel_capacity(req.address, number) {
+            return reject(req, ErrorKind::InsufficientCapacity);
+        }
+
+        if !sessions.bind_external_channel(req.address, &peer, number) {
+            return reject(req, ErrorKind::Forbidden);
+        }
+
+        req.service
+            .observer
+            .external_permission(req.address, &username, &[peer]);
+        req.service
+            .observer
+            .channel_bind(req.address, &username, number);
+        return resolve(req, &digest);
+    }
+
+    if !sessions.check_channel_capacity(req.address, number)
+        || !sessions.check_permission_capacity(req.address, &[peer.port().into()])
     {
+        return reject(req, ErrorKind::InsufficientCapacity);
+    }
+
+    if !req.service.sessions.bind_channel(
+        req.address,
+        &req.service.endpoint,
+        peer.port().into(),
+        number,
+    ) {
         return reject(req, ErrorKind::Forbidden);
     }
 
     req.service
         .observer
-        .channel_bind(&req.address, username, number);
+        .channel_bind(req.address, &username, number);
     resolve(req, &digest)
 }
+
+/// The handler of the ChannelBind requests, see [`process`].
+pub struct ChannelBind;
+
+impl<T: Observer + 'static> Handler<T> for ChannelBind {
+    fn process<'a, 'b: 'a>(
+        &'a self,
+        req: Requet<'a, 'b, T, MessageReader<'a>>,
+    ) -> HandlerFuture<'a, 'b> {
+        Box::pin(process(req))
+    }
+}
@@ -1,6 +1,10 @@
-use super::{Requet, Response, ResponseMethod};
-use crate::Observer;
+use std::net::SocketAddr;
 
+use super::{binding, indication, Requet, Response, ResponseMethod};
+use crate::{ChannelNumber, Observer};
+
+use bytes::{BufMut, BytesMut};
+use rand::{thread_rng, Rng};
 use stun::ChannelData;
 
 /// process channel data
@@ -31,14 +35,92 @@ use stun::ChannelData;
 /// the Length field in the ChannelData message is 0, then there will be
 /// no data in the UDP datagram, but the UDP datagram is still formed and
 /// sent [(Section 4.1 of [RFC6263])](https://tools.ietf.org/html/rfc6263#section-4.1).
+///
+/// With [`SessionsOptions::relay_binding`], a Binding request without
+/// credentials is answered by the server on the same channel instead of
+/// being relayed, see [`binding::relayed`].
+///
+/// [`SessionsOptions::relay_binding`]: crate::SessionsOptions::relay_binding
 pub fn process<'a, T: Observer>(
     bytes: &'a [u8],
     req: Requet<'_, 'a, T, ChannelData<'a>>,
 ) -> Option<Response<'a>> {
-    let relay = req
+    let number = ChannelNumber::new(req.message.number)?;
+    let relay = match req
+        .service
+        .sessions
+        .get_channel_relay_address(req.address, number)
+    {
+        Some(it) => it,
+        None => {
+            // The channel may be bound to a peer that is reached through another relay.
+            let peer = req
+                .service
+                .sessions
+                .get_external_peer(req.address, number)?;
+
+            // Over TCP the application data is followed by the padding.
+            let size = u16::from_be_bytes([bytes[2], bytes[3]]) as usize;
+            req.service.sessions.data_relayed(req.address);
+            req.service
+                .observer
+                .relay_external(req.address, &peer, &req.message.bytes[..size]);
+
+            return None;
+        }
+    };
+
+    // The peer address of a channel is not kept, the interface address is the
+    // relayed address of peers that did not get another one.
+    if !req.service.sessions.is_relay_alive(&relay) {
+        let peer = SocketAddr::new(req.service.interface.ip(), relay.port.get());
+        return indication::unreachable(req, &thread_rng().gen(), peer);
+    }
+
+    if !req
         .service
         .sessions
-        .get_channel_relay_address(&req.address, req.message.number)?;
+        .verify_reverse_path(req.address, &relay)
+    {
+        return None;
+    }
+
+    if req.service.sessions.get_options().relay_binding {
+        let peer = SocketAddr::new(req.service.interface.ip(), relay.port.get());
+        let local = req
+            .service
+            .sessions
+            .get_session(req.address)
+            .get_ref()?
+            .allocate
+            .relayed_address(&peer, req.service.interface.ip())?;
+
+        let size = u16::from_be_bytes([bytes[2], bytes[3]]) as usize;
+        let mut response = BytesMut::new();
+        if binding::relayed(&req.message.bytes[..size], local, peer, &mut response).is_some() {
+            ChannelData {
+                number: req.message.number,
+                bytes: &response,
+            }
+            .encode(req.bytes);
+
+            // The response may be sent over TCP, where the ChannelData messages are
+            // padded to a multiple of four bytes.
+            let pad = req.bytes.len() % 4;
+            if pad > 0 {
+                req.bytes.put_bytes(0, 4 - pad);
+            }
+
+            return Some(Response {
+                method: ResponseMethod::ChannelData,
+                bytes: req.bytes,
+                endpoint: None,
+                relay: None,
+            });
+        }
+    }
+
+    req.service.sessions.data_relayed(req.address);
 
     Some(Response {
         method: ResponseMethod::ChannelData,
@@ -0,0 +1,183 @@
+use super::{Handler, HandlerFuture, Requet, Response, ResponseMethod};
+use crate::{Observer, PeerPort};
+
+use bytes::BytesMut;
+use rand::{thread_rng, Rng};
+use stun::{
+    attribute::{ConnectionId, Error, ErrorCode, ErrorKind, Realm, Software, XorPeerAddress},
+    Integrity, Kind, MessageReader, MessageWriter, Method,
+};
+
+/// return connect error response
+#[inline(always)]
+fn reject<'a, T: Observer>(
+    req: Requet<'_, 'a, T, MessageReader<'_>>,
+    err: ErrorKind,
+) -> Option<Response<'a>> {
+    {
+        let mut message =
+            MessageWriter::extend(Method::Connect(Kind::Error), req.message, req.bytes);
+        message.fingerprint(req.service.fingerprint);
+
+        message.append::<ErrorCode>(Error::from(err));
+        message.append::<Realm>(&req.service.realm);
+        message.flush(None).ok()?;
+    }
+
+    Some(Response {
+        method: ResponseMethod::Stun(Method::Connect(Kind::Error)),
+        bytes: req.bytes,
+        endpoint: None,
+        relay: None,
+    })
+}
+
+/// return connect ok response
+#[inline(always)]
+fn resolve<'a, T: Observer>(
+    req: Requet<'_, 'a, T, MessageReader<'_>>,
+    digest: &Integrity,
+    id: u32,
+) -> Option<Response<'a>> {
+    {
+        let mut message =
+            MessageWriter::extend(Method::Connect(Kind::Response), req.message, req.bytes);
+
+        message.append::<ConnectionId>(id);
+        message.append::<Software>(&req.service.software);
+        message.flush_integrity(Some(digest)).ok()?;
+    }
+
+    Some(Response {
+        method: ResponseMethod::Stun(Method::Connect(Kind::Response)),
+        bytes: req.bytes,
+        endpoint: None,
+        relay: None,
+    })
+}
+
+/// process connect request
+///
+/// [rfc6062](https://tools.ietf.org/html/rfc6062)
+///
+/// When the server receives a Connect request, it processes the request
+/// as follows.
+///
+/// If the request is received on a TCP connection for which no allocation
+/// exists, the server MUST return a 437 (Allocation Mismatch) error.
+///
+/// If the server is currently processing a Connect request for this
+/// allocation with the same XOR-PEER-ADDRESS, it MUST return a 446
+/// (Connection Already Exists) error.
+///
+/// If the server has already successfully processed a Connect request
+/// for this allocation with the same XOR-PEER-ADDRESS, and the resulting
+/// client and peer data connections are either pending or active, it
+/// MUST return a 446 (Connection Already Exists) error.
+///
+/// If the request does not contain an XOR-PEER-ADDRESS attribute, or if
+/// such attribute is invalid, the server MUST return a 400 (Bad Request)
+/// error.
+///
+/// If the new connection is forbidden by local policy, the server MUST
+/// reject the request with a 403 (Forbidden) error.
+///
+/// Otherwise, the server MUST initiate an outgoing TCP connection.  If the
+/// connection attempt fails or times out, the server MUST return a 447
+/// (Connection Timeout or Failure) error.
+///
+/// The peers are the TCP allocations of this server, there is no outgoing
+/// TCP connection. The session needs a permission for the peer, and the
+/// connection is accepted if the peer has a permission for the session, in
+/// which case the peer is told of the connection with a ConnectionAttempt
+/// indication, see [`Observer::connection_attempt`].
+pub async fn process<'a, T: Observer>(
+    req: Requet<'_, 'a, T, MessageReader<'_>>,
+) -> Option<Response<'a>> {
+    if req.service.sessions.is_lingering(req.address) {
+        return reject(req, ErrorKind::AllocationMismatch);
+    }
+
+    let peer = match req.message.get::<XorPeerAddress>() {
+        Some(it) => it,
+        None => return reject(req, ErrorKind::BadRequest),
+    };
+
+    let (_, digest) = match req.auth().await {
+        Err(e) => return reject(req, e),
+        Ok(it) => it,
+    };
+
+    if !req.verify_ip(&peer) {
+        return reject(req, ErrorKind::Forbidden);
+    }
+
+    if !req.verify_family(&peer) {
+        return reject(req, ErrorKind::PeerAddressFamilyMismatch);
+    }
+
+    // The relayed transport address of the session, which the peer sees the
+    // connection coming from.
+    let (local, is_permitted) = {
+        let lock = req.service.sessions.get_session(req.address);
+        match lock.get_ref() {
+            Some(session) => (
+                session
+                    .allocate
+                    .relayed_address(&peer, req.service.interface.ip()),
+                session.permissions.contains(&PeerPort::from(peer.port())),
+            ),
+            None => (None, false),
+        }
+    };
+
+    let local = match local {
+        Some(it) => it,
+        None => return reject(req, ErrorKind::AllocationMismatch),
+    };
+
+    if !is_permitted {
+        return reject(req, ErrorKind::Forbidden);
+    }
+
+    let relay = match req
+        .service
+        .sessions
+        .get_relay_address(req.address, peer.port().into())
+    {
+        Some(it) if req.service.sessions.is_relay_alive(&it) => it,
+        _ => return reject(req, ErrorKind::ConnectionTimeoutOrFailure),
+    };
+
+    let connected = match req.service.sessions.connect(req.address, relay.port) {
+        Ok(it) => it,
+        Err(e) => return reject(req, e),
+    };
+
+    let token = thread_rng().gen::<[u8; 12]>();
+    let mut bytes = BytesMut::with_capacity(64);
+
+    {
+        let mut message =
+            MessageWriter::new(Method::ConnectionAttemptIndication, &token, &mut bytes);
+        message.append::<XorPeerAddress>(local);
+        message.append::<ConnectionId>(connected.peer_id);
+        message.flush(None).ok()?;
+    }
+
+    req.service.observer.connection_attempt(&relay, &bytes);
+
+    resolve(req, &digest, connected.id)
+}
+
+/// The handler of the Connect requests, see [`process`].
+pub struct Connect;
+
+impl<T: Observer + 'static> Handler<T> for Connect {
+    fn process<'a, 'b: 'a>(
+        &'a self,
+        req: Requet<'a, 'b, T, MessageReader<'a>>,
+    ) -> HandlerFuture<'a, 'b> {
+        Box::pin(process(req))
+    }
+}
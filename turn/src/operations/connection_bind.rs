@@ -0,0 +1,132 @@
+use super::{Handler, HandlerFuture, Requet, Response, ResponseMethod, PASSWORD_ALGORITHMS};
+use crate::Observer;
+
+use stun::{
+    attribute::{
+        ConnectionId, Error, ErrorCode, ErrorKind, Nonce, PasswordAlgorithms, Realm, Software,
+        Transport,
+    },
+    Integrity, Kind, MessageReader, MessageWriter, Method,
+};
+
+/// return connection bind error response
+#[inline(always)]
+fn reject<'a, T: Observer>(
+    req: Requet<'_, 'a, T, MessageReader<'_>>,
+    err: ErrorKind,
+) -> Option<Response<'a>> {
+    {
+        let mut message =
+            MessageWriter::extend(Method::ConnectionBind(Kind::Error), req.message, req.bytes);
+        message.fingerprint(req.service.fingerprint);
+
+        // The data connection is a new connection, which has no nonce yet.
+        message.append::<ErrorCode>(Error::from(err));
+        message.append::<Nonce>(
+            &req.service
+                .sessions
+                .get_realm_nonce(req.address, &req.service.realm)
+                .get_ref()?
+                .0,
+        );
+        message.append::<Realm>(&req.service.realm);
+        message.append::<PasswordAlgorithms>(PASSWORD_ALGORITHMS.to_vec());
+        message.flush(None).ok()?;
+    }
+
+    Some(Response {
+        method: ResponseMethod::Stun(Method::ConnectionBind(Kind::Error)),
+        bytes: req.bytes,
+        endpoint: None,
+        relay: None,
+    })
+}
+
+/// return connection bind ok response
+#[inline(always)]
+fn resolve<'a, T: Observer>(
+    req: Requet<'_, 'a, T, MessageReader<'_>>,
+    digest: &Integrity,
+) -> Option<Response<'a>> {
+    {
+        let mut message = MessageWriter::extend(
+            Method::ConnectionBind(Kind::Response),
+            req.message,
+            req.bytes,
+        );
+
+        message.append::<Software>(&req.service.software);
+        message.flush_integrity(Some(digest)).ok()?;
+    }
+
+    Some(Response {
+        method: ResponseMethod::Stun(Method::ConnectionBind(Kind::Response)),
+        bytes: req.bytes,
+        endpoint: None,
+        relay: None,
+    })
+}
+
+/// process connection bind request
+///
+/// [rfc6062](https://tools.ietf.org/html/rfc6062)
+///
+/// When the server receives a ConnectionBind request, it processes the
+/// request as follows.
+///
+/// If the client connection transport is not TCP or TLS, the server MUST
+/// return a 400 (Bad Request) error.
+///
+/// If the request does not contain the CONNECTION-ID attribute, or if
+/// this attribute does not refer to an existing pending connection, the
+/// server MUST return a 400 (Bad Request) error.
+///
+/// Otherwise, the client connection is now called a client data
+/// connection.  Data received on it MUST be sent as-is to the associated
+/// peer data connection.
+///
+/// The credentials of the request have to be the ones of the allocation
+/// that the connection belongs to. Once the success response is sent, the
+/// server stops reading STUN messages from the connection, see
+/// [`Sessions::get_connection_peer`] for where its data goes.
+///
+/// [`Sessions::get_connection_peer`]: crate::Sessions::get_connection_peer
+pub async fn process<'a, T: Observer>(
+    req: Requet<'_, 'a, T, MessageReader<'_>>,
+) -> Option<Response<'a>> {
+    if req.service.transport != Transport::TCP {
+        return reject(req, ErrorKind::BadRequest);
+    }
+
+    let id = match req.message.get::<ConnectionId>() {
+        Some(it) => it,
+        None => return reject(req, ErrorKind::BadRequest),
+    };
+
+    let (username, digest) = match req.auth().await {
+        Err(e) => return reject(req, e),
+        Ok(it) => it,
+    };
+
+    if !req
+        .service
+        .sessions
+        .bind_connection(req.address, &username, id)
+    {
+        return reject(req, ErrorKind::BadRequest);
+    }
+
+    resolve(req, &digest)
+}
+
+/// The handler of the ConnectionBind requests, see [`process`].
+pub struct ConnectionBind;
+
+impl<T: Observer + 'static> Handler<T> for ConnectionBind {
+    fn process<'a, 'b: 'a>(
+        &'a self,
+        req: Requet<'a, 'b, T, MessageReader<'a>>,
+    ) -> HandlerFuture<'a, 'b> {
+        Box::pin(process(req))
+    }
+}
@@ -1,9 +1,9 @@
-use super::{Requet, Response, ResponseMethod};
-use crate::{Observer, SOFTWARE};
+use super::{Handler, HandlerFuture, Requet, Response, ResponseMethod};
+use crate::{Observer, PeerPort};
 
 use stun::{
     attribute::{Error, ErrorCode, ErrorKind, Realm, Software, XorPeerAddress},
-    Kind, MessageReader, MessageWriter, Method,
+    Integrity, Kind, MessageReader, MessageWriter, Method,
 };
 
 /// return create permission error response
@@ -18,6 +18,7 @@ fn reject<'a, T: Observer>(
             req.message,
             req.bytes,
         );
+        message.fingerprint(req.service.fingerprint);
 
         message.append::<ErrorCode>(Error::from(err));
         message.append::<Realm>(&req.service.realm);
@@ -36,7 +37,7 @@ fn reject<'a, T: Observer>(
 #[inline(always)]
 fn resolve<'a, T: Observer>(
     req: Requet<'_, 'a, T, MessageReader<'_>>,
-    digest: &[u8; 16],
+    digest: &Integrity,
 ) -> Option<Response<'a>> {
     {
         let mut message = MessageWriter::extend(
@@ -45,8 +46,8 @@ fn resolve<'a, T: Observer>(
             req.bytes,
         );
 
-        message.append::<Software>(SOFTWARE);
-        message.flush(Some(digest)).ok()?;
+        message.append::<Software>(&req.service.software);
+        message.flush_integrity(Some(digest)).ok()?;
     }
 
     Some(Response {
@@ -99,30 +100,89 @@ fn resolve<'a, T: Observer>(
 pub async fn process<'a, T: Observer>(
     req: Requet<'_, 'a, T, MessageReader<'_>>,
 ) -> Option<Response<'a>> {
+    if req.service.sessions.is_lingering(req.address) {
+        return reject(req, ErrorKind::AllocationMismatch);
+    }
+
     let (username, digest) = match req.auth().await {
-        None => return reject(req, ErrorKind::Unauthorized),
-        Some(it) => it,
+        Err(e) => return reject(req, e),
+        Ok(it) => it,
     };
 
     let mut ports = Vec::with_capacity(15);
+    let mut externals = Vec::new();
     for it in req.message.get_all::<XorPeerAddress>() {
-        if !req.verify_ip(&it) {
+        if !req.verify_ip(&it) && !req.service.observer.is_external(&it) {
             return reject(req, ErrorKind::PeerAddressFamilyMismatch);
         }
 
-        ports.push(it.port());
+        if !req
+            .service
+            .observer
+            .is_peer_allowed(req.address, &username, &it)
+            .await
+        {
+            return reject(req, ErrorKind::Forbidden);
+        }
+
+        if req.service.observer.is_external(&it) {
+            externals.push(it);
+        } else {
+            ports.push(PeerPort::from(it.port()));
+        }
     }
 
     if !req
         .service
         .sessions
-        .create_permission(&req.address, &req.service.endpoint, &ports)
+        .check_permission_capacity(req.address, &ports)
+    {
+        return reject(req, ErrorKind::InsufficientCapacity);
+    }
+
+    // The external peers are not relay ports of this server, a request that only
+    // permits external peers does not touch the other permissions.
+    let is_internal = externals.is_empty() || !ports.is_empty();
+    if is_internal
+        && !req
+            .service
+            .sessions
+            .create_permission(req.address, &req.service.endpoint, &ports)
     {
         return reject(req, ErrorKind::Forbidden);
     }
 
-    req.service
-        .observer
-        .create_permission(&req.address, username, &ports);
+    if !externals.is_empty() {
+        if !req
+            .service
+            .sessions
+            .create_external_permission(req.address, &externals)
+        {
+            return reject(req, ErrorKind::Forbidden);
+        }
+
+        req.service
+            .observer
+            .external_permission(req.address, &username, &externals);
+    }
+
+    if is_internal {
+        req.service
+            .observer
+            .create_permission(req.address, &username, &ports);
+    }
+
     resolve(req, &digest)
 }
+
+/// The handler of the CreatePermission requests, see [`process`].
+pub struct CreatePermission;
+
+impl<T: Observer + 'static> Handler<T> for CreatePermission {
+    fn process<'a, 'b: 'a>(
+        &'a self,
+        req: Requet<'a, 'b, T, MessageReader<'a>>,
+    ) -> HandlerFuture<'a, 'b> {
+        Box::pin(process(req))
+    }
+}
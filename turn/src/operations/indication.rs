@@ -1,13 +1,41 @@
 use std::net::SocketAddr;
 
-use super::{Requet, Response, ResponseMethod};
+use super::{binding, Handler, HandlerFuture, Requet, Response, ResponseMethod};
 use crate::Observer;
 
+use bytes::BytesMut;
 use stun::{
-    attribute::{Data, XorPeerAddress},
+    attribute::{Data, Icmp, IcmpError, XorPeerAddress},
     MessageReader, MessageWriter, Method,
 };
 
+/// Tell the client that the peer can no longer be reached.
+///
+/// The relays in this server are the ports of other sessions, when the
+/// session of a peer has closed, the server answers data sent to it like a
+/// relay socket receiving an ICMP port unreachable error: with a Data
+/// indication that carries the ICMP attribute and the XOR-PEER-ADDRESS of
+/// the peer, so that the client can fail over away from the peer quickly.
+pub(crate) fn unreachable<'a, T: Observer>(
+    req: Requet<'_, 'a, T, impl Sized>,
+    token: &[u8; 12],
+    peer: SocketAddr,
+) -> Option<Response<'a>> {
+    {
+        let mut message = MessageWriter::new(Method::DataIndication, token, req.bytes);
+        message.append::<XorPeerAddress>(peer);
+        message.append::<Icmp>(IcmpError::port_unreachable(peer.is_ipv6()));
+        message.flush(None).ok()?;
+    }
+
+    Some(Response {
+        method: ResponseMethod::Stun(Method::DataIndication),
+        bytes: req.bytes,
+        endpoint: None,
+        relay: None,
+    })
+}
+
 /// process send indication request
 ///
 /// When the server receives a Send indication, it processes as per
@@ -52,26 +80,85 @@ use stun::{
 /// and [15](https://tools.ietf.org/html/rfc8656#section-15).
 ///
 /// The resulting UDP datagram is then sent to the peer.
+///
+/// With [`SessionsOptions::relay_binding`], a Binding request without
+/// credentials in the DATA attribute is answered by the server in a Data
+/// indication from the peer instead of being relayed, see
+/// [`binding::relayed`].
+///
+/// [`SessionsOptions::relay_binding`]: crate::SessionsOptions::relay_binding
 pub fn process<'a, T: Observer>(req: Requet<'_, 'a, T, MessageReader<'_>>) -> Option<Response<'a>> {
     let peer = req.message.get::<XorPeerAddress>()?;
     let data = req.message.get::<Data>()?;
 
+    if req.service.observer.is_external(&peer) {
+        if req
+            .service
+            .sessions
+            .is_external_permitted(req.address, &peer.ip())
+        {
+            req.service.sessions.data_relayed(req.address);
+            req.service
+                .observer
+                .relay_external(req.address, &peer, data);
+        }
+
+        return None;
+    }
+
     let relay = req
         .service
         .sessions
-        .get_relay_address(&req.address, peer.port())?;
+        .get_relay_address(req.address, peer.port().into())?;
 
-    let local_port = req
+    if !req.service.sessions.is_relay_alive(&relay) {
+        let token: [u8; 12] = req.message.token.try_into().ok()?;
+        return unreachable(req, &token, peer);
+    }
+
+    if !req
+        .service
+        .sessions
+        .verify_reverse_path(req.address, &relay)
+    {
+        return None;
+    }
+
+    // The peer sees the data coming from the relayed transport address of the
+    // current session, of its address family for a dual allocation.
+    let local = req
         .service
         .sessions
-        .get_session(&req.address)
+        .get_session(req.address)
         .get_ref()?
         .allocate
-        .port?;
+        .relayed_address(&peer, req.service.interface.ip())?;
+
+    if req.service.sessions.get_options().relay_binding {
+        let mut response = BytesMut::new();
+        if binding::relayed(data, local, peer, &mut response).is_some() {
+            {
+                let mut message =
+                    MessageWriter::extend(Method::DataIndication, req.message, req.bytes);
+                message.append::<XorPeerAddress>(peer);
+                message.append::<Data>(&response);
+                message.flush(None).ok()?;
+            }
+
+            return Some(Response {
+                method: ResponseMethod::Stun(Method::DataIndication),
+                bytes: req.bytes,
+                endpoint: None,
+                relay: None,
+            });
+        }
+    }
+
+    req.service.sessions.data_relayed(req.address);
 
     {
-        let mut message = MessageWriter::extend(Method::DataIndication, &req.message, req.bytes);
-        message.append::<XorPeerAddress>(SocketAddr::new(req.service.interface.ip(), local_port));
+        let mut message = MessageWriter::extend(Method::DataIndication, req.message, req.bytes);
+        message.append::<XorPeerAddress>(local);
         message.append::<Data>(data);
         message.flush(None).ok()?;
     }
@@ -87,3 +174,15 @@ pub fn process<'a, T: Observer>(req: Requet<'_, 'a, T, MessageReader<'_>>) -> Op
         bytes: req.bytes,
     })
 }
+
+/// The handler of the Send indications, see [`process`].
+pub struct SendIndication;
+
+impl<T: Observer + 'static> Handler<T> for SendIndication {
+    fn process<'a, 'b: 'a>(
+        &'a self,
+        req: Requet<'a, 'b, T, MessageReader<'a>>,
+    ) -> HandlerFuture<'a, 'b> {
+        Box::pin(std::future::ready(process(req)))
+    }
+}
@@ -2,27 +2,50 @@ pub mod allocate;
 pub mod binding;
 pub mod channel_bind;
 pub mod channel_data;
+pub mod connect;
+pub mod connection_bind;
 pub mod create_permission;
 pub mod indication;
 pub mod refresh;
 
 use crate::{
+    lockout::AuthFailure,
     sessions::{SessionAddr, Sessions},
     Observer,
 };
 
-use std::{net::SocketAddr, sync::Arc};
+use std::{
+    borrow::Cow,
+    future::Future,
+    net::{IpAddr, SocketAddr},
+    num::NonZeroUsize,
+    pin::Pin,
+    sync::Arc,
+};
 
+use ahash::AHashMap;
 use bytes::BytesMut;
+use lru::LruCache;
 use stun::{
-    attribute::{Nonce, UserName},
-    Decoder, Kind, MessageReader, Method, Payload, StunError,
+    attribute::{
+        Algorithm, ErrorKind, MessageIntegritySha256, Nonce, Origin, PasswordAlgorithm,
+        PasswordAlgorithms, TransactionTransmitCounter, Transport, UserName,
+    },
+    auth::{opaque_string, Key},
+    Decoder, Integrity, Kind, MessageReader, Method, Payload, StunError,
 };
 
+/// The password algorithms that the server offers in PASSWORD-ALGORITHMS, in
+/// the order of preference.
+pub const PASSWORD_ALGORITHMS: [Algorithm; 2] = [Algorithm::Sha256, Algorithm::Md5];
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ResponseMethod {
     Stun(Method),
     ChannelData,
+    /// The data of a client data connection of a TCP allocation, which is
+    /// relayed as is to the data connection of the peer.
+    ConnectionData,
 }
 
 /// The context of the service.
@@ -31,11 +54,29 @@ pub enum ResponseMethod {
 /// addresses and so on, but other things are basically the same.
 pub struct ServiceContext<T: Observer> {
     pub realm: Arc<String>,
+    /// The SOFTWARE attribute of the responses, see
+    /// [`Service::set_software`].
+    ///
+    /// [`Service::set_software`]: crate::Service::set_software
+    pub software: Arc<String>,
     pub sessions: Arc<Sessions<T>>,
     pub endpoint: SocketAddr,
     pub interface: SocketAddr,
     pub interfaces: Arc<Vec<SocketAddr>>,
+    /// The ip addresses that allocations are advertised with in addition to
+    /// the ones of the interfaces, see [`Service::set_relay_ips`].
+    ///
+    /// [`Service::set_relay_ips`]: crate::Service::set_relay_ips
+    pub relay_ips: Arc<Vec<IpAddr>>,
+    /// The transport protocol of the socket that the service is running on.
+    pub transport: Transport,
     pub observer: T,
+    pub handlers: Arc<Handlers<T>>,
+    /// Whether the requests have to carry FINGERPRINT and the responses
+    /// always carry it, see [`SessionsOptions::fingerprint`].
+    ///
+    /// [`SessionsOptions::fingerprint`]: crate::SessionsOptions::fingerprint
+    pub fingerprint: bool,
 }
 
 /// The request of the service.
@@ -53,13 +94,37 @@ impl<'a, 'b, T> Requet<'a, 'b, T, MessageReader<'a>>
 where
     T: Observer + 'static,
 {
-    /// Check if the ip address belongs to the current turn server.
+    /// Check if the ip address belongs to the current turn server, which
+    /// includes the relay ip addresses of the allocations, see
+    /// [`Observer::get_relay_ip`].
     #[inline(always)]
     pub(crate) fn verify_ip(&self, address: &SocketAddr) -> bool {
         self.service
             .interfaces
             .iter()
             .any(|item| item.ip() == address.ip())
+            || self.service.relay_ips.contains(&address.ip())
+            || self.service.sessions.is_relayed_address(address)
+    }
+
+    /// Check if the address is of the address family of the relayed transport
+    /// address of the allocation, or of one of them for a dual allocation.
+    /// Requests of sessions without an allocation are rejected later.
+    #[inline(always)]
+    pub(crate) fn verify_family(&self, address: &SocketAddr) -> bool {
+        let relay = self
+            .service
+            .sessions
+            .get_session(self.address)
+            .get_ref()
+            .and_then(|it| {
+                it.allocate
+                    .relayed_address(address, self.service.interface.ip())
+            })
+            .map(|it| it.ip())
+            .unwrap_or_else(|| self.service.interface.ip());
+
+        relay.is_ipv4() == address.is_ipv4()
     }
 
     /// The key for the HMAC depends on whether long-term or short-term
@@ -107,32 +172,115 @@ where
     /// the end of the MESSAGE-INTEGRITY attribute prior to calculating the
     /// HMAC.  Such adjustment is necessary when attributes, such as
     /// FINGERPRINT, appear after MESSAGE-INTEGRITY.
+    ///
+    /// [RFC8489](https://tools.ietf.org/html/rfc8489#section-9.2.4) adds the
+    /// password algorithms, the key can also be the SHA-256 hash of the same
+    /// string, and the HMAC-SHA256 of MESSAGE-INTEGRITY-SHA256 can replace
+    /// MESSAGE-INTEGRITY. A request without PASSWORD-ALGORITHMS and
+    /// PASSWORD-ALGORITHM uses MD5, otherwise PASSWORD-ALGORITHMS has to be
+    /// the list that the server sent, and PASSWORD-ALGORITHM one of the list,
+    /// or the request is a bid down attack and rejected with a 400 (Bad
+    /// Request) error.
+    ///
+    /// The error is the error code that the request is rejected with, the
+    /// integrity is the one that the response is signed with.
     #[inline(always)]
-    pub(crate) async fn auth(&self) -> Option<(&'a str, [u8; 16])> {
-        let username = self.message.get::<UserName>()?;
-        let digest = self
+    pub(crate) async fn auth(&self) -> Result<(Cow<'a, str>, Integrity), ErrorKind> {
+        // The username is prepared with OpaqueString, so that the lockout, the
+        // password lookup and the observers see one form of the same username
+        // whichever Unicode form the client sent.
+        let username = self
+            .message
+            .get::<UserName>()
+            .and_then(|it| opaque_string(it).ok())
+            .ok_or(ErrorKind::Unauthorized)?;
+
+        // Locked out credentials are rejected without spending any time on the
+        // password lookup and the integrity check.
+        if self.service.sessions.is_locked(self.address, &username) {
+            return Err(ErrorKind::Unauthorized);
+        }
+
+        let algorithm = match (
+            self.message.get::<PasswordAlgorithms>(),
+            self.message.get::<PasswordAlgorithm>(),
+        ) {
+            (None, None) => Algorithm::Md5,
+            (Some(algorithms), Some(algorithm))
+                if algorithms == PASSWORD_ALGORITHMS && algorithms.contains(&algorithm) =>
+            {
+                algorithm
+            }
+            _ => return Err(ErrorKind::BadRequest),
+        };
+
+        let digest = match self
             .service
             .sessions
-            .get_digest(&self.address, username, self.service.realm.as_str())
-            .await?;
+            .try_get_digest(
+                self.address,
+                self.service.transport,
+                &username,
+                self.service.realm.as_str(),
+                self.message.get::<Origin>(),
+            )
+            .await
+        {
+            Ok(Some(it)) => it,
+            Ok(None) => {
+                self.service.sessions.auth_failed(
+                    self.address,
+                    &username,
+                    AuthFailure::UnknownUsername,
+                );
+                return Err(ErrorKind::Unauthorized);
+            }
+            Err(e) => return Err(e.into()),
+        };
 
         // if nonce is not empty, check nonce
         if let Some(nonce) = self.message.get::<Nonce>() {
             if self
                 .service
                 .sessions
-                .get_nonce(&self.address)
-                .get_ref()?
+                .get_realm_nonce(self.address, &self.service.realm)
+                .get_ref()
+                .ok_or(ErrorKind::Unauthorized)?
                 .0
                 .as_str()
                 != nonce
             {
-                return None;
+                return Err(ErrorKind::Unauthorized);
             }
         }
 
-        self.message.integrity(&digest).ok()?;
-        Some((username, digest))
+        let key = match algorithm {
+            Algorithm::Md5 => Key::Md5(digest),
+            Algorithm::Sha256 => self
+                .service
+                .sessions
+                .get_key(self.address, algorithm)
+                .ok_or(ErrorKind::Unauthorized)?,
+        };
+
+        // MESSAGE-INTEGRITY-SHA256 is checked instead of MESSAGE-INTEGRITY if
+        // the request has both.
+        let integrity = if self.message.get::<MessageIntegritySha256>().is_some() {
+            Integrity::Sha256(key)
+        } else {
+            Integrity::Sha1(key)
+        };
+
+        if self.message.check_integrity(&integrity).is_err() {
+            self.service
+                .sessions
+                .auth_failed(self.address, &username, AuthFailure::Integrity);
+            return Err(ErrorKind::Unauthorized);
+        }
+
+        self.service.sessions.auth_succeeded(&username);
+        self.service.sessions.request_received(self.address);
+        Ok((username, integrity))
     }
 }
 
@@ -144,6 +292,155 @@ pub struct Response<'a> {
     pub endpoint: Option<SocketAddr>,
 }
 
+/// The future of a method handler, which resolves to the response to the
+/// request, if the request is answered.
+pub type HandlerFuture<'a, 'b> = Pin<Box<dyn Future<Output = Option<Response<'b>>> + Send + 'a>>;
+
+/// The handler of the requests of a STUN method.
+///
+/// The handler gets the context of the service with every request, it keeps
+/// no state of its own between requests.
+///
+/// # Test
+///
+/// ```
+/// use std::net::SocketAddr;
+///
+/// use bytes::BytesMut;
+/// use mycrl_turn::{operations::*, *};
+/// use stun::{attribute::Transport, Kind, MessageReader, MessageWriter, Method};
+///
+/// #[derive(Clone)]
+/// struct ObserverTest;
+///
+/// impl Observer for ObserverTest {}
+///
+/// // Drops the Binding requests.
+/// struct Silent;
+///
+/// impl Handler<ObserverTest> for Silent {
+///     fn process<'a, 'b: 'a>(
+///         &'a self,
+///         _: Requet<'a, 'b, ObserverTest, MessageReader<'a>>,
+///     ) -> HandlerFuture<'a, 'b> {
+///         Box::pin(async { None })
+///     }
+/// }
+///
+/// let addr = "127.0.0.1:8080".parse::<SocketAddr>().unwrap();
+/// let mut service = Service::new("test".to_string(), vec![addr], ObserverTest);
+/// service.register(Method::Binding(Kind::Request), Silent);
+///
+/// let mut operationer = service.get_operationer(addr, addr, Transport::UDP);
+///
+/// let mut bytes = BytesMut::new();
+/// MessageWriter::new(Method::Binding(Kind::Request), &[0u8; 12], &mut bytes)
+///     .flush(None)
+///     .unwrap();
+///
+/// let res = operationer
+///     .process_blocking(&bytes, "127.0.0.1:1000".parse().unwrap())
+///     .unwrap();
+///
+/// assert!(res.is_none());
+/// ```
+pub trait Handler<T>: Send + Sync
+where
+    T: Observer + 'static,
+{
+    fn process<'a, 'b: 'a>(
+        &'a self,
+        req: Requet<'a, 'b, T, MessageReader<'a>>,
+    ) -> HandlerFuture<'a, 'b>;
+}
+
+/// The dispatch table of the method handlers.
+///
+/// The default table handles the requests and indications of RFC 8489,
+/// RFC 8656 and RFC 6062, the messages of methods that have no handler are not
+/// answered.
+pub struct Handlers<T>(AHashMap<Method, Arc<dyn Handler<T>>>);
+
+impl<T> Clone for Handlers<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T> Default for Handlers<T>
+where
+    T: Observer + 'static,
+{
+    fn default() -> Self {
+        let mut handlers = Self(AHashMap::with_capacity(10));
+        handlers.insert(Method::Binding(Kind::Request), binding::Binding);
+        handlers.insert(Method::Allocate(Kind::Request), allocate::Allocate);
+        handlers.insert(
+            Method::CreatePermission(Kind::Request),
+            create_permission::CreatePermission,
+        );
+        handlers.insert(
+            Method::ChannelBind(Kind::Request),
+            channel_bind::ChannelBind,
+        );
+        handlers.insert(Method::Refresh(Kind::Request), refresh::Refresh);
+        handlers.insert(Method::Connect(Kind::Request), connect::Connect);
+        handlers.insert(
+            Method::ConnectionBind(Kind::Request),
+            connection_bind::ConnectionBind,
+        );
+        handlers.insert(Method::SendIndication, indication::SendIndication);
+        handlers
+    }
+}
+
+impl<T> Handlers<T>
+where
+    T: Observer + 'static,
+{
+    /// Set the handler of the method, the handler that it replaces is
+    /// returned.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use mycrl_turn::{operations::*, *};
+    /// use stun::{Kind, Method};
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {}
+    ///
+    /// let mut handlers = Handlers::<ObserverTest>::default();
+    /// assert!(handlers.get(&Method::Binding(Kind::Request)).is_some());
+    /// assert!(handlers.get(&Method::Binding(Kind::Response)).is_none());
+    ///
+    /// let replaced = handlers.insert(Method::Binding(Kind::Request), binding::Binding);
+    /// assert!(replaced.is_some());
+    ///
+    /// assert!(handlers.remove(&Method::Binding(Kind::Request)).is_some());
+    /// assert!(handlers.get(&Method::Binding(Kind::Request)).is_none());
+    /// ```
+    pub fn insert<H>(&mut self, method: Method, handler: H) -> Option<Arc<dyn Handler<T>>>
+    where
+        H: Handler<T> + 'static,
+    {
+        self.0.insert(method, Arc::new(handler))
+    }
+
+    pub fn remove(&mut self, method: &Method) -> Option<Arc<dyn Handler<T>>> {
+        self.0.remove(method)
+    }
+
+    pub fn get(&self, method: &Method) -> Option<&dyn Handler<T>> {
+        self.0.get(method).map(|it| it.as_ref())
+    }
+}
+
+// The last Binding request and response of each source address.
+type BindingCache = LruCache<SocketAddr, (Vec<u8>, Vec<u8>)>;
+
 /// process udp message and return message + address
 pub struct Operationer<T>
 where
@@ -153,6 +450,7 @@ where
     address: SessionAddr,
     decoder: Decoder,
     bytes: BytesMut,
+    binding_cache: Option<BindingCache>,
 }
 
 impl<T> Operationer<T>
@@ -167,6 +465,12 @@ where
             },
             bytes: BytesMut::with_capacity(4096),
             decoder: Decoder::default(),
+            binding_cache: service
+                .sessions
+                .get_options()
+                .binding_cache
+                .and_then(NonZeroUsize::new)
+                .map(LruCache::new),
             service,
         }
     }
@@ -289,7 +593,25 @@ where
     ) -> Result<Option<Response<'a>>, StunError> {
         self.address.address = address;
 
-        Ok(match self.decoder.decode(bytes)? {
+        // A retransmitted Binding request is answered with the response to the
+        // original request, which is copied instead of encoded again.
+        if let Some(cache) = &mut self.binding_cache {
+            if let Some((request, response)) = cache.get(&address) {
+                if request.as_slice() == bytes {
+                    self.bytes.clear();
+                    self.bytes.extend_from_slice(response);
+
+                    return Ok(Some(Response {
+                        method: ResponseMethod::Stun(Method::Binding(Kind::Response)),
+                        bytes: &self.bytes,
+                        endpoint: None,
+                        relay: None,
+                    }));
+                }
+            }
+        }
+
+        let res = match self.decoder.decode(bytes)? {
             Payload::ChannelData(channel) => channel_data::process(bytes, Requet {
                 bytes: &mut self.bytes,
                 service: &self.service,
@@ -297,23 +619,93 @@ where
                 message: &channel,
             }),
             Payload::Message(message) => {
-                let req = Requet {
-                    bytes: &mut self.bytes,
-                    service: &self.service,
-                    address: &self.address,
-                    message: &message,
-                };
-
-                match req.message.method {
-                    Method::Binding(Kind::Request) => binding::process(req),
-                    Method::Allocate(Kind::Request) => allocate::process(req).await,
-                    Method::CreatePermission(Kind::Request) => create_permission::process(req).await,
-                    Method::ChannelBind(Kind::Request) => channel_bind::process(req).await,
-                    Method::Refresh(Kind::Request) => refresh::process(req).await,
-                    Method::SendIndication => indication::process(req),
-                    _ => None,
+                // When FINGERPRINT is required, a request without a valid one is discarded
+                // without a response, as it may not be a STUN message at all.
+                if self.service.fingerprint
+                    && message.method.is_request()
+                    && message.fingerprint().is_err()
+                {
+                    return Ok(None);
+                }
+
+                if let Some(counter) = message.get::<TransactionTransmitCounter>() {
+                    if counter.req > 1 {
+                        self.service.observer.retransmitted(
+                            &self.address,
+                            message.method,
+                            counter.req - 1,
+                            self.service.transport,
+                        );
+                    }
                 }
+
+                match self.service.handlers.get(&message.method) {
+                    Some(handler) => handler.process(Requet {
+                        bytes: &mut self.bytes,
+                        service: &self.service,
+                        address: &self.address,
+                        message: &message,
+                    }).await,
+                    None => None,
+                }
+            }
+        };
+
+        if let (Some(cache), Some(res)) = (&mut self.binding_cache, &res) {
+            if res.method == ResponseMethod::Stun(Method::Binding(Kind::Response)) {
+                cache.put(address, (bytes.to_vec(), res.bytes.to_vec()));
             }
-        })
+        }
+
+        Ok(res)
+    }
+
+    /// process udp data without an async runtime
+    ///
+    /// The blocking variant of `route`, the request is processed to the end
+    /// on the current thread. The password is looked up with
+    /// `Observer::get_password_blocking`, unless the observer overrides
+    /// `get_password`, which then must not depend on an async runtime either.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use std::net::SocketAddr;
+    ///
+    /// use bytes::BytesMut;
+    /// use mycrl_turn::*;
+    /// use stun::{attribute::Transport, Kind, MessageWriter, Method};
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {
+    ///     fn get_password_blocking(&self, _: &SessionAddr, _: &str) -> Option<String> {
+    ///         Some("test".to_string())
+    ///     }
+    /// }
+    ///
+    /// let addr = "127.0.0.1:8080".parse::<SocketAddr>().unwrap();
+    /// let service = Service::new("test".to_string(), vec![addr], ObserverTest);
+    /// let mut operationer = service.get_operationer(addr, addr, Transport::UDP);
+    ///
+    /// let mut bytes = BytesMut::new();
+    /// MessageWriter::new(Method::Binding(Kind::Request), &[0u8; 12], &mut bytes)
+    ///     .flush(None)
+    ///     .unwrap();
+    ///
+    /// let res = operationer
+    ///     .process_blocking(&bytes, "127.0.0.1:1000".parse().unwrap())
+    ///     .unwrap()
+    ///     .unwrap();
+    ///
+    /// assert_eq!(res.method, ResponseMethod::Stun(Method::Binding(Kind::Response)));
+    /// ```
+    pub fn process_blocking<'a, 'b: 'a>(
+        &'b mut self,
+        bytes: &'b [u8],
+        address: SocketAddr,
+    ) -> Result<Option<Response<'a>>, StunError> {
+        crate::blocking::block_on(self.route(bytes, address))
     }
 }
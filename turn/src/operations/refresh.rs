@@ -1,9 +1,9 @@
 use stun::{
-    attribute::{Error, ErrorCode, ErrorKind, Lifetime},
-    Kind, MessageReader, MessageWriter, Method,
+    attribute::{Error, ErrorCode, ErrorKind, Lifetime, MobilityTicket},
+    Integrity, Kind, MessageReader, MessageWriter, Method,
 };
 
-use super::{Requet, Response, ResponseMethod};
+use super::{Handler, HandlerFuture, Requet, Response, ResponseMethod};
 use crate::Observer;
 
 /// return refresh error response
@@ -14,7 +14,8 @@ fn reject<'a, T: Observer>(
 ) -> Option<Response<'a>> {
     {
         let mut message =
-            MessageWriter::extend(Method::Refresh(Kind::Error), &req.message, req.bytes);
+            MessageWriter::extend(Method::Refresh(Kind::Error), req.message, req.bytes);
+        message.fingerprint(req.service.fingerprint);
 
         message.append::<ErrorCode>(Error::from(err));
         message.flush(None).ok()?;
@@ -29,18 +30,26 @@ fn reject<'a, T: Observer>(
 }
 
 /// return refresh ok response
+///
+/// The new mobility ticket of the allocation is returned in the
+/// MOBILITY-TICKET attribute.
 #[inline(always)]
 pub fn resolve<'a, T: Observer>(
     req: Requet<'_, 'a, T, MessageReader<'_>>,
     lifetime: u32,
-    digest: &[u8; 16],
+    digest: &Integrity,
+    ticket: Option<[u8; 16]>,
 ) -> Option<Response<'a>> {
     {
         let mut message =
-            MessageWriter::extend(Method::Refresh(Kind::Response), &req.message, req.bytes);
+            MessageWriter::extend(Method::Refresh(Kind::Response), req.message, req.bytes);
 
         message.append::<Lifetime>(lifetime);
-        message.flush(Some(digest)).ok()?;
+        if let Some(ticket) = &ticket {
+            message.append::<MobilityTicket>(ticket);
+        }
+
+        message.flush_integrity(Some(digest)).ok()?;
     }
 
     Some(Response {
@@ -89,21 +98,102 @@ pub fn resolve<'a, T: Observer>(
 /// will cause a 437 (Allocation Mismatch) response if the
 /// allocation has already been deleted, but the client will treat
 /// this as equivalent to a success response (see below).
+///
+/// [rfc8016](https://tools.ietf.org/html/rfc8016)
+///
+/// A client whose address changed presents the MOBILITY-TICKET of its
+/// allocation in a Refresh request from its new address. If the server does
+/// not allow mobility, it rejects the request with a 405 (Mobility
+/// Forbidden) error. If the ticket is not valid, it rejects the request
+/// with a 400 (Bad Request) error, and if the ticket belongs to the
+/// allocation of another username with a 441 (Wrong Credentials) error.
+/// Otherwise the allocation moves to the new address, see
+/// [`Sessions::transfer`], and is refreshed as usual. The success response
+/// contains a new MOBILITY-TICKET attribute, the previous ticket is no
+/// longer valid.
+///
+/// [`Sessions::transfer`]: crate::Sessions::transfer
 pub async fn process<'a, T: Observer>(
     req: Requet<'_, 'a, T, MessageReader<'_>>,
 ) -> Option<Response<'a>> {
+    // The allocation was deleted and only lingers to deliver the data of its peers.
+    if req.service.sessions.is_lingering(req.address) {
+        return reject(req, ErrorKind::AllocationMismatch);
+    }
+
     let (username, digest) = match req.auth().await {
-        None => return reject(req, ErrorKind::Unauthorized),
-        Some(it) => it,
+        Err(e) => return reject(req, e),
+        Ok(it) => it,
+    };
+
+    let sessions = &req.service.sessions;
+    let ticket = req.message.get::<MobilityTicket>();
+    if let Some(ticket) = ticket {
+        if !sessions.get_options().mobility {
+            return reject(req, ErrorKind::MobilityForbidden);
+        }
+
+        let from = match sessions.find_ticket(ticket) {
+            Some(it) => it,
+            None => return reject(req, ErrorKind::BadRequest),
+        };
+
+        // The client presents the ticket from the address of the allocation when its
+        // address has not changed.
+        if from != *req.address {
+            let is_owner = sessions
+                .get_session(&from)
+                .get_ref()
+                .map(|it| it.auth.username == username)
+                .unwrap_or(false);
+
+            if !is_owner {
+                return reject(req, ErrorKind::WrongCredentials);
+            }
+
+            if !sessions.transfer(
+                &from,
+                req.address,
+                &req.service.endpoint,
+                req.service.transport,
+            ) {
+                return reject(req, ErrorKind::AllocationMismatch);
+            }
+
+            req.service
+                .observer
+                .transferred(req.address, &username, &from);
+        }
+    }
+
+    let lifetime = match req.message.get::<Lifetime>() {
+        Some(0) => 0,
+        it => req.service.sessions.granted_lifetime(req.address, it),
     };
 
-    let lifetime = req.message.get::<Lifetime>().unwrap_or(600);
-    if !req.service.sessions.refresh(&req.address, lifetime) {
+    if !req.service.sessions.refresh(req.address, lifetime) {
         return reject(req, ErrorKind::AllocationMismatch);
     }
 
+    // A deleted allocation gets no new ticket.
+    let ticket = ticket
+        .filter(|_| lifetime > 0)
+        .and_then(|_| req.service.sessions.issue_ticket(req.address));
+
     req.service
         .observer
-        .refresh(&req.address, username, lifetime);
-    resolve(req, lifetime, &digest)
+        .refresh(req.address, &username, lifetime);
+    resolve(req, lifetime, &digest, ticket)
+}
+
+/// The handler of the Refresh requests, see [`process`].
+pub struct Refresh;
+
+impl<T: Observer + 'static> Handler<T> for Refresh {
+    fn process<'a, 'b: 'a>(
+        &'a self,
+        req: Requet<'a, 'b, T, MessageReader<'a>>,
+    ) -> HandlerFuture<'a, 'b> {
+        Box::pin(process(req))
+    }
 }
@@ -0,0 +1,88 @@
+use std::{future::Future, pin::Pin, thread, time::Duration};
+
+use crate::blocking::block_on;
+
+/// A background task, or a sleep of one.
+pub type Task = Pin<Box<dyn Future<Output = ()> + Send + 'static>>;
+
+/// The async runtime that the session manager runs its background tasks on.
+///
+/// The session manager expires the sessions and deletes the lingering
+/// allocations in background tasks, which only need to be spawned and to
+/// sleep. By default they run on threads of their own, see
+/// [`ThreadRuntime`], an embedder that already runs an executor, such as
+/// async-std or smol, can run them on it instead and does not need a
+/// second runtime. The sockets are never touched by the session manager,
+/// they stay with the embedder.
+///
+/// # Test
+///
+/// ```
+/// use std::{
+///     sync::{
+///         atomic::{AtomicUsize, Ordering},
+///         Arc,
+///     },
+///     time::Duration,
+/// };
+///
+/// use mycrl_turn::*;
+///
+/// #[derive(Clone)]
+/// struct ObserverTest;
+///
+/// impl Observer for ObserverTest {}
+///
+/// // With async-std this would be `async_std::task::spawn(task)` and
+/// // `Box::pin(async_std::task::sleep(duration))`.
+/// #[derive(Clone, Default)]
+/// struct Counting(Arc<AtomicUsize>);
+///
+/// impl Runtime for Counting {
+///     fn spawn(&self, task: Task) {
+///         self.0.fetch_add(1, Ordering::Relaxed);
+///         ThreadRuntime.spawn(task);
+///     }
+///
+///     fn sleep(&self, duration: Duration) -> Task {
+///         ThreadRuntime.sleep(duration)
+///     }
+/// }
+///
+/// let runtime = Counting::default();
+/// let _sessions = Sessions::with_runtime(
+///     ObserverTest,
+///     SessionsOptions {
+///         linger: Some(Duration::from_millis(200)),
+///         ..Default::default()
+///     },
+///     runtime.clone(),
+/// );
+///
+/// assert_eq!(runtime.0.load(Ordering::Relaxed), 2);
+/// ```
+pub trait Runtime: Clone + Send + Sync + 'static {
+    /// Run the task in the background until it completes.
+    fn spawn(&self, task: Task);
+
+    /// A future that completes after `duration`.
+    fn sleep(&self, duration: Duration) -> Task;
+}
+
+/// Runs every task on a thread of its own.
+///
+/// This is the runtime of the session manager unless another one is given,
+/// it does not depend on any executor.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ThreadRuntime;
+
+impl Runtime for ThreadRuntime {
+    fn spawn(&self, task: Task) {
+        thread::spawn(move || block_on(task));
+    }
+
+    fn sleep(&self, duration: Duration) -> Task {
+        // Nothing else runs on the thread of the task, so the sleep can block it.
+        Box::pin(async move { thread::sleep(duration) })
+    }
+}
@@ -1,21 +1,60 @@
-use crate::Observer;
+use crate::{
+    dump::{ChannelDump, LoadError, NonceDump, OutboundDump, RelayDump, SessionDump, StateDump},
+    lockout::{AuthFailure, LockoutKey, LockoutOptions, Lockouts},
+    runtime::{Runtime, ThreadRuntime},
+    types::{ChannelNumber, PeerPort, RelayPort},
+    Credential, Observer, PasswordDigest, PasswordError,
+};
 
 use std::{
+    collections::VecDeque,
     hash::Hash,
-    net::SocketAddr,
+    mem::size_of,
+    net::{IpAddr, SocketAddr},
     ops::{Deref, DerefMut, Range},
     sync::{
         atomic::{AtomicU64, Ordering},
+        mpsc::{channel, Sender},
         Arc,
     },
-    thread::{self, sleep},
-    time::Duration,
+    time::{Duration, Instant},
 };
 
-use ahash::{HashMap, HashMapExt};
+use ahash::{HashMap, HashMapExt, HashSet, HashSetExt};
 use parking_lot::{Mutex, RwLock, RwLockReadGuard};
-use rand::{distributions::Alphanumeric, thread_rng, Rng};
-use stun::util::long_term_credential_digest;
+use rand::{distributions::Alphanumeric, rngs::StdRng, thread_rng, Rng, SeedableRng};
+use stun::{
+    attribute::{Algorithm, ErrorKind, Transport},
+    auth::Key,
+};
+
+/// The lifetime of an allocation in seconds if the client does not request
+/// one, shorter requested lifetimes are raised to it. Used if
+/// [`SessionsOptions::default_lifetime`] is not set.
+pub const DEFAULT_LIFETIME: u32 = 600;
+
+/// The longest lifetime of an allocation in seconds if
+/// [`SessionsOptions::max_lifetime`] is not set.
+pub const MAX_LIFETIME: u32 = 3600;
+
+/// The lifetime of a nonce in seconds if [`SessionsOptions::nonce_lifetime`]
+/// is not set.
+pub const NONCE_LIFETIME: u32 = 600;
+
+/// The nonce cookie of [RFC8489](https://tools.ietf.org/html/rfc8489#section-9.2)
+/// that starts every nonce, with the security feature bit of the password
+/// algorithms set, which tells the clients that the server supports
+/// PASSWORD-ALGORITHMS and MESSAGE-INTEGRITY-SHA256.
+pub const NONCE_COOKIE: &str = "obMatJos2gAAA";
+
+/// The seconds that a client has to bind a data connection to a connection of
+/// its TCP allocation, the connection is closed if it does not.
+pub const CONNECTION_TIMEOUT: u64 = 30;
+
+/// The seconds that the next-higher port of an allocation with an even port
+/// is held in reserve, the port goes back to the pool if no allocation claims
+/// it in time, see [`Sessions::allocate_even`].
+pub const RESERVATION_TIMEOUT: u64 = 30;
 
 /// Authentication information for the session.
 ///
@@ -24,8 +63,19 @@ use stun::util::long_term_credential_digest;
 #[derive(Debug, Clone)]
 pub struct Auth {
     pub username: String,
+    /// The realm that the session was authenticated in.
+    pub realm: String,
+    /// Empty if the password is not known, see [`Observer::try_get_credential`].
     pub password: String,
     pub digest: [u8; 16],
+    /// The key of the SHA-256 password algorithm.
+    pub digest_sha256: [u8; 32],
+    /// The ORIGIN attribute of the request that authenticated the session.
+    pub origin: Option<String>,
+    /// When the password that the session was authenticated with runs out, in
+    /// seconds of the timer of the session manager, see
+    /// [`Observer::get_password_lifetime`].
+    pub expires: Option<u64>,
 }
 
 /// Assignment information for the session.
@@ -33,21 +83,110 @@ pub struct Auth {
 /// Sessions are all bound to only one port and one channel.
 #[derive(Debug, Clone)]
 pub struct Allocate {
-    pub port: Option<u16>,
-    pub channels: Vec<u16>,
+    pub port: Option<RelayPort>,
+    /// The ip address advertised as the relayed transport address.
+    pub ip: Option<IpAddr>,
+    /// The transport protocol of the relayed transport address, TCP for the
+    /// allocations of [RFC6062](https://tools.ietf.org/html/rfc6062).
+    pub transport: Transport,
+    /// The relay port of the other address family of a dual allocation,
+    /// requested with the ADDITIONAL-ADDRESS-FAMILY attribute of
+    /// [RFC8656](https://tools.ietf.org/html/rfc8656).
+    pub additional_port: Option<RelayPort>,
+    /// The ip address advertised as the relayed transport address of the
+    /// other address family.
+    pub additional_ip: Option<IpAddr>,
+    pub channels: Vec<ChannelNumber>,
+    /// The MOBILITY-TICKET of [RFC8016](https://tools.ietf.org/html/rfc8016)
+    /// that the client re-claims the allocation with from another address,
+    /// see [`Sessions::issue_ticket`].
+    pub ticket: Option<[u8; 16]>,
+}
+
+impl Allocate {
+    /// The relay ports of the allocation, both ports of a dual allocation
+    /// lead to the session.
+    pub fn ports(&self) -> impl Iterator<Item = RelayPort> {
+        self.port.into_iter().chain(self.additional_port)
+    }
+
+    /// The relayed transport address of the allocation that is in the
+    /// address family of the peer, which is the one that the peer sees the
+    /// data of the session coming from. `ip` is advertised if the allocation
+    /// has not recorded an ip address.
+    pub fn relayed_address(&self, peer: &SocketAddr, ip: IpAddr) -> Option<SocketAddr> {
+        match (self.additional_ip, self.additional_port) {
+            (Some(ip), Some(port)) if ip.is_ipv4() == peer.is_ipv4() => {
+                Some(SocketAddr::new(ip, port.get()))
+            }
+            _ => Some(SocketAddr::new(self.ip.unwrap_or(ip), self.port?.get())),
+        }
+    }
 }
 
 /// turn session information.
 ///
 /// A user can have many sessions.
 ///
-/// The default survival time for a session is 600 seconds.
+/// The default survival time for a session is 600 seconds, see
+/// [`SessionsOptions::default_lifetime`].
 #[derive(Debug, Clone)]
 pub struct Session {
+    /// The transport protocol that the client is connected over.
+    pub transport: Transport,
     pub auth: Auth,
     pub allocate: Allocate,
-    pub permissions: Vec<u16>,
+    pub permissions: Vec<PeerPort>,
     pub expires: u64,
+    /// Whether the session has been warned that its allocation expires, see
+    /// [`SessionsOptions::expiry_warning`]. Cleared when the allocation is
+    /// refreshed.
+    pub warned: bool,
+    /// When the session was authenticated, in seconds of the timer of the
+    /// session manager, like `expires`.
+    pub created: u64,
+    pub activity: Activity,
+}
+
+/// The last activity of the session, in seconds of the timer of the session
+/// manager.
+///
+/// The timestamps are atomics so that they can be updated while relaying
+/// data, which only holds the read lock of the sessions.
+#[derive(Debug, Default)]
+pub struct Activity {
+    request: AtomicU64,
+    // Zero if the session has never relayed data, otherwise the time plus one.
+    data: AtomicU64,
+}
+
+impl Activity {
+    fn new(now: u64) -> Self {
+        Self {
+            request: AtomicU64::new(now),
+            data: AtomicU64::new(0),
+        }
+    }
+
+    /// The last time that the session sent an authenticated request.
+    pub fn last_request(&self) -> u64 {
+        self.request.load(Ordering::Relaxed)
+    }
+
+    /// The last time that the session sent data to a peer, `None` if it has
+    /// never sent any.
+    pub fn last_data(&self) -> Option<u64> {
+        self.data.load(Ordering::Relaxed).checked_sub(1)
+    }
+}
+
+impl Clone for Activity {
+    fn clone(&self) -> Self {
+        Self {
+            request: AtomicU64::new(self.request.load(Ordering::Relaxed)),
+            data: AtomicU64::new(self.data.load(Ordering::Relaxed)),
+        }
+    }
 }
 
 /// The identifier of the session or addr.
@@ -55,6 +194,7 @@ pub struct Session {
 /// Each session needs to be identified by a combination of three pieces of
 /// information: the addr address, and the transport protocol.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SessionAddr {
     pub address: SocketAddr,
     pub interface: SocketAddr,
@@ -64,9 +204,12 @@ pub struct SessionAddr {
 ///
 /// This is used when forwarding data.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Endpoint {
     pub address: SocketAddr,
     pub endpoint: SocketAddr,
+    /// The relay port allocated to the session being forwarded to.
+    pub port: RelayPort,
 }
 
 /// A specially optimised timer.
@@ -94,6 +237,10 @@ impl Timer {
     pub fn add(&self) -> u64 {
         self.0.fetch_add(1, Ordering::Relaxed) + 1
     }
+
+    pub fn set(&self, value: u64) {
+        self.0.store(value, Ordering::Relaxed)
+    }
 }
 
 #[derive(Default)]
@@ -102,42 +249,449 @@ pub struct State {
     port_allocate_pool: Mutex<PortAllocatePools>,
     // Records the sessions corresponding to each assigned port, which will be needed when looking
     // up sessions assigned to this port based on the port number.
-    port_mapping_table: RwLock<Table</* port */ u16, SessionAddr>>,
+    port_mapping_table: RwLock<Table<RelayPort, SessionAddr>>,
     // Records the nonce value for each network connection, which is independent of the session
     // because it can exist before it is authenticated.
-    address_nonce_tanle: RwLock<Table<SessionAddr, (String, /* expires */ u64)>>,
+    address_nonce_tanle: RwLock<Table<SessionAddr, (String, /* expires */ u64, /* issued */ u64)>>,
+    // Records the addr of each nonce, which is how a client that reconnects with the nonce of
+    // its previous session finds that session. Written along with `address_nonce_tanle`.
+    nonce_table: RwLock<HashMap<String, SessionAddr>>,
+    // The sessions that the clients at each addr have proven to own with the nonce of the
+    // session, which move to the addr once the client authenticates with the nonce of the addr.
+    transfer_table: RwLock<HashMap<SessionAddr, SessionAddr>>,
     // Stores the address to which the session should be forwarded when it sends indication to a
     // port. This is written when permissions are created to allow a certain address to be
     // forwarded to the current session.
-    port_relay_table: RwLock<Table<SessionAddr, HashMap</* port */ u16, Endpoint>>>,
+    port_relay_table: RwLock<Table<SessionAddr, HashMap<RelayPort, Endpoint>>>,
     // Indicates to which session the data sent by a session to a channel should be forwarded.
-    channel_relay_table: RwLock<Table<SessionAddr, HashMap</* channel */ u16, Endpoint>>>,
+    channel_relay_table: RwLock<Table<SessionAddr, HashMap<ChannelNumber, Endpoint>>>,
+    // Records the peer ports that the session bound to each port has sent data to. Only written
+    // when strict reverse path verification is enabled.
+    outbound_table: RwLock<Table<RelayPort, HashSet</* peer */ RelayPort>>>,
+    // The sessions that were deleted with a refresh of lifetime zero and still forward the data
+    // of their peers until the linger time runs out.
+    lingering: RwLock<HashSet<SessionAddr>>,
+    // The peers that the sessions reach through another relay, only a few sessions have any.
+    external_table: RwLock<HashMap<SessionAddr, External>>,
+    // The connections of the TCP allocations by their CONNECTION-ID, each connection has another
+    // half for the allocation at the other end.
+    connections: RwLock<HashMap<u32, Connection>>,
+    // The relay ports held in reserve by their RESERVATION-TOKEN, the ports are taken from the
+    // pool but belong to no session.
+    reservations: Mutex<HashMap<u64, Reservation>>,
+    // Records the session of each mobility ticket, which is how a client that moved to another
+    // address finds its allocation again.
+    ticket_table: RwLock<HashMap<[u8; 16], SessionAddr>>,
+    // The times that the permissions and channels of each session expire, only written when
+    // their lifetimes are limited.
+    lifetime_table: RwLock<HashMap<SessionAddr, Lifetimes>>,
+    // The number of allocations of each username in each realm, which the allocation quota
+    // is checked against.
+    quota_table: RwLock<HashMap<(String, String), usize>>,
+}
+
+/// Why a session was closed, see [`Observer::closed`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloseReason {
+    /// The allocation was not refreshed before its lifetime ran out.
+    Expired,
+    /// The client deleted the allocation with a refresh of lifetime zero, or
+    /// closed its TCP connection.
+    Client,
+    /// The session was removed by the administrator of the server.
+    Removed,
+    /// The allocation relayed no data for [`SessionsOptions::max_idle`].
+    Idle,
+    /// The server is shutting down, see [`Sessions::shutdown`].
+    Shutdown,
+    /// The password that the session was authenticated with ran out or was
+    /// removed, see [`Observer::get_password_lifetime`].
+    Revoked,
+}
+
+/// When the permissions and channels of a session expire, see
+/// [`SessionsOptions::permission_lifetime`].
+#[derive(Debug, Default)]
+struct Lifetimes {
+    permissions: HashMap<PeerPort, u64>,
+    // The peer port that each channel is bound to, with the expiry of the binding.
+    channels: HashMap<ChannelNumber, (PeerPort, u64)>,
+}
+
+// Give back the allocation of the session to the quota of its username.
+fn release_quota(quota_table: &mut HashMap<(String, String), usize>, auth: &Auth) {
+    let key = (auth.realm.clone(), auth.username.clone());
+    if let Some(count) = quota_table.get_mut(&key) {
+        *count -= 1;
+        if *count == 0 {
+            quota_table.remove(&key);
+        }
+    }
+}
+
+/// A relayed transport address held in reserve for an allocation that
+/// presents its RESERVATION-TOKEN, see [`Sessions::allocate_even`].
+#[derive(Debug, Clone, Copy)]
+struct Reservation {
+    port: RelayPort,
+    ip: IpAddr,
+    expires: u64,
+}
+
+/// The peers that a session reaches through another relay, see
+/// [`Observer::is_external`].
+#[derive(Debug, Default)]
+struct External {
+    // Like the permissions of RFC 8656, the permissions only check the ip address of the peer.
+    permissions: HashSet<IpAddr>,
+    channels: HashMap<ChannelNumber, SocketAddr>,
+}
+
+/// One half of a connection between two TCP allocations, see
+/// [`Sessions::connect`].
+#[derive(Debug, Clone, Copy)]
+struct Connection {
+    // The session of the allocation that the connection belongs to.
+    owner: SessionAddr,
+    // The CONNECTION-ID of the other half.
+    remote: u32,
+    // The client data connection, once the client has bound it.
+    data: Option<SessionAddr>,
+    // Whether the client has been told that the data connection is bound, the data of the peer
+    // is not sent to the data connection before.
+    is_ready: bool,
+    created: u64,
+}
+
+/// A connection that a TCP allocation opened to the relayed transport address
+/// of another TCP allocation, see [`Sessions::connect`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Connected {
+    /// The CONNECTION-ID that the session binds its data connection with.
+    pub id: u32,
+    /// The session of the allocation that was connected to.
+    pub peer: SessionAddr,
+    /// The CONNECTION-ID that the peer binds its data connection with.
+    pub peer_id: u32,
+}
+
+/// Optional behaviour of the session manager.
+#[derive(Debug, Clone, Default)]
+pub struct SessionsOptions {
+    /// Strict reverse path verification.
+    ///
+    /// When enabled, data arriving on a relay port is only forwarded to the
+    /// session if the session has a permission for the source port and has
+    /// itself sent data to that port before, which prevents peers from
+    /// injecting traffic toward a client that never talked to them. See
+    /// [`Sessions::verify_reverse_path`].
+    pub strict_reverse_path: bool,
+    /// Lock out usernames and addresses that repeatedly fail
+    /// authentication, disabled if not set.
+    pub lockout: Option<LockoutOptions>,
+    /// Seed the random number generator of the port allocator, so that the
+    /// relay ports are assigned reproducibly. See
+    /// [`PortAllocatePools::with_seed`].
+    pub port_seed: Option<u64>,
+    /// The maximum number of permissions of a session, requests that would
+    /// exceed it are refused. Unlimited if not set.
+    pub max_permissions: Option<usize>,
+    /// The maximum number of channel bindings of a session, requests that
+    /// would exceed it are refused. Unlimited if not set.
+    pub max_channels: Option<usize>,
+    /// The lifetime in seconds of a permission for the relay port of a peer,
+    /// a permission that is not refreshed by a CreatePermission or ChannelBind
+    /// request in time is deleted. The permissions last as long as the
+    /// allocation if not set.
+    pub permission_lifetime: Option<u32>,
+    /// The lifetime in seconds of a channel binding to the relay port of a
+    /// peer, a channel that is not bound again in time is deleted and its
+    /// number can be bound to another peer. The channels last as long as the
+    /// allocation if not set, and binding a channel again is rejected then.
+    pub channel_lifetime: Option<u32>,
+    /// Warn sessions that have not refreshed their allocation this many
+    /// seconds before it expires, see [`Observer::expiring`]. Disabled if not
+    /// set.
+    pub expiry_warning: Option<u64>,
+    /// Delete the allocations that have relayed no data to their peers for
+    /// this many seconds, however often they are refreshed. The TCP
+    /// allocations, whose data goes over the data connections, are never
+    /// idle. Disabled if not set.
+    pub max_idle: Option<u64>,
+    /// Keep an allocation that is deleted with a refresh of lifetime zero for
+    /// this long, the data that its peers sent in the meantime is still
+    /// forwarded to the client, but the requests of the client are refused.
+    /// The allocation is deleted right away if not set.
+    pub linger: Option<Duration>,
+    /// The number of source addresses whose last Binding response is kept by
+    /// each operationer, a retransmitted Binding request is answered with the
+    /// kept response instead of encoding it again. Disabled if not set.
+    pub binding_cache: Option<usize>,
+    /// The longest lifetime in seconds that is granted to an allocation,
+    /// longer lifetimes requested by the clients are clamped to it. Defaults
+    /// to [`MAX_LIFETIME`] if not set.
+    pub max_lifetime: Option<u32>,
+    /// The lifetime in seconds of an allocation whose client does not request
+    /// one, shorter requested lifetimes are raised to it. Defaults to
+    /// [`DEFAULT_LIFETIME`] if not set.
+    pub default_lifetime: Option<u32>,
+    /// Require FINGERPRINT on the requests of the clients and write it in
+    /// every response, as some ICE stacks only accept STUN messages that
+    /// carry it. Requests without a valid FINGERPRINT are discarded.
+    pub fingerprint: bool,
+    /// Answer the Binding requests without credentials that the sessions
+    /// send to the relayed transport addresses of their peers, like the
+    /// relayed transport address would, instead of relaying them. See
+    /// [`binding::relayed`](crate::operations::binding::relayed).
+    pub relay_binding: bool,
+    /// Issue MOBILITY-TICKETs to the clients that ask for them, so that a
+    /// client that moves to another address re-claims its allocation with a
+    /// Refresh request, see [`Sessions::issue_ticket`]. The requests for a
+    /// ticket are refused if not set.
+    pub mobility: bool,
+    /// Assign the relay ports of the allocations that match one of the
+    /// ranges from the first matching range, so that the interfaces or the
+    /// address families do not take the ports of each other. The other
+    /// allocations are assigned ports from the whole
+    /// [`PortAllocatePools::port_range`], including the ranges.
+    pub port_ranges: Vec<PortRange>,
+    /// The lifetime in seconds of the nonces that the clients are challenged
+    /// with, a nonce that is used for an allocation lasts as long as the
+    /// allocation. Defaults to [`NONCE_LIFETIME`] if not set.
+    pub nonce_lifetime: Option<u32>,
+    /// The maximum number of allocations that a username holds at the same
+    /// time in a realm, the Allocate requests beyond it are rejected with a
+    /// 486 (Allocation Quota Reached) error. Unlimited if not set.
+    pub max_allocations: Option<usize>,
+    /// The options of the sessions of each realm, which override the options
+    /// of the session manager, see [`RealmOptions`].
+    pub realms: HashMap<String, RealmOptions>,
+}
+
+/// The options of the sessions of a realm, see [`SessionsOptions::realms`].
+/// The options that are not set are taken from [`SessionsOptions`].
+#[derive(Debug, Clone, Default)]
+pub struct RealmOptions {
+    /// See [`SessionsOptions::nonce_lifetime`].
+    pub nonce_lifetime: Option<u32>,
+    /// See [`SessionsOptions::default_lifetime`].
+    pub default_lifetime: Option<u32>,
+    /// See [`SessionsOptions::max_allocations`].
+    pub max_allocations: Option<usize>,
+}
+
+/// A range of relay ports that is reserved for the allocations on an
+/// interface or of an address family, see [`SessionsOptions::port_ranges`].
+#[derive(Debug, Clone)]
+pub struct PortRange {
+    /// The interface of the sessions, any interface if not set.
+    pub interface: Option<SocketAddr>,
+    /// Whether the relayed addresses are ipv6 or ipv4 ones, either family if
+    /// not set.
+    pub ipv6: Option<bool>,
+    /// The ports, the ones outside [`PortAllocatePools::port_range`] are
+    /// never assigned.
+    pub ports: Range<u16>,
+}
+
+impl PortRange {
+    /// Whether an allocation of the session on the relayed ip address takes
+    /// its port from the range.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use mycrl_turn::{sessions::PortRange, SessionAddr};
+    ///
+    /// let range = PortRange {
+    ///     interface: Some("127.0.0.1:3478".parse().unwrap()),
+    ///     ipv6: Some(false),
+    ///     ports: 50000..51000,
+    /// };
+    ///
+    /// let addr = SessionAddr {
+    ///     address: "127.0.0.1:8080".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// assert!(range.matches(&addr, &"127.0.0.1".parse().unwrap()));
+    /// assert!(!range.matches(&addr, &"::1".parse().unwrap()));
+    ///
+    /// let addr = SessionAddr {
+    ///     address: "127.0.0.1:8080".parse().unwrap(),
+    ///     interface: "127.0.0.1:3479".parse().unwrap(),
+    /// };
+    ///
+    /// assert!(!range.matches(&addr, &"127.0.0.1".parse().unwrap()));
+    /// ```
+    pub fn matches(&self, addr: &SessionAddr, ip: &IpAddr) -> bool {
+        self.interface
+            .map(|it| it == addr.interface)
+            .unwrap_or(true)
+            && self.ipv6.map(|it| it == ip.is_ipv6()).unwrap_or(true)
+    }
 }
 
 pub struct Sessions<T> {
     timer: Timer,
     state: State,
+    lockouts: Option<Mutex<Lockouts>>,
+    linger: Option<Sender<(SessionAddr, Instant, CloseReason)>>,
+    options: SessionsOptions,
     observer: T,
 }
 
 impl<T: Observer + 'static> Sessions<T> {
     pub fn new(observer: T) -> Arc<Self> {
+        Self::with_options(observer, SessionsOptions::default())
+    }
+
+    pub fn get_options(&self) -> &SessionsOptions {
+        &self.options
+    }
+
+    /// Create the session manager with options.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use mycrl_turn::*;
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {}
+    ///
+    /// Sessions::with_options(
+    ///     ObserverTest,
+    ///     SessionsOptions {
+    ///         strict_reverse_path: true,
+    ///         ..Default::default()
+    ///     },
+    /// );
+    /// ```
+    pub fn with_options(observer: T, options: SessionsOptions) -> Arc<Self> {
+        Self::with_runtime(observer, options, ThreadRuntime)
+    }
+
+    /// Create the session manager with options, whose background tasks run
+    /// on the runtime, see [`Runtime`].
+    pub fn with_runtime<R: Runtime>(
+        observer: T,
+        options: SessionsOptions,
+        runtime: R,
+    ) -> Arc<Self> {
+        let (linger, lingering) = match options.linger {
+            Some(_) => {
+                let (sender, receiver) = channel::<(SessionAddr, Instant, CloseReason)>();
+                (Some(sender), Some(receiver))
+            }
+            None => (None, None),
+        };
+
         let this = Arc::new(Self {
-            state: State::default(),
+            lockouts: options
+                .lockout
+                .clone()
+                .map(|it| Mutex::new(Lockouts::new(it))),
+            state: State {
+                port_allocate_pool: Mutex::new(
+                    options
+                        .port_seed
+                        .map(PortAllocatePools::with_seed)
+                        .unwrap_or_default(),
+                ),
+                ..Default::default()
+            },
             timer: Timer::default(),
+            options,
             observer,
+            linger,
         });
 
-        // This is a background thread that silently handles expiring sessions and
+        // Deletes the lingering sessions when their linger time runs out. The linger time
+        // is the same for all sessions, so they run out in the order they were queued.
+        if let (Some(linger), Some(receiver)) = (this.options.linger, lingering) {
+            let this_ = Arc::downgrade(&this);
+            let runtime_ = runtime.clone();
+            runtime.spawn(Box::pin(async move {
+                let mut queue = VecDeque::new();
+
+                loop {
+                    queue.extend(receiver.try_iter());
+
+                    let now = Instant::now();
+                    while let Some(&(addr, _, reason)) =
+                        queue.front().filter(|(_, it, _)| *it <= now)
+                    {
+                        match this_.upgrade() {
+                            Some(this) => {
+                                this.remove_session(&[addr], reason);
+                                this.remove_nonce(&[addr]);
+                            }
+                            None => return,
+                        }
+
+                        queue.pop_front();
+                    }
+
+                    if this_.strong_count() == 0 {
+                        return;
+                    }
+
+                    // A session queued while sleeping runs out no earlier than one linger
+                    // time from now, so sleeping for one linger time does not miss it.
+                    let next = queue
+                        .front()
+                        .map(|(_, it, _)| it.saturating_duration_since(now))
+                        .unwrap_or(linger);
+
+                    runtime_.sleep(next).await;
+                }
+            }));
+        }
+
+        // This is a background task that silently handles expiring sessions and
         // cleans up session information when it expires.
         let this_ = Arc::downgrade(&this);
-        thread::spawn(move || {
+        let runtime_ = runtime.clone();
+        runtime.spawn(Box::pin(async move {
             let mut address = Vec::with_capacity(255);
 
             while let Some(this) = this_.upgrade() {
                 // The timer advances one second and gets the current time offset.
                 let now = this.timer.add();
 
+                // Warns the sessions that are about to expire once, a session that
+                // refreshes its allocation moves its expiry and is warned again next time.
+                // The window also catches the sessions whose warning time was skipped.
+                if let Some(warning) = this.options.expiry_warning {
+                    let expiring = this
+                        .state
+                        .sessions
+                        .write()
+                        .iter_mut()
+                        .filter(|(_, v)| !v.warned && v.expires <= now + warning)
+                        .filter_map(|(k, v)| {
+                            let relay = SocketAddr::new(v.allocate.ip?, v.allocate.port?.get());
+                            v.warned = true;
+
+                            Some((
+                                *k,
+                                v.auth.username.clone(),
+                                v.transport,
+                                relay,
+                                v.expires.saturating_sub(now),
+                            ))
+                        })
+                        .collect::<Vec<_>>();
+
+                    for (addr, username, transport, relay, lifetime) in expiring {
+                        this.observer
+                            .expiring(&addr, &username, transport, relay, lifetime);
+                    }
+                }
+
                 // This is the part that deletes the session information.
                 {
                     // Finds sessions that have expired.
@@ -152,11 +706,91 @@ impl<T: Observer + 'static> Sessions<T> {
 
                     // Delete the expired sessions.
                     if !address.is_empty() {
-                        this.remove_session(&address);
+                        this.remove_session(&address, CloseReason::Expired);
+                        address.clear();
+                    }
+
+                    // The sessions whose password has run out are closed, a refresh does
+                    // not extend the password.
+                    {
+                        this.state
+                            .sessions
+                            .read()
+                            .iter()
+                            .filter(|(_, v)| v.auth.expires.map(|it| it <= now).unwrap_or(false))
+                            .for_each(|(k, _)| address.push(*k));
+                    }
+
+                    if !address.is_empty() {
+                        this.remove_session(&address, CloseReason::Revoked);
+                        address.clear();
+                    }
+                }
+
+                // Zombie clients keep refreshing allocations that they do not use, the
+                // allocations that have relayed no data for too long are deleted.
+                if let Some(max_idle) = this.options.max_idle {
+                    this.state
+                        .sessions
+                        .read()
+                        .iter()
+                        .filter(|(_, v)| {
+                            v.allocate.port.is_some() && v.allocate.transport == Transport::UDP
+                        })
+                        .filter(|(_, v)| {
+                            v.activity.last_data().unwrap_or(v.created) + max_idle <= now
+                        })
+                        .for_each(|(k, _)| address.push(*k));
+
+                    if !address.is_empty() {
+                        this.remove_session(&address, CloseReason::Idle);
                         address.clear();
                     }
                 }
 
+                // The permissions and channels that were not refreshed in time are deleted.
+                if this.options.permission_lifetime.is_some()
+                    || this.options.channel_lifetime.is_some()
+                {
+                    this.remove_expired_bindings(now);
+                }
+
+                // The connections whose client data connection has not been bound in time
+                // are closed, together with their other half.
+                {
+                    let mut connections = this.state.connections.write();
+                    if !connections.is_empty() {
+                        let expired = connections
+                            .iter()
+                            .filter(|(_, v)| {
+                                v.data.is_none() && v.created + CONNECTION_TIMEOUT <= now
+                            })
+                            .map(|(k, v)| (*k, v.remote))
+                            .collect::<Vec<_>>();
+
+                        for (id, remote) in expired {
+                            connections.remove(&id);
+                            connections.remove(&remote);
+                        }
+                    }
+                }
+
+                // The reserved ports that were not claimed in time go back to the pool.
+                {
+                    let mut reservations = this.state.reservations.lock();
+                    if !reservations.is_empty() {
+                        let mut port_allocate_pool = this.state.port_allocate_pool.lock();
+                        reservations.retain(|_, v| {
+                            let is_alive = v.expires > now;
+                            if !is_alive {
+                                port_allocate_pool.restore(v.port.get());
+                            }
+
+                            is_alive
+                        });
+                    }
+                }
+
                 // Because nonce does not follow session creation, nonce is created for each
                 // addr, so nonce deletion is handled independently.
                 {
@@ -173,44 +807,170 @@ impl<T: Observer + 'static> Sessions<T> {
                     }
                 }
 
+                // The session manager is not kept alive while sleeping.
+                drop(this);
+
                 // Fixing a second tick.
-                sleep(Duration::from_secs(1));
+                runtime_.sleep(Duration::from_secs(1)).await;
             }
-        });
+        }));
 
         this
     }
 
-    fn remove_session(&self, addrs: &[SessionAddr]) {
+    fn remove_expired_bindings(&self, now: u64) {
+        let is_expired = self.state.lifetime_table.read().values().any(|it| {
+            it.permissions.values().any(|it| *it <= now)
+                || it.channels.values().any(|(_, it)| *it <= now)
+        });
+
+        if !is_expired {
+            return;
+        }
+
+        let mut sessions = self.state.sessions.write();
+        let port_mapping_table = self.state.port_mapping_table.read();
+        let mut port_relay_table = self.state.port_relay_table.write();
+        let mut channel_relay_table = self.state.channel_relay_table.write();
+        let mut lifetime_table = self.state.lifetime_table.write();
+
+        // The session that the relay port of a peer is allocated to.
+        let peer = |port: PeerPort| {
+            RelayPort::try_from(port)
+                .ok()
+                .and_then(|it| port_mapping_table.get(&it))
+        };
+
+        for (addr, lifetimes) in lifetime_table.iter_mut() {
+            let session = if let Some(it) = sessions.get_mut(addr) {
+                it
+            } else {
+                lifetimes.permissions.clear();
+                lifetimes.channels.clear();
+                continue;
+            };
+
+            let local_ports = session.allocate.ports().collect::<Vec<_>>();
+
+            lifetimes.channels.retain(|channel, (port, expires)| {
+                if *expires > now {
+                    return true;
+                }
+
+                session.allocate.channels.retain(|it| it != channel);
+
+                // The channel number may have been bound to the peer by another session
+                // since, which is not deleted.
+                if let Some(relays) = peer(*port).and_then(|it| channel_relay_table.get_mut(it)) {
+                    if relays
+                        .get(channel)
+                        .map(|it| local_ports.contains(&it.port))
+                        .unwrap_or(false)
+                    {
+                        relays.remove(channel);
+                    }
+                }
+
+                false
+            });
+
+            lifetimes.permissions.retain(|port, expires| {
+                if *expires > now {
+                    return true;
+                }
+
+                session.permissions.retain(|it| it != port);
+                if let Some(relays) = peer(*port).and_then(|it| port_relay_table.get_mut(it)) {
+                    for it in &local_ports {
+                        relays.remove(it);
+                    }
+                }
+
+                false
+            });
+        }
+
+        lifetime_table.retain(|_, it| !it.permissions.is_empty() || !it.channels.is_empty());
+    }
+
+    fn remove_session(&self, addrs: &[SessionAddr], reason: CloseReason) {
         let mut sessions = self.state.sessions.write();
         let mut port_allocate_pool = self.state.port_allocate_pool.lock();
         let mut port_mapping_table = self.state.port_mapping_table.write();
         let mut port_relay_table = self.state.port_relay_table.write();
         let mut channel_relay_table = self.state.channel_relay_table.write();
+        let mut outbound_table = self.state.outbound_table.write();
+        let mut lingering = self.state.lingering.write();
+        let mut external_table = self.state.external_table.write();
+        let mut connections = self.state.connections.write();
+        let mut ticket_table = self.state.ticket_table.write();
+        let mut lifetime_table = self.state.lifetime_table.write();
+        let mut quota_table = self.state.quota_table.write();
 
         addrs.iter().for_each(|k| {
             port_relay_table.remove(k);
             channel_relay_table.remove(k);
+            lifetime_table.remove(k);
+            lingering.remove(k);
+            external_table.remove(k);
+
+            // The connections of a TCP allocation are closed with it.
+            if !connections.is_empty() {
+                let closed = connections
+                    .iter()
+                    .filter(|(_, v)| v.owner == *k)
+                    .map(|(id, v)| (*id, v.remote))
+                    .collect::<Vec<_>>();
+
+                for (id, remote) in closed {
+                    connections.remove(&id);
+                    connections.remove(&remote);
+                }
+            }
 
             if let Some(session) = sessions.remove(k) {
+                if let Some(ticket) = &session.allocate.ticket {
+                    ticket_table.remove(ticket);
+                }
+
+                if session.allocate.port.is_some() {
+                    release_quota(&mut quota_table, &session.auth);
+                }
+
                 // Removes the session-bound port from the port binding table and
                 // releases the port back into the allocation pool.
-                if let Some(port) = session.allocate.port {
+                for port in session.allocate.ports() {
                     port_mapping_table.remove(&port);
-                    port_allocate_pool.restore(port);
+                    port_allocate_pool.restore(port.get());
+
+                    // The port may be handed to another session, so forget everything that
+                    // was recorded for it.
+                    if !outbound_table.is_empty() {
+                        outbound_table.remove(&port);
+                        outbound_table.values_mut().for_each(|it| {
+                            it.remove(&port);
+                        });
+                    }
                 }
 
                 // Notifies that the external session has been closed.
-                self.observer.closed(k, &session.auth.username);
+                self.observer.closed(k, &session.auth.username, reason);
             }
         });
     }
 
     fn remove_nonce(&self, addrs: &[SessionAddr]) {
         let mut address_nonce_tanle = self.state.address_nonce_tanle.write();
+        let mut nonce_table = self.state.nonce_table.write();
+        let mut transfer_table = self.state.transfer_table.write();
 
         addrs.iter().for_each(|k| {
-            address_nonce_tanle.remove(k);
+            if let Some((nonce, _, _)) = address_nonce_tanle.remove(k) {
+                nonce_table.remove(&nonce);
+            }
+
+            // A pending transfer lasts as long as the nonce that it has to be claimed with.
+            transfer_table.remove(k);
         });
     }
 
@@ -220,6 +980,7 @@ impl<T: Observer + 'static> Sessions<T> {
     ///
     /// ```
     /// use mycrl_turn::*;
+    /// use stun::attribute::Transport;
     ///
     /// #[derive(Clone)]
     /// struct ObserverTest;
@@ -252,7 +1013,7 @@ impl<T: Observer + 'static> Sessions<T> {
     ///
     /// assert!(sessions.get_session(&addr).get_ref().is_none());
     ///
-    /// pollster::block_on(sessions.get_digest(&addr, "test", "test"));
+    /// pollster::block_on(sessions.get_digest(&addr, Transport::UDP, "test", "test"));
     ///
     /// let lock = sessions.get_session(&addr);
     /// let session = lock.get_ref().unwrap();
@@ -271,66 +1032,72 @@ impl<T: Observer + 'static> Sessions<T> {
         }
     }
 
-    /// Get nonce for addr.
+    /// Whether the address is the relayed transport address of an allocation,
+    /// whose ip address may differ from the ones of the interfaces, see
+    /// [`Observer::get_relay_ip`].
     ///
     /// # Test
     ///
     /// ```
     /// use mycrl_turn::*;
+    /// use stun::attribute::Transport;
     ///
     /// #[derive(Clone)]
     /// struct ObserverTest;
     ///
-    /// impl Observer for ObserverTest {}
+    /// impl Observer for ObserverTest {
+    ///     async fn get_password(
+    ///         &self,
+    ///         addr: &SessionAddr,
+    ///         username: &str,
+    ///     ) -> Option<String> {
+    ///         Some("test".to_string())
+    ///     }
+    /// }
     ///
     /// let addr = SessionAddr {
     ///     address: "127.0.0.1:8080".parse().unwrap(),
     ///     interface: "127.0.0.1:3478".parse().unwrap(),
     /// };
     ///
+    /// let ip = "192.0.2.1".parse().unwrap();
     /// let sessions = Sessions::new(ObserverTest);
     ///
-    /// let a = sessions.get_nonce(&addr).get_ref().unwrap().clone();
-    /// assert!(a.0.len() == 16);
-    /// assert!(a.1 == 600 || a.1 == 601 || a.1 == 602);
+    /// pollster::block_on(sessions.get_digest(&addr, Transport::UDP, "test", "test"));
     ///
-    /// let b = sessions.get_nonce(&addr).get_ref().unwrap().clone();
-    /// assert_eq!(a.0, b.0);
-    /// assert!(b.1 == 600 || b.1 == 601 || b.1 == 602);
+    /// let port = sessions.allocate_with_transport(&addr, ip, Transport::UDP).unwrap();
+    /// assert!(sessions.is_relayed_address(&std::net::SocketAddr::new(ip, port.get())));
+    /// assert!(!sessions.is_relayed_address(&"192.0.2.2:0".parse().unwrap()));
+    /// assert!(!sessions.is_relayed_address(&std::net::SocketAddr::new(
+    ///     "192.0.2.2".parse().unwrap(),
+    ///     port.get()
+    /// )));
     /// ```
-    pub fn get_nonce<'a, 'b>(
-        &'a self,
-        key: &'b SessionAddr,
-    ) -> ReadLock<'b, 'a, SessionAddr, Table<SessionAddr, (String, u64)>> {
-        // If no nonce is created, create a new one.
+    pub fn is_relayed_address(&self, address: &SocketAddr) -> bool {
+        // The port mapping is released before the sessions are locked, the
+        // sessions are always locked first.
+        let addr = match RelayPort::new(address.port())
+            .and_then(|port| self.state.port_mapping_table.read().get(&port).copied())
         {
-            if !self.state.address_nonce_tanle.read().contains_key(key) {
-                self.state.address_nonce_tanle.write().insert(
-                    *key,
-                    (
-                        // A random string of length 16.
-                        {
-                            let mut rng = thread_rng();
-                            std::iter::repeat(())
-                                .map(|_| rng.sample(Alphanumeric) as char)
-                                .take(16)
-                                .collect::<String>()
-                                .to_lowercase()
-                        },
-                        // Current time stacks for 600 seconds.
-                        self.timer.get() + 600,
-                    ),
-                );
-            }
-        }
+            Some(it) => it,
+            None => return false,
+        };
 
-        ReadLock {
-            lock: self.state.address_nonce_tanle.read(),
-            key,
-        }
+        self.state
+            .sessions
+            .read()
+            .get(&addr)
+            .map(|it| {
+                let allocate = &it.allocate;
+                (allocate.ip == Some(address.ip())
+                    && allocate.port.map(|it| it.get()) == Some(address.port()))
+                    || (allocate.additional_ip == Some(address.ip())
+                        && allocate.additional_port.map(|it| it.get()) == Some(address.port()))
+            })
+            .unwrap_or(false)
     }
 
-    /// Get digest for addr.
+    /// Get nonce for addr.
     ///
     /// # Test
     ///
@@ -340,98 +1107,3037 @@ impl<T: Observer + 'static> Sessions<T> {
     /// #[derive(Clone)]
     /// struct ObserverTest;
     ///
-    /// impl Observer for ObserverTest {
-    ///     async fn get_password(
-    ///         &self,
-    ///         addr: &SessionAddr,
-    ///         username: &str,
-    ///     ) -> Option<String> {
-    ///         if username == "test" {
-    ///             Some("test".to_string())
-    ///         } else {
-    ///             None
-    ///         }
-    ///     }
-    /// }
+    /// impl Observer for ObserverTest {}
     ///
     /// let addr = SessionAddr {
     ///     address: "127.0.0.1:8080".parse().unwrap(),
     ///     interface: "127.0.0.1:3478".parse().unwrap(),
     /// };
     ///
-    /// let digest = [
-    ///     174, 238, 187, 253, 117, 209, 73, 157, 36, 56, 143, 91, 155, 16, 224,
-    ///     239,
-    /// ];
-    ///
     /// let sessions = Sessions::new(ObserverTest);
     ///
-    /// assert_eq!(
-    ///     pollster::block_on(sessions.get_digest(&addr, "test1", "test")),
-    ///     None
-    /// );
+    /// let a = sessions.get_nonce(&addr).get_ref().unwrap().clone();
+    /// assert!(a.0.starts_with(sessions::NONCE_COOKIE));
+    /// assert!(a.0.len() == sessions::NONCE_COOKIE.len() + 16);
+    /// assert!(a.1 == 600 || a.1 == 601 || a.1 == 602);
+    /// assert_eq!(a.1 - a.2, 600);
+    ///
+    /// let b = sessions.get_nonce(&addr).get_ref().unwrap().clone();
+    /// assert_eq!(a.0, b.0);
+    /// assert!(b.1 == 600 || b.1 == 601 || b.1 == 602);
+    /// ```
+    pub fn get_nonce<'a, 'b>(
+        &'a self,
+        key: &'b SessionAddr,
+    ) -> ReadLock<'b, 'a, SessionAddr, Table<SessionAddr, (String, u64, u64)>> {
+        self.get_realm_nonce(key, "")
+    }
+
+    /// Get the nonce of the addr like [`Sessions::get_nonce`] does, a new
+    /// nonce lasts for the nonce lifetime of the realm, see
+    /// [`RealmOptions::nonce_lifetime`].
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use ahash::HashMap;
+    /// use mycrl_turn::{sessions::RealmOptions, *};
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {}
+    ///
+    /// let addr = SessionAddr {
+    ///     address: "127.0.0.1:8080".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let other_addr = SessionAddr {
+    ///     address: "127.0.0.1:8081".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let mut realms = HashMap::default();
+    /// realms.insert(
+    ///     "tenant".to_string(),
+    ///     RealmOptions {
+    ///         nonce_lifetime: Some(60),
+    ///         ..Default::default()
+    ///     },
+    /// );
+    ///
+    /// let sessions = Sessions::with_options(
+    ///     ObserverTest,
+    ///     SessionsOptions {
+    ///         nonce_lifetime: Some(300),
+    ///         realms,
+    ///         ..Default::default()
+    ///     },
+    /// );
+    ///
+    /// let expires = sessions.get_realm_nonce(&addr, "tenant").get_ref().unwrap().1;
+    /// assert!((60..=62).contains(&expires));
+    ///
+    /// let expires = sessions.get_realm_nonce(&other_addr, "test").get_ref().unwrap().1;
+    /// assert!((300..=302).contains(&expires));
+    /// ```
+    pub fn get_realm_nonce<'a, 'b>(
+        &'a self,
+        key: &'b SessionAddr,
+        realm: &str,
+    ) -> ReadLock<'b, 'a, SessionAddr, Table<SessionAddr, (String, u64, u64)>> {
+        // If no nonce is created, create a new one.
+        {
+            if !self.state.address_nonce_tanle.read().contains_key(key) {
+                let now = self.timer.get();
+
+                // The nonce cookie and a random string of length 16.
+                let nonce = {
+                    let mut rng = thread_rng();
+                    let random = std::iter::repeat(())
+                        .map(|_| rng.sample(Alphanumeric) as char)
+                        .take(16)
+                        .collect::<String>()
+                        .to_lowercase();
+
+                    format!("{}{}", NONCE_COOKIE, random)
+                };
+
+                let mut address_nonce_tanle = self.state.address_nonce_tanle.write();
+
+                // Another request of the addr may have created it in the meantime.
+                if !address_nonce_tanle.contains_key(key) {
+                    self.state.nonce_table.write().insert(nonce.clone(), *key);
+
+                    let expires = now + self.nonce_lifetime(realm) as u64;
+                    address_nonce_tanle.insert(*key, (nonce, expires, now));
+                }
+            }
+        }
+
+        ReadLock {
+            lock: self.state.address_nonce_tanle.read(),
+            key,
+        }
+    }
+
+    /// The time when the current nonce of the addr was issued, `None` if no
+    /// nonce has been issued. Unlike [`Sessions::get_nonce`], this does not
+    /// issue a nonce.
+    pub fn get_nonce_issued(&self, key: &SessionAddr) -> Option<u64> {
+        self.state
+            .address_nonce_tanle
+            .read()
+            .get(key)
+            .map(|it| it.2)
+    }
+
+    /// Get digest for addr.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use mycrl_turn::*;
+    /// use stun::attribute::Transport;
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {
+    ///     async fn get_password(
+    ///         &self,
+    ///         addr: &SessionAddr,
+    ///         username: &str,
+    ///     ) -> Option<String> {
+    ///         if username == "test" {
+    ///             Some("test".to_string())
+    ///         } else {
+    ///             None
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// let addr = SessionAddr {
+    ///     address: "127.0.0.1:8080".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let digest = [
+    ///     174, 238, 187, 253, 117, 209, 73, 157, 36, 56, 143, 91, 155, 16, 224,
+    ///     239,
+    /// ];
+    ///
+    /// let sessions = Sessions::new(ObserverTest);
+    ///
+    /// assert_eq!(
+    ///     pollster::block_on(sessions.get_digest(&addr, Transport::UDP, "test1", "test")),
+    ///     None
+    /// );
     ///
     /// assert_eq!(
-    ///     pollster::block_on(sessions.get_digest(&addr, "test", "test")),
+    ///     pollster::block_on(sessions.get_digest(&addr, Transport::UDP, "test", "test")),
     ///     Some(digest)
     /// );
     ///
     /// assert_eq!(
-    ///     pollster::block_on(sessions.get_digest(&addr, "test", "test")),
+    ///     pollster::block_on(sessions.get_digest(&addr, Transport::UDP, "test", "test")),
     ///     Some(digest)
     /// );
     /// ```
     pub async fn get_digest(
         &self,
         addr: &SessionAddr,
+        transport: Transport,
+        username: &str,
+        realm: &str,
+    ) -> Option<[u8; 16]> {
+        self.get_digest_with_origin(addr, transport, username, realm, None)
+            .await
+    }
+
+    /// Get the digest of the session, like [`Sessions::get_digest`], for a
+    /// request that carries the ORIGIN attribute.
+    ///
+    /// The origin is passed to the observer when looking up the password and
+    /// recorded in the session.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use mycrl_turn::*;
+    /// use stun::attribute::Transport;
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {
+    ///     async fn get_password_with_origin(
+    ///         &self,
+    ///         addr: &SessionAddr,
+    ///         username: &str,
+    ///         origin: Option<&str>,
+    ///     ) -> Option<String> {
+    ///         if origin == Some("https://example.com") {
+    ///             Some("test".to_string())
+    ///         } else {
+    ///             None
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// let addr = SessionAddr {
+    ///     address: "127.0.0.1:8080".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let sessions = Sessions::new(ObserverTest);
+    ///
+    /// assert!(pollster::block_on(sessions.get_digest(&addr, Transport::UDP, "test", "test")).is_none());
+    /// assert!(pollster::block_on(sessions.get_digest_with_origin(
+    ///     &addr,
+    ///     Transport::UDP,
+    ///     "test",
+    ///     "test",
+    ///     Some("https://example.com")
+    /// ))
+    /// .is_some());
+    ///
+    /// let lock = sessions.get_session(&addr);
+    /// let session = lock.get_ref().unwrap();
+    /// assert_eq!(session.auth.origin.as_deref(), Some("https://example.com"));
+    /// ```
+    pub async fn get_digest_with_origin(
+        &self,
+        addr: &SessionAddr,
+        transport: Transport,
         username: &str,
         realm: &str,
+        origin: Option<&str>,
     ) -> Option<[u8; 16]> {
+        self.try_get_digest(addr, transport, username, realm, origin)
+            .await
+            .ok()
+            .flatten()
+    }
+
+    /// Get the digest of the session, like
+    /// [`Sessions::get_digest_with_origin`], with the reason why the password
+    /// was not given, see [`Observer::try_get_password`].
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use mycrl_turn::*;
+    /// use stun::attribute::Transport;
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {
+    ///     async fn try_get_password(
+    ///         &self,
+    ///         _: &SessionAddr,
+    ///         username: &str,
+    ///         _: Option<&str>,
+    ///     ) -> Result<Option<String>, PasswordError> {
+    ///         match username {
+    ///             "test" => Ok(Some("test".to_string())),
+    ///             "banned" => Err(PasswordError::Forbidden),
+    ///             "down" => Err(PasswordError::Unavailable),
+    ///             _ => Ok(None),
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// let addr = SessionAddr {
+    ///     address: "127.0.0.1:8080".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let sessions = Sessions::new(ObserverTest);
+    /// let digest = |username| {
+    ///     pollster::block_on(sessions.try_get_digest(&addr, Transport::UDP, username, "test", None))
+    /// };
+    ///
+    /// assert_eq!(digest("unknown"), Ok(None));
+    /// assert_eq!(digest("banned"), Err(PasswordError::Forbidden));
+    /// assert_eq!(digest("down"), Err(PasswordError::Unavailable));
+    /// assert!(digest("test").unwrap().is_some());
+    /// ```
+    pub async fn try_get_digest(
+        &self,
+        addr: &SessionAddr,
+        transport: Transport,
+        username: &str,
+        realm: &str,
+        origin: Option<&str>,
+    ) -> Result<Option<[u8; 16]>, PasswordError> {
         // Already authenticated, get the cached digest directly.
         {
             if let Some(it) = self.state.sessions.read().get(addr) {
-                return Some(it.auth.digest);
+                return Ok(Some(it.auth.digest));
             }
         }
 
         // Get the current user's password from an external observer and create a
-        // digest.
-        let password = self.observer.get_password(addr, username).await?;
-        let digest = long_term_credential_digest(&username, &password, realm);
+        // digest, unless the observer only has the digest.
+        let (password, digest) = match self
+            .observer
+            .try_get_credential(addr, username, realm, origin)
+            .await?
+        {
+            Some(Credential::Password(password)) => {
+                match PasswordDigest::new(username, &password, realm) {
+                    Ok(it) => (password, it),
+                    Err(_) => return Ok(None),
+                }
+            }
+            Some(Credential::Digest(it)) => (String::new(), it),
+            None => return Ok(None),
+        };
+
+        let lifetime = self.observer.get_password_lifetime(addr, username);
 
         // Record a new session.
         {
+            let now = self.timer.get();
             self.state.sessions.write().insert(
                 *addr,
                 Session {
                     permissions: Vec::with_capacity(10),
-                    expires: self.timer.get() + 600,
+                    transport,
+                    expires: now + self.default_lifetime(realm) as u64,
+                    warned: false,
+                    created: now,
+                    activity: Activity::new(now),
                     auth: Auth {
                         username: username.to_string(),
+                        realm: realm.to_string(),
+                        origin: origin.map(ToString::to_string),
+                        expires: lifetime.map(|it| now + it),
                         password,
-                        digest,
+                        digest: digest.md5,
+                        digest_sha256: digest.sha256,
                     },
                     allocate: Allocate {
                         channels: Vec::with_capacity(10),
+                        transport: Transport::UDP,
+                        additional_port: None,
+                        additional_ip: None,
                         port: None,
+                        ip: None,
+                        ticket: None,
                     },
                 },
             );
         }
 
-        Some(digest)
+        Ok(Some(digest.md5))
     }
 
-    pub fn allocated(&self) -> usize {
-        self.state.port_allocate_pool.lock().len()
+    /// The key of the long-term credential of an authenticated session for
+    /// the password algorithm, `None` if the session is not authenticated.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use mycrl_turn::*;
+    /// use stun::attribute::{Algorithm, Transport};
+    /// use stun::auth::{long_term_credential_key, Key};
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {
+    ///     async fn get_password(&self, _: &SessionAddr, _: &str) -> Option<String> {
+    ///         Some("test".to_string())
+    ///     }
+    /// }
+    ///
+    /// let addr = SessionAddr {
+    ///     address: "127.0.0.1:8080".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let sessions = Sessions::new(ObserverTest);
+    ///
+    /// assert_eq!(sessions.get_key(&addr, Algorithm::Sha256), None);
+    ///
+    /// let digest = pollster::block_on(sessions.get_digest(&addr, Transport::UDP, "test", "test"));
+    /// assert_eq!(sessions.get_key(&addr, Algorithm::Md5), digest.map(Key::Md5));
+    /// assert_eq!(
+    ///     sessions.get_key(&addr, Algorithm::Sha256),
+    ///     long_term_credential_key("test", "test", "test", Algorithm::Sha256).ok()
+    /// );
+    /// ```
+    pub fn get_key(&self, addr: &SessionAddr, algorithm: Algorithm) -> Option<Key> {
+        self.state
+            .sessions
+            .read()
+            .get(addr)
+            .map(|it| match algorithm {
+                Algorithm::Md5 => Key::Md5(it.auth.digest),
+                Algorithm::Sha256 => Key::Sha256(it.auth.digest_sha256),
+            })
+    }
+
+    /// Whether the session was deleted with a refresh of lifetime zero and is
+    /// lingering, see [`SessionsOptions::linger`].
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use std::{thread::sleep, time::Duration};
+    ///
+    /// use mycrl_turn::*;
+    /// use stun::attribute::Transport;
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {
+    ///     fn get_password_blocking(&self, _: &SessionAddr, _: &str) -> Option<String> {
+    ///         Some("test".to_string())
+    ///     }
+    /// }
+    ///
+    /// let addr = SessionAddr {
+    ///     address: "127.0.0.1:8080".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let sessions = Sessions::with_options(
+    ///     ObserverTest,
+    ///     SessionsOptions {
+    ///         linger: Some(Duration::from_millis(100)),
+    ///         ..Default::default()
+    ///     },
+    /// );
+    ///
+    /// pollster::block_on(sessions.get_digest(&addr, Transport::UDP, "test", "test"));
+    ///
+    /// assert!(sessions.refresh(&addr, 0));
+    /// assert!(sessions.is_lingering(&addr));
+    /// assert!(sessions.get_session(&addr).get_ref().is_some());
+    ///
+    /// sleep(Duration::from_millis(500));
+    ///
+    /// assert!(!sessions.is_lingering(&addr));
+    /// assert!(sessions.get_session(&addr).get_ref().is_none());
+    /// ```
+    pub fn is_lingering(&self, addr: &SessionAddr) -> bool {
+        let lingering = self.state.lingering.read();
+        !lingering.is_empty() && lingering.contains(addr)
+    }
+
+    /// Whether authentication of the username or from the address is
+    /// currently locked out.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use mycrl_turn::{
+    ///     lockout::{AuthFailure, LockoutOptions},
+    ///     *,
+    /// };
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {}
+    ///
+    /// let addr = SessionAddr {
+    ///     address: "127.0.0.1:8080".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let sessions = Sessions::with_options(
+    ///     ObserverTest,
+    ///     SessionsOptions {
+    ///         lockout: Some(LockoutOptions {
+    ///             max_failures: 1,
+    ///             ..Default::default()
+    ///         }),
+    ///         ..Default::default()
+    ///     },
+    /// );
+    ///
+    /// sessions.auth_failed(&addr, "test", AuthFailure::Integrity);
+    /// assert!(!sessions.is_locked(&addr, "test"));
+    ///
+    /// sessions.auth_failed(&addr, "test", AuthFailure::Integrity);
+    /// assert!(sessions.is_locked(&addr, "test"));
+    /// assert!(sessions.is_locked(&addr, "other"));
+    /// ```
+    pub fn is_locked(&self, addr: &SessionAddr, username: &str) -> bool {
+        if let Some(lockouts) = &self.lockouts {
+            let now = self.timer.get();
+            let lockouts = lockouts.lock();
+
+            lockouts.is_locked(&LockoutKey::Address(addr.address.ip()), now)
+                || lockouts.is_locked(&LockoutKey::Username(username.to_string()), now)
+        } else {
+            false
+        }
+    }
+
+    /// Record an authentication failure of the username from the address.
+    ///
+    /// The observer is notified of the failure, and for every username or
+    /// address that is locked out by this failure.
+    pub fn auth_failed(&self, addr: &SessionAddr, username: &str, reason: AuthFailure) {
+        self.observer.auth_failed(addr, username, reason);
+
+        if let Some(lockouts) = &self.lockouts {
+            let now = self.timer.get();
+            let mut locked = Vec::with_capacity(2);
+
+            {
+                let mut lockouts = lockouts.lock();
+                for key in [
+                    LockoutKey::Address(addr.address.ip()),
+                    LockoutKey::Username(username.to_string()),
+                ] {
+                    if let Some(lockout) = lockouts.failed(key.clone(), now) {
+                        locked.push((key, lockout));
+                    }
+                }
+            }
+
+            for (key, lockout) in locked {
+                self.observer.locked(addr, username, &key, lockout);
+            }
+        }
+    }
+
+    /// Forget the authentication failures of the username.
+    pub fn auth_succeeded(&self, username: &str) {
+        if let Some(lockouts) = &self.lockouts {
+            lockouts
+                .lock()
+                .succeeded(&LockoutKey::Username(username.to_string()));
+        }
+    }
+
+    /// The lifetime that is granted to an allocation of the session for the
+    /// lifetime that the client requested, see
+    /// [`SessionsOptions::default_lifetime`], [`RealmOptions::default_lifetime`]
+    /// and [`SessionsOptions::max_lifetime`].
+    ///
+    /// A zero lifetime, which deletes the allocation on refresh, is not
+    /// handled here.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use ahash::HashMap;
+    /// use mycrl_turn::{sessions::RealmOptions, *};
+    /// use stun::attribute::Transport;
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {
+    ///     fn get_password_blocking(&self, _: &SessionAddr, _: &str) -> Option<String> {
+    ///         Some("test".to_string())
+    ///     }
+    /// }
+    ///
+    /// let addr = SessionAddr {
+    ///     address: "127.0.0.1:8080".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let sessions = Sessions::new(ObserverTest);
+    ///
+    /// assert_eq!(sessions.granted_lifetime(&addr, None), 600);
+    /// assert_eq!(sessions.granted_lifetime(&addr, Some(60)), 600);
+    /// assert_eq!(sessions.granted_lifetime(&addr, Some(1800)), 1800);
+    /// assert_eq!(sessions.granted_lifetime(&addr, Some(86400)), 3600);
+    ///
+    /// let sessions = Sessions::with_options(
+    ///     ObserverTest,
+    ///     SessionsOptions {
+    ///         max_lifetime: Some(300),
+    ///         ..Default::default()
+    ///     },
+    /// );
+    ///
+    /// assert_eq!(sessions.granted_lifetime(&addr, None), 300);
+    /// assert_eq!(sessions.granted_lifetime(&addr, Some(1800)), 300);
+    ///
+    /// let mut realms = HashMap::default();
+    /// realms.insert(
+    ///     "tenant".to_string(),
+    ///     RealmOptions {
+    ///         default_lifetime: Some(900),
+    ///         ..Default::default()
+    ///     },
+    /// );
+    ///
+    /// let sessions = Sessions::with_options(
+    ///     ObserverTest,
+    ///     SessionsOptions {
+    ///         default_lifetime: Some(120),
+    ///         realms,
+    ///         ..Default::default()
+    ///     },
+    /// );
+    ///
+    /// assert_eq!(sessions.granted_lifetime(&addr, None), 120);
+    /// assert_eq!(sessions.granted_lifetime(&addr, Some(60)), 120);
+    /// assert_eq!(sessions.granted_lifetime(&addr, Some(300)), 300);
+    ///
+    /// // The sessions of the realm are granted the default lifetime of the realm.
+    /// pollster::block_on(sessions.get_digest(&addr, Transport::UDP, "test", "tenant"));
+    /// assert_eq!(sessions.granted_lifetime(&addr, None), 900);
+    /// assert_eq!(sessions.granted_lifetime(&addr, Some(1800)), 1800);
+    /// ```
+    pub fn granted_lifetime(&self, addr: &SessionAddr, requested: Option<u32>) -> u32 {
+        let default = match self.state.sessions.read().get(addr) {
+            Some(it) => self.default_lifetime(&it.auth.realm),
+            None => self.default_lifetime(""),
+        };
+
+        requested
+            .unwrap_or(default)
+            .max(default)
+            .min(self.options.max_lifetime.unwrap_or(MAX_LIFETIME))
+    }
+
+    fn default_lifetime(&self, realm: &str) -> u32 {
+        self.options
+            .realms
+            .get(realm)
+            .and_then(|it| it.default_lifetime)
+            .or(self.options.default_lifetime)
+            .unwrap_or(DEFAULT_LIFETIME)
+    }
+
+    fn nonce_lifetime(&self, realm: &str) -> u32 {
+        self.options
+            .realms
+            .get(realm)
+            .and_then(|it| it.nonce_lifetime)
+            .or(self.options.nonce_lifetime)
+            .unwrap_or(NONCE_LIFETIME)
+    }
+
+    fn max_allocations(&self, realm: &str) -> Option<usize> {
+        self.options
+            .realms
+            .get(realm)
+            .and_then(|it| it.max_allocations)
+            .or(self.options.max_allocations)
+    }
+
+    /// Whether the username of the session holds as many allocations in its
+    /// realm as it may, see [`SessionsOptions::max_allocations`]. The
+    /// allocations of the session fail while it does.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use mycrl_turn::*;
+    /// use stun::attribute::Transport;
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {
+    ///     fn get_password_blocking(&self, _: &SessionAddr, _: &str) -> Option<String> {
+    ///         Some("test".to_string())
+    ///     }
+    /// }
+    ///
+    /// let addr = SessionAddr {
+    ///     address: "127.0.0.1:8080".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let other_addr = SessionAddr {
+    ///     address: "127.0.0.1:8081".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let sessions = Sessions::with_options(
+    ///     ObserverTest,
+    ///     SessionsOptions {
+    ///         max_allocations: Some(1),
+    ///         ..Default::default()
+    ///     },
+    /// );
+    ///
+    /// pollster::block_on(sessions.get_digest(&addr, Transport::UDP, "test", "test"));
+    /// pollster::block_on(sessions.get_digest(&other_addr, Transport::UDP, "test", "test"));
+    ///
+    /// assert!(!sessions.is_quota_reached(&other_addr));
+    /// sessions.allocate(&addr).unwrap();
+    ///
+    /// assert!(sessions.is_quota_reached(&other_addr));
+    /// assert!(sessions.allocate(&other_addr).is_none());
+    ///
+    /// // The quota is given back with the allocation.
+    /// sessions.refresh(&addr, 0);
+    /// assert!(!sessions.is_quota_reached(&other_addr));
+    /// assert!(sessions.allocate(&other_addr).is_some());
+    /// ```
+    pub fn is_quota_reached(&self, addr: &SessionAddr) -> bool {
+        let sessions = self.state.sessions.read();
+        let auth = match sessions.get(addr) {
+            Some(it) => &it.auth,
+            None => return false,
+        };
+
+        self.is_quota_reached_for(auth)
+    }
+
+    fn is_quota_reached_for(&self, auth: &Auth) -> bool {
+        match self.max_allocations(&auth.realm) {
+            Some(max) => {
+                let key = (auth.realm.clone(), auth.username.clone());
+                self.state
+                    .quota_table
+                    .read()
+                    .get(&key)
+                    .copied()
+                    .unwrap_or(0)
+                    >= max
+            }
+            None => false,
+        }
+    }
+
+    /// The current time of the timer of the session manager, in seconds
+    /// since the session manager was created.
+    ///
+    /// The times of the sessions, such as `expires`, are relative to this
+    /// timer.
+    pub fn now(&self) -> u64 {
+        self.timer.get()
+    }
+
+    /// Record an authenticated request of the session.
+    pub fn request_received(&self, addr: &SessionAddr) {
+        if let Some(session) = self.state.sessions.read().get(addr) {
+            session
+                .activity
+                .request
+                .store(self.timer.get(), Ordering::Relaxed);
+        }
+    }
+
+    /// Record that the session sent data to a peer.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use mycrl_turn::*;
+    /// use stun::attribute::Transport;
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {
+    ///     async fn get_password(
+    ///         &self,
+    ///         addr: &SessionAddr,
+    ///         username: &str,
+    ///     ) -> Option<String> {
+    ///         Some("test".to_string())
+    ///     }
+    /// }
+    ///
+    /// let addr = SessionAddr {
+    ///     address: "127.0.0.1:8080".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let sessions = Sessions::new(ObserverTest);
+    ///
+    /// pollster::block_on(sessions.get_digest(&addr, Transport::UDP, "test", "test"));
+    ///
+    /// {
+    ///     let lock = sessions.get_session(&addr);
+    ///     let session = lock.get_ref().unwrap();
+    ///     assert!(session.created <= sessions.now());
+    ///     assert_eq!(session.activity.last_request(), session.created);
+    ///     assert_eq!(session.activity.last_data(), None);
+    /// }
+    ///
+    /// sessions.data_relayed(&addr);
+    ///
+    /// let lock = sessions.get_session(&addr);
+    /// let session = lock.get_ref().unwrap();
+    /// let last_data = session.activity.last_data().unwrap();
+    /// assert!(last_data >= session.created && last_data <= sessions.now());
+    /// ```
+    pub fn data_relayed(&self, addr: &SessionAddr) {
+        if let Some(session) = self.state.sessions.read().get(addr) {
+            session
+                .activity
+                .data
+                .store(self.timer.get() + 1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn allocated(&self) -> usize {
+        self.state.port_allocate_pool.lock().len()
+    }
+
+    /// The configured port ranges, each with the number of its ports that
+    /// are allocated, see [`SessionsOptions::port_ranges`].
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use mycrl_turn::{sessions::PortRange, *};
+    /// use stun::attribute::Transport;
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {
+    ///     async fn get_password(
+    ///         &self,
+    ///         addr: &SessionAddr,
+    ///         username: &str,
+    ///     ) -> Option<String> {
+    ///         Some("test".to_string())
+    ///     }
+    /// }
+    ///
+    /// let addr = SessionAddr {
+    ///     address: "127.0.0.1:8080".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let sessions = Sessions::with_options(
+    ///     ObserverTest,
+    ///     SessionsOptions {
+    ///         port_ranges: vec![
+    ///             PortRange {
+    ///                 interface: None,
+    ///                 ipv6: Some(true),
+    ///                 ports: 60000..60010,
+    ///             },
+    ///             PortRange {
+    ///                 interface: Some(addr.interface),
+    ///                 ipv6: None,
+    ///                 ports: 50000..50010,
+    ///             },
+    ///         ],
+    ///         ..Default::default()
+    ///     },
+    /// );
+    ///
+    /// pollster::block_on(sessions.get_digest(&addr, Transport::UDP, "test", "test"));
+    ///
+    /// let port = sessions.allocate(&addr).unwrap();
+    /// assert!((50000..50010).contains(&port.get()));
+    ///
+    /// let port = sessions.allocate_additional(&addr, "::1".parse().unwrap()).unwrap();
+    /// assert!((60000..60010).contains(&port.get()));
+    ///
+    /// let ranges = sessions.get_port_ranges();
+    /// assert_eq!(ranges[0].1, 1);
+    /// assert_eq!(ranges[1].1, 1);
+    /// ```
+    pub fn get_port_ranges(&self) -> Vec<(&PortRange, usize)> {
+        let port_allocate_pool = self.state.port_allocate_pool.lock();
+        self.options
+            .port_ranges
+            .iter()
+            .map(|it| (it, port_allocate_pool.count_in(it.ports.clone())))
+            .collect()
+    }
+
+    // The ports of the first port range that an allocation of the session on the
+    // relayed ip address matches.
+    fn get_port_range(&self, addr: &SessionAddr, ip: &IpAddr) -> Option<Range<u16>> {
+        self.options
+            .port_ranges
+            .iter()
+            .find(|it| it.matches(addr, ip))
+            .map(|it| it.ports.clone())
+    }
+
+    // Assign a port to an allocation of the session on the relayed ip address,
+    // from the first port range that it matches.
+    fn alloc_port(&self, addr: &SessionAddr, ip: &IpAddr) -> Option<RelayPort> {
+        let range = self.get_port_range(addr, ip);
+        let mut port_allocate_pool = self.state.port_allocate_pool.lock();
+        let port = match range {
+            Some(it) => port_allocate_pool.alloc_in(it),
+            None => port_allocate_pool.alloc(None),
+        };
+
+        RelayPort::new(port?)
+    }
+
+    /// An estimate of the memory used by the session tables, in bytes.
+    ///
+    /// The tables are counted by their capacity, which is reserved up front
+    /// for all relay ports, and the sessions also by the strings and lists
+    /// that they own. The tables are locked one after another, so this
+    /// should not be called on the hot path.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use mycrl_turn::*;
+    /// use stun::attribute::Transport;
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {
+    ///     async fn get_password(
+    ///         &self,
+    ///         addr: &SessionAddr,
+    ///         username: &str,
+    ///     ) -> Option<String> {
+    ///         Some("test".to_string())
+    ///     }
+    /// }
+    ///
+    /// let addr = SessionAddr {
+    ///     address: "127.0.0.1:8080".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let sessions = Sessions::new(ObserverTest);
+    /// let empty = sessions.memory_usage();
+    /// assert!(empty > 0);
+    ///
+    /// pollster::block_on(sessions.get_digest(&addr, Transport::UDP, "test", "test"));
+    /// assert!(sessions.memory_usage() > empty);
+    /// ```
+    pub fn memory_usage(&self) -> usize {
+        fn table<K, V>(it: &HashMap<K, V>) -> usize {
+            // One control byte per entry of the hash table.
+            it.capacity() * (size_of::<K>() + size_of::<V>() + 1)
+        }
+
+        fn set<K>(it: &HashSet<K>) -> usize {
+            it.capacity() * (size_of::<K>() + 1)
+        }
+
+        let state = &self.state;
+        let mut usage = state.port_allocate_pool.lock().buckets.capacity() * size_of::<u64>();
+
+        {
+            let sessions = state.sessions.read();
+            usage += table(&sessions);
+
+            for session in sessions.values() {
+                usage += session.auth.username.capacity()
+                    + session.auth.realm.capacity()
+                    + session.auth.password.capacity()
+                    + session
+                        .auth
+                        .origin
+                        .as_ref()
+                        .map(String::capacity)
+                        .unwrap_or(0)
+                    + session.permissions.capacity() * size_of::<PeerPort>()
+                    + session.allocate.channels.capacity() * size_of::<ChannelNumber>();
+            }
+        }
+
+        {
+            let nonces = state.address_nonce_tanle.read();
+            usage += table(&nonces);
+            usage += nonces
+                .values()
+                .map(|(it, _, _)| it.capacity())
+                .sum::<usize>();
+
+            let nonce_table = state.nonce_table.read();
+            usage += table(&nonce_table);
+            usage += nonce_table.keys().map(String::capacity).sum::<usize>();
+            usage += table(&state.transfer_table.read());
+        }
+
+        {
+            let table_ = state.port_relay_table.read();
+            usage += table(&table_) + table_.values().map(table).sum::<usize>();
+        }
+
+        {
+            let table_ = state.channel_relay_table.read();
+            usage += table(&table_) + table_.values().map(table).sum::<usize>();
+        }
+
+        {
+            let table_ = state.outbound_table.read();
+            usage += table(&table_) + table_.values().map(set).sum::<usize>();
+        }
+
+        {
+            let quota_table = state.quota_table.read();
+            usage += table(&quota_table);
+            usage += quota_table
+                .keys()
+                .map(|(realm, username)| realm.capacity() + username.capacity())
+                .sum::<usize>();
+        }
+
+        usage += table(&state.port_mapping_table.read());
+        usage += table(&state.connections.read());
+        usage += set(&state.lingering.read());
+        usage
+    }
+
+    /// Take a snapshot of the state, to attach it to a bug report.
+    ///
+    /// The tables are read one after another, a dump of a server that is
+    /// busy may catch a request half way. See [`StateDump`].
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use mycrl_turn::*;
+    /// use stun::attribute::Transport;
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {
+    ///     fn get_password_blocking(&self, _: &SessionAddr, _: &str) -> Option<String> {
+    ///         Some("test".to_string())
+    ///     }
+    /// }
+    ///
+    /// let addr = SessionAddr {
+    ///     address: "127.0.0.1:8080".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let sessions = Sessions::new(ObserverTest);
+    /// pollster::block_on(sessions.get_digest(&addr, Transport::UDP, "test", "test"));
+    /// let port = sessions.allocate(&addr).unwrap();
+    ///
+    /// let dump = sessions.dump();
+    /// assert_eq!(dump.sessions.len(), 1);
+    /// assert_eq!(dump.sessions[0].username, "test");
+    /// assert_eq!(dump.sessions[0].port, Some(port));
+    /// ```
+    pub fn dump(&self) -> StateDump {
+        fn key(addr: &SessionAddr) -> (SocketAddr, SocketAddr) {
+            (addr.interface, addr.address)
+        }
+
+        let mut sessions = self
+            .state
+            .sessions
+            .read()
+            .iter()
+            .map(|(addr, it)| SessionDump {
+                addr: *addr,
+                transport: it.transport,
+                username: it.auth.username.clone(),
+                realm: it.auth.realm.clone(),
+                digest: it.auth.digest,
+                digest_sha256: it.auth.digest_sha256,
+                origin: it.auth.origin.clone(),
+                credential_expires: it.auth.expires,
+                port: it.allocate.port,
+                ip: it.allocate.ip,
+                relay_transport: it.allocate.transport,
+                additional_port: it.allocate.additional_port,
+                additional_ip: it.allocate.additional_ip,
+                channels: it.allocate.channels.clone(),
+                permissions: it.permissions.clone(),
+                expires: it.expires,
+                created: it.created,
+                last_request: it.activity.last_request(),
+                last_data: it.activity.last_data(),
+            })
+            .collect::<Vec<_>>();
+
+        sessions.sort_by_key(|it| key(&it.addr));
+
+        let mut nonces = self
+            .state
+            .address_nonce_tanle
+            .read()
+            .iter()
+            .map(|(addr, (nonce, expires, issued))| NonceDump {
+                addr: *addr,
+                nonce: nonce.clone(),
+                expires: *expires,
+                issued: *issued,
+            })
+            .collect::<Vec<_>>();
+
+        nonces.sort_by_key(|it| key(&it.addr));
+
+        let mut relays = self
+            .state
+            .port_relay_table
+            .read()
+            .iter()
+            .flat_map(|(addr, it)| {
+                it.iter().map(|(port, endpoint)| RelayDump {
+                    addr: *addr,
+                    port: *port,
+                    endpoint: *endpoint,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        relays.sort_by_key(|it| (key(&it.addr), it.port));
+
+        let mut channels = self
+            .state
+            .channel_relay_table
+            .read()
+            .iter()
+            .flat_map(|(addr, it)| {
+                it.iter().map(|(channel, endpoint)| ChannelDump {
+                    addr: *addr,
+                    channel: *channel,
+                    endpoint: *endpoint,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        channels.sort_by_key(|it| (key(&it.addr), it.channel));
+
+        let mut outbound = self
+            .state
+            .outbound_table
+            .read()
+            .iter()
+            .map(|(port, peers)| {
+                let mut peers = peers.iter().copied().collect::<Vec<_>>();
+                peers.sort();
+
+                OutboundDump { port: *port, peers }
+            })
+            .collect::<Vec<_>>();
+
+        outbound.sort_by_key(|it| it.port);
+
+        let mut lingering = self
+            .state
+            .lingering
+            .read()
+            .iter()
+            .copied()
+            .collect::<Vec<_>>();
+
+        lingering.sort_by_key(key);
+
+        StateDump {
+            timer: self.timer.get(),
+            sessions,
+            nonces,
+            relays,
+            channels,
+            outbound,
+            lingering,
+        }
+    }
+
+    /// Load a snapshot that was taken with [`Sessions::dump`], to replay the
+    /// requests of a bug report against the same state.
+    ///
+    /// The dump is only loaded into a session manager without sessions, the
+    /// observer is not notified of the loaded sessions. The loaded sessions
+    /// have no password, their digests authenticate the requests. Lingering
+    /// sessions only linger if [`SessionsOptions::linger`] is set, they run
+    /// out a full linger time after the load.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use mycrl_turn::{dump::LoadError, *};
+    /// use stun::attribute::Transport;
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {
+    ///     fn get_password_blocking(&self, _: &SessionAddr, _: &str) -> Option<String> {
+    ///         Some("test".to_string())
+    ///     }
+    /// }
+    ///
+    /// let addr = SessionAddr {
+    ///     address: "127.0.0.1:8080".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let sessions = Sessions::new(ObserverTest);
+    /// let digest = pollster::block_on(sessions.get_digest(&addr, Transport::UDP, "test", "test"));
+    /// let port = sessions.allocate(&addr).unwrap();
+    /// let dump = sessions.dump();
+    ///
+    /// assert_eq!(sessions.load(dump.clone()), Err(LoadError::NotEmpty));
+    ///
+    /// let replay = Sessions::new(ObserverTest);
+    /// replay.load(dump.clone()).unwrap();
+    ///
+    /// assert_eq!(replay.dump().sessions, dump.sessions);
+    /// assert_eq!(replay.allocated(), 1);
+    /// assert_eq!(replay.get_session(&addr).get_ref().unwrap().allocate.port, Some(port));
+    /// assert_eq!(
+    ///     pollster::block_on(replay.get_digest(&addr, Transport::UDP, "test", "test")),
+    ///     digest
+    /// );
+    /// ```
+    pub fn load(&self, dump: StateDump) -> Result<(), LoadError> {
+        {
+            let mut sessions = self.state.sessions.write();
+            let mut port_allocate_pool = self.state.port_allocate_pool.lock();
+            let mut port_mapping_table = self.state.port_mapping_table.write();
+            let mut port_relay_table = self.state.port_relay_table.write();
+            let mut channel_relay_table = self.state.channel_relay_table.write();
+            let mut outbound_table = self.state.outbound_table.write();
+            let mut lingering = self.state.lingering.write();
+
+            if !sessions.is_empty() {
+                return Err(LoadError::NotEmpty);
+            }
+
+            // Take the ports first, so that nothing is left behind if a port is
+            // allocated twice.
+            let mut taken = Vec::with_capacity(dump.sessions.len());
+            for port in dump
+                .sessions
+                .iter()
+                .flat_map(|it| it.port.into_iter().chain(it.additional_port))
+            {
+                if !port_allocate_pool.take(port.get()) {
+                    taken
+                        .into_iter()
+                        .for_each(|it| port_allocate_pool.restore(it));
+                    return Err(LoadError::PortInUse(port));
+                }
+
+                taken.push(port.get());
+            }
+
+            let mut quota_table = self.state.quota_table.write();
+            for it in dump.sessions {
+                for port in it.port.into_iter().chain(it.additional_port) {
+                    port_mapping_table.insert(port, it.addr);
+                }
+
+                if it.port.is_some() {
+                    *quota_table
+                        .entry((it.realm.clone(), it.username.clone()))
+                        .or_default() += 1;
+                }
+
+                sessions.insert(
+                    it.addr,
+                    Session {
+                        transport: it.transport,
+                        permissions: it.permissions,
+                        expires: it.expires,
+                        warned: false,
+                        created: it.created,
+                        activity: Activity {
+                            request: AtomicU64::new(it.last_request),
+                            data: AtomicU64::new(it.last_data.map(|it| it + 1).unwrap_or(0)),
+                        },
+                        auth: Auth {
+                            username: it.username,
+                            realm: it.realm,
+                            password: String::new(),
+                            digest: it.digest,
+                            digest_sha256: it.digest_sha256,
+                            origin: it.origin,
+                            expires: it.credential_expires,
+                        },
+                        allocate: Allocate {
+                            port: it.port,
+                            ip: it.ip,
+                            transport: it.relay_transport,
+                            additional_port: it.additional_port,
+                            additional_ip: it.additional_ip,
+                            channels: it.channels,
+                            ticket: None,
+                        },
+                    },
+                );
+            }
+
+            for it in dump.relays {
+                port_relay_table
+                    .entry(it.addr)
+                    .or_default()
+                    .insert(it.port, it.endpoint);
+            }
+
+            for it in dump.channels {
+                channel_relay_table
+                    .entry(it.addr)
+                    .or_default()
+                    .insert(it.channel, it.endpoint);
+            }
+
+            for it in dump.outbound {
+                outbound_table.insert(it.port, it.peers.into_iter().collect());
+            }
+
+            // The lifetimes of the permissions and channels are not dumped, the loaded
+            // ones start a new lifetime.
+            let mut lifetime_table = self.state.lifetime_table.write();
+            if let Some(lifetime) = self.options.permission_lifetime {
+                for (addr, session) in sessions.iter() {
+                    if !session.permissions.is_empty() {
+                        lifetime_table.entry(*addr).or_default().permissions.extend(
+                            session
+                                .permissions
+                                .iter()
+                                .map(|it| (*it, dump.timer + lifetime as u64)),
+                        );
+                    }
+                }
+            }
+
+            if let Some(lifetime) = self.options.channel_lifetime {
+                for (peer, relays) in channel_relay_table.iter() {
+                    let port = match sessions.get(peer).and_then(|it| it.allocate.port) {
+                        Some(it) => PeerPort::from(it),
+                        None => continue,
+                    };
+
+                    for (channel, endpoint) in relays {
+                        if let Some(addr) = port_mapping_table.get(&endpoint.port) {
+                            lifetime_table
+                                .entry(*addr)
+                                .or_default()
+                                .channels
+                                .insert(*channel, (port, dump.timer + lifetime as u64));
+                        }
+                    }
+                }
+            }
+
+            if let (Some(linger), Some(sender)) = (self.options.linger, &self.linger) {
+                for addr in dump.lingering {
+                    if lingering.insert(addr) {
+                        let _ = sender.send((addr, Instant::now() + linger, CloseReason::Client));
+                    }
+                }
+            }
+        }
+
+        {
+            let mut address_nonce_tanle = self.state.address_nonce_tanle.write();
+            let mut nonce_table = self.state.nonce_table.write();
+            for it in dump.nonces {
+                if let Some((nonce, _, _)) =
+                    address_nonce_tanle.insert(it.addr, (it.nonce.clone(), it.expires, it.issued))
+                {
+                    nonce_table.remove(&nonce);
+                }
+
+                nonce_table.insert(it.nonce, it.addr);
+            }
+        }
+
+        self.timer.set(dump.timer);
+        Ok(())
+    }
+
+    /// Get all sessions.
+    ///
+    /// This holds the read lock of the session table, so the guard should be
+    /// dropped as soon as possible.
+    pub fn get_all_sessions(&self) -> RwLockReadGuard<'_, Table<SessionAddr, Session>> {
+        self.state.sessions.read()
+    }
+
+    /// Assign a port number to the session.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use mycrl_turn::*;
+    /// use stun::attribute::Transport;
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {
+    ///     async fn get_password(
+    ///         &self,
+    ///         addr: &SessionAddr,
+    ///         username: &str,
+    ///     ) -> Option<String> {
+    ///         if username == "test" {
+    ///             Some("test".to_string())
+    ///         } else {
+    ///             None
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// let addr = SessionAddr {
+    ///     address: "127.0.0.1:8080".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let digest = [
+    ///     174, 238, 187, 253, 117, 209, 73, 157, 36, 56, 143, 91, 155, 16, 224,
+    ///     239,
+    /// ];
+    ///
+    /// let sessions = Sessions::new(ObserverTest);
+    ///
+    /// pollster::block_on(sessions.get_digest(&addr, Transport::UDP, "test", "test"));
+    ///
+    /// {
+    ///     let lock = sessions.get_session(&addr);
+    ///     let session = lock.get_ref().unwrap();
+    ///     assert_eq!(session.auth.username, "test");
+    ///     assert_eq!(session.auth.password, "test");
+    ///     assert_eq!(session.allocate.port, None);
+    ///     assert_eq!(session.allocate.channels.len(), 0);
+    /// }
+    ///
+    /// let port = sessions.allocate(&addr).unwrap();
+    /// {
+    ///     let lock = sessions.get_session(&addr);
+    ///     let session = lock.get_ref().unwrap();
+    ///     assert_eq!(session.auth.username, "test");
+    ///     assert_eq!(session.auth.password, "test");
+    ///     assert_eq!(session.allocate.port, Some(port));
+    ///     assert_eq!(session.allocate.channels.len(), 0);
+    /// }
+    ///
+    /// assert!(sessions.allocate(&addr).is_none());
+    /// ```
+    pub fn allocate(&self, addr: &SessionAddr) -> Option<RelayPort> {
+        self.allocate_with_ip(addr, addr.interface.ip())
+    }
+
+    /// Assign a port number to the session, and advertise the relayed
+    /// transport address with the given ip address.
+    ///
+    /// This is used by hosts with multiple external ip addresses on one
+    /// interface.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use mycrl_turn::*;
+    /// use stun::attribute::Transport;
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {
+    ///     async fn get_password(
+    ///         &self,
+    ///         addr: &SessionAddr,
+    ///         username: &str,
+    ///     ) -> Option<String> {
+    ///         Some("test".to_string())
+    ///     }
+    /// }
+    ///
+    /// let addr = SessionAddr {
+    ///     address: "127.0.0.1:8080".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let ip = "127.0.0.2".parse().unwrap();
+    /// let sessions = Sessions::new(ObserverTest);
+    ///
+    /// pollster::block_on(sessions.get_digest(&addr, Transport::UDP, "test", "test"));
+    ///
+    /// let port = sessions.allocate_with_ip(&addr, ip).unwrap();
+    /// let lock = sessions.get_session(&addr);
+    /// let session = lock.get_ref().unwrap();
+    /// assert_eq!(session.allocate.port, Some(port));
+    /// assert_eq!(session.allocate.ip, Some(ip));
+    /// ```
+    pub fn allocate_with_ip(&self, addr: &SessionAddr, ip: IpAddr) -> Option<RelayPort> {
+        self.allocate_with_transport(addr, ip, Transport::UDP)
+    }
+
+    /// Assign a port number to the session, with the transport protocol of
+    /// the relayed transport address.
+    ///
+    /// The TCP allocations of [RFC6062](https://tools.ietf.org/html/rfc6062)
+    /// relay the connections that they open to each other, see
+    /// [`Sessions::connect`].
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use mycrl_turn::*;
+    /// use stun::attribute::Transport;
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {
+    ///     async fn get_password(
+    ///         &self,
+    ///         addr: &SessionAddr,
+    ///         username: &str,
+    ///     ) -> Option<String> {
+    ///         Some("test".to_string())
+    ///     }
+    /// }
+    ///
+    /// let addr = SessionAddr {
+    ///     address: "127.0.0.1:8080".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let ip = "127.0.0.1".parse().unwrap();
+    /// let sessions = Sessions::new(ObserverTest);
+    ///
+    /// pollster::block_on(sessions.get_digest(&addr, Transport::TCP, "test", "test"));
+    ///
+    /// let port = sessions.allocate_with_transport(&addr, ip, Transport::TCP).unwrap();
+    /// let lock = sessions.get_session(&addr);
+    /// let session = lock.get_ref().unwrap();
+    /// assert_eq!(session.allocate.port, Some(port));
+    /// assert_eq!(session.allocate.transport, Transport::TCP);
+    /// ```
+    pub fn allocate_with_transport(
+        &self,
+        addr: &SessionAddr,
+        ip: IpAddr,
+        transport: Transport,
+    ) -> Option<RelayPort> {
+        self.set_allocation(addr, transport, || Some((self.alloc_port(addr, &ip)?, ip)))
+    }
+
+    /// Assign an even port number to the session, like
+    /// [`Sessions::allocate_with_transport`], for the clients that ask for
+    /// one with the EVEN-PORT attribute. With `reserve` the next-higher port
+    /// is held in reserve for [`RESERVATION_TIMEOUT`] seconds as well, and
+    /// the token that claims it with [`Sessions::allocate_reserved`] is
+    /// returned.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use mycrl_turn::*;
+    /// use stun::attribute::Transport;
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {
+    ///     async fn get_password(
+    ///         &self,
+    ///         addr: &SessionAddr,
+    ///         username: &str,
+    ///     ) -> Option<String> {
+    ///         Some("test".to_string())
+    ///     }
+    /// }
+    ///
+    /// let addr = SessionAddr {
+    ///     address: "127.0.0.1:8080".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let reserved_addr = SessionAddr {
+    ///     address: "127.0.0.1:8081".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let ip = "127.0.0.1".parse().unwrap();
+    /// let sessions = Sessions::new(ObserverTest);
+    ///
+    /// pollster::block_on(sessions.get_digest(&addr, Transport::UDP, "test", "test"));
+    /// pollster::block_on(sessions.get_digest(&reserved_addr, Transport::UDP, "test", "test"));
+    ///
+    /// let (port, token) = sessions
+    ///     .allocate_even(&addr, ip, Transport::UDP, true)
+    ///     .unwrap();
+    ///
+    /// assert_eq!(port.get() % 2, 0);
+    /// assert_eq!(sessions.allocated(), 2);
+    ///
+    /// let token = token.unwrap();
+    /// let (reserved_port, reserved_ip) = sessions
+    ///     .allocate_reserved(&reserved_addr, token, Transport::UDP)
+    ///     .unwrap();
+    ///
+    /// assert_eq!(reserved_port.get(), port.get() + 1);
+    /// assert_eq!(reserved_ip, ip);
+    /// assert_eq!(sessions.allocated(), 2);
+    ///
+    /// // A token claims its port once.
+    /// assert!(sessions.allocate_reserved(&addr, token, Transport::UDP).is_none());
+    /// ```
+    pub fn allocate_even(
+        &self,
+        addr: &SessionAddr,
+        ip: IpAddr,
+        transport: Transport,
+        reserve: bool,
+    ) -> Option<(RelayPort, Option<u64>)> {
+        let mut reserved = None;
+        let port = self.set_allocation(addr, transport, || {
+            let range = self
+                .get_port_range(addr, &ip)
+                .unwrap_or_else(PortAllocatePools::port_range);
+
+            let mut pool = self.state.port_allocate_pool.lock();
+            let port = pool.alloc_even(range, reserve)?;
+
+            // Both ports go back to the pool if either of them is not a relay port.
+            match (
+                RelayPort::new(port),
+                reserve.then(|| RelayPort::new(port + 1)),
+            ) {
+                (Some(it), None) => Some((it, ip)),
+                (Some(it), Some(Some(next))) => {
+                    reserved = Some(next);
+                    Some((it, ip))
+                }
+                _ => {
+                    pool.restore(port);
+                    if reserve {
+                        pool.restore(port + 1);
+                    }
+
+                    None
+                }
+            }
+        })?;
+
+        let reserved = match reserved {
+            Some(it) => it,
+            None => return Some((port, None)),
+        };
+
+        let token = thread_rng().gen::<u64>();
+        self.state.reservations.lock().insert(
+            token,
+            Reservation {
+                port: reserved,
+                expires: self.timer.get() + RESERVATION_TIMEOUT,
+                ip,
+            },
+        );
+
+        Some((port, Some(token)))
+    }
+
+    /// Assign the port held in reserve with the token to the session, see
+    /// [`Sessions::allocate_even`]. The relayed transport address is the one
+    /// of the reservation, which is returned with the port. Returns `None` if
+    /// the token is unknown or its reservation has run out.
+    pub fn allocate_reserved(
+        &self,
+        addr: &SessionAddr,
+        token: u64,
+        transport: Transport,
+    ) -> Option<(RelayPort, IpAddr)> {
+        let mut ip = None;
+        let port = self.set_allocation(addr, transport, || {
+            let reservation = self.state.reservations.lock().remove(&token)?;
+            ip = Some(reservation.ip);
+
+            Some((reservation.port, reservation.ip))
+        })?;
+
+        Some((port, ip?))
+    }
+
+    // Records the port that the closure assigns to the session, the closure is only
+    // called if the session has no allocation yet.
+    fn set_allocation(
+        &self,
+        addr: &SessionAddr,
+        transport: Transport,
+        alloc: impl FnOnce() -> Option<(RelayPort, IpAddr)>,
+    ) -> Option<RelayPort> {
+        let mut lock = self.state.sessions.write();
+        let session = lock.get_mut(addr)?;
+
+        // If the port has already been allocated, re-allocation is not allowed.
+        if session.allocate.port.is_some() || self.is_quota_reached_for(&session.auth) {
+            return None;
+        }
+
+        // Records the port assigned to the current session and resets the alive time.
+        let (port, ip) = alloc()?;
+        *self
+            .state
+            .quota_table
+            .write()
+            .entry((session.auth.realm.clone(), session.auth.username.clone()))
+            .or_default() += 1;
+
+        session.expires = self.timer.get() + self.default_lifetime(&session.auth.realm) as u64;
+        session.allocate.port = Some(port);
+        session.allocate.ip = Some(ip);
+        session.allocate.transport = transport;
+
+        // Write the allocation port binding table.
+        self.state.port_mapping_table.write().insert(port, *addr);
+        Some(port)
+    }
+
+    /// Assign a second port number to the session that has an allocation,
+    /// which makes it a dual allocation. The relayed transport address of the
+    /// other address family is advertised with the given ip address.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use mycrl_turn::*;
+    /// use stun::attribute::Transport;
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {
+    ///     async fn get_password(
+    ///         &self,
+    ///         addr: &SessionAddr,
+    ///         username: &str,
+    ///     ) -> Option<String> {
+    ///         Some("test".to_string())
+    ///     }
+    /// }
+    ///
+    /// let endpoint = "127.0.0.1:3478".parse().unwrap();
+    /// let addr = SessionAddr {
+    ///     address: "127.0.0.1:8080".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let peer_addr = SessionAddr {
+    ///     address: "127.0.0.1:8081".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let ip = "::1".parse().unwrap();
+    /// let sessions = Sessions::new(ObserverTest);
+    ///
+    /// pollster::block_on(sessions.get_digest(&addr, Transport::UDP, "test", "test"));
+    /// pollster::block_on(sessions.get_digest(&peer_addr, Transport::UDP, "test", "test"));
+    ///
+    /// assert!(sessions.allocate_additional(&addr, ip).is_none());
+    ///
+    /// let port = sessions.allocate(&addr).unwrap();
+    /// let additional_port = sessions.allocate_additional(&addr, ip).unwrap();
+    /// assert_ne!(port, additional_port);
+    /// assert!(sessions.allocate_additional(&addr, ip).is_none());
+    /// {
+    ///     let lock = sessions.get_session(&addr);
+    ///     let session = lock.get_ref().unwrap();
+    ///     assert_eq!(session.allocate.additional_port, Some(additional_port));
+    ///     assert_eq!(session.allocate.additional_ip, Some(ip));
+    /// }
+    ///
+    /// // The peer reaches the session on both relayed transport addresses.
+    /// let peer_port = sessions.allocate(&peer_addr).unwrap();
+    /// assert!(sessions.create_permission(&addr, &endpoint, &[peer_port.into()]));
+    /// assert!(sessions.get_relay_address(&peer_addr, port.into()).is_some());
+    /// assert!(sessions.get_relay_address(&peer_addr, additional_port.into()).is_some());
+    /// ```
+    pub fn allocate_additional(&self, addr: &SessionAddr, ip: IpAddr) -> Option<RelayPort> {
+        let mut lock = self.state.sessions.write();
+        let session = lock.get_mut(addr)?;
+
+        if session.allocate.port.is_none() || session.allocate.additional_port.is_some() {
+            return None;
+        }
+
+        let port = self.alloc_port(addr, &ip)?;
+        session.allocate.additional_port = Some(port);
+        session.allocate.additional_ip = Some(ip);
+
+        self.state.port_mapping_table.write().insert(port, *addr);
+        Some(port)
+    }
+
+    /// Find the session that a client which moved to `addr` can re-claim.
+    ///
+    /// A client that reconnects over another interface or transport presents
+    /// the nonce of its previous session, which identifies that session. It
+    /// is only returned if it belongs to the same username and realm and has
+    /// an allocation.
+    ///
+    /// The nonce proves nothing about `addr`, a request with it can be
+    /// replayed from any address, so the session is only transferred once the
+    /// client has also authenticated with the nonce of `addr`, see
+    /// [`Sessions::prepare_transfer`].
+    pub fn find_transferable(
+        &self,
+        addr: &SessionAddr,
+        username: &str,
+        realm: &str,
+        nonce: &str,
+    ) -> Option<SessionAddr> {
+        let from = *self.state.nonce_table.read().get(nonce)?;
+        if from == *addr {
+            return None;
+        }
+
+        let sessions = self.state.sessions.read();
+        let session = sessions.get(&from)?;
+        if session.auth.username == username
+            && session.auth.realm == realm
+            && session.allocate.port.is_some()
+        {
+            Some(from)
+        } else {
+            None
+        }
+    }
+
+    /// Record that the client at `to` has proven to own the session at `from`,
+    /// see [`Sessions::find_transferable`]. The client is challenged with the
+    /// nonce of `to`, and the session is handed over by
+    /// [`Sessions::take_transfer`] once the client authenticates with it. The
+    /// record is dropped along with the nonce of `to`.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use mycrl_turn::*;
+    /// use stun::attribute::Transport;
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {
+    ///     fn get_password_blocking(&self, _: &SessionAddr, _: &str) -> Option<String> {
+    ///         Some("test".to_string())
+    ///     }
+    /// }
+    ///
+    /// let addr = SessionAddr {
+    ///     address: "127.0.0.1:8080".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let moved_addr = SessionAddr {
+    ///     address: "127.0.0.1:9090".parse().unwrap(),
+    ///     interface: "127.0.0.1:443".parse().unwrap(),
+    /// };
+    ///
+    /// let sessions = Sessions::new(ObserverTest);
+    ///
+    /// pollster::block_on(sessions.get_digest(&addr, Transport::UDP, "test", "test"));
+    /// sessions.allocate(&addr).unwrap();
+    ///
+    /// let nonce = sessions.get_nonce(&addr).get_ref().unwrap().0.clone();
+    /// assert_eq!(
+    ///     sessions.find_transferable(&moved_addr, "test", "test", &nonce),
+    ///     Some(addr)
+    /// );
+    ///
+    /// assert_eq!(sessions.find_transferable(&moved_addr, "other", "test", &nonce), None);
+    /// assert_eq!(sessions.find_transferable(&moved_addr, "test", "other", &nonce), None);
+    ///
+    /// // Nothing is handed over before the client has proven to own the session.
+    /// assert_eq!(sessions.take_transfer(&moved_addr, "test", "test"), None);
+    ///
+    /// sessions.prepare_transfer(&moved_addr, &addr);
+    /// assert_eq!(sessions.take_transfer(&moved_addr, "other", "test"), None);
+    ///
+    /// sessions.prepare_transfer(&moved_addr, &addr);
+    /// assert_eq!(sessions.take_transfer(&moved_addr, "test", "test"), Some(addr));
+    /// assert_eq!(sessions.take_transfer(&moved_addr, "test", "test"), None);
+    /// ```
+    pub fn prepare_transfer(&self, to: &SessionAddr, from: &SessionAddr) {
+        self.state.transfer_table.write().insert(*to, *from);
+    }
+
+    /// Take the session that the client at `to` has proven to own, see
+    /// [`Sessions::prepare_transfer`]. The client has authenticated at `to`
+    /// with the username in the realm, which the session has to belong to.
+    pub fn take_transfer(
+        &self,
+        to: &SessionAddr,
+        username: &str,
+        realm: &str,
+    ) -> Option<SessionAddr> {
+        let from = self.state.transfer_table.write().remove(to)?;
+
+        let sessions = self.state.sessions.read();
+        let session = sessions.get(&from)?;
+        if session.auth.username == username
+            && session.auth.realm == realm
+            && session.allocate.port.is_some()
+        {
+            Some(from)
+        } else {
+            None
+        }
+    }
+
+    /// Transfer the session and its allocation to another address.
+    ///
+    /// The allocated port, permissions, channels and mobility ticket are
+    /// kept, and the peers forward to the new address from now on. The new
+    /// address keeps its own nonce, which the client authenticated the
+    /// transfer with, and the nonce of the old address is forgotten. Fails
+    /// if the new address already has an allocation.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use mycrl_turn::*;
+    /// use stun::attribute::Transport;
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {
+    ///     async fn get_password(
+    ///         &self,
+    ///         addr: &SessionAddr,
+    ///         username: &str,
+    ///     ) -> Option<String> {
+    ///         Some("test".to_string())
+    ///     }
+    /// }
+    ///
+    /// let endpoint = "127.0.0.1:3478".parse().unwrap();
+    /// let addr = SessionAddr {
+    ///     address: "127.0.0.1:8080".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let peer_addr = SessionAddr {
+    ///     address: "127.0.0.1:8081".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let moved_addr = SessionAddr {
+    ///     address: "127.0.0.1:9090".parse().unwrap(),
+    ///     interface: "127.0.0.1:443".parse().unwrap(),
+    /// };
+    ///
+    /// let sessions = Sessions::new(ObserverTest);
+    ///
+    /// pollster::block_on(sessions.get_digest(&addr, Transport::UDP, "test", "test"));
+    /// pollster::block_on(sessions.get_digest(&peer_addr, Transport::UDP, "test", "test"));
+    ///
+    /// let port = sessions.allocate(&addr).unwrap();
+    /// let peer_port = sessions.allocate(&peer_addr).unwrap();
+    ///
+    /// assert!(sessions.create_permission(&addr, &endpoint, &[peer_port.into()]));
+    /// assert!(sessions.create_permission(&peer_addr, &endpoint, &[port.into()]));
+    ///
+    /// let nonce = sessions.get_nonce(&addr).get_ref().unwrap().0.clone();
+    /// assert_eq!(
+    ///     sessions.find_transferable(&moved_addr, "test", "test", &nonce),
+    ///     Some(addr)
+    /// );
+    ///
+    /// let moved_nonce = sessions.get_nonce(&moved_addr).get_ref().unwrap().0.clone();
+    ///
+    /// assert!(sessions.transfer(&addr, &moved_addr, &moved_addr.address, Transport::TCP));
+    /// assert!(sessions.get_session(&addr).get_ref().is_none());
+    ///
+    /// {
+    ///     let lock = sessions.get_session(&moved_addr);
+    ///     let session = lock.get_ref().unwrap();
+    ///     assert_eq!(session.allocate.port, Some(port));
+    ///     assert_eq!(session.transport, Transport::TCP);
+    /// }
+    ///
+    /// let relay = sessions.get_relay_address(&peer_addr, port.into()).unwrap();
+    /// assert_eq!(relay.address, moved_addr.address);
+    /// assert_eq!(relay.endpoint, moved_addr.address);
+    /// assert!(sessions.get_relay_address(&moved_addr, peer_port.into()).is_some());
+    /// assert_eq!(sessions.get_nonce(&moved_addr).get_ref().unwrap().0, moved_nonce);
+    ///
+    /// // The nonce of the new address leads to the session from now on.
+    /// assert_eq!(
+    ///     sessions.find_transferable(&addr, "test", "test", &moved_nonce),
+    ///     Some(moved_addr)
+    /// );
+    ///
+    /// assert_eq!(sessions.find_transferable(&addr, "test", "test", &nonce), None);
+    /// ```
+    pub fn transfer(
+        &self,
+        from: &SessionAddr,
+        to: &SessionAddr,
+        endpoint: &SocketAddr,
+        transport: Transport,
+    ) -> bool {
+        let mut sessions = self.state.sessions.write();
+        let mut port_mapping_table = self.state.port_mapping_table.write();
+        let mut port_relay_table = self.state.port_relay_table.write();
+        let mut channel_relay_table = self.state.channel_relay_table.write();
+        let mut address_nonce_tanle = self.state.address_nonce_tanle.write();
+
+        if sessions
+            .get(to)
+            .map(|it| it.allocate.port.is_some())
+            .unwrap_or(false)
+        {
+            return false;
+        }
+
+        let mut session = if let Some(it) = sessions.remove(from) {
+            it
+        } else {
+            return false;
+        };
+
+        session.transport = transport;
+        let ports = session.allocate.ports().collect::<Vec<_>>();
+        if let Some(ticket) = session.allocate.ticket {
+            self.state.ticket_table.write().insert(ticket, *to);
+        }
+
+        sessions.insert(*to, session);
+
+        for port in &ports {
+            port_mapping_table.insert(*port, *to);
+        }
+
+        // The client already uses the nonce of the new address, replacing it would
+        // only cost it a 438 round trip, the nonce of the old address is forgotten.
+        if let Some((it, _, _)) = address_nonce_tanle.remove(from) {
+            self.state.nonce_table.write().remove(&it);
+        }
+
+        // The session's own forwarding entries move to the new address, and the
+        // entries of other sessions that forward to this session are pointed at the
+        // new address.
+        if let Some(it) = port_relay_table.remove(from) {
+            port_relay_table.insert(*to, it);
+        }
+
+        if let Some(it) = channel_relay_table.remove(from) {
+            channel_relay_table.insert(*to, it);
+        }
+
+        {
+            let mut external_table = self.state.external_table.write();
+            if let Some(it) = external_table.remove(from) {
+                external_table.insert(*to, it);
+            }
+        }
+
+        {
+            let mut lifetime_table = self.state.lifetime_table.write();
+            if let Some(it) = lifetime_table.remove(from) {
+                lifetime_table.insert(*to, it);
+            }
+        }
+
+        if !ports.is_empty() {
+            port_relay_table
+                .values_mut()
+                .flat_map(|it| it.values_mut())
+                .chain(
+                    channel_relay_table
+                        .values_mut()
+                        .flat_map(|it| it.values_mut()),
+                )
+                .filter(|it| ports.contains(&it.port))
+                .for_each(|it| {
+                    it.address = to.address;
+                    it.endpoint = *endpoint;
+                });
+        }
+
+        true
+    }
+
+    /// Issue a new mobility ticket for the allocation of the session, the
+    /// previous ticket of the session is no longer valid. Returns `None` if
+    /// the session has no allocation.
+    ///
+    /// The ticket is a random token that only has a meaning for this server,
+    /// the session is looked up by it with [`Sessions::find_ticket`] and
+    /// moved with [`Sessions::transfer`].
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use mycrl_turn::*;
+    /// use stun::attribute::Transport;
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {
+    ///     async fn get_password(
+    ///         &self,
+    ///         addr: &SessionAddr,
+    ///         username: &str,
+    ///     ) -> Option<String> {
+    ///         Some("test".to_string())
+    ///     }
+    /// }
+    ///
+    /// let addr = SessionAddr {
+    ///     address: "127.0.0.1:8080".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let moved_addr = SessionAddr {
+    ///     address: "127.0.0.1:9090".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let sessions = Sessions::new(ObserverTest);
+    ///
+    /// pollster::block_on(sessions.get_digest(&addr, Transport::UDP, "test", "test"));
+    /// assert!(sessions.issue_ticket(&addr).is_none());
+    ///
+    /// let port = sessions.allocate(&addr).unwrap();
+    /// let ticket = sessions.issue_ticket(&addr).unwrap();
+    /// assert_eq!(sessions.find_ticket(&ticket), Some(addr));
+    /// assert_eq!(sessions.find_ticket(&[0; 4]), None);
+    ///
+    /// // The ticket follows the session to its new address.
+    /// assert!(sessions.transfer(&addr, &moved_addr, &moved_addr.address, Transport::UDP));
+    /// assert_eq!(sessions.find_ticket(&ticket), Some(moved_addr));
+    ///
+    /// // A new ticket replaces the previous one.
+    /// let new_ticket = sessions.issue_ticket(&moved_addr).unwrap();
+    /// assert_eq!(sessions.find_ticket(&ticket), None);
+    /// assert_eq!(sessions.find_ticket(&new_ticket), Some(moved_addr));
+    ///
+    /// sessions.refresh(&moved_addr, 0);
+    /// assert_eq!(sessions.find_ticket(&new_ticket), None);
+    /// ```
+    pub fn issue_ticket(&self, addr: &SessionAddr) -> Option<[u8; 16]> {
+        let mut sessions = self.state.sessions.write();
+        let session = sessions.get_mut(addr)?;
+        session.allocate.port?;
+
+        let ticket = thread_rng().gen::<[u8; 16]>();
+        let mut ticket_table = self.state.ticket_table.write();
+        if let Some(it) = session.allocate.ticket.replace(ticket) {
+            ticket_table.remove(&it);
+        }
+
+        ticket_table.insert(ticket, *addr);
+        Some(ticket)
+    }
+
+    /// Find the session of a mobility ticket, see [`Sessions::issue_ticket`].
+    /// The tickets of the allocations that were deleted and only linger are
+    /// not found.
+    pub fn find_ticket(&self, ticket: &[u8]) -> Option<SessionAddr> {
+        let ticket: [u8; 16] = ticket.try_into().ok()?;
+        let addr = *self.state.ticket_table.read().get(&ticket)?;
+        if self.state.lingering.read().contains(&addr) {
+            return None;
+        }
+
+        Some(addr)
+    }
+
+    /// Check whether the session can create permissions for the ports without
+    /// exceeding the permission limit.
+    ///
+    /// Ports that the session already has a permission for only refresh the
+    /// permission and are not counted. Returns `true` if the session does not
+    /// exist, that case is handled by [`Sessions::create_permission`].
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use mycrl_turn::*;
+    /// use stun::attribute::Transport;
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {
+    ///     async fn get_password(
+    ///         &self,
+    ///         addr: &SessionAddr,
+    ///         username: &str,
+    ///     ) -> Option<String> {
+    ///         if username == "test" {
+    ///             Some("test".to_string())
+    ///         } else {
+    ///             None
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// let endpoint = "127.0.0.1:3478".parse().unwrap();
+    /// let addr = SessionAddr {
+    ///     address: "127.0.0.1:8080".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let peer_addr = SessionAddr {
+    ///     address: "127.0.0.1:8081".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let other_addr = SessionAddr {
+    ///     address: "127.0.0.1:8082".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let sessions = Sessions::with_options(
+    ///     ObserverTest,
+    ///     SessionsOptions {
+    ///         max_permissions: Some(1),
+    ///         ..Default::default()
+    ///     },
+    /// );
+    ///
+    /// pollster::block_on(sessions.get_digest(&addr, Transport::UDP, "test", "test"));
+    /// pollster::block_on(sessions.get_digest(&peer_addr, Transport::UDP, "test", "test"));
+    /// pollster::block_on(sessions.get_digest(&other_addr, Transport::UDP, "test", "test"));
+    ///
+    /// sessions.allocate(&addr).unwrap();
+    /// let peer_port = sessions.allocate(&peer_addr).unwrap();
+    /// let other_port = sessions.allocate(&other_addr).unwrap();
+    ///
+    /// assert!(sessions.check_permission_capacity(&addr, &[peer_port.into()]));
+    /// assert!(sessions.create_permission(&addr, &endpoint, &[peer_port.into()]));
+    ///
+    /// assert!(sessions.check_permission_capacity(&addr, &[peer_port.into()]));
+    /// assert!(!sessions.check_permission_capacity(&addr, &[other_port.into()]));
+    /// assert!(!sessions.create_permission(&addr, &endpoint, &[other_port.into()]));
+    /// ```
+    pub fn check_permission_capacity(&self, addr: &SessionAddr, ports: &[PeerPort]) -> bool {
+        self.state
+            .sessions
+            .read()
+            .get(addr)
+            .map(|it| Self::has_permission_capacity(&self.options, it, ports))
+            .unwrap_or(true)
+    }
+
+    /// Check whether the session can bind the channel without exceeding the
+    /// channel binding limit.
+    ///
+    /// A channel that is already bound is not counted. Returns `true` if the session does not exist, that case is handled by
+    /// [`Sessions::bind_channel`].
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use mycrl_turn::*;
+    /// use stun::attribute::Transport;
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {
+    ///     async fn get_password(
+    ///         &self,
+    ///         addr: &SessionAddr,
+    ///         username: &str,
+    ///     ) -> Option<String> {
+    ///         if username == "test" {
+    ///             Some("test".to_string())
+    ///         } else {
+    ///             None
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// let endpoint = "127.0.0.1:3478".parse().unwrap();
+    /// let addr = SessionAddr {
+    ///     address: "127.0.0.1:8080".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let peer_addr = SessionAddr {
+    ///     address: "127.0.0.1:8081".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let sessions = Sessions::with_options(
+    ///     ObserverTest,
+    ///     SessionsOptions {
+    ///         max_channels: Some(1),
+    ///         ..Default::default()
+    ///     },
+    /// );
+    ///
+    /// pollster::block_on(sessions.get_digest(&addr, Transport::UDP, "test", "test"));
+    /// pollster::block_on(sessions.get_digest(&peer_addr, Transport::UDP, "test", "test"));
+    ///
+    /// sessions.allocate(&addr).unwrap();
+    /// let peer_port = sessions.allocate(&peer_addr).unwrap();
+    /// let channel = ChannelNumber::new(0x4000).unwrap();
+    /// let other_channel = ChannelNumber::new(0x4001).unwrap();
+    ///
+    /// assert!(sessions.check_channel_capacity(&addr, channel));
+    /// assert!(sessions.bind_channel(&addr, &endpoint, peer_port.into(), channel));
+    ///
+    /// assert!(sessions.check_channel_capacity(&addr, channel));
+    /// assert!(!sessions.check_channel_capacity(&addr, other_channel));
+    /// assert!(!sessions.bind_channel(&addr, &endpoint, peer_port.into(), other_channel));
+    /// ```
+    pub fn check_channel_capacity(&self, addr: &SessionAddr, channel: ChannelNumber) -> bool {
+        self.state
+            .sessions
+            .read()
+            .get(addr)
+            .map(|it| Self::has_channel_capacity(&self.options, it, channel))
+            .unwrap_or(true)
+    }
+
+    fn has_permission_capacity(
+        options: &SessionsOptions,
+        session: &Session,
+        ports: &[PeerPort],
+    ) -> bool {
+        let max = if let Some(it) = options.max_permissions {
+            it
+        } else {
+            return true;
+        };
+
+        let mut added = 0;
+        for (i, port) in ports.iter().enumerate() {
+            // Duplicate ports in the request and existing permissions are not
+            // new permissions.
+            if !session.permissions.contains(port) && !ports[..i].contains(port) {
+                added += 1;
+            }
+        }
+
+        session.permissions.len() + added <= max
+    }
+
+    fn has_channel_capacity(
+        options: &SessionsOptions,
+        session: &Session,
+        channel: ChannelNumber,
+    ) -> bool {
+        options
+            .max_channels
+            .map(|max| {
+                session.allocate.channels.contains(&channel)
+                    || session.allocate.channels.len() < max
+            })
+            .unwrap_or(true)
+    }
+
+    /// Create permission for session.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use mycrl_turn::*;
+    /// use stun::attribute::Transport;
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {
+    ///     async fn get_password(
+    ///         &self,
+    ///         addr: &SessionAddr,
+    ///         username: &str,
+    ///     ) -> Option<String> {
+    ///         if username == "test" {
+    ///             Some("test".to_string())
+    ///         } else {
+    ///             None
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// let endpoint = "127.0.0.1:3478".parse().unwrap();
+    /// let addr = SessionAddr {
+    ///     address: "127.0.0.1:8080".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let peer_addr = SessionAddr {
+    ///     address: "127.0.0.1:8081".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let digest = [
+    ///     174, 238, 187, 253, 117, 209, 73, 157, 36, 56, 143, 91, 155, 16, 224,
+    ///     239,
+    /// ];
+    ///
+    /// let sessions = Sessions::new(ObserverTest);
+    ///
+    /// pollster::block_on(sessions.get_digest(&addr, Transport::UDP, "test", "test"));
+    /// pollster::block_on(sessions.get_digest(&peer_addr, Transport::UDP, "test", "test"));
+    ///
+    /// let port = sessions.allocate(&addr).unwrap();
+    /// let peer_port = sessions.allocate(&peer_addr).unwrap();
+    ///
+    /// assert!(!sessions.create_permission(&addr, &endpoint, &[port.into()]));
+    /// assert!(sessions.create_permission(&addr, &endpoint, &[peer_port.into()]));
+    ///
+    /// assert!(!sessions.create_permission(&peer_addr, &endpoint, &[peer_port.into()]));
+    /// assert!(sessions.create_permission(&peer_addr, &endpoint, &[port.into()]));
+    /// ```
+    pub fn create_permission(
+        &self,
+        addr: &SessionAddr,
+        endpoint: &SocketAddr,
+        ports: &[PeerPort],
+    ) -> bool {
+        let mut sessions = self.state.sessions.write();
+        let mut port_relay_table = self.state.port_relay_table.write();
+        let port_mapping_table = self.state.port_mapping_table.read();
+
+        // Finds information about the current session.
+        let session = if let Some(it) = sessions.get_mut(addr) {
+            it
+        } else {
+            return false;
+        };
+
+        if !Self::has_permission_capacity(&self.options, session, ports) {
+            return false;
+        }
+
+        // The port numbers assigned to the current session, the peers can reach a dual
+        // allocation on the relayed transport addresses of both address families.
+        let local_ports = session.allocate.ports().collect::<Vec<_>>();
+        if local_ports.is_empty() {
+            return false;
+        }
+
+        // You cannot create permissions for yourself.
+        if local_ports.iter().any(|it| ports.contains(&(*it).into())) {
+            return false;
+        }
+
+        // Each peer port must be the relay port of a session.
+        let mut peers = Vec::with_capacity(15);
+        for port in ports {
+            if let Some(it) = RelayPort::try_from(*port)
+                .ok()
+                .and_then(|it| port_mapping_table.get(&it))
+            {
+                peers.push((it, *port));
+            } else {
+                return false;
+            }
+        }
+
+        // Create a port forwarding mapping relationship for each peer session.
+        for (peer, port) in peers {
+            let relays = port_relay_table
+                .entry(*peer)
+                .or_insert_with(|| HashMap::with_capacity(20));
+
+            for local_port in &local_ports {
+                relays.insert(
+                    *local_port,
+                    Endpoint {
+                        address: addr.address,
+                        endpoint: *endpoint,
+                        port: *local_port,
+                    },
+                );
+            }
+
+            // Do not store the same peer ports to the permission list over and over again.
+            if !session.permissions.contains(&port) {
+                session.permissions.push(port);
+            }
+        }
+
+        // Creating the permissions again refreshes them.
+        if let Some(lifetime) = self.options.permission_lifetime {
+            let expires = self.timer.get() + lifetime as u64;
+            let mut lifetime_table = self.state.lifetime_table.write();
+            let lifetimes = lifetime_table.entry(*addr).or_default();
+            for port in ports {
+                lifetimes.permissions.insert(*port, expires);
+            }
+        }
+
+        true
+    }
+
+    /// The remaining lifetimes in seconds of the permissions and channels of
+    /// the session, see [`SessionsOptions::permission_lifetime`] and
+    /// [`SessionsOptions::channel_lifetime`]. The permissions and channels
+    /// that last as long as the allocation are not included.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use mycrl_turn::*;
+    /// use stun::attribute::Transport;
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {
+    ///     fn get_password_blocking(&self, _: &SessionAddr, _: &str) -> Option<String> {
+    ///         Some("test".to_string())
+    ///     }
+    /// }
+    ///
+    /// let endpoint = "127.0.0.1:3478".parse().unwrap();
+    /// let addr = SessionAddr {
+    ///     address: "127.0.0.1:8080".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let peer_addr = SessionAddr {
+    ///     address: "127.0.0.1:8081".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let sessions = Sessions::with_options(
+    ///     ObserverTest,
+    ///     SessionsOptions {
+    ///         permission_lifetime: Some(3600),
+    ///         ..Default::default()
+    ///     },
+    /// );
+    ///
+    /// pollster::block_on(sessions.get_digest(&addr, Transport::UDP, "test", "test"));
+    /// pollster::block_on(sessions.get_digest(&peer_addr, Transport::UDP, "test", "test"));
+    ///
+    /// sessions.allocate(&addr).unwrap();
+    /// let peer_port = PeerPort::from(sessions.allocate(&peer_addr).unwrap());
+    /// let channel = ChannelNumber::new(0x4000).unwrap();
+    ///
+    /// assert!(sessions.bind_channel(&addr, &endpoint, peer_port, channel));
+    ///
+    /// // The channels do not expire, they cannot be bound again.
+    /// assert!(!sessions.bind_channel(&addr, &endpoint, peer_port, channel));
+    ///
+    /// let (permissions, channels) = sessions.get_lifetimes(&addr);
+    /// assert!((3599..=3600).contains(&permissions[&peer_port]));
+    /// assert!(channels.is_empty());
+    /// ```
+    pub fn get_lifetimes(
+        &self,
+        addr: &SessionAddr,
+    ) -> (HashMap<PeerPort, u64>, HashMap<ChannelNumber, u64>) {
+        let now = self.timer.get();
+        self.state
+            .lifetime_table
+            .read()
+            .get(addr)
+            .map(|it| {
+                (
+                    it.permissions
+                        .iter()
+                        .map(|(k, v)| (*k, v.saturating_sub(now)))
+                        .collect(),
+                    it.channels
+                        .iter()
+                        .map(|(k, (_, v))| (*k, v.saturating_sub(now)))
+                        .collect(),
+                )
+            })
+            .unwrap_or_default()
+    }
+
+    /// Binding a channel to the session.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use mycrl_turn::*;
+    /// use stun::attribute::Transport;
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {
+    ///     async fn get_password(
+    ///         &self,
+    ///         addr: &SessionAddr,
+    ///         username: &str,
+    ///     ) -> Option<String> {
+    ///         if username == "test" {
+    ///             Some("test".to_string())
+    ///         } else {
+    ///             None
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// let endpoint = "127.0.0.1:3478".parse().unwrap();
+    /// let addr = SessionAddr {
+    ///     address: "127.0.0.1:8080".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let peer_addr = SessionAddr {
+    ///     address: "127.0.0.1:8081".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let digest = [
+    ///     174, 238, 187, 253, 117, 209, 73, 157, 36, 56, 143, 91, 155, 16, 224,
+    ///     239,
+    /// ];
+    ///
+    /// let sessions = Sessions::new(ObserverTest);
+    ///
+    /// pollster::block_on(sessions.get_digest(&addr, Transport::UDP, "test", "test"));
+    /// pollster::block_on(sessions.get_digest(&peer_addr, Transport::UDP, "test", "test"));
+    ///
+    /// let port = sessions.allocate(&addr).unwrap();
+    /// let peer_port = sessions.allocate(&peer_addr).unwrap();
+    /// let channel = ChannelNumber::new(0x4000).unwrap();
+    /// assert_eq!(
+    ///     sessions
+    ///         .get_session(&addr)
+    ///         .get_ref()
+    ///         .unwrap()
+    ///         .allocate
+    ///         .channels
+    ///         .len(),
+    ///     0
+    /// );
+    ///
+    /// assert_eq!(
+    ///     sessions
+    ///         .get_session(&peer_addr)
+    ///         .get_ref()
+    ///         .unwrap()
+    ///         .allocate
+    ///         .channels
+    ///         .len(),
+    ///     0
+    /// );
+    ///
+    /// assert!(sessions.bind_channel(&addr, &endpoint, peer_port.into(), channel));
+    /// assert!(sessions.bind_channel(&peer_addr, &endpoint, port.into(), channel));
+    /// assert_eq!(
+    ///     sessions
+    ///         .get_session(&addr)
+    ///         .get_ref()
+    ///         .unwrap()
+    ///         .allocate
+    ///         .channels,
+    ///     vec![channel]
+    /// );
+    ///
+    /// assert_eq!(
+    ///     sessions
+    ///         .get_session(&peer_addr)
+    ///         .get_ref()
+    ///         .unwrap()
+    ///         .allocate
+    ///         .channels,
+    ///     vec![channel]
+    /// );
+    /// ```
+    pub fn bind_channel(
+        &self,
+        addr: &SessionAddr,
+        endpoint: &SocketAddr,
+        port: PeerPort,
+        channel: ChannelNumber,
+    ) -> bool {
+        // Finds the address of the bound opposing port.
+        let peer = if let Some(it) = RelayPort::try_from(port)
+            .ok()
+            .and_then(|it| self.state.port_mapping_table.read().get(&it).copied())
+        {
+            it
+        } else {
+            return false;
+        };
+
+        // Records the channel used for the current session.
+        let local_port = {
+            let mut lock = self.state.sessions.write();
+            let session = if let Some(it) = lock.get_mut(addr) {
+                it
+            } else {
+                return false;
+            };
+
+            let local_port = if let Some(it) = session.allocate.port {
+                it
+            } else {
+                return false;
+            };
+
+            if session.allocate.channels.contains(&channel) {
+                // Binding the channel to the same peer again refreshes it, a channel
+                // is never bound to another peer. Channels without a lifetime have
+                // nothing to refresh.
+                let is_bound = self.options.channel_lifetime.is_some()
+                    && self
+                        .state
+                        .channel_relay_table
+                        .read()
+                        .get(&peer)
+                        .and_then(|it| it.get(&channel))
+                        .map(|it| it.port == local_port)
+                        .unwrap_or(false);
+
+                if !is_bound {
+                    return false;
+                }
+            } else if Self::has_channel_capacity(&self.options, session, channel) {
+                session.allocate.channels.push(channel);
+            } else {
+                return false;
+            }
+
+            local_port
+        };
+
+        // Binding ports also creates permissions.
+        if !self.create_permission(addr, endpoint, &[port]) {
+            return false;
+        }
+
+        // Create channel forwarding mapping relationships for peers.
+        self.state
+            .channel_relay_table
+            .write()
+            .entry(peer)
+            .or_insert_with(|| HashMap::with_capacity(10))
+            .insert(
+                channel,
+                Endpoint {
+                    address: addr.address,
+                    endpoint: *endpoint,
+                    port: local_port,
+                },
+            );
+
+        if let Some(lifetime) = self.options.channel_lifetime {
+            self.state
+                .lifetime_table
+                .write()
+                .entry(*addr)
+                .or_default()
+                .channels
+                .insert(channel, (port, self.timer.get() + lifetime as u64));
+        }
+
+        true
+    }
+
+    /// Permit the session to exchange data with peers that are reached
+    /// through another relay, see [`Observer::is_external`].
+    ///
+    /// The permissions for external peers only check the ip address of the
+    /// peer, and are counted against [`SessionsOptions::max_permissions`]
+    /// separately from the permissions for the relay ports of this server.
+    /// Fails if the session has no allocation.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use std::net::SocketAddr;
+    ///
+    /// use mycrl_turn::*;
+    /// use stun::attribute::Transport;
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {
+    ///     async fn get_password(
+    ///         &self,
+    ///         addr: &SessionAddr,
+    ///         username: &str,
+    ///     ) -> Option<String> {
+    ///         if username == "test" {
+    ///             Some("test".to_string())
+    ///         } else {
+    ///             None
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// let addr = SessionAddr {
+    ///     address: "127.0.0.1:8080".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let peer: SocketAddr = "192.0.2.1:50000".parse().unwrap();
+    /// let other: SocketAddr = "192.0.2.1:50001".parse().unwrap();
+    /// let channel = ChannelNumber::new(0x4000).unwrap();
+    ///
+    /// let sessions = Sessions::new(ObserverTest);
+    ///
+    /// pollster::block_on(sessions.get_digest(&addr, Transport::UDP, "test", "test"));
+    ///
+    /// // Only sessions with an allocation can permit peers.
+    /// assert!(!sessions.create_external_permission(&addr, &[peer]));
+    ///
+    /// sessions.allocate(&addr).unwrap();
+    ///
+    /// assert!(sessions.create_external_permission(&addr, &[peer]));
+    /// assert!(sessions.is_external_permitted(&addr, &other.ip()));
+    ///
+    /// assert!(sessions.bind_external_channel(&addr, &peer, channel));
+    /// assert_eq!(sessions.get_external_peer(&addr, channel), Some(peer));
+    /// assert_eq!(sessions.get_external_channel(&addr, &peer), Some(channel));
+    /// assert_eq!(sessions.get_external_channel(&addr, &other), None);
+    ///
+    /// // A channel is bound to one peer, and a peer to one channel.
+    /// assert!(!sessions.bind_external_channel(&addr, &other, channel));
+    /// assert!(!sessions.bind_external_channel(
+    ///     &addr,
+    ///     &peer,
+    ///     ChannelNumber::new(0x4001).unwrap()
+    /// ));
+    ///
+    /// assert!(sessions.refresh(&addr, 0));
+    /// assert!(!sessions.is_external_permitted(&addr, &peer.ip()));
+    /// ```
+    pub fn create_external_permission(&self, addr: &SessionAddr, peers: &[SocketAddr]) -> bool {
+        let sessions = self.state.sessions.read();
+        if !sessions
+            .get(addr)
+            .map(|it| it.allocate.port.is_some())
+            .unwrap_or(false)
+        {
+            return false;
+        }
+
+        let mut external_table = self.state.external_table.write();
+        let external = external_table.entry(*addr).or_default();
+
+        if let Some(max) = self.options.max_permissions {
+            let added = peers
+                .iter()
+                .map(|it| it.ip())
+                .filter(|it| !external.permissions.contains(it))
+                .collect::<HashSet<_>>();
+
+            if external.permissions.len() + added.len() > max {
+                return false;
+            }
+        }
+
+        external.permissions.extend(peers.iter().map(|it| it.ip()));
+        true
+    }
+
+    /// Bind a channel of the session to a peer that is reached through
+    /// another relay, which also permits the peer, see
+    /// [`Sessions::create_external_permission`].
+    ///
+    /// The channel numbers are shared with the channels bound to the relay
+    /// ports of this server.
+    pub fn bind_external_channel(
+        &self,
+        addr: &SessionAddr,
+        peer: &SocketAddr,
+        channel: ChannelNumber,
+    ) -> bool {
+        let mut sessions = self.state.sessions.write();
+        let session = match sessions.get_mut(addr) {
+            Some(it) if it.allocate.port.is_some() => it,
+            _ => return false,
+        };
+
+        if session.allocate.channels.contains(&channel)
+            || !Self::has_channel_capacity(&self.options, session, channel)
+        {
+            return false;
+        }
+
+        let mut external_table = self.state.external_table.write();
+        let external = external_table.entry(*addr).or_default();
+
+        if external.channels.values().any(|it| it == peer) {
+            return false;
+        }
+
+        if let Some(max) = self.options.max_permissions {
+            if !external.permissions.contains(&peer.ip()) && external.permissions.len() >= max {
+                return false;
+            }
+        }
+
+        session.allocate.channels.push(channel);
+        external.channels.insert(channel, *peer);
+        external.permissions.insert(peer.ip());
+        true
+    }
+
+    /// Whether the session has permitted the external peer.
+    pub fn is_external_permitted(&self, addr: &SessionAddr, peer: &IpAddr) -> bool {
+        let external_table = self.state.external_table.read();
+        !external_table.is_empty()
+            && external_table
+                .get(addr)
+                .map(|it| it.permissions.contains(peer))
+                .unwrap_or(false)
+    }
+
+    /// The external peer that the channel of the session is bound to.
+    pub fn get_external_peer(
+        &self,
+        addr: &SessionAddr,
+        channel: ChannelNumber,
+    ) -> Option<SocketAddr> {
+        let external_table = self.state.external_table.read();
+        if external_table.is_empty() {
+            return None;
+        }
+
+        external_table.get(addr)?.channels.get(&channel).copied()
+    }
+
+    /// The channel of the session that is bound to the external peer.
+    pub fn get_external_channel(
+        &self,
+        addr: &SessionAddr,
+        peer: &SocketAddr,
+    ) -> Option<ChannelNumber> {
+        self.state
+            .external_table
+            .read()
+            .get(addr)?
+            .channels
+            .iter()
+            .find(|(_, it)| *it == peer)
+            .map(|(channel, _)| *channel)
+    }
+
+    /// Gets the peer of the current session bound channel.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use mycrl_turn::*;
+    /// use stun::attribute::Transport;
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {
+    ///     async fn get_password(
+    ///         &self,
+    ///         addr: &SessionAddr,
+    ///         username: &str,
+    ///     ) -> Option<String> {
+    ///         if username == "test" {
+    ///             Some("test".to_string())
+    ///         } else {
+    ///             None
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// let endpoint = "127.0.0.1:3478".parse().unwrap();
+    /// let addr = SessionAddr {
+    ///     address: "127.0.0.1:8080".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let peer_addr = SessionAddr {
+    ///     address: "127.0.0.1:8081".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let digest = [
+    ///     174, 238, 187, 253, 117, 209, 73, 157, 36, 56, 143, 91, 155, 16, 224,
+    ///     239,
+    /// ];
+    ///
+    /// let sessions = Sessions::new(ObserverTest);
+    ///
+    /// pollster::block_on(sessions.get_digest(&addr, Transport::UDP, "test", "test"));
+    /// pollster::block_on(sessions.get_digest(&peer_addr, Transport::UDP, "test", "test"));
+    ///
+    /// let port = sessions.allocate(&addr).unwrap();
+    /// let peer_port = sessions.allocate(&peer_addr).unwrap();
+    /// let channel = ChannelNumber::new(0x4000).unwrap();
+    ///
+    /// assert!(sessions.bind_channel(&addr, &endpoint, peer_port.into(), channel));
+    /// assert!(sessions.bind_channel(&peer_addr, &endpoint, port.into(), channel));
+    /// assert_eq!(
+    ///     sessions
+    ///         .get_channel_relay_address(&addr, channel)
+    ///         .unwrap()
+    ///         .endpoint,
+    ///     endpoint
+    /// );
+    ///
+    /// assert_eq!(
+    ///     sessions
+    ///         .get_channel_relay_address(&peer_addr, channel)
+    ///         .unwrap()
+    ///         .endpoint,
+    ///     endpoint
+    /// );
+    /// ```
+    pub fn get_channel_relay_address(
+        &self,
+        addr: &SessionAddr,
+        channel: ChannelNumber,
+    ) -> Option<Endpoint> {
+        self.state
+            .channel_relay_table
+            .read()
+            .get(addr)?
+            .get(&channel)
+            .copied()
     }
 
-    /// Assign a port number to the session.
+    /// Get the address of the port binding.
     ///
     /// # Test
     ///
     /// ```
     /// use mycrl_turn::*;
+    /// use stun::attribute::Transport;
     ///
     /// #[derive(Clone)]
     /// struct ObserverTest;
@@ -450,11 +4156,17 @@ impl<T: Observer + 'static> Sessions<T> {
     ///     }
     /// }
     ///
+    /// let endpoint = "127.0.0.1:3478".parse().unwrap();
     /// let addr = SessionAddr {
     ///     address: "127.0.0.1:8080".parse().unwrap(),
     ///     interface: "127.0.0.1:3478".parse().unwrap(),
     /// };
     ///
+    /// let peer_addr = SessionAddr {
+    ///     address: "127.0.0.1:8081".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
     /// let digest = [
     ///     174, 238, 187, 253, 117, 209, 73, 157, 36, 56, 143, 91, 155, 16, 224,
     ///     239,
@@ -462,54 +4174,52 @@ impl<T: Observer + 'static> Sessions<T> {
     ///
     /// let sessions = Sessions::new(ObserverTest);
     ///
-    /// pollster::block_on(sessions.get_digest(&addr, "test", "test"));
-    ///
-    /// {
-    ///     let lock = sessions.get_session(&addr);
-    ///     let session = lock.get_ref().unwrap();
-    ///     assert_eq!(session.auth.username, "test");
-    ///     assert_eq!(session.auth.password, "test");
-    ///     assert_eq!(session.allocate.port, None);
-    ///     assert_eq!(session.allocate.channels.len(), 0);
-    /// }
+    /// pollster::block_on(sessions.get_digest(&addr, Transport::UDP, "test", "test"));
+    /// pollster::block_on(sessions.get_digest(&peer_addr, Transport::UDP, "test", "test"));
     ///
     /// let port = sessions.allocate(&addr).unwrap();
-    /// {
-    ///     let lock = sessions.get_session(&addr);
-    ///     let session = lock.get_ref().unwrap();
-    ///     assert_eq!(session.auth.username, "test");
-    ///     assert_eq!(session.auth.password, "test");
-    ///     assert_eq!(session.allocate.port, Some(port));
-    ///     assert_eq!(session.allocate.channels.len(), 0);
-    /// }
+    /// let peer_port = sessions.allocate(&peer_addr).unwrap();
     ///
-    /// assert!(sessions.allocate(&addr).is_none());
+    /// assert!(sessions.create_permission(&addr, &endpoint, &[peer_port.into()]));
+    /// assert!(sessions.create_permission(&peer_addr, &endpoint, &[port.into()]));
+    ///
+    /// assert_eq!(
+    ///     sessions
+    ///         .get_relay_address(&addr, peer_port.into())
+    ///         .unwrap()
+    ///         .endpoint,
+    ///     endpoint
+    /// );
+    ///
+    /// assert_eq!(
+    ///     sessions
+    ///         .get_relay_address(&peer_addr, port.into())
+    ///         .unwrap()
+    ///         .endpoint,
+    ///     endpoint
+    /// );
     /// ```
-    pub fn allocate(&self, addr: &SessionAddr) -> Option<u16> {
-        let mut lock = self.state.sessions.write();
-        let session = lock.get_mut(addr)?;
-
-        // If the port has already been allocated, re-allocation is not allowed.
-        if session.allocate.port.is_some() {
-            return None;
-        }
-
-        // Records the port assigned to the current session and resets the alive time.
-        let port = self.state.port_allocate_pool.lock().alloc(None)?;
-        session.expires = self.timer.get() + 600;
-        session.allocate.port = Some(port);
-
-        // Write the allocation port binding table.
-        self.state.port_mapping_table.write().insert(port, *addr);
-        Some(port)
+    pub fn get_relay_address(&self, addr: &SessionAddr, port: PeerPort) -> Option<Endpoint> {
+        self.state
+            .port_relay_table
+            .read()
+            .get(addr)?
+            .get(&RelayPort::try_from(port).ok()?)
+            .copied()
     }
 
-    /// Create permission for session.
+    /// Whether the session that a relay forwards to still holds the relay
+    /// port.
+    ///
+    /// Permissions and channels are kept when the peer session closes, the
+    /// relay is dead from then on, even if the port is allocated again by
+    /// another session.
     ///
     /// # Test
     ///
     /// ```
     /// use mycrl_turn::*;
+    /// use stun::attribute::Transport;
     ///
     /// #[derive(Clone)]
     /// struct ObserverTest;
@@ -520,11 +4230,7 @@ impl<T: Observer + 'static> Sessions<T> {
     ///         addr: &SessionAddr,
     ///         username: &str,
     ///     ) -> Option<String> {
-    ///         if username == "test" {
-    ///             Some("test".to_string())
-    ///         } else {
-    ///             None
-    ///         }
+    ///         Some("test".to_string())
     ///     }
     /// }
     ///
@@ -539,92 +4245,49 @@ impl<T: Observer + 'static> Sessions<T> {
     ///     interface: "127.0.0.1:3478".parse().unwrap(),
     /// };
     ///
-    /// let digest = [
-    ///     174, 238, 187, 253, 117, 209, 73, 157, 36, 56, 143, 91, 155, 16, 224,
-    ///     239,
-    /// ];
-    ///
     /// let sessions = Sessions::new(ObserverTest);
     ///
-    /// pollster::block_on(sessions.get_digest(&addr, "test", "test"));
-    /// pollster::block_on(sessions.get_digest(&peer_addr, "test", "test"));
+    /// pollster::block_on(sessions.get_digest(&addr, Transport::UDP, "test", "test"));
+    /// pollster::block_on(sessions.get_digest(&peer_addr, Transport::UDP, "test", "test"));
     ///
     /// let port = sessions.allocate(&addr).unwrap();
     /// let peer_port = sessions.allocate(&peer_addr).unwrap();
     ///
-    /// assert!(!sessions.create_permission(&addr, &endpoint, &[port]));
-    /// assert!(sessions.create_permission(&addr, &endpoint, &[peer_port]));
+    /// assert!(sessions.create_permission(&peer_addr, &endpoint, &[port.into()]));
     ///
-    /// assert!(!sessions.create_permission(&peer_addr, &endpoint, &[peer_port]));
-    /// assert!(sessions.create_permission(&peer_addr, &endpoint, &[port]));
+    /// let relay = sessions.get_relay_address(&addr, peer_port.into()).unwrap();
+    /// assert!(sessions.is_relay_alive(&relay));
+    ///
+    /// sessions.refresh(&peer_addr, 0);
+    /// assert!(!sessions.is_relay_alive(&relay));
     /// ```
-    pub fn create_permission(
-        &self,
-        addr: &SessionAddr,
-        endpoint: &SocketAddr,
-        ports: &[u16],
-    ) -> bool {
-        let mut sessions = self.state.sessions.write();
-        let mut port_relay_table = self.state.port_relay_table.write();
-        let port_mapping_table = self.state.port_mapping_table.read();
-
-        // Finds information about the current session.
-        let session = if let Some(it) = sessions.get_mut(addr) {
-            it
-        } else {
-            return false;
-        };
-
-        // The port number assigned to the current session.
-        let local_port = if let Some(it) = session.allocate.port {
-            it
-        } else {
-            return false;
-        };
-
-        // You cannot create permissions for yourself.
-        if ports.contains(&local_port) {
-            return false;
-        }
-
-        // Each peer port must be present.
-        let mut peers = Vec::with_capacity(15);
-        for port in ports {
-            if let Some(it) = port_mapping_table.get(&port) {
-                peers.push((it, *port));
-            } else {
-                return false;
-            }
-        }
-
-        // Create a port forwarding mapping relationship for each peer session.
-        for (peer, port) in peers {
-            port_relay_table
-                .entry(*peer)
-                .or_insert_with(|| HashMap::with_capacity(20))
-                .insert(
-                    local_port,
-                    Endpoint {
-                        address: addr.address,
-                        endpoint: *endpoint,
-                    },
-                );
-
-            // Do not store the same peer ports to the permission list over and over again.
-            if !session.permissions.contains(&port) {
-                session.permissions.push(port);
-            }
-        }
-
-        true
+    pub fn is_relay_alive(&self, relay: &Endpoint) -> bool {
+        self.state
+            .port_mapping_table
+            .read()
+            .get(&relay.port)
+            .map(|it| it.address == relay.address)
+            .unwrap_or(false)
     }
 
-    /// Binding a channel to the session.
+    /// Verify the reverse path of data that a session sends to a relay.
+    ///
+    /// Always passes unless strict reverse path verification is enabled. In
+    /// that case the send is recorded as outbound traffic of the sending
+    /// session, and the data is only allowed through if the receiving session
+    /// has itself sent data to the sender's port before. Rejected data is
+    /// reported to the observer on behalf of the receiving session.
+    ///
+    /// The data of the session that sends first is dropped until the other
+    /// session has replied, as it is indistinguishable from injected traffic.
+    /// The data of the reply passes, since the first send was recorded even
+    /// though it was dropped.
     ///
     /// # Test
     ///
     /// ```
     /// use mycrl_turn::*;
+    /// use stun::attribute::Transport;
     ///
     /// #[derive(Clone)]
     /// struct ObserverTest;
@@ -635,11 +4298,7 @@ impl<T: Observer + 'static> Sessions<T> {
     ///         addr: &SessionAddr,
     ///         username: &str,
     ///     ) -> Option<String> {
-    ///         if username == "test" {
-    ///             Some("test".to_string())
-    ///         } else {
-    ///             None
-    ///         }
+    ///         Some("test".to_string())
     ///     }
     /// }
     ///
@@ -654,120 +4313,120 @@ impl<T: Observer + 'static> Sessions<T> {
     ///     interface: "127.0.0.1:3478".parse().unwrap(),
     /// };
     ///
-    /// let digest = [
-    ///     174, 238, 187, 253, 117, 209, 73, 157, 36, 56, 143, 91, 155, 16, 224,
-    ///     239,
-    /// ];
-    ///
-    /// let sessions = Sessions::new(ObserverTest);
+    /// let sessions = Sessions::with_options(
+    ///     ObserverTest,
+    ///     SessionsOptions {
+    ///         strict_reverse_path: true,
+    ///         ..Default::default()
+    ///     },
+    /// );
     ///
-    /// pollster::block_on(sessions.get_digest(&addr, "test", "test"));
-    /// pollster::block_on(sessions.get_digest(&peer_addr, "test", "test"));
+    /// pollster::block_on(sessions.get_digest(&addr, Transport::UDP, "test", "test"));
+    /// pollster::block_on(sessions.get_digest(&peer_addr, Transport::UDP, "test", "test"));
     ///
     /// let port = sessions.allocate(&addr).unwrap();
     /// let peer_port = sessions.allocate(&peer_addr).unwrap();
-    /// assert_eq!(
-    ///     sessions
-    ///         .get_session(&addr)
-    ///         .get_ref()
-    ///         .unwrap()
-    ///         .allocate
-    ///         .channels
-    ///         .len(),
-    ///     0
-    /// );
     ///
-    /// assert_eq!(
-    ///     sessions
-    ///         .get_session(&peer_addr)
-    ///         .get_ref()
-    ///         .unwrap()
-    ///         .allocate
-    ///         .channels
-    ///         .len(),
-    ///     0
-    /// );
+    /// assert!(sessions.create_permission(&addr, &endpoint, &[peer_port.into()]));
+    /// assert!(sessions.create_permission(&peer_addr, &endpoint, &[port.into()]));
     ///
-    /// assert!(sessions.bind_channel(&addr, &endpoint, peer_port, 0x4000));
-    /// assert!(sessions.bind_channel(&peer_addr, &endpoint, port, 0x4000));
-    /// assert_eq!(
-    ///     sessions
-    ///         .get_session(&addr)
-    ///         .get_ref()
-    ///         .unwrap()
-    ///         .allocate
-    ///         .channels,
-    ///     vec![0x4000]
-    /// );
+    /// let to_peer = sessions.get_relay_address(&addr, peer_port.into()).unwrap();
+    /// let to_addr = sessions.get_relay_address(&peer_addr, port.into()).unwrap();
     ///
-    /// assert_eq!(
-    ///     sessions
-    ///         .get_session(&peer_addr)
-    ///         .get_ref()
-    ///         .unwrap()
-    ///         .allocate
-    ///         .channels,
-    ///     vec![0x4000]
-    /// );
+    /// // The session has never sent anything to the peer.
+    /// assert!(!sessions.verify_reverse_path(&peer_addr, &to_addr));
+    ///
+    /// assert!(sessions.verify_reverse_path(&addr, &to_peer));
+    /// assert!(sessions.verify_reverse_path(&peer_addr, &to_addr));
     /// ```
-    pub fn bind_channel(
-        &self,
-        addr: &SessionAddr,
-        endpoint: &SocketAddr,
-        port: u16,
-        channel: u16,
-    ) -> bool {
-        // Finds the address of the bound opposing port.
-        let peer = if let Some(it) = self.state.port_mapping_table.read().get(&port) {
+    pub fn verify_reverse_path(&self, addr: &SessionAddr, relay: &Endpoint) -> bool {
+        if !self.options.strict_reverse_path {
+            return true;
+        }
+
+        // Both ports of a dual allocation are recorded, the peer may reply to the
+        // relayed transport address of either address family.
+        let local_ports = if let Some(it) = self
+            .get_session(addr)
+            .get_ref()
+            .map(|it| it.allocate.ports().collect::<Vec<_>>())
+        {
+            it
+        } else {
+            return false;
+        };
+
+        let local_port = if let Some(it) = local_ports.first() {
             *it
         } else {
             return false;
         };
 
-        // Records the channel used for the current session.
-        {
-            let mut lock = self.state.sessions.write();
-            let session = if let Some(it) = lock.get_mut(addr) {
-                it
-            } else {
-                return false;
-            };
+        // Recording the outbound port only needs the write lock the first time.
+        let is_recorded = {
+            let outbound_table = self.state.outbound_table.read();
+            local_ports.iter().all(|port| {
+                outbound_table
+                    .get(port)
+                    .map(|it| it.contains(&relay.port))
+                    .unwrap_or(false)
+            })
+        };
 
-            if !session.allocate.channels.contains(&channel) {
-                session.allocate.channels.push(channel);
-            } else {
-                return false;
+        if !is_recorded {
+            let mut outbound_table = self.state.outbound_table.write();
+            for port in &local_ports {
+                outbound_table
+                    .entry(*port)
+                    .or_insert_with(|| HashSet::with_capacity(10))
+                    .insert(relay.port);
             }
         }
 
-        // Binding ports also creates permissions.
-        if !self.create_permission(addr, endpoint, &[port]) {
-            return false;
-        }
+        let is_allowed = self
+            .state
+            .outbound_table
+            .read()
+            .get(&relay.port)
+            .map(|it| local_ports.iter().any(|port| it.contains(port)))
+            .unwrap_or(false);
 
-        // Create channel forwarding mapping relationships for peers.
-        self.state
-            .channel_relay_table
-            .write()
-            .entry(peer)
-            .or_insert_with(|| HashMap::with_capacity(10))
-            .insert(
-                channel,
-                Endpoint {
-                    address: addr.address,
-                    endpoint: *endpoint,
-                },
-            );
+        if !is_allowed {
+            let peer = self
+                .state
+                .port_mapping_table
+                .read()
+                .get(&relay.port)
+                .copied();
+            if let Some(peer) = peer {
+                if let Some(transport) = self.get_session(&peer).get_ref().map(|it| it.transport) {
+                    self.observer.rejected(&peer, local_port.into(), transport);
+                }
+            }
+        }
 
-        true
+        is_allowed
     }
 
-    /// Gets the peer of the current session bound channel.
+    /// Open a connection from the TCP allocation of the session to the TCP
+    /// allocation that holds the relay port.
+    ///
+    /// Both allocations get a half of the connection, which is identified by
+    /// its CONNECTION-ID, and each client binds a data connection to its half
+    /// with [`Sessions::bind_connection`]. The data of the two bound data
+    /// connections is then relayed to each other as is. A half whose data
+    /// connection is not bound within [`CONNECTION_TIMEOUT`] closes the
+    /// connection.
+    ///
+    /// The error is the error code that the Connect request is rejected with:
+    /// 437 if the session has no TCP allocation, 446 if it is already
+    /// connected to the peer, and 447 if the peer is not a TCP allocation.
     ///
     /// # Test
     ///
     /// ```
     /// use mycrl_turn::*;
+    /// use stun::attribute::{ErrorKind, Transport};
     ///
     /// #[derive(Clone)]
     /// struct ObserverTest;
@@ -778,15 +4437,11 @@ impl<T: Observer + 'static> Sessions<T> {
     ///         addr: &SessionAddr,
     ///         username: &str,
     ///     ) -> Option<String> {
-    ///         if username == "test" {
-    ///             Some("test".to_string())
-    ///         } else {
-    ///             None
-    ///         }
+    ///         Some("test".to_string())
     ///     }
     /// }
     ///
-    /// let endpoint = "127.0.0.1:3478".parse().unwrap();
+    /// let ip = "127.0.0.1".parse().unwrap();
     /// let addr = SessionAddr {
     ///     address: "127.0.0.1:8080".parse().unwrap(),
     ///     interface: "127.0.0.1:3478".parse().unwrap(),
@@ -797,52 +4452,117 @@ impl<T: Observer + 'static> Sessions<T> {
     ///     interface: "127.0.0.1:3478".parse().unwrap(),
     /// };
     ///
-    /// let digest = [
-    ///     174, 238, 187, 253, 117, 209, 73, 157, 36, 56, 143, 91, 155, 16, 224,
-    ///     239,
-    /// ];
-    ///
     /// let sessions = Sessions::new(ObserverTest);
     ///
-    /// pollster::block_on(sessions.get_digest(&addr, "test", "test"));
-    /// pollster::block_on(sessions.get_digest(&peer_addr, "test", "test"));
+    /// pollster::block_on(sessions.get_digest(&addr, Transport::TCP, "test", "test"));
+    /// pollster::block_on(sessions.get_digest(&peer_addr, Transport::TCP, "test", "test"));
     ///
-    /// let port = sessions.allocate(&addr).unwrap();
-    /// let peer_port = sessions.allocate(&peer_addr).unwrap();
+    /// sessions.allocate_with_transport(&addr, ip, Transport::TCP).unwrap();
+    /// let peer_port = sessions.allocate_with_transport(&peer_addr, ip, Transport::TCP).unwrap();
     ///
-    /// assert!(sessions.bind_channel(&addr, &endpoint, peer_port, 0x4000));
-    /// assert!(sessions.bind_channel(&peer_addr, &endpoint, port, 0x4000));
-    /// assert_eq!(
-    ///     sessions
-    ///         .get_channel_relay_address(&addr, 0x4000)
-    ///         .unwrap()
-    ///         .endpoint,
-    ///     endpoint
-    /// );
+    /// let connected = sessions.connect(&addr, peer_port).unwrap();
+    /// assert_eq!(connected.peer, peer_addr);
+    /// assert_ne!(connected.id, connected.peer_id);
     ///
     /// assert_eq!(
-    ///     sessions
-    ///         .get_channel_relay_address(&peer_addr, 0x4000)
-    ///         .unwrap()
-    ///         .endpoint,
-    ///     endpoint
+    ///     sessions.connect(&addr, peer_port),
+    ///     Err(ErrorKind::ConnectionAlreadyExists)
     /// );
     /// ```
-    pub fn get_channel_relay_address(&self, addr: &SessionAddr, channel: u16) -> Option<Endpoint> {
-        self.state
-            .channel_relay_table
+    pub fn connect(&self, addr: &SessionAddr, port: RelayPort) -> Result<Connected, ErrorKind> {
+        let peer = self
+            .state
+            .port_mapping_table
             .read()
-            .get(&addr)?
-            .get(&channel)
+            .get(&port)
             .copied()
+            .ok_or(ErrorKind::ConnectionTimeoutOrFailure)?;
+
+        {
+            let sessions = self.state.sessions.read();
+            let is_tcp = |addr: &SessionAddr| {
+                sessions
+                    .get(addr)
+                    .filter(|it| it.allocate.port.is_some())
+                    .map(|it| it.allocate.transport == Transport::TCP)
+                    .unwrap_or(false)
+            };
+
+            if !is_tcp(addr) {
+                return Err(ErrorKind::AllocationMismatch);
+            }
+
+            if peer == *addr || !is_tcp(&peer) {
+                return Err(ErrorKind::ConnectionTimeoutOrFailure);
+            }
+        }
+
+        let mut connections = self.state.connections.write();
+        if connections.values().any(|it| {
+            it.owner == *addr
+                && connections
+                    .get(&it.remote)
+                    .map(|it| it.owner == peer)
+                    .unwrap_or(false)
+        }) {
+            return Err(ErrorKind::ConnectionAlreadyExists);
+        }
+
+        let mut rng = thread_rng();
+        let mut next_id = || loop {
+            let id = rng.gen::<u32>();
+            if !connections.contains_key(&id) {
+                break id;
+            }
+        };
+
+        let id = next_id();
+        let peer_id = loop {
+            let it = next_id();
+            if it != id {
+                break it;
+            }
+        };
+
+        let now = self.timer.get();
+        connections.insert(
+            id,
+            Connection {
+                owner: *addr,
+                remote: peer_id,
+                data: None,
+                is_ready: false,
+                created: now,
+            },
+        );
+
+        connections.insert(
+            peer_id,
+            Connection {
+                owner: peer,
+                remote: id,
+                data: None,
+                is_ready: false,
+                created: now,
+            },
+        );
+
+        Ok(Connected { id, peer, peer_id })
     }
 
-    /// Get the address of the port binding.
+    /// Bind a client data connection to a half of a connection, see
+    /// [`Sessions::connect`].
+    ///
+    /// The half has to belong to an allocation of the same username and must
+    /// not be bound yet, and the data connection must not be the connection
+    /// of an allocation itself. The data connection is no session of its own from
+    /// then on, it is closed with [`Sessions::close_connection`].
     ///
     /// # Test
     ///
     /// ```
     /// use mycrl_turn::*;
+    /// use stun::attribute::Transport;
     ///
     /// #[derive(Clone)]
     /// struct ObserverTest;
@@ -853,64 +4573,164 @@ impl<T: Observer + 'static> Sessions<T> {
     ///         addr: &SessionAddr,
     ///         username: &str,
     ///     ) -> Option<String> {
-    ///         if username == "test" {
-    ///             Some("test".to_string())
-    ///         } else {
-    ///             None
-    ///         }
+    ///         Some("test".to_string())
+    ///     }
+    ///
+    ///     // The sessions that the data connections were authenticated with are not
+    ///     // reported as closed.
+    ///     fn closed(&self, _: &SessionAddr, _: &str, _: CloseReason) {
+    ///         panic!("closed");
     ///     }
     /// }
     ///
-    /// let endpoint = "127.0.0.1:3478".parse().unwrap();
+    /// let ip = "127.0.0.1".parse().unwrap();
+    /// let interface = "127.0.0.1:3478".parse().unwrap();
     /// let addr = SessionAddr {
     ///     address: "127.0.0.1:8080".parse().unwrap(),
-    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    ///     interface,
     /// };
     ///
     /// let peer_addr = SessionAddr {
     ///     address: "127.0.0.1:8081".parse().unwrap(),
-    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    ///     interface,
     /// };
     ///
-    /// let digest = [
-    ///     174, 238, 187, 253, 117, 209, 73, 157, 36, 56, 143, 91, 155, 16, 224,
-    ///     239,
-    /// ];
+    /// let data = SessionAddr {
+    ///     address: "127.0.0.1:8082".parse().unwrap(),
+    ///     interface,
+    /// };
+    ///
+    /// let peer_data = SessionAddr {
+    ///     address: "127.0.0.1:8083".parse().unwrap(),
+    ///     interface,
+    /// };
     ///
     /// let sessions = Sessions::new(ObserverTest);
     ///
-    /// pollster::block_on(sessions.get_digest(&addr, "test", "test"));
-    /// pollster::block_on(sessions.get_digest(&peer_addr, "test", "test"));
+    /// pollster::block_on(sessions.get_digest(&addr, Transport::TCP, "test", "test"));
+    /// pollster::block_on(sessions.get_digest(&peer_addr, Transport::TCP, "test", "test"));
+    /// pollster::block_on(sessions.get_digest(&data, Transport::TCP, "test", "test"));
     ///
-    /// let port = sessions.allocate(&addr).unwrap();
-    /// let peer_port = sessions.allocate(&peer_addr).unwrap();
+    /// sessions.allocate_with_transport(&addr, ip, Transport::TCP).unwrap();
+    /// let peer_port = sessions.allocate_with_transport(&peer_addr, ip, Transport::TCP).unwrap();
+    /// let connected = sessions.connect(&addr, peer_port).unwrap();
     ///
-    /// assert!(sessions.create_permission(&addr, &endpoint, &[peer_port]));
-    /// assert!(sessions.create_permission(&peer_addr, &endpoint, &[port]));
+    /// assert!(!sessions.bind_connection(&data, "other", connected.id));
+    /// assert!(sessions.bind_connection(&data, "test", connected.id));
+    /// assert!(sessions.get_session(&data).get_ref().is_none());
+    /// assert!(!sessions.bind_connection(&data, "test", connected.id));
+    /// assert!(sessions.is_connection_alive(&data));
+    /// assert_eq!(sessions.get_connection_peer(&data), None);
     ///
-    /// assert_eq!(
-    ///     sessions
-    ///         .get_relay_address(&addr, peer_port)
-    ///         .unwrap()
-    ///         .endpoint,
-    ///     endpoint
-    /// );
+    /// assert!(sessions.bind_connection(&peer_data, "test", connected.peer_id));
+    /// assert_eq!(sessions.get_connection_peer(&data), None);
     ///
-    /// assert_eq!(
-    ///     sessions
-    ///         .get_relay_address(&peer_addr, port)
-    ///         .unwrap()
-    ///         .endpoint,
-    ///     endpoint
-    /// );
+    /// sessions.connection_ready(&data);
+    /// sessions.connection_ready(&peer_data);
+    /// assert_eq!(sessions.get_connection_peer(&data), Some(peer_data));
+    /// assert_eq!(sessions.get_connection_peer(&peer_data), Some(data));
+    ///
+    /// sessions.close_connection(&peer_data);
+    /// assert!(!sessions.is_connection_alive(&data));
     /// ```
-    pub fn get_relay_address(&self, addr: &SessionAddr, port: u16) -> Option<Endpoint> {
+    pub fn bind_connection(&self, addr: &SessionAddr, username: &str, id: u32) -> bool {
+        let owner = match self.state.connections.read().get(&id) {
+            Some(it) if it.data.is_none() => it.owner,
+            _ => return false,
+        };
+
+        {
+            let sessions = self.state.sessions.read();
+            if sessions
+                .get(&owner)
+                .map(|it| it.auth.username != username)
+                .unwrap_or(true)
+            {
+                return false;
+            }
+
+            // The control connection of an allocation is never a data connection.
+            if sessions
+                .get(addr)
+                .map(|it| it.allocate.port.is_some())
+                .unwrap_or(false)
+            {
+                return false;
+            }
+        }
+
+        // The session that the data connection was authenticated with is not
+        // needed anymore, the data connection cannot send requests. It never
+        // allocated, so there is nothing to release and it is not reported as
+        // closed.
+        self.state.sessions.write().remove(addr);
+        self.remove_nonce(&[*addr]);
+
+        match self.state.connections.write().get_mut(&id) {
+            Some(it) if it.data.is_none() => {
+                it.data = Some(*addr);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// The client of the data connection has been told that it is bound, with
+    /// the success response to its ConnectionBind request.
+    ///
+    /// The data of the peer is only relayed to the data connection from then
+    /// on, so that it does not overtake the response.
+    pub fn connection_ready(&self, addr: &SessionAddr) {
+        if let Some(it) = self
+            .state
+            .connections
+            .write()
+            .values_mut()
+            .find(|it| it.data == Some(*addr))
+        {
+            it.is_ready = true;
+        }
+    }
+
+    /// The data connection of the other half of the connection that the data
+    /// connection is bound to, `None` until the peer has bound it and is
+    /// ready, see [`Sessions::connection_ready`].
+    pub fn get_connection_peer(&self, addr: &SessionAddr) -> Option<SessionAddr> {
+        let connections = self.state.connections.read();
+        let remote = connections
+            .values()
+            .find(|it| it.data == Some(*addr))?
+            .remote;
+
+        connections
+            .get(&remote)
+            .filter(|it| it.is_ready)
+            .and_then(|it| it.data)
+    }
+
+    /// Whether the data connection is still bound to a connection, the
+    /// connection is closed with either allocation, with either data
+    /// connection, or when its other half is not bound in time.
+    pub fn is_connection_alive(&self, addr: &SessionAddr) -> bool {
         self.state
-            .port_relay_table
+            .connections
             .read()
-            .get(&addr)?
-            .get(&port)
-            .copied()
+            .values()
+            .any(|it| it.data == Some(*addr))
+    }
+
+    /// Close the connection that the data connection is bound to.
+    pub fn close_connection(&self, addr: &SessionAddr) {
+        let mut connections = self.state.connections.write();
+        let closed = connections
+            .iter()
+            .find(|(_, v)| v.data == Some(*addr))
+            .map(|(id, v)| (*id, v.remote));
+
+        if let Some((id, remote)) = closed {
+            connections.remove(&id);
+            connections.remove(&remote);
+        }
     }
 
     /// Refresh the session for addr.
@@ -919,6 +4739,7 @@ impl<T: Observer + 'static> Sessions<T> {
     ///
     /// ```
     /// use mycrl_turn::*;
+    /// use stun::attribute::Transport;
     ///
     /// #[derive(Clone)]
     /// struct ObserverTest;
@@ -951,7 +4772,7 @@ impl<T: Observer + 'static> Sessions<T> {
     ///
     /// assert!(sessions.get_session(&addr).get_ref().is_none());
     ///
-    /// pollster::block_on(sessions.get_digest(&addr, "test", "test"));
+    /// pollster::block_on(sessions.get_digest(&addr, Transport::UDP, "test", "test"));
     ///
     /// let expires = sessions.get_session(&addr).get_ref().unwrap().expires;
     /// assert!(expires == 600 || expires == 601 || expires == 602);
@@ -961,16 +4782,16 @@ impl<T: Observer + 'static> Sessions<T> {
     /// assert!(sessions.get_session(&addr).get_ref().is_none());
     /// ```
     pub fn refresh(&self, addr: &SessionAddr, lifetime: u32) -> bool {
-        if lifetime > 3600 {
+        if lifetime > self.options.max_lifetime.unwrap_or(MAX_LIFETIME) {
             return false;
         }
 
         if lifetime == 0 {
-            self.remove_session(&[*addr]);
-            self.remove_nonce(&[*addr]);
+            self.close(addr, CloseReason::Client);
         } else {
             if let Some(session) = self.state.sessions.write().get_mut(addr) {
                 session.expires = self.timer.get() + lifetime as u64;
+                session.warned = false;
             } else {
                 return false;
             }
@@ -982,6 +4803,86 @@ impl<T: Observer + 'static> Sessions<T> {
 
         true
     }
+
+    /// Delete the session for the reason, like a refresh of lifetime zero
+    /// does. The allocation lingers if [`SessionsOptions::linger`] is set.
+    /// Returns `false` if there is no such session.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// use mycrl_turn::*;
+    /// use stun::attribute::Transport;
+    ///
+    /// #[derive(Clone, Default)]
+    /// struct ObserverTest(Arc<Mutex<Vec<CloseReason>>>);
+    ///
+    /// impl Observer for ObserverTest {
+    ///     fn get_password_blocking(&self, _: &SessionAddr, _: &str) -> Option<String> {
+    ///         Some("test".to_string())
+    ///     }
+    ///
+    ///     fn closed(&self, _: &SessionAddr, _: &str, reason: CloseReason) {
+    ///         self.0.lock().unwrap().push(reason);
+    ///     }
+    /// }
+    ///
+    /// let addr = SessionAddr {
+    ///     address: "127.0.0.1:8080".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let peer_addr = SessionAddr {
+    ///     address: "127.0.0.1:8081".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let observer = ObserverTest::default();
+    /// let sessions = Sessions::new(observer.clone());
+    ///
+    /// pollster::block_on(sessions.get_digest(&addr, Transport::UDP, "test", "test"));
+    /// pollster::block_on(sessions.get_digest(&peer_addr, Transport::UDP, "test", "test"));
+    ///
+    /// assert!(sessions.close(&addr, CloseReason::Removed));
+    /// assert!(!sessions.close(&addr, CloseReason::Removed));
+    ///
+    /// sessions.shutdown();
+    /// assert!(sessions.get_session(&peer_addr).get_ref().is_none());
+    /// assert_eq!(
+    ///     *observer.0.lock().unwrap(),
+    ///     vec![CloseReason::Removed, CloseReason::Shutdown]
+    /// );
+    /// ```
+    pub fn close(&self, addr: &SessionAddr, reason: CloseReason) -> bool {
+        let is_existing = self.state.sessions.read().contains_key(addr);
+        if let (Some(linger), Some(sender)) = (self.options.linger, &self.linger) {
+            if is_existing && self.state.lingering.write().insert(*addr) {
+                let _ = sender.send((*addr, Instant::now() + linger, reason));
+            }
+        } else {
+            self.remove_session(&[*addr], reason);
+            self.remove_nonce(&[*addr]);
+        }
+
+        is_existing
+    }
+
+    /// Delete all sessions right away, as the server is shutting down, the
+    /// sessions are closed with [`CloseReason::Shutdown`].
+    pub fn shutdown(&self) {
+        let addrs = self
+            .state
+            .sessions
+            .read()
+            .keys()
+            .copied()
+            .collect::<Vec<_>>();
+
+        self.remove_session(&addrs, CloseReason::Shutdown);
+        self.remove_nonce(&addrs);
+    }
 }
 
 /// The default HashMap is created without allocating capacity. To improve
@@ -1073,6 +4974,7 @@ pub struct PortAllocatePools {
     allocated: usize,
     bit_len: u32,
     peak: usize,
+    rng: Option<StdRng>,
 }
 
 impl Default for PortAllocatePools {
@@ -1082,11 +4984,38 @@ impl Default for PortAllocatePools {
             peak: Self::bucket_size() - 1,
             bit_len: Self::bit_len(),
             allocated: 0,
+            rng: None,
         }
     }
 }
 
 impl PortAllocatePools {
+    /// Create a pool that picks ports from a seeded random number generator.
+    ///
+    /// Pools with the same seed assign the same ports for the same sequence
+    /// of allocations, which makes port assignments reproducible in tests
+    /// and staging environments. The ports are predictable in this case, so
+    /// it should not be used in production.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use mycrl_turn::sessions::PortAllocatePools;
+    ///
+    /// let mut pool_1 = PortAllocatePools::with_seed(42);
+    /// let mut pool_2 = PortAllocatePools::with_seed(42);
+    ///
+    /// for _ in 0..10 {
+    ///     assert_eq!(pool_1.alloc(None), pool_2.alloc(None));
+    /// }
+    /// ```
+    pub fn with_seed(seed: u64) -> Self {
+        Self {
+            rng: Some(StdRng::seed_from_u64(seed)),
+            ..Default::default()
+        }
+    }
+
     /// compute bucket size.
     ///
     /// # Test
@@ -1184,8 +5113,11 @@ impl PortAllocatePools {
     /// ```
     pub fn alloc(&mut self, start_index: Option<usize>) -> Option<u16> {
         let mut index = None;
-        let mut start =
-            start_index.unwrap_or_else(|| thread_rng().gen_range(0..self.peak as u16) as usize);
+        let peak = self.peak as u16;
+        let mut start = start_index.unwrap_or_else(|| match &mut self.rng {
+            Some(rng) => rng.gen_range(0..peak) as usize,
+            None => thread_rng().gen_range(0..peak) as usize,
+        });
 
         // When the partition lookup has gone through the entire partition list, the
         // lookup should be stopped, and the location where it should be stopped is
@@ -1239,6 +5171,137 @@ impl PortAllocatePools {
         Some(port)
     }
 
+    /// random assign a port within the range, the part of the range outside
+    /// [`Self::port_range`] is ignored.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use mycrl_turn::sessions::PortAllocatePools;
+    ///
+    /// let mut pool = PortAllocatePools::default();
+    ///
+    /// for _ in 0..10 {
+    ///     let port = pool.alloc_in(50000..50010).unwrap();
+    ///     assert!((50000..50010).contains(&port));
+    /// }
+    ///
+    /// assert_eq!(pool.alloc_in(50000..50010), None);
+    /// assert_eq!(pool.alloc_in(0..1000), None);
+    /// assert_eq!(pool.len(), 10);
+    /// ```
+    pub fn alloc_in(&mut self, range: Range<u16>) -> Option<u16> {
+        let range = Self::clamp(range);
+        let len = range.len();
+        if len == 0 {
+            return None;
+        }
+
+        // Scans the range from a random offset, wrapping around at its end.
+        let offset = match &mut self.rng {
+            Some(rng) => rng.gen_range(0..len),
+            None => thread_rng().gen_range(0..len),
+        };
+
+        (0..len)
+            .map(|i| range.start + ((offset + i) % len) as u16)
+            .find(|port| self.take(*port))
+    }
+
+    /// get the number of allocated ports within the range.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use mycrl_turn::sessions::PortAllocatePools;
+    ///
+    /// let mut pool = PortAllocatePools::default();
+    ///
+    /// assert!(pool.take(50000));
+    /// assert!(pool.take(50010));
+    /// assert!(pool.take(60000));
+    ///
+    /// assert_eq!(pool.count_in(50000..50011), 2);
+    /// assert_eq!(pool.count_in(0..65535), 3);
+    /// ```
+    pub fn count_in(&self, range: Range<u16>) -> usize {
+        Self::clamp(range)
+            .filter(|port| self.is_allocated(*port))
+            .count()
+    }
+
+    /// random assign an even port within the range, with `reserve` the
+    /// next-higher port, which has to be within the range too, is taken as
+    /// well.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use mycrl_turn::sessions::PortAllocatePools;
+    ///
+    /// let mut pool = PortAllocatePools::default();
+    ///
+    /// let port = pool.alloc_even(50000..50004, true).unwrap();
+    /// assert!(port == 50000 || port == 50002);
+    /// assert_eq!(pool.len(), 2);
+    ///
+    /// let other = pool.alloc_even(50000..50004, false).unwrap();
+    /// assert_eq!(other, if port == 50000 { 50002 } else { 50000 });
+    /// assert_eq!(pool.alloc_even(50000..50004, false), None);
+    ///
+    /// // The next-higher port of the only even port is allocated.
+    /// let mut pool = PortAllocatePools::default();
+    ///
+    /// assert!(pool.take(50001));
+    /// assert_eq!(pool.alloc_even(50000..50002, true), None);
+    /// assert_eq!(pool.alloc_even(50000..50002, false), Some(50000));
+    /// ```
+    pub fn alloc_even(&mut self, range: Range<u16>, reserve: bool) -> Option<u16> {
+        let range = Self::clamp(range);
+        if range.is_empty() {
+            return None;
+        }
+
+        // The number of even ports in the range.
+        let start = range.start + range.start % 2;
+        let len = (range.end.saturating_sub(start) as usize).div_ceil(2);
+        if len == 0 {
+            return None;
+        }
+
+        let offset = match &mut self.rng {
+            Some(rng) => rng.gen_range(0..len),
+            None => thread_rng().gen_range(0..len),
+        };
+
+        (0..len)
+            .map(|i| start + ((offset + i) % len) as u16 * 2)
+            .find(|port| {
+                if reserve && (port + 1 >= range.end || self.is_allocated(port + 1)) {
+                    return false;
+                }
+
+                if !self.take(*port) {
+                    return false;
+                }
+
+                if reserve {
+                    self.take(port + 1);
+                }
+
+                true
+            })
+    }
+
+    fn is_allocated(&self, port: u16) -> bool {
+        let offset = (port - Self::port_range().start) as usize;
+        self.buckets[offset / 64] & (1 << (63 - offset % 64)) != 0
+    }
+
+    fn clamp(range: Range<u16>) -> Range<u16> {
+        range.start.max(Self::port_range().start)..range.end.min(Self::port_range().end)
+    }
+
     /// write bit flag in the bucket.
     ///
     /// # Test
@@ -1314,4 +5377,37 @@ impl PortAllocatePools {
         self.set_bit(bucket, index, Bit::Low);
         self.allocated -= 1;
     }
+
+    /// take a specific port out of the buckets, returns `false` if the port is
+    /// already allocated.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use mycrl_turn::sessions::PortAllocatePools;
+    ///
+    /// let mut pool = PortAllocatePools::default();
+    ///
+    /// assert!(pool.take(49152));
+    /// assert!(!pool.take(49152));
+    /// assert_eq!(pool.len(), 1);
+    ///
+    /// pool.restore(49152);
+    /// assert_eq!(pool.len(), 0);
+    /// ```
+    pub fn take(&mut self, port: u16) -> bool {
+        assert!(Self::port_range().contains(&port));
+
+        let offset = (port - Self::port_range().start) as usize;
+        let bucket = offset / 64;
+        let index = offset - (bucket * 64);
+
+        if self.buckets[bucket] & (1 << (63 - index)) != 0 {
+            return false;
+        }
+
+        self.set_bit(bucket, index, Bit::High);
+        self.allocated += 1;
+        true
+    }
 }
@@ -0,0 +1,168 @@
+//! The numbers of the relay.
+//!
+//! Relay ports, peer ports and channel numbers are all 16 bit numbers on the
+//! wire. They are wrapped in their own types, so that one cannot be passed
+//! where another is expected, and the ranges of relay ports and channel
+//! numbers are checked when they are created.
+
+use std::fmt;
+
+use crate::PortAllocatePools;
+
+/// The number is out of the range of the type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutOfRange(pub u16);
+
+impl fmt::Display for OutOfRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "out of range: {}", self.0)
+    }
+}
+
+impl std::error::Error for OutOfRange {}
+
+macro_rules! number {
+    ($name:ident) => {
+        impl $name {
+            pub const fn get(self) -> u16 {
+                self.0
+            }
+        }
+
+        impl From<$name> for u16 {
+            fn from(value: $name) -> Self {
+                value.0
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                self.0.fmt(f)
+            }
+        }
+    };
+}
+
+/// The port of the relayed transport address of an allocation.
+///
+/// Relay ports are always in [`PortAllocatePools::port_range`].
+///
+/// # Test
+///
+/// ```
+/// use mycrl_turn::*;
+///
+/// assert_eq!(RelayPort::new(49152).map(RelayPort::get), Some(49152));
+/// assert_eq!(RelayPort::new(3478), None);
+/// assert!(RelayPort::try_from(PeerPort::from(3478)).is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "u16", into = "u16")
+)]
+pub struct RelayPort(u16);
+
+number!(RelayPort);
+
+impl RelayPort {
+    pub fn new(port: u16) -> Option<Self> {
+        if PortAllocatePools::port_range().contains(&port) {
+            Some(Self(port))
+        } else {
+            None
+        }
+    }
+}
+
+impl TryFrom<u16> for RelayPort {
+    type Error = OutOfRange;
+
+    fn try_from(value: u16) -> Result<Self, Self::Error> {
+        Self::new(value).ok_or(OutOfRange(value))
+    }
+}
+
+/// A peer is only reachable through this server on the relay port of
+/// another allocation.
+impl TryFrom<PeerPort> for RelayPort {
+    type Error = OutOfRange;
+
+    fn try_from(value: PeerPort) -> Result<Self, Self::Error> {
+        Self::try_from(value.0)
+    }
+}
+
+/// The port of a peer address, as in the XOR-PEER-ADDRESS attribute.
+///
+/// # Test
+///
+/// ```
+/// use mycrl_turn::*;
+///
+/// let relay = RelayPort::new(49152).unwrap();
+/// assert_eq!(PeerPort::from(relay), PeerPort::from(49152));
+/// assert_eq!(u16::from(PeerPort::from(3478)), 3478);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(from = "u16", into = "u16")
+)]
+pub struct PeerPort(u16);
+
+number!(PeerPort);
+
+impl From<u16> for PeerPort {
+    fn from(value: u16) -> Self {
+        Self(value)
+    }
+}
+
+impl From<RelayPort> for PeerPort {
+    fn from(value: RelayPort) -> Self {
+        Self(value.0)
+    }
+}
+
+/// The number of a channel, in the range 0x4000 through 0x7FFF.
+///
+/// # Test
+///
+/// ```
+/// use mycrl_turn::*;
+///
+/// assert_eq!(ChannelNumber::new(0x4000).map(ChannelNumber::get), Some(0x4000));
+/// assert_eq!(ChannelNumber::new(0x7FFF).map(ChannelNumber::get), Some(0x7FFF));
+/// assert_eq!(ChannelNumber::new(0x3FFF), None);
+/// assert_eq!(ChannelNumber::try_from(0x8000), Err(OutOfRange(0x8000)));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "u16", into = "u16")
+)]
+pub struct ChannelNumber(u16);
+
+number!(ChannelNumber);
+
+impl ChannelNumber {
+    pub const fn new(number: u16) -> Option<Self> {
+        if number >= 0x4000 && number <= 0x7FFF {
+            Some(Self(number))
+        } else {
+            None
+        }
+    }
+}
+
+impl TryFrom<u16> for ChannelNumber {
+    type Error = OutOfRange;
+
+    fn try_from(value: u16) -> Result<Self, Self::Error> {
+        Self::new(value).ok_or(OutOfRange(value))
+    }
+}